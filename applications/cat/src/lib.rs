@@ -8,17 +8,19 @@ extern crate getopts;
 extern crate path;
 extern crate fs_node;
 extern crate core_io;
+extern crate file_handle;
+extern crate spin;
 
-use core::str;
 use alloc::{
     vec::Vec,
     string::{String, ToString},
     sync::Arc,
 };
 use getopts::Options;
-use path::Path;
-use fs_node::FileOrDir;
+use path::PathBuf;
+use spin::Mutex;
 use core_io::{Read, Write};
+use file_handle::{OpenOptions, FileDescriptor, FileDescriptorTable};
 
 
 pub fn main(args: Vec<String>) -> isize {
@@ -58,44 +60,26 @@ pub fn main(args: Vec<String>) -> isize {
         let curr_env = locked_task.env.lock();
         Arc::clone(&curr_env.working_dir)
     };
-    let path = Path::new(matches.free[0].to_string());
-    
-    // navigate to the filepath specified by first argument
-    match path.get(&curr_wr) {
-        Some(file_dir_enum) => { 
-            match file_dir_enum {
-                FileOrDir::Dir(directory) => {
-                    println!("{:?} is a directory, cannot 'cat' non-files.", directory.lock().get_name());
-                    return -1;
-                }
-                FileOrDir::File(file) => {
-                    let file_locked = file.lock();
-                    let file_size = file_locked.size();
-                    let mut string_slice_as_bytes = vec![0; file_size];
-                    
-                    let _num_bytes_read = match file_locked.read(&mut string_slice_as_bytes,0) {
-                        Ok(num) => num,
-                        Err(e) => {
-                            println!("Failed to read {:?}, error {:?}", file_locked.get_name(), e);
-                            return -1;
-                        }
-                    };
-                    let read_string = match str::from_utf8(&string_slice_as_bytes) {
-                        Ok(string_slice) => string_slice,
-                        Err(utf8_err) => {
-                            println!("File {:?} was not a printable UTF-8 text file: {}", file_locked.get_name(), utf8_err);
-                            return -1;
-                        }
-                    };
-                    println!("{}", read_string);
-                }
+    let fd_table = taskref.get_file_descriptors();
+
+    // concatenate each file specified by the arguments, in order
+    for file_path in matches.free.iter() {
+        let path = PathBuf::from(file_path.to_string());
+        let file = match OpenOptions::new().read(true).open(&path, &curr_wr) {
+            Ok(f) => f,
+            Err(e) => {
+                println!("Couldn't open file at path {}: {}", path, e);
+                return -1;
             }
-        },
-        _ => {
-            println!("Couldn't find file at path {}", path);
+        };
+        let fd = fd_table.lock().insert(file);
+        let result = echo_from_file(&fd_table, fd);
+        let _ = fd_table.lock().close(fd);
+        if let Err(e) = result {
+            println!("Failed to read {}: {}", path, e);
             return -1;
         }
-    };
+    }
     return 0;
 }
 
@@ -103,6 +87,23 @@ fn print_usage(opts: Options) {
     println!("{}", opts.usage(USAGE));
 }
 
+/// Copies everything from the current offset of the open file `fd` until the end of that file to stdout.
+fn echo_from_file(fd_table: &Mutex<FileDescriptorTable>, fd: FileDescriptor) -> Result<(), &'static str> {
+    let file = fd_table.lock().get(fd).ok_or("invalid file descriptor")?;
+    let mut file_locked = file.lock();
+    let stdout = app_io::stdout()?;
+    let mut stdout_locked = stdout.lock();
+    let mut buf = [0u8; 4096];
+
+    loop {
+        let cnt = file_locked.read(&mut buf).or(Err("failed to perform read"))?;
+        if cnt == 0 { break; }
+        stdout_locked.write_all(&buf[0..cnt])
+            .or(Err("failed to perform write_all"))?;
+    }
+    Ok(())
+}
+
 fn echo_from_stdin() -> Result<(), &'static str> {
     let stdin = app_io::stdin()?;
     let mut stdin_locked = stdin.lock();
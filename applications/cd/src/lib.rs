@@ -14,7 +14,7 @@ use alloc::string::String;
 use alloc::sync::Arc;
 use alloc::string::ToString;
 use getopts::Options;
-use path::Path;
+use path::PathBuf;
 use fs_node::FileOrDir;
 
 
@@ -57,7 +57,7 @@ pub fn main(args: Vec<String>) -> isize {
         return 0;
     }
 
-    let path = Path::new(matches.free[0].to_string());
+    let path = PathBuf::from(matches.free[0].to_string());
     
     // navigate to the filepath specified by first argument
     match path.get(&curr_wr) {
@@ -70,6 +70,10 @@ pub fn main(args: Vec<String>) -> isize {
                     println!("{:?} is not a directory.", file.lock().get_name());
                     return -1;
                 }
+                FileOrDir::Symlink(_) => {
+                    println!("{} is not a directory.", path);
+                    return -1;
+                }
             }
         },
         _ => {
@@ -0,0 +1,169 @@
+//! This application copies files and directories.
+//!
+//! Like `cp` on Unix, `cp SOURCE DEST` copies `SOURCE` to the path `DEST`,
+//! unless `DEST` is an existing directory, in which case each `SOURCE` is copied into it.
+//! Copies are created as in-memory files and directories, or stored on disk if the destination is on-disk.
+
+#![no_std]
+#[macro_use] extern crate alloc;
+#[macro_use] extern crate terminal_print;
+
+extern crate getopts;
+extern crate task;
+extern crate fs_node;
+extern crate path;
+extern crate vfs_node;
+extern crate heapfile;
+
+use alloc::{
+    string::{String, ToString},
+    sync::Arc,
+    vec::Vec,
+};
+use getopts::{Options, Matches};
+use fs_node::{DirRef, FileRef, FileOrDir, FsNode};
+use path::{Path, PathBuf};
+use vfs_node::{VFSDirectory, VFSSymlink};
+use heapfile::HeapFile;
+
+
+pub fn main(args: Vec<String>) -> isize {
+    let mut opts = Options::new();
+    opts.optflag("h", "help", "print this help menu");
+    opts.optflag("r", "recursive", "recursively copy directories and their contents");
+
+    let matches = match opts.parse(&args) {
+        Ok(m) => m,
+        Err(_f) => {
+            println!("{}", _f);
+            print_usage(opts);
+            return -1;
+        }
+    };
+
+    if matches.opt_present("h") {
+        print_usage(opts);
+        return 0;
+    }
+
+    match rmain(matches) {
+        Ok(_) => 0,
+        Err(e) => {
+            println!("Error: {}", e);
+            -1
+        }
+    }
+}
+
+
+fn rmain(matches: Matches) -> Result<(), String> {
+    if matches.free.len() < 2 {
+        return Err(format!("expected at least one SOURCE and a DEST, see 'cp --help'"));
+    }
+    let curr_wd = {
+        let curr_task = task::get_my_current_task().ok_or_else(|| format!("unable to get current task"))?;
+        let locked_task = curr_task.lock();
+        let curr_env = locked_task.env.lock();
+        Arc::clone(&curr_env.working_dir)
+    };
+
+    let (sources, dest) = matches.free.split_at(matches.free.len() - 1);
+    let dest_path = PathBuf::from(dest[0].clone());
+    let (dest_dir, dest_name) = match dest_path.get(&curr_wd) {
+        Some(FileOrDir::Dir(dir)) => (dir, None),
+        _ if sources.len() > 1 => return Err(format!("target {:?} is not a directory", dest_path.as_str())),
+        _ => {
+            let (parent, name) = parent_and_name(&dest_path, &curr_wd)?;
+            (parent, Some(name))
+        }
+    };
+
+    for source in sources {
+        let source_path = PathBuf::from(source.clone());
+        let node = source_path.get(&curr_wd).ok_or_else(|| format!("couldn't find {:?}", source_path.as_str()))?;
+        if let FileOrDir::Dir(ref dir) = node {
+            if !matches.opt_present("r") {
+                println!("Skipping the directory {:?}, try specifying the \"-r\" flag", source_path.as_str());
+                continue;
+            }
+            if fs_node::is_within(&dest_dir, dir) {
+                return Err(format!("cannot copy directory {:?} into itself", source_path.as_str()));
+            }
+        }
+        let name = dest_name.clone().unwrap_or_else(|| node.get_name());
+        copy_node(&node, &dest_dir, &name)?;
+    }
+    Ok(())
+}
+
+
+/// Copies the given `node` into the `dest_dir` directory, where the copy is called `name`.
+/// Directories are copied along with all of their contents,
+/// and symbolic links are copied as links to the same target.
+fn copy_node(node: &FileOrDir, dest_dir: &DirRef, name: &str) -> Result<(), String> {
+    match node {
+        FileOrDir::File(file) => {
+            let content = read_file(file)?;
+            HeapFile::from_vec(content, name.to_string(), dest_dir)?;
+        }
+        FileOrDir::Dir(dir) => {
+            VFSDirectory::new(name.to_string(), dest_dir)?;
+            // Some directories (e.g., on-disk filesystems) store a copy of the inserted directory rather than the directory itself.
+            let new_dir = dest_dir.lock().get_dir(name).ok_or_else(|| format!("couldn't create directory {:?}", name))?;
+            let child_names = dir.lock().list();
+            for child_name in child_names {
+                let child = dir.lock().get(&child_name);
+                if let Some(child) = child {
+                    copy_node(&child, &new_dir, &child_name)?;
+                }
+            }
+        }
+        FileOrDir::Symlink(symlink) => {
+            let target = symlink.lock().target();
+            VFSSymlink::new(name.to_string(), target, dest_dir)?;
+        }
+    }
+    Ok(())
+}
+
+/// Reads the entire contents of the given `file`.
+fn read_file(file: &FileRef) -> Result<Vec<u8>, String> {
+    let locked_file = file.lock();
+    let mut content = vec![0u8; locked_file.size()];
+    let mut offset = 0;
+    while offset < content.len() {
+        let count = locked_file.read(&mut content[offset..], offset)?;
+        if count == 0 {
+            break;
+        }
+        offset += count;
+    }
+    content.truncate(offset);
+    Ok(content)
+}
+
+/// Splits the given `path` into the directory that it leads to, which must exist, and its final component.
+fn parent_and_name(path: &Path, working_dir: &DirRef) -> Result<(DirRef, String), String> {
+    let name = path.basename().to_string();
+    if name.is_empty() || name == "." || name == ".." {
+        return Err(format!("invalid destination {:?}", path.as_str()));
+    }
+    let parent = match path.parent() {
+        Some(parent) if parent.is_empty() => Some(FileOrDir::Dir(Arc::clone(working_dir))),
+        Some(parent) => parent.get(working_dir),
+        None => return Err(format!("invalid destination {:?}", path.as_str())),
+    };
+    match parent {
+        Some(FileOrDir::Dir(dir)) => Ok((dir, name)),
+        _ => Err(format!("the parent directory of {:?} doesn't exist", path.as_str())),
+    }
+}
+
+
+fn print_usage(opts: Options) {
+    println!("{}", opts.usage(USAGE));
+}
+
+const USAGE: &'static str = "Usage: cp [OPTION]... SOURCE DEST
+   or: cp [OPTION]... SOURCE... DIRECTORY
+Copy SOURCE to DEST, or copy multiple SOURCEs into DIRECTORY.";
@@ -0,0 +1,101 @@
+//! This application reports the size and space usage of every mounted filesystem.
+
+#![no_std]
+#[macro_use] extern crate alloc;
+#[macro_use] extern crate terminal_print;
+
+extern crate getopts;
+extern crate mount_table;
+
+use core::fmt::Write;
+use alloc::{
+    string::String,
+    vec::Vec,
+};
+use getopts::{Options, Matches};
+
+
+pub fn main(args: Vec<String>) -> isize {
+    let mut opts = Options::new();
+    opts.optflag("", "help", "print this help menu");
+    opts.optflag("h", "human-readable", "print sizes in powers of 1024 (e.g., 1023M)");
+
+    let matches = match opts.parse(&args) {
+        Ok(m) => m,
+        Err(_f) => {
+            println!("{}", _f);
+            print_usage(opts);
+            return -1;
+        }
+    };
+
+    if matches.opt_present("help") {
+        print_usage(opts);
+        return 0;
+    }
+
+    match rmain(matches) {
+        Ok(_) => 0,
+        Err(e) => {
+            println!("Error: {}", e);
+            -1
+        }
+    }
+}
+
+
+fn rmain(matches: Matches) -> Result<(), String> {
+    let human_readable = matches.opt_present("h");
+    let format_size = |bytes: usize| if human_readable { human_size(bytes) } else { format!("{}", bytes / 1024) };
+
+    let mut output = String::new();
+    let size_header = if human_readable { "Size" } else { "1K-blocks" };
+    writeln!(output, "Filesystem   Type     {:>10}       Used  Available  Use%  Mounted on", size_header)
+        .map_err(|_e| String::from("String formatting error"))?;
+
+    for mount in mount_table::get_mounts() {
+        let (size, used, available, percent) = match mount.filesystem.stats() {
+            Some(stats) => {
+                let total = stats.total_blocks * stats.block_size;
+                let free = stats.free_blocks * stats.block_size;
+                let used = total.saturating_sub(free);
+                let percent = if total == 0 { 0 } else { (used * 100 + total - 1) / total };
+                (format_size(total), format_size(used), format_size(free), format!("{}%", percent))
+            }
+            // filesystems without a fixed capacity, e.g., generated ones, have no usage to report
+            None => (String::from("-"), String::from("-"), String::from("-"), String::from("-")),
+        };
+        writeln!(output, "{:<12} {:<8} {:>10} {:>10} {:>10} {:>5}  {}",
+            mount.source, mount.fs_type, size, used, available, percent, mount.mountpoint
+        ).map_err(|_e| String::from("String formatting error"))?;
+    }
+
+    print!("{}", output);
+    Ok(())
+}
+
+/// Formats the given number of bytes using the largest fitting binary unit, e.g., `"1.5G"`.
+fn human_size(bytes: usize) -> String {
+    const UNITS: [&str; 5] = ["B", "K", "M", "G", "T"];
+    let mut unit = 0;
+    let mut divisor = 1;
+    while bytes / divisor >= 1024 && unit < UNITS.len() - 1 {
+        divisor *= 1024;
+        unit += 1;
+    }
+    // the size in tenths of a unit, rounded up
+    let tenths = (bytes * 10 + divisor - 1) / divisor;
+    if unit == 0 || tenths >= 100 {
+        format!("{}{}", (tenths + 9) / 10, UNITS[unit])
+    } else {
+        format!("{}.{}{}", tenths / 10, tenths % 10, UNITS[unit])
+    }
+}
+
+fn print_usage(opts: Options) {
+    println!("{}", opts.usage(USAGE));
+}
+
+
+const USAGE: &'static str = "Usage: df [OPTIONS]
+Shows the size and space usage of every mounted filesystem.";
@@ -13,23 +13,23 @@ extern crate spin;
 extern crate app_io;
 extern crate stdio;
 extern crate core_io;
+extern crate file_handle;
 #[macro_use] extern crate log;
 
 use keycodes_ascii::{Keycode, KeyAction};
-use core::str;
 use alloc::{
     vec::Vec,
     string::{String, ToString},
     sync::Arc,
 };
 use getopts::Options;
-use path::Path;
-use fs_node::FileOrDir;
+use path::PathBuf;
 use alloc::collections::BTreeMap;
 use libterm::Terminal;
 use spin::Mutex;
 use stdio::{StdioWriter, KeyEventQueueReader};
-use core_io::Write;
+use core_io::{Read, Write};
+use file_handle::{OpenOptions, FileDescriptor, FileDescriptorTable};
 
 /// The metadata for each line in the file.
 struct LineSlice {
@@ -54,41 +54,36 @@ fn get_content_string(file_path: String) -> Result<String, String> {
         let curr_env = locked_task.env.lock();
         Arc::clone(&curr_env.working_dir)
     };
-    let path = Path::new(file_path);
-    
-    // navigate to the filepath specified by first argument
-    match path.get(&curr_wr) {
-        Some(file_dir_enum) => { 
-            match file_dir_enum {
-                FileOrDir::Dir(directory) => {
-                    Err(format!("{:?} is a directory, cannot 'less' non-files.", directory.lock().get_name()))
-                }
-                FileOrDir::File(file) => {
-                    let file_locked = file.lock();
-                    let file_size = file_locked.size();
-                    let mut string_slice_as_bytes = vec![0; file_size];
-                    
-                    let _num_bytes_read = match file_locked.read(&mut string_slice_as_bytes,0) {
-                        Ok(num) => num,
-                        Err(e) => {
-                            return Err(format!("Failed to read {:?}, error {:?}",
-                                               file_locked.get_name(), e).to_string())
-                        }
-                    };
-                    let read_string = match str::from_utf8(&string_slice_as_bytes) {
-                        Ok(string_slice) => string_slice,
-                        Err(utf8_err) => {
-                            return Err(format!("File {:?} was not a printable UTF-8 text file: {}",
-                                               file_locked.get_name(), utf8_err).to_string())
-                        }
-                    };
-                    Ok(read_string.to_string())
-                }
-            }
-        },
-        _ => {
-            Err(format!("Couldn't find file at path {}", path).to_string())
+    let path = PathBuf::from(file_path);
+
+    // open the file specified by first argument, and keep it in this task's file descriptor table while reading it
+    let file = OpenOptions::new().read(true).open(&path, &curr_wr)
+        .map_err(|e| format!("Couldn't open file at path {}: {}", path, e))?;
+    let fd_table = taskref.get_file_descriptors();
+    let fd = fd_table.lock().insert(file);
+    let read_result = read_to_end(&fd_table, fd);
+    let _ = fd_table.lock().close(fd);
+
+    let bytes = read_result.map_err(|e| format!("Failed to read {}, error {:?}", path, e))?;
+    match String::from_utf8(bytes) {
+        Ok(read_string) => Ok(read_string),
+        Err(utf8_err) => Err(format!("File {} was not a printable UTF-8 text file: {}", path, utf8_err.utf8_error())),
+    }
+}
+
+/// Reads everything from the current offset of the open file `fd` until the end of that file.
+fn read_to_end(fd_table: &Mutex<FileDescriptorTable>, fd: FileDescriptor) -> Result<Vec<u8>, core_io::Error> {
+    let file = fd_table.lock().get(fd)
+        .ok_or(core_io::Error::new(core_io::ErrorKind::NotFound, "invalid file descriptor"))?;
+    let mut file = file.lock();
+    let mut contents = Vec::new();
+    let mut buf = [0u8; 4096];
+    loop {
+        let count = file.read(&mut buf)?;
+        if count == 0 {
+            return Ok(contents);
         }
+        contents.extend_from_slice(&buf[..count]);
     }
 }
 
@@ -0,0 +1,100 @@
+//! This application creates symbolic links.
+//!
+//! Like `ln -s` on Unix, `ln -s TARGET NAME` creates a symbolic link at the path `NAME` that points to `TARGET`.
+//! The `TARGET` is stored as given, without checking whether it exists,
+//! so a relative `TARGET` is resolved relative to the directory containing the link.
+//! Hard links are not supported.
+
+#![no_std]
+#[macro_use] extern crate alloc;
+#[macro_use] extern crate terminal_print;
+
+extern crate getopts;
+extern crate task;
+extern crate fs_node;
+extern crate path;
+extern crate vfs_node;
+
+use alloc::{
+    string::{String, ToString},
+    sync::Arc,
+    vec::Vec,
+};
+use getopts::{Options, Matches};
+use fs_node::FileOrDir;
+use path::Path;
+use vfs_node::VFSSymlink;
+
+
+pub fn main(args: Vec<String>) -> isize {
+    let mut opts = Options::new();
+    opts.optflag("h", "help", "print this help menu");
+    opts.optflag("s", "symbolic", "make a symbolic link (required, hard links are not supported)");
+
+    let matches = match opts.parse(&args) {
+        Ok(m) => m,
+        Err(_f) => {
+            println!("{}", _f);
+            print_usage(opts);
+            return -1;
+        }
+    };
+
+    if matches.opt_present("h") {
+        print_usage(opts);
+        return 0;
+    }
+
+    match rmain(matches) {
+        Ok(_) => 0,
+        Err(e) => {
+            println!("Error: {}", e);
+            -1
+        }
+    }
+}
+
+
+fn rmain(matches: Matches) -> Result<(), String> {
+    if !matches.opt_present("s") {
+        return Err("hard links are not supported, use 'ln -s' to create a symbolic link".to_string());
+    }
+    if matches.free.len() != 2 {
+        return Err("expected a TARGET and a NAME, see 'ln --help'".to_string());
+    }
+    let curr_wd = {
+        let curr_task = task::get_my_current_task().ok_or_else(|| "unable to get current task".to_string())?;
+        let locked_task = curr_task.lock();
+        let curr_env = locked_task.env.lock();
+        Arc::clone(&curr_env.working_dir)
+    };
+
+    let target = &matches.free[0];
+    let link_path = Path::new(&matches.free[1]);
+    let name = link_path.basename();
+    if name.is_empty() || name == "." || name == ".." {
+        return Err(format!("invalid link name {:?}", link_path.as_str()));
+    }
+    let parent = match link_path.parent() {
+        Some(parent) if parent.is_empty() => Some(FileOrDir::Dir(Arc::clone(&curr_wd))),
+        Some(parent) => parent.get(&curr_wd),
+        None => return Err(format!("invalid link name {:?}", link_path.as_str())),
+    };
+    let parent_dir = match parent {
+        Some(FileOrDir::Dir(dir)) => dir,
+        _ => return Err(format!("the parent directory of {:?} doesn't exist", link_path.as_str())),
+    };
+    if parent_dir.lock().get(name).is_some() {
+        return Err(format!("{:?} already exists", link_path.as_str()));
+    }
+    VFSSymlink::new(name.to_string(), target.clone(), &parent_dir)?;
+    Ok(())
+}
+
+
+fn print_usage(opts: Options) {
+    println!("{}", opts.usage(USAGE));
+}
+
+const USAGE: &'static str = "Usage: ln -s TARGET NAME
+Create a symbolic link called NAME that points to TARGET.";
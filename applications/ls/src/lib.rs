@@ -10,7 +10,6 @@ extern crate path;
 
 use alloc::vec::Vec;
 use alloc::string::String;
-use alloc::string::ToString;
 use fs_node::{FileOrDir, DirRef};
 use getopts::Options;
 use path::Path;
@@ -55,23 +54,27 @@ pub fn main(args: Vec<String>) -> isize {
         return 0;
     }
 
-    let path = Path::new(matches.free[0].to_string());
-
-    // navigate to the path specified by first argument
-    match path.get(&curr_wd) {
-        Some(FileOrDir::Dir(dir)) => {
-            print_children(&dir);
-            return 0;
-        }
-        Some(FileOrDir::File(file)) => {
-            println!("'{}' is not a directory; `ls` currently only supports listing directory contents.", file.lock().get_name());
-            return -1;
+    // print the name of each file argument, and the children of each directory argument
+    let mut ret = 0;
+    for arg in matches.free.iter() {
+        let path = Path::new(arg);
+        match path.get(&curr_wd) {
+            Some(FileOrDir::Dir(dir)) => {
+                if matches.free.len() > 1 {
+                    println!("{}:", path);
+                }
+                print_children(&dir);
+            }
+            Some(FileOrDir::File(file)) => {
+                println!("{}", file.lock().get_name());
+            }
+            _ => {
+                println!("Couldn't find path: {}", path);
+                ret = -1;
+            }
         }
-        _ => {
-            println!("Couldn't find path: {}", path); 
-            return -1;
-        }
-    };
+    }
+    ret
 }
 
 fn print_children(dir: &DirRef) {
@@ -89,6 +92,6 @@ fn print_usage(opts: Options) {
 }
 
 
-const USAGE: &'static str = "Usage: ls [DIR | FILE]
-List the contents of the given directory or info about the given file.
+const USAGE: &'static str = "Usage: ls [DIR | FILE]...
+List the contents of each given directory or info about each given file.
 If no arguments are provided, it lists the contents of the current directory.";
\ No newline at end of file
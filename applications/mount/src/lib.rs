@@ -0,0 +1,104 @@
+//! This application mounts a filesystem on top of a directory,
+//! or lists all currently-mounted filesystems if no arguments are given.
+
+#![no_std]
+#[macro_use] extern crate alloc;
+#[macro_use] extern crate terminal_print;
+
+extern crate getopts;
+extern crate task;
+extern crate fs_node;
+extern crate path;
+extern crate mount_table;
+
+use alloc::{
+    string::{String, ToString},
+    sync::Arc,
+    vec::Vec,
+};
+use getopts::{Options, Matches};
+use fs_node::FileOrDir;
+use path::PathBuf;
+use mount_table::MountOptions;
+
+
+pub fn main(args: Vec<String>) -> isize {
+    let mut opts = Options::new();
+    opts.optflag("h", "help", "print this help menu");
+    opts.optopt("t", "type", "the type of the filesystem, which is detected automatically if not given", "TYPE");
+    opts.optopt("o", "options", "a comma-separated list of mount options, e.g., \"ro\"", "OPTIONS");
+
+    let matches = match opts.parse(&args) {
+        Ok(m) => m,
+        Err(_f) => {
+            println!("{}", _f);
+            print_usage(opts);
+            return -1;
+        }
+    };
+
+    if matches.opt_present("h") {
+        print_usage(opts);
+        return 0;
+    }
+
+    match rmain(matches) {
+        Ok(_) => 0,
+        Err(e) => {
+            println!("Error: {}", e);
+            -1
+        }
+    }
+}
+
+
+fn rmain(matches: Matches) -> Result<(), String> {
+    if matches.free.is_empty() {
+        for mount in mount_table::get_mounts() {
+            println!("{} on {} type {} ({})", mount.source, mount.mountpoint, mount.fs_type, mount.options);
+        }
+        return Ok(());
+    }
+    if matches.free.len() != 2 {
+        return Err(format!("expected a SOURCE and a MOUNTPOINT, see 'mount --help'"));
+    }
+    let source = &matches.free[0];
+    let mountpoint_path = PathBuf::from(matches.free[1].to_string());
+
+    let curr_wd = {
+        let curr_task = task::get_my_current_task().ok_or_else(|| format!("unable to get current task"))?;
+        let locked_task = curr_task.lock();
+        let curr_env = locked_task.env.lock();
+        Arc::clone(&curr_env.working_dir)
+    };
+    let mountpoint = match mountpoint_path.get(&curr_wd) {
+        Some(FileOrDir::Dir(dir)) => dir,
+        Some(_) => return Err(format!("mountpoint {:?} is not a directory", mountpoint_path.as_str())),
+        None => return Err(format!("couldn't find mountpoint {:?}", mountpoint_path.as_str())),
+    };
+    let options = MountOptions::parse(&matches.opt_str("o").unwrap_or_default());
+
+    if let Some(fs_type) = matches.opt_str("t") {
+        mount_table::mount(source, &fs_type, &mountpoint, options).map_err(|e| e.to_string())?;
+        return Ok(());
+    }
+
+    // No type was given, so try each of the registered filesystem types.
+    for fs_type in mount_table::filesystem_types() {
+        if mount_table::mount(source, fs_type, &mountpoint, options.clone()).is_ok() {
+            println!("Mounted {} as {} on {}", source, fs_type, mountpoint_path);
+            return Ok(());
+        }
+    }
+    Err(format!("couldn't mount {:?} as any known filesystem type: {:?}", source, mount_table::filesystem_types()))
+}
+
+
+fn print_usage(opts: Options) {
+    println!("{}", opts.usage(USAGE));
+}
+
+
+const USAGE: &'static str = "Usage: mount [OPTIONS] [SOURCE MOUNTPOINT]
+Mounts the filesystem from SOURCE (e.g., a storage device like \"disk0\") on top of the MOUNTPOINT directory.
+If no arguments are provided, it lists all mounted filesystems.";
@@ -0,0 +1,184 @@
+//! This application moves or renames files and directories.
+//!
+//! Like `mv` on Unix, `mv SOURCE DEST` moves `SOURCE` to the path `DEST`,
+//! unless `DEST` is an existing directory, in which case each `SOURCE` is moved into it.
+//!
+//! Nodes are moved using `fs_node::move_node()`, which keeps the node itself intact.
+//! Nodes that cannot be detached from their directory that way, e.g., those in on-disk directories that
+//! can only be renamed in place, are instead copied to their destination and then removed.
+//! All other errors, e.g., a failure to insert the node into its destination, are reported.
+
+#![no_std]
+#[macro_use] extern crate alloc;
+#[macro_use] extern crate terminal_print;
+
+extern crate getopts;
+extern crate task;
+extern crate fs_node;
+extern crate path;
+extern crate vfs_node;
+extern crate heapfile;
+extern crate mount_table;
+
+use alloc::{
+    string::{String, ToString},
+    sync::Arc,
+    vec::Vec,
+};
+use getopts::{Options, Matches};
+use fs_node::{DirRef, FileRef, FileOrDir, FsNode};
+use path::{Path, PathBuf};
+use vfs_node::{VFSDirectory, VFSSymlink};
+use heapfile::HeapFile;
+
+
+pub fn main(args: Vec<String>) -> isize {
+    let mut opts = Options::new();
+    opts.optflag("h", "help", "print this help menu");
+
+    let matches = match opts.parse(&args) {
+        Ok(m) => m,
+        Err(_f) => {
+            println!("{}", _f);
+            print_usage(opts);
+            return -1;
+        }
+    };
+
+    if matches.opt_present("h") {
+        print_usage(opts);
+        return 0;
+    }
+
+    match rmain(matches) {
+        Ok(_) => 0,
+        Err(e) => {
+            println!("Error: {}", e);
+            -1
+        }
+    }
+}
+
+
+fn rmain(matches: Matches) -> Result<(), String> {
+    if matches.free.len() < 2 {
+        return Err(format!("expected at least one SOURCE and a DEST, see 'mv --help'"));
+    }
+    let curr_wd = {
+        let curr_task = task::get_my_current_task().ok_or_else(|| format!("unable to get current task"))?;
+        let locked_task = curr_task.lock();
+        let curr_env = locked_task.env.lock();
+        Arc::clone(&curr_env.working_dir)
+    };
+
+    let (sources, dest) = matches.free.split_at(matches.free.len() - 1);
+    let dest_path = PathBuf::from(dest[0].clone());
+    let (dest_dir, dest_name) = match dest_path.get(&curr_wd) {
+        Some(FileOrDir::Dir(dir)) => (dir, None),
+        _ if sources.len() > 1 => return Err(format!("target {:?} is not a directory", dest_path.as_str())),
+        _ => {
+            let (parent, name) = parent_and_name(&dest_path, &curr_wd)?;
+            (parent, Some(name))
+        }
+    };
+
+    for source in sources {
+        let source_path = PathBuf::from(source.clone());
+        // a symbolic link is moved itself, rather than the node it points to
+        let node = source_path.get_no_follow(&curr_wd).ok_or_else(|| format!("couldn't find {:?}", source_path.as_str()))?;
+        if let FileOrDir::Dir(ref dir) = node {
+            if mount_table::get_mounts().iter().any(|m| Arc::ptr_eq(m.root(), dir)) {
+                return Err(format!("{:?} is a mounted filesystem, try 'umount' instead", source_path.as_str()));
+            }
+            if fs_node::is_within(&dest_dir, dir) {
+                return Err(format!("cannot move directory {:?} into itself", source_path.as_str()));
+            }
+        }
+        let source_dir = node.get_parent_dir().ok_or_else(|| format!("couldn't find the parent directory of {:?}", source_path.as_str()))?;
+        let name = node.get_name();
+        let new_name = dest_name.clone().unwrap_or_else(|| name.clone());
+
+        match fs_node::move_node(&source_dir, &name, &dest_dir, &new_name) {
+            Ok(_) => { }
+            Err(fs_node::DETACH_UNSUPPORTED) => {
+                // fall back to copying the node and then removing the original
+                copy_node(&node, &dest_dir, &new_name)?;
+                source_dir.lock().remove(&node).ok_or_else(|| format!("couldn't remove {:?} after copying it", source_path.as_str()))?;
+            }
+            Err(e) => return Err(format!("couldn't move {:?}: {}", source_path.as_str(), e)),
+        }
+    }
+    Ok(())
+}
+
+
+/// Copies the given `node` into the `dest_dir` directory, where the copy is called `name`.
+/// Directories are copied along with all of their contents,
+/// and symbolic links are copied as links to the same target.
+fn copy_node(node: &FileOrDir, dest_dir: &DirRef, name: &str) -> Result<(), String> {
+    match node {
+        FileOrDir::File(file) => {
+            let content = read_file(file)?;
+            HeapFile::from_vec(content, name.to_string(), dest_dir)?;
+        }
+        FileOrDir::Dir(dir) => {
+            VFSDirectory::new(name.to_string(), dest_dir)?;
+            // Some directories (e.g., on-disk filesystems) store a copy of the inserted directory rather than the directory itself.
+            let new_dir = dest_dir.lock().get_dir(name).ok_or_else(|| format!("couldn't create directory {:?}", name))?;
+            let child_names = dir.lock().list();
+            for child_name in child_names {
+                let child = dir.lock().get(&child_name);
+                if let Some(child) = child {
+                    copy_node(&child, &new_dir, &child_name)?;
+                }
+            }
+        }
+        FileOrDir::Symlink(symlink) => {
+            let target = symlink.lock().target();
+            VFSSymlink::new(name.to_string(), target, dest_dir)?;
+        }
+    }
+    Ok(())
+}
+
+/// Reads the entire contents of the given `file`.
+fn read_file(file: &FileRef) -> Result<Vec<u8>, String> {
+    let locked_file = file.lock();
+    let mut content = vec![0u8; locked_file.size()];
+    let mut offset = 0;
+    while offset < content.len() {
+        let count = locked_file.read(&mut content[offset..], offset)?;
+        if count == 0 {
+            break;
+        }
+        offset += count;
+    }
+    content.truncate(offset);
+    Ok(content)
+}
+
+/// Splits the given `path` into the directory that it leads to, which must exist, and its final component.
+fn parent_and_name(path: &Path, working_dir: &DirRef) -> Result<(DirRef, String), String> {
+    let name = path.basename().to_string();
+    if name.is_empty() || name == "." || name == ".." {
+        return Err(format!("invalid destination {:?}", path.as_str()));
+    }
+    let parent = match path.parent() {
+        Some(parent) if parent.is_empty() => Some(FileOrDir::Dir(Arc::clone(working_dir))),
+        Some(parent) => parent.get(working_dir),
+        None => return Err(format!("invalid destination {:?}", path.as_str())),
+    };
+    match parent {
+        Some(FileOrDir::Dir(dir)) => Ok((dir, name)),
+        _ => Err(format!("the parent directory of {:?} doesn't exist", path.as_str())),
+    }
+}
+
+
+fn print_usage(opts: Options) {
+    println!("{}", opts.usage(USAGE));
+}
+
+const USAGE: &'static str = "Usage: mv [OPTION]... SOURCE DEST
+   or: mv [OPTION]... SOURCE... DIRECTORY
+Rename SOURCE to DEST, or move multiple SOURCEs into DIRECTORY.";
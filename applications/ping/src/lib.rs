@@ -1,4 +1,4 @@
-//! This application pings a specific IPv4 address and gets ping statistics.
+//! This application pings a specific IPv4 address or host name and gets ping statistics.
 //! Important: QEMU does not support the ICMP protocol by default so it's important to 
 //! run this command: sudo sh -c "echo \"0 2147483647\" > /proc/sys/net/ipv4/ping_group_range"
 //! in the environment prior to running this application
@@ -17,6 +17,7 @@ extern crate smoltcp_helper;
 extern crate hashbrown;
 extern crate ota_update_client;
 extern crate getopts;
+extern crate dns_resolver;
 
 
 use getopts::{Matches, Options};
@@ -33,6 +34,7 @@ use smoltcp::{
 use network_manager::{NetworkInterfaceRef, NETWORK_INTERFACES};
 use byteorder::{ByteOrder, NetworkEndian};
 use smoltcp_helper::{millis_since, poll_iface};
+use dns_resolver::RecordType;
 
 
 macro_rules! hpet_ticks {
@@ -71,7 +73,7 @@ pub fn main(args: Vec<String>) -> isize {
 
 
     if matches.free.len() != 0 {
-        match IpAddress::from_str(&matches.free[0]) {
+        match resolve_destination(&matches.free[0]) {
             Ok(address) => {
                 let ping_address = address;
                 let result = rmain(&matches, opts, ping_address);
@@ -84,8 +86,8 @@ pub fn main(args: Vec<String>) -> isize {
                 }
                 
             }
-            _ => { 
-                println!("Invalid argument {}, not a valid adress", matches.free[0]); 
+            Err(e) => { 
+                println!("Invalid argument {}, not a valid address or host name: {}", matches.free[0], e); 
                 return -1;
             },
         }   
@@ -140,6 +142,16 @@ pub fn rmain(matches: &Matches, _opts: Options, address: IpAddress) -> Result<()
     }
 }
 
+/// Resolves the given destination, which is either an IPv4 address or a host name, into an IPv4 address.
+fn resolve_destination(destination: &str) -> Result<IpAddress, String> {
+    if let Ok(address) = IpAddress::from_str(destination) {
+        return Ok(address);
+    }
+    let iface = get_default_iface()?;
+    let addresses = dns_resolver::lookup(&iface, destination, RecordType::A).map_err(String::from)?;
+    addresses.first().cloned().ok_or_else(|| format!("host name has no IPv4 address"))
+}
+
 /// Used to gain access to the ethernet interface
 fn get_default_iface() -> Result<NetworkInterfaceRef, String> {
     NETWORK_INTERFACES.lock()
@@ -359,7 +371,7 @@ fn ping(address: IpAddress, count: usize, interval: u64, timeout: u64, verbose:
 fn print_usage(opts: &Options) -> isize {
     let mut brief = format!("Usage: ping DESTINATION \n \n");
 
-    brief.push_str("pings an IPv4 address and returns ping statistics");
+    brief.push_str("pings an IPv4 address or host name and returns ping statistics");
 
     println!("{} \n", opts.usage(&brief));
 
@@ -14,7 +14,7 @@ use alloc::string::String;
 use alloc::sync::Arc;
 use alloc::string::ToString;
 use getopts::Options;
-use path::Path;
+use path::PathBuf;
 use fs_node::{FsNode, FileOrDir};
 
 
@@ -65,8 +65,9 @@ pub fn remove_node(args: Vec<String>) -> Result<(), String> {
     }
 
     for path_string in &matches.free {
-        let path = Path::new(path_string.clone());
-        let node_to_delete = match path.get(&working_dir) {
+        let path = PathBuf::from(path_string.clone());
+        // a symbolic link is removed itself, rather than the node it points to
+        let node_to_delete = match path.get_no_follow(&working_dir) {
             Some(node) => node,
             _ => return Err(format!("Couldn't find path {}", path)),
         };
@@ -77,7 +78,7 @@ pub fn remove_node(args: Vec<String>) -> Result<(), String> {
         let parent = node_to_delete.get_parent_dir().ok_or_else(path_error)?;
 
         match node_to_delete {
-            FileOrDir::File(_) => {
+            FileOrDir::File(_) | FileOrDir::Symlink(_) => {
                 parent.lock().remove(&node_to_delete).ok_or_else(path_error)?;
             } 
             FileOrDir::Dir(_) => {
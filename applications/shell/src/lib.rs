@@ -20,6 +20,7 @@ extern crate stdio;
 extern crate core_io;
 extern crate app_io;
 extern crate fs_node;
+extern crate file_handle;
 extern crate terminal_print;
 extern crate print;
 extern crate environment;
@@ -32,7 +33,7 @@ use event_types::{Event};
 use keycodes_ascii::{Keycode, KeyAction, KeyEvent};
 use alloc::string::{String, ToString};
 use alloc::vec::Vec;
-use path::Path;
+use path::{Path, PathBuf};
 use task::{TaskRef, ExitValue, KillReason};
 use libterm::Terminal;
 use dfqueue::{DFQueue, DFQueueConsumer, DFQueueProducer};
@@ -43,10 +44,11 @@ use core::mem;
 use alloc::collections::BTreeMap;
 use stdio::{Stdio, KeyEventQueue, KeyEventQueueReader, KeyEventQueueWriter,
             StdioReader, StdioWriter};
-use core_io::Write;
+use core_io::{Read, Write};
 use core::ops::Deref;
 use app_io::{IoStreams, IoControlFlags};
 use fs_node::FileOrDir;
+use file_handle::{OpenOptions, FileDescriptor, FileDescriptorTable};
 
 /// The status of a job.
 #[derive(PartialEq)]
@@ -62,6 +64,7 @@ enum JobStatus {
 /// evaluated command line will create a `Job`. Each job contains one or more tasks.
 /// Tasks are stored in `tasks` in the same sequence as in the command line.
 /// When pipe is used, the i-th job's `stdout` is directed to the (i+1)-th job's `stdin`.
+/// The `stdin` of the first task and the `stdout` of the last task can be redirected from and to files.
 /// `stderr` is always read by shell and currently cannot be redirected.
 struct Job {
     /// References to the tasks that form this job. They are stored in the same sequence as
@@ -87,6 +90,9 @@ struct Job {
     stdin_writer: StdioWriter,
     /// The output reader of the job. It is the reader of `pipe_queues[N]`.
     stdout_reader: StdioReader,
+    /// The file that the output of the job is written to instead of the terminal, if redirected.
+    /// This is a file descriptor in the shell's own file descriptor table.
+    stdout_redirect: Option<FileDescriptor>,
     /// Command line that was used to create the job.
     cmd: String
 }
@@ -133,7 +139,19 @@ enum AppErr {
     NamespaceErr,
     /// The terminal could not spawn a new task to run the new application.
     /// Includes the String error returned from the task spawn function.
-    SpawnErr(String)
+    SpawnErr(String),
+    /// The command line contains an invalid redirection, or a redirected file could not be opened.
+    RedirectErr(String)
+}
+
+/// The files that a command line redirects its input from and its output to.
+#[derive(Default)]
+struct Redirections {
+    /// The file that the `stdin` of the first task is read from, given by `< file`.
+    stdin: Option<String>,
+    /// The file that the `stdout` of the last task is written to, given by `> file`,
+    /// and whether the output should be appended to it, given by `>> file`.
+    stdout: Option<(String, bool)>,
 }
 
 struct Shell {
@@ -165,6 +183,8 @@ struct Shell {
     print_producer: DFQueueProducer<Event>,
     /// The terminal's current environment
     env: Arc<Mutex<Environment>>,
+    /// The shell task's own file descriptor table, which holds the files that jobs are redirected from and to
+    file_descriptors: Arc<Mutex<FileDescriptorTable>>,
     /// the terminal that is bind with the shell instance
     terminal: Arc<Mutex<Terminal>>
 }
@@ -195,6 +215,10 @@ impl Shell {
 
         let terminal = Arc::new(Mutex::new(Terminal::new()?));
 
+        let file_descriptors = task::get_my_current_task()
+            .ok_or("failed to get the shell's current task")?
+            .get_file_descriptors();
+
         Ok(Shell {
             jobs: BTreeMap::new(),
             task_to_job: BTreeMap::new(),
@@ -209,6 +233,7 @@ impl Shell {
             print_consumer,
             print_producer,
             env: Arc::new(Mutex::new(env)),
+            file_descriptors,
             terminal
         })
     }
@@ -670,7 +695,7 @@ impl Shell {
         let app_file = matching_apps.next();
         let second_match = matching_apps.next(); // return an error if there are multiple matching apps 
         let app_path = app_file.xor(second_match)
-            .map(|f| Path::new(f.lock().get_absolute_path()))
+            .map(|f| PathBuf::from(f.lock().get_absolute_path()))
             .ok_or(AppErr::NotFound(cmd))?;
 
         let taskref = spawn::new_application_task_builder(app_path, None)
@@ -681,6 +706,9 @@ impl Shell {
             .map_err(|e| AppErr::SpawnErr(e.to_string()))?;
         
         taskref.set_env(self.env.clone()); // Set environment variable of application to the same as terminal task
+        // The application's stdin, stdout and stderr are its `app_io` streams, so it must not inherit
+        // any of the shell's open files, e.g., those opened for redirections, which the shell alone closes.
+        *taskref.get_file_descriptors().lock() = FileDescriptorTable::new();
 
         // Gets the task id so we can reference this task if we need to kill it with Ctrl+C
         return Ok(taskref);
@@ -689,9 +717,8 @@ impl Shell {
     /// Evaluate the command line. It creates a sequence of jobs, which forms a chain of applications that
     /// pipe the output from one to the next, and finally back to the shell. If any task fails to start up,
     /// all tasks that have already been spawned will be killed immeidately before returning error.
-    fn eval_cmdline(&mut self) -> Result<Vec<TaskRef>, AppErr> {
+    fn eval_cmdline(&mut self, cmdline: &str) -> Result<Vec<TaskRef>, AppErr> {
 
-        let cmdline = self.cmdline.clone();
         let mut task_refs = Vec::new();
 
         for single_task_cmd in cmdline.split("|") {
@@ -704,6 +731,7 @@ impl Shell {
                     args.pop();
                 }
             }
+            let args = self.expand_wildcards(args);
             match self.create_single_task(command, args) {
                 Ok(task_ref) => task_refs.push(task_ref),
 
@@ -721,10 +749,101 @@ impl Shell {
         Ok(task_refs)
     }
 
+    /// Expands each argument that contains a wildcard, `*` or `?`, into the paths that it matches,
+    /// which are relative to the shell's working directory unless the argument is an absolute path.
+    /// An argument that doesn't match anything is passed along unchanged, like in most shells.
+    fn expand_wildcards(&self, args: Vec<String>) -> Vec<String> {
+        let working_dir = Arc::clone(&self.env.lock().working_dir);
+        let mut expanded_args = Vec::with_capacity(args.len());
+        for arg in args {
+            let matches = if Path::new(&arg).has_wildcards() {
+                Path::new(&arg).glob(&working_dir)
+            } else {
+                Vec::new()
+            };
+            if matches.is_empty() {
+                expanded_args.push(arg);
+            } else {
+                expanded_args.extend(matches.into_iter().map(String::from));
+            }
+        }
+        expanded_args
+    }
+
+    /// Opens the files that the command line redirects to and from in the shell's own file descriptor table.
+    /// Returns the file descriptors of the `stdin` file and the `stdout` file, respectively.
+    fn open_redirections(&self, redirections: &Redirections) -> Result<(Option<FileDescriptor>, Option<FileDescriptor>), AppErr> {
+        let working_dir = Arc::clone(&self.env.lock().working_dir);
+        let stdin_fd = match redirections.stdin {
+            Some(ref file_path) => {
+                let file = OpenOptions::new().read(true).open(Path::new(&file_path), &working_dir)
+                    .map_err(|e| AppErr::RedirectErr(format!("couldn't open {:?}: {}", file_path, e)))?;
+                Some(self.file_descriptors.lock().insert(file))
+            }
+            None => None,
+        };
+        let stdout_fd = match redirections.stdout {
+            Some((ref file_path, append)) => {
+                let options = OpenOptions::new().write(true).create(true).append(append).truncate(!append);
+                match options.open(Path::new(&file_path), &working_dir) {
+                    Ok(file) => Some(self.file_descriptors.lock().insert(file)),
+                    Err(e) => {
+                        if let Some(fd) = stdin_fd {
+                            let _ = self.file_descriptors.lock().close(fd);
+                        }
+                        return Err(AppErr::RedirectErr(format!("couldn't open {:?}: {}", file_path, e)));
+                    }
+                }
+            }
+            None => None,
+        };
+        Ok((stdin_fd, stdout_fd))
+    }
+
+    /// Copies the entire contents of the open file `fd` into the given `stdin` of a job,
+    /// and then closes the file and marks the end of the `stdin` stream.
+    fn redirect_file_to_stdin(&self, fd: FileDescriptor, stdin: &StdioWriter) -> Result<(), &'static str> {
+        let file = self.file_descriptors.lock().get(fd);
+        let result = match file {
+            Some(file) => {
+                let mut file = file.lock();
+                let mut buf = [0u8; 256];
+                loop {
+                    match file.read(&mut buf) {
+                        Ok(0) => break Ok(()),
+                        Ok(cnt) => {
+                            if stdin.lock().write_all(&buf[0..cnt]).is_err() {
+                                break Err("shell failed to write to stdin");
+                            }
+                        }
+                        Err(_) => break Err("shell failed to read the redirected input file"),
+                    }
+                }
+            }
+            None => Err("invalid file descriptor for redirected input"),
+        };
+        let _ = self.file_descriptors.lock().close(fd);
+        stdin.lock().set_eof();
+        result
+    }
+
     /// Start a new job in the shell by the command line.
     fn build_new_job(&mut self) -> Result<isize, &'static str> {
-        match self.eval_cmdline() {
-            Ok(task_refs) => {
+        let job = parse_redirections(&self.cmdline)
+            .and_then(|(cmdline, redirections)| {
+                let (stdin_fd, stdout_fd) = self.open_redirections(&redirections)?;
+                match self.eval_cmdline(&cmdline) {
+                    Ok(task_refs) => Ok((task_refs, stdin_fd, stdout_fd)),
+                    Err(e) => {
+                        for fd in stdin_fd.iter().chain(stdout_fd.iter()) {
+                            let _ = self.file_descriptors.lock().close(*fd);
+                        }
+                        Err(e)
+                    }
+                }
+            });
+        match job {
+            Ok((task_refs, stdin_fd, stdout_fd)) => {
 
                 let mut task_ids = Vec::new();
                 let mut pipe_queues = Vec::new();
@@ -739,6 +858,11 @@ impl Shell {
                 let first_stdio_queue = Stdio::new();
                 let job_stdin_writer = first_stdio_queue.get_writer();
                 let mut previous_queue_reader = first_stdio_queue.get_reader();
+                if let Some(fd) = stdin_fd {
+                    if let Err(e) = self.redirect_file_to_stdin(fd, &job_stdin_writer) {
+                        self.terminal.lock().print_to_terminal(format!("{}\n", e));
+                    }
+                }
                 pipe_queues.push(first_stdio_queue);
                 for task_id in &task_ids {
                     let stdio_queue_for_stdin_and_stdout = Stdio::new();
@@ -773,6 +897,7 @@ impl Shell {
                     stderr_queues,
                     stdin_writer: job_stdin_writer,
                     stdout_reader: job_stdout_reader,
+                    stdout_redirect: stdout_fd,
                     cmd: self.cmdline.clone()
                 };
 
@@ -804,6 +929,7 @@ impl Shell {
                     AppErr::NotFound(command) => format!("{:?} command not found.\n", command),
                     AppErr::NamespaceErr      => format!("Failed to find directory of application executables.\n"),
                     AppErr::SpawnErr(e)       => format!("Failed to spawn new task to run command. Error: {}.\n", e),
+                    AppErr::RedirectErr(e)    => format!("Failed to redirect command: {}.\n", e),
                 };
                 self.terminal.lock().print_to_terminal(err_msg);
                 if let Err(msg) = self.clear_cmdline(false) {
@@ -902,12 +1028,12 @@ impl Shell {
 
         // Walk through nodes existing in the command.
         for node in &nodes {
-            let path = Path::new(node.to_string());
+            let path = PathBuf::from(node.to_string());
             match path.get(&curr_wd) {
                 Some(file_dir_enum) => {
                     match file_dir_enum {
                         FileOrDir::Dir(dir) => { curr_wd = dir; },
-                        FileOrDir::File(_file) => { return Ok(match_list); }
+                        FileOrDir::File(_) | FileOrDir::Symlink(_) => { return Ok(match_list); }
                     }
                 },
                 _ => { return Ok(match_list); }
@@ -1191,6 +1317,15 @@ impl Shell {
         // remove the queues in app_io.
         for finished_job_num in job_to_be_removed {
             if let Some(job) = self.jobs.remove(&finished_job_num) {
+                if let Some(fd) = job.stdout_redirect {
+                    // The job's tasks have all exited, so write whatever is left in its stdout queue
+                    // to the file before closing it, otherwise the last of its output would be lost.
+                    let mut buf: [u8; 256] = [0; 256];
+                    if let Err(e) = self.redirect_stdout_to_file(&job, fd, &mut buf) {
+                        error!("{}", e);
+                    }
+                    let _ = self.file_descriptors.lock().close(fd);
+                }
                 for task_id in job.task_ids {
                     self.task_to_job.remove(&task_id);
                     app_io::remove_child_streams(&task_id);
@@ -1232,21 +1367,27 @@ impl Shell {
         // iterate through all jobs to see if they have something to print
         for (_job_num, job) in self.jobs.iter() {
 
-            // Deal with all stdout output.
-            let mut stdout = job.stdout_reader.lock();
-            match stdout.try_read(&mut buf) {
-                Ok(cnt) => {
-                    mem::drop(stdout);
-                    let s = String::from_utf8_lossy(&buf[0..cnt]);
-                    let mut locked_terminal = self.terminal.lock();
-                    locked_terminal.print_to_terminal(s.to_string());
-                    if cnt != 0 { need_refresh = true; }
-                },
-                Err(_) => {
-                    mem::drop(stdout);
-                    error!("failed to read from stdout");
+            // Deal with all stdout output, which goes to a file if it was redirected.
+            if let Some(fd) = job.stdout_redirect {
+                if let Err(e) = self.redirect_stdout_to_file(job, fd, &mut buf) {
+                    error!("{}", e);
                 }
-            };
+            } else {
+                let mut stdout = job.stdout_reader.lock();
+                match stdout.try_read(&mut buf) {
+                    Ok(cnt) => {
+                        mem::drop(stdout);
+                        let s = String::from_utf8_lossy(&buf[0..cnt]);
+                        let mut locked_terminal = self.terminal.lock();
+                        locked_terminal.print_to_terminal(s.to_string());
+                        if cnt != 0 { need_refresh = true; }
+                    },
+                    Err(_) => {
+                        mem::drop(stdout);
+                        error!("failed to read from stdout");
+                    }
+                };
+            }
 
             // Deal with all stderr output.
             for stderr in &job.stderr_queues {
@@ -1271,6 +1412,19 @@ impl Shell {
         need_refresh
     }
 
+    /// Writes all output that is currently available from the `stdout` of the given `job` to the open file `fd`.
+    fn redirect_stdout_to_file(&self, job: &Job, fd: FileDescriptor, buf: &mut [u8]) -> Result<(), &'static str> {
+        let file = self.file_descriptors.lock().get(fd).ok_or("invalid file descriptor for redirected output")?;
+        let mut stdout = job.stdout_reader.lock();
+        loop {
+            let cnt = stdout.try_read(buf).or(Err("failed to read from stdout"))?;
+            if cnt == 0 {
+                return Ok(());
+            }
+            file.lock().write_all(&buf[0..cnt]).or(Err("failed to write to the redirected output file"))?;
+        }
+    }
+
     /// This main loop is the core component of the shell's event-driven architecture. The shell receives events
     /// from two queues
     /// 
@@ -1502,6 +1656,56 @@ impl Shell {
 }
 
 
+/// Removes the redirections, e.g., `< input`, `> output` or `>> output`, from the given command line.
+/// Returns the remaining command line and the files it is redirected from and to.
+/// 
+/// Only the first command in a pipeline can redirect its input, and only the last command can redirect its output.
+fn parse_redirections(cmdline: &str) -> Result<(String, Redirections), AppErr> {
+    let single_task_cmds: Vec<&str> = cmdline.split("|").collect();
+    let last_index = single_task_cmds.len() - 1;
+    let mut redirections = Redirections::default();
+    let mut remaining_cmds = Vec::new();
+
+    for (index, single_task_cmd) in single_task_cmds.iter().enumerate() {
+        let mut words = Vec::new();
+        let mut tokens = single_task_cmd.split_whitespace();
+        while let Some(token) = tokens.next() {
+            let operator = if token.starts_with(">>") {
+                ">>"
+            } else if token.starts_with(">") {
+                ">"
+            } else if token.starts_with("<") {
+                "<"
+            } else {
+                words.push(token);
+                continue;
+            };
+            // The file name may either be attached to the operator or be the next word.
+            let file_name = match &token[operator.len() ..] {
+                "" => tokens.next().ok_or(AppErr::RedirectErr(format!("missing file name after {:?}", operator)))?,
+                attached => attached,
+            };
+            if operator == "<" {
+                if index != 0 {
+                    return Err(AppErr::RedirectErr("only the first command in a pipeline can redirect its input".to_string()));
+                }
+                redirections.stdin = Some(file_name.to_string());
+            } else {
+                if index != last_index {
+                    return Err(AppErr::RedirectErr("only the last command in a pipeline can redirect its output".to_string()));
+                }
+                redirections.stdout = Some((file_name.to_string(), operator == ">>"));
+            }
+        }
+        if words.is_empty() {
+            return Err(AppErr::RedirectErr("missing command".to_string()));
+        }
+        remaining_cmds.push(words.join(" "));
+    }
+    Ok((remaining_cmds.join("|"), redirections))
+}
+
+
 /// Start a new shell. Shell::start() is an infinite loop, so normally we do not return from this function.
 fn shell_loop(mut _dummy: ()) -> Result<(), &'static str> {
     Shell::new()?.start()?;
@@ -27,7 +27,7 @@ use getopts::{Options, Matches};
 use mod_mgmt::{NamespaceDir, IntoCrateObjectFile};
 use crate_swap::SwapRequest;
 use hpet::get_hpet;
-use path::Path;
+use path::{Path, PathBuf};
 use fs_node::{FileOrDir, DirRef};
 
 
@@ -79,7 +79,7 @@ fn rmain(matches: Matches) -> Result<(), String> {
     };
 
     let override_namespace_crate_dir = if let Some(path) = matches.opt_str("d") {
-        let path = Path::new(path);
+        let path = PathBuf::from(path);
         let dir = match path.get(&curr_dir) {
             Some(FileOrDir::Dir(dir)) => dir,
             _ => return Err(format!("Error: could not find specified namespace crate directory: {}.", path)),
@@ -177,7 +177,7 @@ fn do_swap(
             let (into_new_crate_file, new_namespace) = {
                 if let Some(f) = override_namespace_crate_dir.as_ref().and_then(|ns_dir| ns_dir.get_file_starting_with(new_crate_str)) {
                     (IntoCrateObjectFile::File(f), None)
-                } else if let Some(FileOrDir::File(f)) = Path::new(String::from(new_crate_str)).get(curr_dir) {
+                } else if let Some(FileOrDir::File(f)) = Path::new(new_crate_str).get(curr_dir) {
                     (IntoCrateObjectFile::File(f), None)
                 } else {
                     (IntoCrateObjectFile::Prefix(String::from(new_crate_str)), None)
@@ -0,0 +1,78 @@
+//! This application unmounts the filesystems mounted on the given directories.
+
+#![no_std]
+#[macro_use] extern crate alloc;
+#[macro_use] extern crate terminal_print;
+
+extern crate getopts;
+extern crate task;
+extern crate fs_node;
+extern crate path;
+extern crate mount_table;
+
+use alloc::{
+    string::{String, ToString},
+    sync::Arc,
+    vec::Vec,
+};
+use getopts::{Options, Matches};
+use fs_node::FileOrDir;
+use path::PathBuf;
+
+
+pub fn main(args: Vec<String>) -> isize {
+    let mut opts = Options::new();
+    opts.optflag("h", "help", "print this help menu");
+
+    let matches = match opts.parse(&args) {
+        Ok(m) => m,
+        Err(_f) => {
+            println!("{}", _f);
+            print_usage(opts);
+            return -1;
+        }
+    };
+
+    if matches.opt_present("h") || matches.free.is_empty() {
+        print_usage(opts);
+        return 0;
+    }
+
+    match rmain(matches) {
+        Ok(_) => 0,
+        Err(e) => {
+            println!("Error: {}", e);
+            -1
+        }
+    }
+}
+
+
+fn rmain(matches: Matches) -> Result<(), String> {
+    let curr_wd = {
+        let curr_task = task::get_my_current_task().ok_or_else(|| format!("unable to get current task"))?;
+        let locked_task = curr_task.lock();
+        let curr_env = locked_task.env.lock();
+        Arc::clone(&curr_env.working_dir)
+    };
+
+    for mountpoint in matches.free.iter() {
+        let path = PathBuf::from(mountpoint.to_string());
+        let dir = match path.get(&curr_wd) {
+            Some(FileOrDir::Dir(dir)) => dir,
+            Some(_) => return Err(format!("{:?} is not a directory", mountpoint)),
+            None => return Err(format!("couldn't find {:?}", mountpoint)),
+        };
+        mount_table::unmount(&dir).map_err(|e| format!("couldn't unmount {:?}: {}", mountpoint, e))?;
+    }
+    Ok(())
+}
+
+
+fn print_usage(opts: Options) {
+    println!("{}", opts.usage(USAGE));
+}
+
+
+const USAGE: &'static str = "Usage: umount MOUNTPOINT...
+Unmounts the filesystem mounted on each given MOUNTPOINT directory.";
@@ -23,6 +23,7 @@ extern crate memfs;
 extern crate fs_node;
 extern crate vfs_node;
 extern crate spin;
+extern crate dns_resolver;
 
 
 use core::str::FromStr;
@@ -35,7 +36,7 @@ use alloc::{
 use spin::Once;
 use getopts::{Matches, Options};
 use network_manager::{NetworkInterfaceRef, NETWORK_INTERFACES};
-use smoltcp::wire::IpEndpoint;
+use smoltcp::wire::{IpAddress, IpEndpoint};
 use mod_mgmt::{
     CrateNamespace,
     NamespaceDir,
@@ -46,7 +47,7 @@ use crate_swap::{
     SwapRequestList,
 };
 use memfs::MemFile;
-use path::Path;
+use path::{Path, PathBuf};
 use vfs_node::VFSDirectory;
 use fs_node::{FileOrDir, DirRef};
 use ota_update_client::DIFF_FILE_NAME;
@@ -63,7 +64,7 @@ pub fn main(args: Vec<String>) -> isize {
     let mut opts = Options::new();
     opts.optflag("h", "help", "print this help menu");
     opts.optflag("v", "verbose", "enable verbose logging");
-    opts.optopt ("d", "destination", "specify the IP address (and optionally, the port) of the update server", "IP_ADDR[:PORT]");
+    opts.optopt ("d", "destination", "specify the IP address or host name (and optionally, the port) of the update server", "HOST[:PORT]");
 
     let matches = match opts.parse(&args) {
         Ok(m) => m,
@@ -98,9 +99,8 @@ pub fn main(args: Vec<String>) -> isize {
 
 
 fn rmain(matches: Matches) -> Result<(), String> {
-    let mut remote_endpoint = if let Some(ip_str) = matches.opt_str("d") {
-        IpEndpoint::from_str(&ip_str)
-            .map_err(|_e| format!("couldn't parse destination IP address/port"))?
+    let mut remote_endpoint = if let Some(destination) = matches.opt_str("d") {
+        parse_destination(&destination)?
     } else {
         ota_update_client::default_remote_endpoint()
     };
@@ -124,7 +124,7 @@ fn rmain(matches: Matches) -> Result<(), String> {
         }
         "apply" | "ap" => {
             let base_dir_path = matches.free.get(1).ok_or_else(|| String::from("missing BASE_DIR path argument"))?;
-            apply(&Path::new(base_dir_path.clone()))
+            apply(Path::new(&base_dir_path))
         }
         other => {
             Err(format!("unrecognized command {:?}", other))
@@ -196,7 +196,7 @@ fn download(remote_endpoint: IpEndpoint, update_build: &str, crate_list: Option<
         let size = content.len();
         // The name of the crate file that we downloaded is something like: "/keyboard_log/k#keyboard-36be916209949cef.o".
         // We need to get just the basename of the file, then remove the crate type prefix ("k#").
-        let df_path = Path::new(df.name);
+        let df_path = PathBuf::from(df.name);
         let cfile = new_namespace_dir.write_crate_object_file(df_path.basename(), content)?;
         println!("Downloaded crate: {:?}, size {}", cfile.lock().get_absolute_path(), size);
     }
@@ -254,7 +254,7 @@ fn apply(base_dir_path: &Path) -> Result<(), String> {
             // An empty old_crate_name indicates that there is no old crate or object file to remove, we are just loading a new crate (or inserting its object file)
             None
         } else {
-            let old_crate_name = mod_mgmt::crate_name_from_path(&Path::new(old_crate_module_file_name)).to_string();
+            let old_crate_name = mod_mgmt::crate_name_from_path(Path::new(&old_crate_module_file_name)).to_string();
             if curr_namespace.get_crate(&old_crate_name).is_none() {
                 println!("\t Note: old crate {:?} was not currently loaded into namespace {:?}.", old_crate_name, curr_namespace.name());
             }
@@ -297,6 +297,27 @@ fn get_my_current_namespace() -> Arc<CrateNamespace> {
 }
 
 
+/// Parses the given destination, which is an IP address or a host name optionally followed by a port,
+/// e.g., `10.0.2.2:8090` or `updates.example.com`. If no port is given, the returned port is 0.
+fn parse_destination(destination: &str) -> Result<IpEndpoint, String> {
+    if let Ok(endpoint) = IpEndpoint::from_str(destination) {
+        return Ok(endpoint);
+    }
+    let (host, port) = match destination.rfind(':') {
+        Some(i) if IpAddress::from_str(destination).is_err() => {
+            let port = destination[i + 1 ..].parse::<u16>().map_err(|_e| format!("couldn't parse destination port"))?;
+            (&destination[.. i], port)
+        }
+        _ => (destination, 0),
+    };
+    let iface = get_default_iface()?;
+    let addresses = dns_resolver::resolve(&iface, host)
+        .map_err(|e| format!("couldn't resolve destination {:?}: {}", host, e))?;
+    let address = addresses.first().cloned().ok_or_else(|| format!("destination {:?} has no addresses", host))?;
+    Ok(IpEndpoint::new(address, port))
+}
+
+
 /// Returns the first network interface available in the system.
 fn get_default_iface() -> Result<NetworkInterfaceRef, String> {
     NETWORK_INTERFACES.lock()
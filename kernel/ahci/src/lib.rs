@@ -0,0 +1,670 @@
+//! Support for SATA drives attached to an AHCI (Advanced Host Controller Interface) controller.
+//!
+//! The primary structs of interest are [`AhciController`](struct.AhciController.html),
+//! which implements `StorageController`, and [`AhciDrive`](struct.AhciDrive.html), which implements `StorageDevice`.
+//!
+//! An AHCI controller's host bus adapter (HBA) has up to 32 ports, each of which can have one drive attached.
+//! A command is given to a drive by filling in one of its port's command slots
+//! and then setting that slot's bit in the port's command issue register.
+//! The HBA then transfers the command's data via DMA and raises an interrupt once the command has completed.
+//!
+//! Drives that support native command queuing (NCQ) are given several queued commands at once, one per slot,
+//! which the drive may complete in any order.
+
+#![no_std]
+#![feature(abi_x86_interrupt)]
+
+extern crate alloc;
+#[macro_use] extern crate log;
+#[macro_use] extern crate lazy_static;
+extern crate spin;
+extern crate irq_safety;
+extern crate volatile;
+extern crate owning_ref;
+extern crate x86_64;
+extern crate kernel_config;
+extern crate memory;
+extern crate pci;
+extern crate apic;
+extern crate pic;
+extern crate interrupts;
+extern crate wait_queue;
+extern crate driver_utils;
+extern crate storage_device;
+extern crate ata;
+
+mod regs;
+
+use core::{
+    fmt,
+    sync::atomic::{AtomicU32, Ordering},
+    time::Duration,
+};
+use alloc::{
+    boxed::Box,
+    sync::Arc,
+    vec::Vec,
+};
+use spin::Mutex;
+use irq_safety::MutexIrqSafe;
+use owning_ref::BoxRefMut;
+use x86_64::structures::idt::ExceptionStackFrame;
+use kernel_config::memory::PAGE_SIZE;
+use memory::{MappedPages, PhysicalAddress, create_contiguous_mapping};
+use pci::{PciDevice, PCI_INTERRUPT_LINE};
+use pic::PIC_MASTER_OFFSET;
+use wait_queue::WaitQueue;
+use driver_utils::{DEVICE_MAPPING_FLAGS, map_device_memory, poll, wait_or_poll};
+use storage_device::{StorageDevice, StorageDeviceRef, StorageController};
+use ata::AtaIdentifyData;
+use regs::*;
+
+
+const SECTOR_SIZE_IN_BYTES: usize = 512;
+
+/// The maximum number of queued commands that are given to an NCQ-capable drive at once.
+const MAX_COMMANDS_IN_FLIGHT: usize = 4;
+/// The maximum amount of data that is transferred by a single command.
+const BYTES_PER_COMMAND: usize = 32 * 1024;
+const SECTORS_PER_COMMAND: usize = BYTES_PER_COMMAND / SECTOR_SIZE_IN_BYTES;
+/// The size of the buffer that each port uses for DMA transfers,
+/// which has room for the data of every command that may be in flight at once.
+const DMA_BUFFER_SIZE_IN_BYTES: usize = MAX_COMMANDS_IN_FLIGHT * BYTES_PER_COMMAND;
+
+/// The offset of the received FIS area within a port's command memory,
+/// which starts with the port's command list.
+const RECEIVED_FIS_OFFSET: usize = COMMAND_LIST_SIZE;
+/// The offset of the first command table within a port's command memory,
+/// which directly follows the received FIS area and is thus 128-byte-aligned.
+const COMMAND_TABLES_OFFSET: usize = RECEIVED_FIS_OFFSET + RECEIVED_FIS_SIZE;
+/// The space reserved for each command table within a port's command memory.
+const COMMAND_TABLE_SIZE: usize = 0x100;
+
+/// How long to wait for a command to complete before giving up.
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(5);
+/// How long to wait for a port to start or stop, in milliseconds.
+const PORT_TIMEOUT_MS: u32 = 500;
+
+
+/// The ATA commands that are given to SATA drives.
+#[derive(Copy, Clone, Debug)]
+#[repr(u8)]
+enum AtaCommand {
+    /// Read sectors using DMA (48-bit LBA)
+    ReadDmaExt          = 0x25,
+    /// Write sectors using DMA (48-bit LBA)
+    WriteDmaExt         = 0x35,
+    /// Read sectors using a queued (NCQ) command
+    ReadFpdmaQueued     = 0x60,
+    /// Write sectors using a queued (NCQ) command
+    WriteFpdmaQueued    = 0x61,
+    /// Flush the drive's cache (48-bit LBA).
+    /// This is to be used after each write.
+    CacheFlushExt       = 0xEA,
+    /// Get identifying details of an ATA drive.
+    IdentifyDevice      = 0xEC,
+}
+
+
+/// A shared reference to the registers of an HBA, which are accessed by all of its drives and its interrupt handler.
+type HbaRef = Arc<MutexIrqSafe<BoxRefMut<MappedPages, HbaRegisters>>>;
+
+
+/// The state shared between the interrupt handler of an HBA
+/// and the task that is waiting for commands on one of its ports to complete.
+struct PortInterrupt {
+    /// The error bits of the port's interrupt status, accumulated by the interrupt handler.
+    /// This is cleared right before commands are issued.
+    errors: AtomicU32,
+    /// The task waiting for the current commands to complete,
+    /// which is woken up by the interrupt handler.
+    waiters: WaitQueue,
+}
+
+/// An HBA whose interrupts are handled by `ahci_handler()`.
+struct InterruptSource {
+    hba: HbaRef,
+    /// The interrupt number that the HBA raises.
+    interrupt_num: u8,
+    /// The ports that have drives attached to them.
+    ports: Vec<(usize, Arc<PortInterrupt>)>,
+}
+
+impl InterruptSource {
+    /// Acknowledges the interrupts that the HBA's ports have raised and wakes up the tasks waiting on them.
+    /// Returns `true` if the HBA had raised an interrupt.
+    fn handle(&self) -> bool {
+        let pending = self.hba.lock().is.read();
+        if pending == 0 {
+            return false;
+        }
+        for &(port_num, ref port_interrupt) in self.ports.iter() {
+            if pending & (1 << port_num) == 0 {
+                continue;
+            }
+            // The HBA lock must be released before notifying the waiting task,
+            // because that task checks the HBA's registers while holding the wait queue's lock.
+            let status = {
+                let mut hba = self.hba.lock();
+                let port = &mut hba.ports[port_num];
+                let status = port.is.read();
+                port.is.write(status);
+                status
+            };
+            port_interrupt.errors.fetch_or(status & PORT_INT_ERRORS, Ordering::AcqRel);
+            port_interrupt.waiters.notify_one();
+        }
+        // The HBA's interrupt status can only be cleared after the status of its ports.
+        self.hba.lock().is.write(pending);
+        true
+    }
+}
+
+lazy_static! {
+    /// The AHCI controllers whose interrupts are handled by `ahci_handler()`.
+    static ref INTERRUPT_SOURCES: MutexIrqSafe<Vec<InterruptSource>> = MutexIrqSafe::new(Vec::new());
+}
+
+/// The interrupt handler shared by all AHCI controllers.
+extern "x86-interrupt" fn ahci_handler(_stack_frame: &mut ExceptionStackFrame) {
+    let sources = INTERRUPT_SOURCES.lock();
+    let mut irq = sources.first().map(|source| source.interrupt_num);
+    for source in sources.iter() {
+        if source.handle() {
+            irq = Some(source.interrupt_num);
+        }
+    }
+    interrupts::eoi(irq);
+}
+
+
+/// An AHCI controller, whose HBA has one drive attached to each of its ports that is in use.
+#[derive(Debug)]
+pub struct AhciController {
+    /// The drives attached to this controller, in order of their port number.
+    drives: Vec<AhciDriveRef>,
+}
+
+impl AhciController {
+    /// Creates a new instance of an AHCI controller based on the given PCI device,
+    /// and initializes all of the drives attached to it.
+    pub fn new(pci_device: &PciDevice) -> Result<AhciController, &'static str> {
+        // BAR5 (also known as ABAR) holds the physical address of the HBA's memory-mapped registers.
+        let abar = pci_device.bars[5];
+        if abar & 0x1 != 0 {
+            return Err("AhciController::new(): BAR5 is of I/O type");
+        }
+        let abar = PhysicalAddress::new((abar & !0xF) as usize)?;
+
+        // set the bus mastering bit for this PciDevice, which allows it to use DMA
+        pci_device.pci_set_command_bus_master_bit();
+
+        let hba: HbaRef = Arc::new(MutexIrqSafe::new(map_hba_registers(abar)?));
+        let (capabilities, ports_implemented) = {
+            let mut regs = hba.lock();
+            let ghc = regs.ghc.read();
+            regs.ghc.write(ghc | GHC_AE);
+            (regs.cap.read(), regs.pi.read())
+        };
+
+        let mut drives = Vec::new();
+        for port_num in 0 .. MAX_PORTS {
+            if ports_implemented & (1 << port_num) == 0 {
+                continue;
+            }
+            match AhciDrive::new(Arc::clone(&hba), port_num, capabilities) {
+                Ok(Some(drive)) => drives.push(drive),
+                Ok(None) => { }
+                Err(e) => error!("AhciController::new(): failed to initialize drive on port {}: {}", port_num, e),
+            }
+        }
+
+        // Until now, the drives' commands were completed by polling.
+        if !drives.is_empty() {
+            match register_interrupt_handler(pci_device) {
+                Ok(interrupt_num) => {
+                    INTERRUPT_SOURCES.lock().push(InterruptSource {
+                        hba: Arc::clone(&hba),
+                        interrupt_num,
+                        ports: drives.iter().map(|d| (d.port_num, Arc::clone(&d.interrupt))).collect(),
+                    });
+                    for drive in drives.iter_mut() {
+                        drive.use_interrupts = true;
+                    }
+                    let mut regs = hba.lock();
+                    let ghc = regs.ghc.read();
+                    regs.ghc.write(ghc | GHC_IE);
+                }
+                Err(e) => warn!("AhciController::new(): couldn't register interrupt handler, falling back to polling: {}", e),
+            }
+        }
+
+        info!("AHCI controller at {}: {} drive(s) found", pci_device.location, drives.len());
+        for drive in drives.iter() {
+            info!("--> port {}: {}, size: {} sectors, {}",
+                drive.port_num,
+                { drive.identify_data.model_number },
+                drive.size_in_sectors(),
+                if drive.ncq { "NCQ" } else { "no NCQ" },
+            );
+        }
+
+        Ok(AhciController {
+            drives: drives.into_iter().map(|d| Arc::new(Mutex::new(d))).collect(),
+        })
+    }
+
+    /// Returns an `Iterator` over all of the `AhciDrive`s attached to this controller.
+    pub fn iter(&self) -> impl Iterator<Item = &AhciDriveRef> {
+        self.drives.iter()
+    }
+}
+
+impl StorageController for AhciController {
+    fn devices<'c>(&'c self) -> Box<(dyn Iterator<Item = StorageDeviceRef> + 'c)> {
+        Box::new(
+            self.iter().map(|drive_ref| Arc::clone(drive_ref) as StorageDeviceRef)
+        )
+    }
+}
+
+
+/// A SATA drive attached to one port of an AHCI controller.
+pub struct AhciDrive {
+    /// The registers of the HBA that this drive is attached to.
+    hba: HbaRef,
+    /// The number of the HBA port that this drive is attached to.
+    port_num: usize,
+    /// The state shared with the HBA's interrupt handler.
+    interrupt: Arc<PortInterrupt>,
+    /// Whether to wait for the HBA's interrupts once commands are issued, rather than polling.
+    use_interrupts: bool,
+    /// The memory that holds the port's command list, received FIS area, and command tables.
+    command_memory: MappedPages,
+    /// The physical address of the `command_memory`.
+    command_memory_phys_addr: PhysicalAddress,
+    /// The physically-contiguous buffer that all data transfers of this drive go through.
+    buffer: MappedPages,
+    /// The physical address of the `buffer`.
+    buffer_phys_addr: PhysicalAddress,
+    /// Data that represents the characteristics of the drive.
+    identify_data: AtaIdentifyData,
+    /// Whether this drive is given queued (NCQ) commands.
+    ncq: bool,
+    /// The maximum number of commands given to this drive at once, which is `1` unless it uses NCQ.
+    commands_in_flight: usize,
+}
+
+impl fmt::Debug for AhciDrive {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("AhciDrive")
+            .field("port_num", &self.port_num)
+            .field("use_interrupts", &self.use_interrupts)
+            .field("command_memory_phys_addr", &self.command_memory_phys_addr)
+            .field("buffer_phys_addr", &self.buffer_phys_addr)
+            .field("identify_data", &self.identify_data)
+            .field("ncq", &self.ncq)
+            .field("commands_in_flight", &self.commands_in_flight)
+            .finish()
+    }
+}
+
+impl AhciDrive {
+    /// Looks for a SATA drive on the given port of the given HBA, and if found,
+    /// starts that port and identifies the drive.
+    ///
+    /// Returns `Ok(None)` if no drive is attached to the port, or if the attached device isn't a SATA drive.
+    fn new(hba: HbaRef, port_num: usize, capabilities: u32) -> Result<Option<AhciDrive>, &'static str> {
+        let (status, signature) = {
+            let regs = hba.lock();
+            let port = &regs.ports[port_num];
+            (port.ssts.read(), port.sig.read())
+        };
+        if status & PORT_SSTS_DET_MASK != PORT_SSTS_DET_PRESENT {
+            return Ok(None);
+        }
+        match signature {
+            SATA_SIG_ATA => { }
+            SATA_SIG_ATAPI => {
+                warn!("AhciDrive::new(): ignoring SATAPI device on port {}, which isn't yet supported", port_num);
+                return Ok(None);
+            }
+            SATA_SIG_SEMB | SATA_SIG_PM => {
+                warn!("AhciDrive::new(): ignoring enclosure management bridge or port multiplier on port {}", port_num);
+                return Ok(None);
+            }
+            other => {
+                warn!("AhciDrive::new(): ignoring unknown device on port {}, signature {:#X}", port_num, other);
+                return Ok(None);
+            }
+        }
+
+        let (mut command_memory, command_memory_phys_addr) = create_contiguous_mapping(PAGE_SIZE, DEVICE_MAPPING_FLAGS)?;
+        let (buffer, buffer_phys_addr) = create_contiguous_mapping(DMA_BUFFER_SIZE_IN_BYTES, DEVICE_MAPPING_FLAGS)?;
+        if capabilities & CAP_S64A == 0
+            && (command_memory_phys_addr.value() + PAGE_SIZE > u32::max_value() as usize
+            || buffer_phys_addr.value() + DMA_BUFFER_SIZE_IN_BYTES > u32::max_value() as usize)
+        {
+            return Err("AHCI memory was allocated above 4 GiB, which the HBA cannot access");
+        }
+        for byte in command_memory.as_slice_mut::<u8>(0, PAGE_SIZE)?.iter_mut() {
+            *byte = 0;
+        }
+
+        let mut drive = AhciDrive {
+            hba,
+            port_num,
+            interrupt: Arc::new(PortInterrupt {
+                errors: AtomicU32::new(0),
+                waiters: WaitQueue::new(),
+            }),
+            use_interrupts: false,
+            command_memory,
+            command_memory_phys_addr,
+            buffer,
+            buffer_phys_addr,
+            identify_data: AtaIdentifyData::default(),
+            ncq: false,
+            commands_in_flight: 1,
+        };
+        drive.start()?;
+        drive.identify_data = drive.identify()?;
+
+        // Bit 8 of the SATA capabilities indicates that the drive supports NCQ,
+        // and the queue depth is the number of queued commands it accepts at once, minus one.
+        let command_slots = ((capabilities >> CAP_NCS_SHIFT) & CAP_NCS_MASK) as usize + 1;
+        let queue_depth = (drive.identify_data.queue_depth & 0x1F) as usize + 1;
+        if capabilities & CAP_SNCQ != 0 && drive.identify_data.serial_ata_capabilities & (1 << 8) != 0 {
+            drive.ncq = true;
+            drive.commands_in_flight = core::cmp::min(MAX_COMMANDS_IN_FLIGHT, core::cmp::min(queue_depth, command_slots));
+        }
+        Ok(Some(drive))
+    }
+
+    /// Returns the number of the HBA port that this drive is attached to.
+    pub fn port_num(&self) -> usize {
+        self.port_num
+    }
+
+    /// Returns `true` if this drive is given queued commands using native command queuing (NCQ).
+    pub fn is_ncq_enabled(&self) -> bool {
+        self.ncq
+    }
+
+    /// (Re)starts this drive's port, which sets up the port's command memory and enables its interrupts.
+    ///
+    /// Any commands that were outstanding on the port are discarded.
+    fn start(&mut self) -> Result<(), &'static str> {
+        self.stop()?;
+        let command_list = self.command_memory_phys_addr.value();
+        let received_fis = command_list + RECEIVED_FIS_OFFSET;
+        {
+            let mut hba = self.hba.lock();
+            let port = &mut hba.ports[self.port_num];
+            port.clb.write(command_list as u32);
+            port.clbu.write((command_list >> 32) as u32);
+            port.fb.write(received_fis as u32);
+            port.fbu.write((received_fis >> 32) as u32);
+            // the error and interrupt status bits are cleared by writing `1` to them
+            port.serr.write(!0);
+            port.is.write(!0);
+            let cmd = port.cmd.read();
+            port.cmd.write(cmd | PORT_CMD_FRE | PORT_CMD_SUD | PORT_CMD_POD);
+        }
+
+        // The port may only start processing commands once the drive isn't busy.
+        let port_num = self.port_num;
+        let hba = &self.hba;
+        poll(PORT_TIMEOUT_MS, || {
+            let tfd = hba.lock().ports[port_num].tfd.read();
+            if tfd & (PORT_TFD_BSY | PORT_TFD_DRQ) == 0 { Some(()) } else { None }
+        }).ok_or("AHCI drive was still busy after starting its port")?;
+
+        let mut hba = self.hba.lock();
+        let port = &mut hba.ports[self.port_num];
+        port.ie.write(PORT_INT_DHRS | PORT_INT_SDBS | PORT_INT_ERRORS);
+        let cmd = port.cmd.read();
+        port.cmd.write(cmd | PORT_CMD_ST);
+        Ok(())
+    }
+
+    /// Stops this drive's port from processing commands and receiving FISes.
+    fn stop(&mut self) -> Result<(), &'static str> {
+        let port_num = self.port_num;
+        let hba = &self.hba;
+        {
+            let mut hba = hba.lock();
+            let cmd = hba.ports[port_num].cmd.read();
+            hba.ports[port_num].cmd.write(cmd & !PORT_CMD_ST);
+        }
+        poll(PORT_TIMEOUT_MS, || {
+            if hba.lock().ports[port_num].cmd.read() & PORT_CMD_CR == 0 { Some(()) } else { None }
+        }).ok_or("AHCI port's command list didn't stop running")?;
+        {
+            let mut hba = hba.lock();
+            let cmd = hba.ports[port_num].cmd.read();
+            hba.ports[port_num].cmd.write(cmd & !PORT_CMD_FRE);
+        }
+        poll(PORT_TIMEOUT_MS, || {
+            if hba.lock().ports[port_num].cmd.read() & PORT_CMD_FR == 0 { Some(()) } else { None }
+        }).ok_or("AHCI port's FIS receive didn't stop running")
+    }
+
+    /// Issues the identify command and returns the drive's response.
+    fn identify(&mut self) -> Result<AtaIdentifyData, &'static str> {
+        let mut fis = FisRegH2D::new(AtaCommand::IdentifyDevice as u8, 0);
+        fis.device = 0;
+        self.prepare_command(0, fis, false, 0, SECTOR_SIZE_IN_BYTES)?;
+        self.issue_commands(0x1, false)?;
+        let mut arr = [0u8; SECTOR_SIZE_IN_BYTES];
+        arr.copy_from_slice(self.buffer.as_slice(0, SECTOR_SIZE_IN_BYTES)?);
+        Ok(AtaIdentifyData::new(arr))
+    }
+
+    /// Transfers `sector_count` sectors starting at `lba_start` between the drive and this drive's DMA buffer,
+    /// reading from the drive unless `write` is true.
+    ///
+    /// The transfer is split into one command per `BYTES_PER_COMMAND`,
+    /// so the `sector_count` must not exceed what `commands_in_flight` commands can transfer.
+    fn transfer(&mut self, write: bool, lba_start: usize, sector_count: usize) -> Result<(), &'static str> {
+        let mut slots = 0;
+        let command_count = (sector_count + SECTORS_PER_COMMAND - 1) / SECTORS_PER_COMMAND;
+        for slot in 0 .. command_count {
+            let lba = lba_start + slot * SECTORS_PER_COMMAND;
+            let count = core::cmp::min(SECTORS_PER_COMMAND, sector_count - slot * SECTORS_PER_COMMAND);
+            let fis = if self.ncq {
+                let command = if write { AtaCommand::WriteFpdmaQueued } else { AtaCommand::ReadFpdmaQueued };
+                let mut fis = FisRegH2D::new(command as u8, lba);
+                // Queued commands hold the sector count in the features fields, and their tag (the slot) in the count field.
+                fis.feature_low = count as u8;
+                fis.feature_high = (count >> 8) as u8;
+                fis.count_low = (slot << 3) as u8;
+                fis
+            } else {
+                let command = if write { AtaCommand::WriteDmaExt } else { AtaCommand::ReadDmaExt };
+                let mut fis = FisRegH2D::new(command as u8, lba);
+                fis.count_low = count as u8;
+                fis.count_high = (count >> 8) as u8;
+                fis
+            };
+            self.prepare_command(slot, fis, write, slot * BYTES_PER_COMMAND, count * SECTOR_SIZE_IN_BYTES)?;
+            slots |= 1 << slot;
+        }
+        let ncq = self.ncq;
+        self.issue_commands(slots, ncq)
+    }
+
+    /// Issues the flush cache command, which ensures that all written data has actually been stored on the drive.
+    fn flush(&mut self) -> Result<(), &'static str> {
+        self.prepare_command(0, FisRegH2D::new(AtaCommand::CacheFlushExt as u8, 0), false, 0, 0)?;
+        self.issue_commands(0x1, false)
+    }
+
+    /// Fills in the command header and command table of the given command `slot`,
+    /// such that it issues the given `fis` and transfers `length` bytes starting at `buffer_offset` in the DMA buffer.
+    fn prepare_command(&mut self,
+        slot: usize,
+        fis: FisRegH2D,
+        write: bool,
+        buffer_offset: usize,
+        length: usize
+    ) -> Result<(), &'static str> {
+        let table_offset = COMMAND_TABLES_OFFSET + slot * COMMAND_TABLE_SIZE;
+        let table_phys_addr = self.command_memory_phys_addr.value() + table_offset;
+        {
+            let table: &mut CommandTable = self.command_memory.as_type_mut(table_offset)?;
+            table.command_fis.write(fis);
+            table.prdt[0].data_base_address.write((self.buffer_phys_addr.value() + buffer_offset) as u64);
+            // the byte count of a PRDT entry is stored minus one
+            table.prdt[0].byte_count.write(length.saturating_sub(1) as u32);
+        }
+        let headers: &mut [CommandHeader] = self.command_memory.as_slice_mut(0, MAX_COMMAND_SLOTS)?;
+        let header = &mut headers[slot];
+        let fis_length_in_dwords = (core::mem::size_of::<FisRegH2D>() / 4) as u16;
+        header.flags.write(fis_length_in_dwords | if write { COMMAND_HEADER_WRITE } else { 0 });
+        header.prdt_length.write(if length > 0 { 1 } else { 0 });
+        header.prd_byte_count.write(0);
+        header.command_table_base.write(table_phys_addr as u64);
+        Ok(())
+    }
+
+    /// Issues the prepared commands in the given bitmask of `slots`, and waits for all of them to complete.
+    /// If `queued` is true, the commands are queued (NCQ) commands.
+    ///
+    /// If a command fails, the port is restarted, which discards the other commands.
+    fn issue_commands(&mut self, slots: u32, queued: bool) -> Result<(), &'static str> {
+        self.interrupt.errors.store(0, Ordering::Release);
+        {
+            let mut hba = self.hba.lock();
+            let port = &mut hba.ports[self.port_num];
+            // clear the interrupt status left over from previous commands, which isn't cleared when polling
+            let status = port.is.read();
+            port.is.write(status);
+            if queued {
+                port.sact.write(slots);
+            }
+            port.ci.write(slots);
+        }
+
+        let result = self.wait_for_completion(slots);
+        if result.is_err() {
+            {
+                let hba = self.hba.lock();
+                let port = &hba.ports[self.port_num];
+                error!("AhciDrive: commands {:#X} failed on port {}, task file: {:#X}, SATA error: {:#X}",
+                    slots, self.port_num, port.tfd.read(), port.serr.read()
+                );
+            }
+            if let Err(e) = self.start() {
+                error!("AhciDrive: failed to restart port {} after error: {}", self.port_num, e);
+            }
+        }
+        result
+    }
+
+    /// Waits until all of the commands in the given bitmask of `slots` have completed.
+    ///
+    /// This blocks on the port's interrupts if they are handled, and otherwise polls the port's registers.
+    fn wait_for_completion(&self, slots: u32) -> Result<(), &'static str> {
+        let waiters = if self.use_interrupts { Some(&self.interrupt.waiters) } else { None };
+        wait_or_poll(waiters, COMMAND_TIMEOUT, &|| self.completion_status(slots))
+            .unwrap_or(Err("timed out waiting for an AHCI command to complete"))
+    }
+
+    /// Returns `Some(Ok(()))` if all of the commands in the given bitmask of `slots` have completed,
+    /// `Some(Err(..))` if any command has failed, or `None` if they are still in progress.
+    fn completion_status(&self, slots: u32) -> Option<Result<(), &'static str>> {
+        let hba = self.hba.lock();
+        let port = &hba.ports[self.port_num];
+        let errors = self.interrupt.errors.load(Ordering::Acquire) | (port.is.read() & PORT_INT_ERRORS);
+        if errors != 0 {
+            return Some(Err("AHCI command failed"));
+        }
+        // A slot's bits are cleared by the HBA once its command has completed.
+        if (port.ci.read() | port.sact.read()) & slots == 0 {
+            Some(Ok(()))
+        } else {
+            None
+        }
+    }
+
+    /// Checks that a transfer of the given `length_in_bytes` starting at `offset_in_sectors` fits within this drive.
+    fn check_bounds(&self, length_in_bytes: usize, offset_in_sectors: usize) -> Result<(), &'static str> {
+        if length_in_bytes % SECTOR_SIZE_IN_BYTES != 0 {
+            return Err("The buffer length must be a multiple of sector size (512) bytes. SATA drives can only transfer at sector granularity.");
+        }
+        if offset_in_sectors + (length_in_bytes / SECTOR_SIZE_IN_BYTES) > self.size_in_sectors() {
+            return Err("offset_in_sectors was out of bounds");
+        }
+        Ok(())
+    }
+}
+
+impl StorageDevice for AhciDrive {
+    fn read_sectors(&mut self, buffer: &mut [u8], offset_in_sectors: usize) -> Result<usize, &'static str> {
+        self.check_bounds(buffer.len(), offset_in_sectors)?;
+        let mut lba = offset_in_sectors;
+        for chunk in buffer.chunks_mut(self.commands_in_flight * BYTES_PER_COMMAND) {
+            let sector_count = chunk.len() / SECTOR_SIZE_IN_BYTES;
+            self.transfer(false, lba, sector_count)?;
+            chunk.copy_from_slice(self.buffer.as_slice(0, chunk.len())?);
+            lba += sector_count;
+        }
+        Ok(lba - offset_in_sectors)
+    }
+
+    fn write_sectors(&mut self, buffer: &[u8], offset_in_sectors: usize) -> Result<usize, &'static str> {
+        self.check_bounds(buffer.len(), offset_in_sectors)?;
+        let mut lba = offset_in_sectors;
+        for chunk in buffer.chunks(self.commands_in_flight * BYTES_PER_COMMAND) {
+            let sector_count = chunk.len() / SECTOR_SIZE_IN_BYTES;
+            self.buffer.as_slice_mut(0, chunk.len())?.copy_from_slice(chunk);
+            self.transfer(true, lba, sector_count)?;
+            lba += sector_count;
+        }
+        // Flush the drive's cache after each write
+        self.flush()?;
+        Ok(lba - offset_in_sectors)
+    }
+
+    /// Returns the number of sectors in this drive.
+    fn size_in_sectors(&self) -> usize {
+        // Bit 10 of word 83 of the identify data indicates that the drive supports 48-bit LBAs.
+        let command_set_support = self.identify_data.command_set_support;
+        if command_set_support[1] & (1 << 10) != 0 {
+            self.identify_data.max_48_bit_lba as usize
+        } else {
+            self.identify_data.user_addressable_sectors as usize
+        }
+    }
+
+    fn sector_size_in_bytes(&self) -> usize {
+        SECTOR_SIZE_IN_BYTES
+    }
+}
+
+pub type AhciDriveRef = Arc<Mutex<AhciDrive>>;
+
+
+/// Maps the HBA's registers, which start at the given physical address.
+fn map_hba_registers(abar: PhysicalAddress) -> Result<BoxRefMut<MappedPages, HbaRegisters>, &'static str> {
+    let hba_mapped_pages = map_device_memory(abar, core::mem::size_of::<HbaRegisters>())?;
+    BoxRefMut::new(Box::new(hba_mapped_pages)).try_map_mut(|mp| mp.as_type_mut::<HbaRegisters>(abar.value() % PAGE_SIZE))
+}
+
+/// Registers the interrupt handler for the AHCI controller of the given PCI device,
+/// and returns the interrupt number it was registered for.
+///
+/// An MSI interrupt is used if the controller supports it, otherwise its legacy interrupt line is used.
+fn register_interrupt_handler(pci_device: &PciDevice) -> Result<u8, &'static str> {
+    if let Ok(interrupt_num) = interrupts::register_msi_interrupt(ahci_handler) {
+        if pci_device.pci_enable_msi(apic::get_my_apic_id(), interrupt_num).is_ok() {
+            pci_device.pci_set_interrupt_disable_bit();
+            return Ok(interrupt_num);
+        }
+        interrupts::deregister_interrupt(interrupt_num, ahci_handler)?;
+    }
+    let interrupt_num = pci_device.pci_read_8(PCI_INTERRUPT_LINE) + PIC_MASTER_OFFSET;
+    interrupts::register_interrupt(interrupt_num, ahci_handler)?;
+    Ok(interrupt_num)
+}
@@ -0,0 +1,233 @@
+//! The memory-mapped registers of an AHCI host bus adapter (HBA),
+//! and the in-memory structures through which commands are given to it.
+//!
+//! See the Serial ATA AHCI specification (revision 1.3.1) for more details.
+
+use volatile::{Volatile, ReadOnly};
+
+
+/// The maximum number of ports that a single HBA can have.
+pub const MAX_PORTS: usize = 32;
+
+/// The maximum number of command slots that a single port can have.
+pub const MAX_COMMAND_SLOTS: usize = 32;
+
+
+/// CAP: the HBA supports 64-bit addressing
+pub const CAP_S64A:                 u32 = 1 << 31;
+/// CAP: the HBA supports native command queuing (NCQ)
+pub const CAP_SNCQ:                 u32 = 1 << 30;
+/// CAP: the bit offset of the number of command slots per port, minus one
+pub const CAP_NCS_SHIFT:            u32 = 8;
+pub const CAP_NCS_MASK:             u32 = 0x1F;
+
+/// GHC: interrupt enable
+pub const GHC_IE:                   u32 = 1 << 1;
+/// GHC: AHCI enable, as opposed to legacy IDE emulation
+pub const GHC_AE:                   u32 = 1 << 31;
+
+/// PxCMD: start processing the command list
+pub const PORT_CMD_ST:              u32 = 1 << 0;
+/// PxCMD: spin-up device
+pub const PORT_CMD_SUD:             u32 = 1 << 1;
+/// PxCMD: power on device
+pub const PORT_CMD_POD:             u32 = 1 << 2;
+/// PxCMD: FIS receive enable
+pub const PORT_CMD_FRE:             u32 = 1 << 4;
+/// PxCMD: FIS receive running
+pub const PORT_CMD_FR:              u32 = 1 << 14;
+/// PxCMD: command list running
+pub const PORT_CMD_CR:              u32 = 1 << 15;
+
+/// PxIS/PxIE: a device-to-host register FIS was received, i.e., a non-queued command completed
+pub const PORT_INT_DHRS:            u32 = 1 << 0;
+/// PxIS/PxIE: a set device bits FIS was received, i.e., a queued (NCQ) command completed
+pub const PORT_INT_SDBS:            u32 = 1 << 3;
+/// PxIS/PxIE: an interface (link) error that the HBA could not recover from
+pub const PORT_INT_IFS:             u32 = 1 << 27;
+/// PxIS/PxIE: a data error on the host bus
+pub const PORT_INT_HBDS:            u32 = 1 << 28;
+/// PxIS/PxIE: a fatal error on the host bus
+pub const PORT_INT_HBFS:            u32 = 1 << 29;
+/// PxIS/PxIE: the device reported an error in its task file
+pub const PORT_INT_TFES:            u32 = 1 << 30;
+/// The PxIS bits that indicate that a command failed.
+pub const PORT_INT_ERRORS:          u32 = PORT_INT_IFS | PORT_INT_HBDS | PORT_INT_HBFS | PORT_INT_TFES;
+
+/// PxTFD: the data request bit of the device's status
+pub const PORT_TFD_DRQ:             u32 = 1 << 3;
+/// PxTFD: the busy bit of the device's status
+pub const PORT_TFD_BSY:             u32 = 1 << 7;
+
+/// PxSSTS: the mask of the device detection field
+pub const PORT_SSTS_DET_MASK:       u32 = 0xF;
+/// PxSSTS: a device is present and communication with it is established
+pub const PORT_SSTS_DET_PRESENT:    u32 = 0x3;
+
+/// PxSIG: the signature of a SATA drive
+pub const SATA_SIG_ATA:             u32 = 0x0000_0101;
+/// PxSIG: the signature of a SATAPI drive, e.g., an optical drive
+pub const SATA_SIG_ATAPI:           u32 = 0xEB14_0101;
+/// PxSIG: the signature of an enclosure management bridge
+pub const SATA_SIG_SEMB:            u32 = 0xC33C_0101;
+/// PxSIG: the signature of a port multiplier
+pub const SATA_SIG_PM:              u32 = 0x9669_0101;
+
+
+/// The generic host control registers of an HBA, followed by the registers of each of its ports.
+/// These are located at the physical address given by the AHCI controller's BAR5 (ABAR).
+#[repr(C)]
+pub struct HbaRegisters {
+    /// Host capabilities
+    pub cap:                        ReadOnly<u32>,          // 0x00
+    /// Global host control
+    pub ghc:                        Volatile<u32>,          // 0x04
+    /// Interrupt status, one bit per port
+    pub is:                         Volatile<u32>,          // 0x08
+    /// Ports implemented, one bit per port
+    pub pi:                         ReadOnly<u32>,          // 0x0C
+    /// Version
+    pub vs:                         ReadOnly<u32>,          // 0x10
+    _padding0:                      [u8; 236],              // 0x14 - 0xFF
+    /// The registers of each port
+    pub ports:                      [PortRegisters; MAX_PORTS], // 0x100 - 0x10FF
+}
+
+/// The registers of a single port on an HBA, to which one SATA device can be attached.
+#[repr(C)]
+pub struct PortRegisters {
+    /// Command list base address, which must be 1 KiB-aligned
+    pub clb:                        Volatile<u32>,          // 0x00
+    /// Upper 32 bits of the command list base address
+    pub clbu:                       Volatile<u32>,          // 0x04
+    /// FIS receive base address, which must be 256-byte-aligned
+    pub fb:                         Volatile<u32>,          // 0x08
+    /// Upper 32 bits of the FIS receive base address
+    pub fbu:                        Volatile<u32>,          // 0x0C
+    /// Interrupt status
+    pub is:                         Volatile<u32>,          // 0x10
+    /// Interrupt enable
+    pub ie:                         Volatile<u32>,          // 0x14
+    /// Command and status
+    pub cmd:                        Volatile<u32>,          // 0x18
+    _padding0:                      u32,                    // 0x1C
+    /// Task file data, i.e., the status and error registers of the device
+    pub tfd:                        ReadOnly<u32>,          // 0x20
+    /// Signature of the attached device
+    pub sig:                        ReadOnly<u32>,          // 0x24
+    /// SATA status
+    pub ssts:                       ReadOnly<u32>,          // 0x28
+    /// SATA control
+    pub sctl:                       Volatile<u32>,          // 0x2C
+    /// SATA error
+    pub serr:                       Volatile<u32>,          // 0x30
+    /// SATA active, one bit per command slot that holds a queued (NCQ) command
+    pub sact:                       Volatile<u32>,          // 0x34
+    /// Command issue, one bit per command slot that holds a command
+    pub ci:                         Volatile<u32>,          // 0x38
+    /// SATA notification
+    pub sntf:                       Volatile<u32>,          // 0x3C
+    /// FIS-based switching control
+    pub fbs:                        Volatile<u32>,          // 0x40
+    _padding1:                      [u8; 60],               // 0x44 - 0x7F
+}
+
+
+/// One entry in a port's command list, which describes the command in one command slot.
+#[repr(C)]
+pub struct CommandHeader {
+    /// Bits `[0:5)` are the length of the command FIS in dwords, and bit 6 is set if data is written to the device.
+    pub flags:                      Volatile<u16>,
+    /// The number of entries in the PRDT of the command table.
+    pub prdt_length:                Volatile<u16>,
+    /// The number of bytes transferred so far, which is updated by the HBA.
+    pub prd_byte_count:             Volatile<u32>,
+    /// The physical address of the command table, which must be 128-byte-aligned.
+    pub command_table_base:         Volatile<u64>,
+    _reserved:                      [u32; 4],
+}
+
+/// `CommandHeader::flags`: the data of this command is written to the device
+pub const COMMAND_HEADER_WRITE:     u16 = 1 << 6;
+
+/// The size of a command list, which holds one `CommandHeader` per command slot.
+pub const COMMAND_LIST_SIZE: usize = MAX_COMMAND_SLOTS * 32;
+/// The size of the area that the HBA copies the FISes that it receives from the device into.
+pub const RECEIVED_FIS_SIZE: usize = 256;
+
+
+/// The command table of a command slot, which holds the command FIS
+/// and the physical region descriptor table (PRDT) that describes the command's data buffer.
+#[repr(C)]
+pub struct CommandTable {
+    /// The command FIS.
+    pub command_fis:                Volatile<FisRegH2D>,
+    _padding0:                      [u8; 44],
+    /// The ATAPI command, unused for SATA drives.
+    _atapi_command:                 [u8; 16],
+    _reserved:                      [u8; 48],
+    /// The PRDT, which only needs one entry because each port's DMA buffer is physically contiguous.
+    pub prdt:                       [PrdtEntry; 1],
+}
+
+/// An entry in a command table's PRDT, which describes one physically-contiguous region of a data buffer.
+#[repr(C)]
+pub struct PrdtEntry {
+    /// The physical address of the region, which must be 2-byte-aligned.
+    pub data_base_address:          Volatile<u64>,
+    _reserved:                      u32,
+    /// Bits `[0:22)` are the size of the region in bytes minus one,
+    /// and bit 31 requests an interrupt once the region has been transferred.
+    pub byte_count:                 Volatile<u32>,
+}
+
+
+/// The type of a register FIS sent from the host to the device.
+pub const FIS_TYPE_REG_H2D:         u8 = 0x27;
+/// `FisRegH2D::flags`: this FIS holds a command, rather than an update of the device's control register
+pub const FIS_REG_H2D_COMMAND:      u8 = 1 << 7;
+/// `FisRegH2D::device`: the LBA fields hold a logical block address
+pub const FIS_DEVICE_LBA:           u8 = 1 << 6;
+
+/// A register FIS sent from the host to the device, which is used to issue ATA commands.
+/// Its fields mirror the registers of a legacy ATA bus.
+#[derive(Copy, Clone, Debug, Default)]
+#[repr(C)]
+pub struct FisRegH2D {
+    pub fis_type:                   u8,
+    pub flags:                      u8,
+    pub command:                    u8,
+    pub feature_low:                u8,
+    pub lba0:                       u8,
+    pub lba1:                       u8,
+    pub lba2:                       u8,
+    pub device:                     u8,
+    pub lba3:                       u8,
+    pub lba4:                       u8,
+    pub lba5:                       u8,
+    pub feature_high:               u8,
+    pub count_low:                  u8,
+    pub count_high:                 u8,
+    pub icc:                        u8,
+    pub control:                    u8,
+    _reserved:                      [u8; 4],
+}
+
+impl FisRegH2D {
+    /// Creates a new FIS that issues the given ATA `command` with the given 48-bit `lba`.
+    pub fn new(command: u8, lba: usize) -> FisRegH2D {
+        FisRegH2D {
+            fis_type: FIS_TYPE_REG_H2D,
+            flags:    FIS_REG_H2D_COMMAND,
+            command,
+            lba0:     (lba >>  0) as u8,
+            lba1:     (lba >>  8) as u8,
+            lba2:     (lba >> 16) as u8,
+            lba3:     (lba >> 24) as u8,
+            lba4:     (lba >> 32) as u8,
+            lba5:     (lba >> 40) as u8,
+            device:   FIS_DEVICE_LBA,
+            .. FisRegH2D::default()
+        }
+    }
+}
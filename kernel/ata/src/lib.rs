@@ -1,18 +1,37 @@
 //! Support for accessing ATA drives (IDE).
 //! 
 //! The primary struct of interest is [`AtaDrive`](struct.AtaDrive.html).
+//! 
+//! Drives are accessed using bus master DMA if the IDE controller supports it,
+//! in which the controller transfers data directly to and from memory and raises an interrupt once it's done.
+//! Otherwise, drives are accessed using slow PIO (port I/O), in which the CPU polls the drive 
+//! and transfers every word of data itself.
 
 #![no_std]
+#![feature(abi_x86_interrupt)]
 
 #[macro_use] extern crate alloc;
 #[macro_use] extern crate log;
+#[macro_use] extern crate lazy_static;
 extern crate spin;
+extern crate x86_64;
 extern crate port_io;
 extern crate pci;
 #[macro_use] extern crate bitflags;
 extern crate storage_device;
-
-use core::fmt;
+extern crate kernel_config;
+extern crate memory;
+extern crate pic;
+extern crate interrupts;
+extern crate wait_queue;
+extern crate mutex_sleep;
+extern crate driver_utils;
+
+use core::{
+	fmt,
+	sync::atomic::{AtomicBool, Ordering},
+	time::Duration,
+};
 use spin::Mutex;
 use alloc::{
 	string::String,
@@ -22,6 +41,13 @@ use alloc::{
 use port_io::{Port, PortReadOnly, PortWriteOnly};
 use pci::PciDevice;
 use storage_device::{StorageDevice, StorageDeviceRef, StorageController};
+use kernel_config::memory::PAGE_SIZE;
+use memory::{MappedPages, PhysicalAddress, create_contiguous_mapping};
+use pic::PIC_MASTER_OFFSET;
+use x86_64::structures::idt::ExceptionStackFrame;
+use wait_queue::WaitQueue;
+use driver_utils::{DEVICE_MAPPING_FLAGS, wait_or_poll};
+use mutex_sleep::MutexSleep;
 
 
 const SECTOR_SIZE_IN_BYTES: usize = 512;
@@ -36,6 +62,21 @@ const MAX_LBA_28_VALUE: usize = (1 << 28) - 1;
 /// To use a BAR as a Port address, you must mask out the lowest 2 bits.
 const PCI_BAR_PORT_MASK: u16 = 0xFFFC;
 
+/// The legacy IRQ used by the primary channel (bus) of an IDE controller in compatibility mode.
+const PRIMARY_CHANNEL_IRQ: u8 = 14;
+/// The legacy IRQ used by the secondary channel (bus) of an IDE controller in compatibility mode.
+const SECONDARY_CHANNEL_IRQ: u8 = 15;
+
+/// The offset of the secondary channel's bus master registers from the start of the bus master I/O port block (BAR4).
+const SECONDARY_CHANNEL_BUS_MASTER_OFFSET: u16 = 8;
+
+/// The size of the buffer that each bus uses for DMA transfers, 
+/// which is the largest amount of data that can be transferred by a single DMA command.
+const DMA_BUFFER_SIZE_IN_BYTES: usize = 64 * 1024;
+
+/// How long to wait for the interrupt that signals the completion of a DMA transfer before giving up.
+const DMA_TIMEOUT: Duration = Duration::from_secs(5);
+
 
 bitflags! {
 	/// The possible error values found in an ATA drive's error port.
@@ -82,6 +123,35 @@ bitflags! {
     }
 }
 
+bitflags! {
+	/// The possible values written to a bus master IDE command port.
+	struct BusMasterCommand: u8 {
+		/// Starts the DMA transfer when set. Clearing it stops (or aborts) the transfer.
+		const START = 0x01;
+		/// The direction of the DMA transfer: set when reading from the drive into memory,
+		/// cleared when writing from memory to the drive.
+		const READ  = 0x08;
+	}
+}
+
+bitflags! {
+	/// The possible values found in a bus master IDE status port.
+	struct BusMasterStatus: u8 {
+		/// Whether the controller can only perform DMA transfers on one bus at a time.
+		const SIMPLEX             = 0x80;
+		/// Set by the firmware if the slave drive on this bus is capable of DMA.
+		const DRIVE_1_DMA_CAPABLE = 0x40;
+		/// Set by the firmware if the master drive on this bus is capable of DMA.
+		const DRIVE_0_DMA_CAPABLE = 0x20;
+		/// Set when the drive has raised an interrupt. Cleared by writing `1` to it.
+		const INTERRUPT           = 0x04;
+		/// Set when the DMA transfer failed. Cleared by writing `1` to it.
+		const ERROR               = 0x02;
+		/// Set while the DMA transfer is in progress.
+		const ACTIVE              = 0x01;
+	}
+}
+
 #[allow(dead_code)]
 /// The possible commands that can be issued to an ATA drive's command port. 
 /// More esoteric commands (nearly a full list) are here: <https://wiki.osdev.org/ATA_Command_Matrix>.
@@ -159,6 +229,181 @@ enum BusDriveSelect {
 }
 
 
+/// An entry in a physical region descriptor table (PRDT), 
+/// which describes one physically-contiguous region of memory that a DMA transfer reads from or writes to. 
+/// 
+/// A region must not cross a 64 KiB boundary, and its byte count of `0` means 64 KiB.
+#[derive(Copy, Clone, Debug)]
+#[repr(C)]
+struct PhysicalRegionDescriptor {
+	/// The physical address of the region, which must be below 4 GiB.
+	address: u32,
+	/// The size of the region in bytes.
+	byte_count: u16,
+	/// Only the highest bit is used, which marks the last entry in the table.
+	flags: u16,
+}
+
+/// The value of `PhysicalRegionDescriptor::flags` that marks the last entry in a PRDT.
+const PRD_END_OF_TABLE: u16 = 0x8000;
+
+
+/// The state shared between the interrupt handler of an IDE channel (bus)
+/// and the task that is waiting for a DMA transfer on that channel to complete.
+struct ChannelInterrupt {
+	/// Set by the interrupt handler whenever the channel raises an interrupt.
+	/// This is cleared right before a DMA command is issued.
+	occurred: AtomicBool,
+	/// The task waiting for the current DMA transfer to complete, 
+	/// which is woken up by the interrupt handler.
+	waiters: WaitQueue,
+}
+
+impl ChannelInterrupt {
+	fn new() -> ChannelInterrupt {
+		ChannelInterrupt {
+			occurred: AtomicBool::new(false),
+			waiters: WaitQueue::new(),
+		}
+	}
+
+	/// Records that an interrupt occurred and wakes up the waiting task.
+	/// This is invoked from the interrupt handler, so it doesn't touch the bus itself;
+	/// the waiting task acknowledges the interrupt by reading the bus's status ports.
+	fn handle(&self) {
+		self.occurred.store(true, Ordering::Release);
+		self.waiters.notify_one();
+	}
+}
+
+lazy_static! {
+	static ref PRIMARY_CHANNEL_INTERRUPT: ChannelInterrupt = ChannelInterrupt::new();
+	static ref SECONDARY_CHANNEL_INTERRUPT: ChannelInterrupt = ChannelInterrupt::new();
+}
+
+/// The interrupt handler for the primary channel of an IDE controller (IRQ 14).
+extern "x86-interrupt" fn primary_channel_handler(_stack_frame: &mut ExceptionStackFrame) {
+	PRIMARY_CHANNEL_INTERRUPT.handle();
+	interrupts::eoi(Some(PIC_MASTER_OFFSET + PRIMARY_CHANNEL_IRQ));
+}
+
+/// The interrupt handler for the secondary channel of an IDE controller (IRQ 15).
+extern "x86-interrupt" fn secondary_channel_handler(_stack_frame: &mut ExceptionStackFrame) {
+	SECONDARY_CHANNEL_INTERRUPT.handle();
+	interrupts::eoi(Some(PIC_MASTER_OFFSET + SECONDARY_CHANNEL_IRQ));
+}
+
+
+/// The bus master IDE registers of one ATA bus, along with the memory used for its DMA transfers.
+/// 
+/// The registers are located in the I/O port block given by the IDE controller's BAR4,
+/// at offset `0` for the primary bus and offset `8` for the secondary bus.
+struct BusMasterDma {
+	/// The bus master command port, located at `BAR4 + 0`.
+	command: Port<u8>,
+	/// The bus master status port, located at `BAR4 + 2`.
+	status: Port<u8>,
+	/// The port that holds the physical address of the PRDT, located at `BAR4 + 4`.
+	prdt_address: Port<u32>,
+	/// The physical region descriptor table that describes the `buffer` to the controller.
+	prdt: MappedPages,
+	/// The physical address of the `prdt`.
+	prdt_phys_addr: PhysicalAddress,
+	/// The physically-contiguous buffer that all DMA transfers on this bus go through.
+	buffer: MappedPages,
+	/// The physical address of the `buffer`.
+	buffer_phys_addr: PhysicalAddress,
+	/// The interrupt state of this bus's channel.
+	interrupt: &'static ChannelInterrupt,
+}
+
+impl fmt::Debug for BusMasterDma {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.debug_struct("BusMasterDma")
+			.field("command", &self.command)
+			.field("status", &self.status)
+			.field("prdt_address", &self.prdt_address)
+			.field("prdt_phys_addr", &self.prdt_phys_addr)
+			.field("buffer_phys_addr", &self.buffer_phys_addr)
+			.finish()
+	}
+}
+
+impl BusMasterDma {
+	/// Sets up bus master DMA for the bus whose bus master registers start at the given I/O port,
+	/// allocating its PRDT and DMA buffer.
+	fn new(bus_master_base: u16, interrupt: &'static ChannelInterrupt) -> Result<BusMasterDma, &'static str> {
+		let (prdt, prdt_phys_addr) = create_contiguous_mapping(PAGE_SIZE, DEVICE_MAPPING_FLAGS)?;
+		let (buffer, buffer_phys_addr) = create_contiguous_mapping(DMA_BUFFER_SIZE_IN_BYTES, DEVICE_MAPPING_FLAGS)?;
+		// The IDE controller can only access the lowest 4 GiB of physical memory.
+		if prdt_phys_addr.value() + PAGE_SIZE > u32::max_value() as usize 
+			|| buffer_phys_addr.value() + DMA_BUFFER_SIZE_IN_BYTES > u32::max_value() as usize 
+		{
+			return Err("ATA DMA memory was allocated above 4 GiB, which the IDE controller cannot access");
+		}
+		Ok(BusMasterDma {
+			command: Port::new(bus_master_base + 0),
+			status: Port::new(bus_master_base + 2),
+			prdt_address: Port::new(bus_master_base + 4),
+			prdt,
+			prdt_phys_addr,
+			buffer,
+			buffer_phys_addr,
+			interrupt,
+		})
+	}
+
+	/// Fills in the PRDT such that it describes the first `length` bytes of the DMA buffer. 
+	/// 
+	/// The buffer is described one page at a time, which ensures that no region crosses a 64 KiB boundary.
+	fn prepare_prdt(&mut self, length: usize) -> Result<(), &'static str> {
+		let entry_count = (length + PAGE_SIZE - 1) / PAGE_SIZE;
+		let buffer_phys_addr = self.buffer_phys_addr.value();
+		let entries: &mut [PhysicalRegionDescriptor] = self.prdt.as_slice_mut(0, entry_count)?;
+		for (i, entry) in entries.iter_mut().enumerate() {
+			let offset = i * PAGE_SIZE;
+			*entry = PhysicalRegionDescriptor {
+				address: (buffer_phys_addr + offset) as u32,
+				byte_count: core::cmp::min(PAGE_SIZE, length - offset) as u16,
+				flags: if i == entry_count - 1 { PRD_END_OF_TABLE } else { 0 },
+			};
+		}
+		Ok(())
+	}
+
+	/// Reads the bus master `status` port.
+	fn status(&self) -> BusMasterStatus {
+		BusMasterStatus::from_bits_truncate(self.status.read())
+	}
+
+	/// Clears the interrupt and error bits of the bus master `status` port,
+	/// which are cleared by writing `1` to them.
+	fn clear_status(&mut self) {
+		let status = self.status.read();
+		unsafe { self.status.write(status | (BusMasterStatus::INTERRUPT | BusMasterStatus::ERROR).bits()); }
+	}
+
+	/// Waits until the drive raises an interrupt to signal that the current DMA transfer is done,
+	/// which is seen either by the channel's interrupt handler or in the bus master `status` port.
+	/// 
+	/// This blocks on the channel's interrupt, or polls the `status` port if there is no task to block.
+	fn wait_for_completion(&self) -> Result<(), &'static str> {
+		let interrupt = self.interrupt;
+		let done = || {
+			if interrupt.occurred.load(Ordering::Acquire)
+				|| self.status().intersects(BusMasterStatus::INTERRUPT | BusMasterStatus::ERROR)
+			{
+				Some(())
+			} else {
+				None
+			}
+		};
+		wait_or_poll(Some(&interrupt.waiters), DMA_TIMEOUT, &done)
+			.ok_or("timed out waiting for an ATA DMA transfer to complete")
+	}
+}
+
+
 /// There are two ATA buses on an IDE controller,
 /// and each one can have two drives attached to it:
 /// one master drive and one slave drive. 
@@ -215,6 +460,10 @@ struct AtaBus {
 	/// `DEVADDRESS`, located at `BAR1 + 3`. 
 	/// Not sure what this is used for.
 	drive_address: Port<u8>,
+
+	/// The bus master registers and memory used for DMA transfers on this bus, 
+	/// or `None` if this bus doesn't support DMA.
+	dma: Option<BusMasterDma>,
 }
 
 impl AtaBus {
@@ -237,6 +486,8 @@ impl AtaBus {
 			alternate_status: PortReadOnly::new(control_bar + 2),
 			control: PortWriteOnly::new(control_bar + 2),
 			drive_address: Port::new(control_bar + 3),
+
+			dma: None,
 		}
 	}
 
@@ -253,39 +504,10 @@ impl AtaBus {
 			return Ok(0);
 		}
 
-		// Use 28-bit LBAs, unless the LBA is too large, then we use 48-bit LBAs
-		let using_lba_28 = lba_start <= MAX_LBA_28_VALUE;
-
 		self.wait_for_data_done().map_err(|_| "error before issuing read pio command")?;
 
 		// Set up and issue the read command.
-		if using_lba_28 {
-			unsafe {
-				// bits [24:28] of the LBA need to go into the lower 4 bits of the `drive_select` port.
-				self.drive_select.write(0xE0 | (which as u8) | ((lba_start >> 24) as u8 & 0x0F));
-				self.sector_count.write(sector_count as u8);
-				self.lba_high.write((lba_start >> 16) as u8);
-				self.lba_mid.write( (lba_start >>  8) as u8);
-				self.lba_low.write( (lba_start >>  0) as u8);
-				self.command.write(AtaCommand::ReadPio as u8);
-			}
-		} else {
-			// When using 48-bit LBAs, the high bytes of the sector_count and LBA must be written *before* the low bytes.
-			unsafe {
-				self.drive_select.write(0x40 | (which as u8));
-				// write the high bytes
-				self.sector_count.write((sector_count >> 8) as u8);
-				self.lba_high.write((lba_start >> 40) as u8);
-				self.lba_mid.write( (lba_start >> 32) as u8);
-				self.lba_low.write( (lba_start >> 24) as u8);
-				// write the low bytes
-				self.sector_count.write(sector_count as u8);
-				self.lba_high.write((lba_start >> 16) as u8);
-				self.lba_mid.write( (lba_start >>  8) as u8);
-				self.lba_low.write( (lba_start >>  0) as u8);
-				self.command.write(AtaCommand::ReadPioExt as u8);
-			}
-		} 
+		self.issue_transfer_command(which, lba_start, sector_count, AtaCommand::ReadPio, AtaCommand::ReadPioExt);
 
 		// Read the actual data, one sector at a time.
 		let mut buffer_offset = 0;
@@ -306,8 +528,6 @@ impl AtaBus {
 		Ok(sector_count)
 	}
 
-	// TODO: refactor read and write into a single internal "I/O" function, since they're mostly identical.
-
 	/// Issues the actual write PIO command on the ATA Bus without performing any bounds checks.
 	/// 
 	/// See `AtaDrive::write_pio()` (the caller of this function) for more documentation.
@@ -321,12 +541,154 @@ impl AtaBus {
 			return Ok(0);
 		}
 
-		// Use 28-bit LBAs, unless the LBA is too large, then we use 48-bit LBAs
-		let using_lba_28 = lba_start <= MAX_LBA_28_VALUE;
-
 		self.wait_for_data_done().map_err(|_| "error before issuing write command")?;
 
 		// Set up and issue the write command.
+		let using_lba_28 = self.issue_transfer_command(which, lba_start, sector_count, AtaCommand::WritePio, AtaCommand::WritePioExt);
+
+		// Write the actual data, one sector at a time. 
+		let mut buffer_offset = 0;
+		for _lba in lba_start .. (lba_start + sector_count) {
+			// Before transferring each sector, we have to wait for the drive to be ready for data
+			self.wait_for_data_ready().map_err(|_| "error during data write")?;
+
+			for chunk in buffer[buffer_offset .. (buffer_offset + SECTOR_SIZE_IN_BYTES)].chunks_exact(2) {
+				// ATA PIO works by writing one 16-bit word at a time, 
+				// so one 16-bit write covers two bytes of the buffer.
+				let word = (chunk[1] as u16) << 8 | (chunk[0] as u16);
+				unsafe { self.data.write(word); }
+			}
+			buffer_offset += SECTOR_SIZE_IN_BYTES;
+		}
+		self.wait_for_data_done().map_err(|_| "error after data write")?;
+
+		// Flush the drive's cache after each write command
+		let cache_flush_cmd = if using_lba_28 { AtaCommand::CacheFlush } else { AtaCommand::CacheFlushExt };
+		unsafe { self.command.write(cache_flush_cmd as u8) };
+
+		self.wait_for_data_done().map_err(|_| "error after cache flush after data write")?;
+		Ok(sector_count)
+	}
+
+	/// Issues the actual read DMA command on the ATA Bus without performing any bounds checks,
+	/// and copies the data that was read into the given `buffer`.
+	/// 
+	/// The `sector_count` must not exceed the size of this bus's DMA buffer.
+	/// See `AtaDrive::read_dma()` (the caller of this function) for more documentation.
+	fn read_dma(&mut self, 
+		buffer: &mut [u8],
+		which: BusDriveSelect,
+		lba_start: usize,
+		sector_count: usize
+	) -> Result<usize, &'static str> {
+		if sector_count == 0 {
+			return Ok(0);
+		}
+		let length_in_bytes = sector_count * SECTOR_SIZE_IN_BYTES;
+		self.transfer_dma(which, lba_start, sector_count, true)?;
+		let dma = self.dma.as_ref().ok_or("ATA bus doesn't support DMA")?;
+		buffer[.. length_in_bytes].copy_from_slice(dma.buffer.as_slice(0, length_in_bytes)?);
+		Ok(sector_count)
+	}
+
+	/// Copies the given `buffer` into the DMA buffer and issues the actual write DMA command on the ATA Bus
+	/// without performing any bounds checks.
+	/// 
+	/// The `sector_count` must not exceed the size of this bus's DMA buffer.
+	/// See `AtaDrive::write_dma()` (the caller of this function) for more documentation.
+	fn write_dma(&mut self, 
+		buffer: &[u8],
+		which: BusDriveSelect,
+		lba_start: usize,
+		sector_count: usize
+	) -> Result<usize, &'static str> {
+		if sector_count == 0 {
+			return Ok(0);
+		}
+		let length_in_bytes = sector_count * SECTOR_SIZE_IN_BYTES;
+		{
+			let dma = self.dma.as_mut().ok_or("ATA bus doesn't support DMA")?;
+			dma.buffer.as_slice_mut(0, length_in_bytes)?.copy_from_slice(&buffer[.. length_in_bytes]);
+		}
+		let using_lba_28 = self.transfer_dma(which, lba_start, sector_count, false)?;
+
+		// Flush the drive's cache after each write command
+		let cache_flush_cmd = if using_lba_28 { AtaCommand::CacheFlush } else { AtaCommand::CacheFlushExt };
+		unsafe { self.command.write(cache_flush_cmd as u8) };
+
+		self.wait_for_data_done().map_err(|_| "error after cache flush after DMA write")?;
+		Ok(sector_count)
+	}
+
+	/// Performs a DMA transfer of `sector_count` sectors between the drive and this bus's DMA buffer,
+	/// reading from the drive if `read` is true, and writing to the drive otherwise.
+	/// 
+	/// This issues the DMA command, starts the bus master, and waits for the drive to raise an interrupt.
+	/// Returns `true` if 28-bit LBAs were used.
+	fn transfer_dma(&mut self, 
+		which: BusDriveSelect,
+		lba_start: usize,
+		sector_count: usize,
+		read: bool,
+	) -> Result<bool, &'static str> {
+		self.wait_for_data_done().map_err(|_| "error before issuing DMA command")?;
+
+		// Set up the bus master: the PRDT, the transfer direction, and cleared status bits.
+		{
+			let dma = self.dma.as_mut().ok_or("ATA bus doesn't support DMA")?;
+			dma.prepare_prdt(sector_count * SECTOR_SIZE_IN_BYTES)?;
+			let direction = if read { BusMasterCommand::READ } else { BusMasterCommand::empty() };
+			unsafe {
+				dma.prdt_address.write(dma.prdt_phys_addr.value() as u32);
+				dma.command.write(direction.bits());
+			}
+			dma.clear_status();
+			dma.interrupt.occurred.store(false, Ordering::Release);
+		}
+
+		let using_lba_28 = if read {
+			self.issue_transfer_command(which, lba_start, sector_count, AtaCommand::ReadDma, AtaCommand::ReadDmaExt)
+		} else {
+			self.issue_transfer_command(which, lba_start, sector_count, AtaCommand::WriteDma, AtaCommand::WriteDmaExt)
+		};
+
+		let dma = self.dma.as_mut().ok_or("ATA bus doesn't support DMA")?;
+		let direction = if read { BusMasterCommand::READ } else { BusMasterCommand::empty() };
+		unsafe { dma.command.write((direction | BusMasterCommand::START).bits()); }
+		let wait_result = dma.wait_for_completion();
+
+		// Stop the bus master, which must be done even if the transfer failed.
+		unsafe { dma.command.write(direction.bits()); }
+		let bus_master_status = dma.status();
+		dma.clear_status();
+		wait_result?;
+		// Reading the status port also acknowledges the drive's interrupt.
+		let status = self.status();
+		if bus_master_status.intersects(BusMasterStatus::ERROR) || status.intersects(AtaStatus::ERROR | AtaStatus::DRIVE_WRITE_FAULT) {
+			error!("AtaBus::transfer_dma(): DMA transfer failed, bus master status: {:?}, status: {:?}, error: {:?}",
+				bus_master_status, status, self.error()
+			);
+			return Err("ATA DMA transfer failed");
+		}
+		Ok(using_lba_28)
+	}
+
+	/// Selects the given drive, sets up the starting LBA and the number of sectors of a transfer, 
+	/// and then issues the given command to start that transfer. 
+	/// 
+	/// 28-bit LBAs and the `command_lba_28` are used unless the LBA is too large,
+	/// in which case 48-bit LBAs and the `command_lba_48` are used.
+	/// Returns `true` if 28-bit LBAs were used.
+	fn issue_transfer_command(&mut self,
+		which: BusDriveSelect,
+		lba_start: usize,
+		sector_count: usize,
+		command_lba_28: AtaCommand,
+		command_lba_48: AtaCommand,
+	) -> bool {
+		// Use 28-bit LBAs, unless the LBA is too large, then we use 48-bit LBAs
+		let using_lba_28 = lba_start <= MAX_LBA_28_VALUE;
+
 		if using_lba_28 {
 			unsafe {
 				// bits [24:28] of the LBA need to go into the lower 4 bits of the `drive_select` port.
@@ -335,7 +697,7 @@ impl AtaBus {
 				self.lba_high.write((lba_start >> 16) as u8);
 				self.lba_mid.write( (lba_start >>  8) as u8);
 				self.lba_low.write( (lba_start >>  0) as u8);
-				self.command.write(AtaCommand::WritePio as u8);
+				self.command.write(command_lba_28 as u8);
 			}
 		} else {
 			// When using 48-bit LBAs, the high bytes of the sector_count and LBA must be written *before* the low bytes.
@@ -351,32 +713,10 @@ impl AtaBus {
 				self.lba_high.write((lba_start >> 16) as u8);
 				self.lba_mid.write( (lba_start >>  8) as u8);
 				self.lba_low.write( (lba_start >>  0) as u8);
-				self.command.write(AtaCommand::WritePioExt as u8);
+				self.command.write(command_lba_48 as u8);
 			}
 		}
-
-		// Write the actual data, one sector at a time. 
-		let mut buffer_offset = 0;
-		for _lba in lba_start .. (lba_start + sector_count) {
-			// Before transferring each sector, we have to wait for the drive to be ready for data
-			self.wait_for_data_ready().map_err(|_| "error during data write")?;
-
-			for chunk in buffer[buffer_offset .. (buffer_offset + SECTOR_SIZE_IN_BYTES)].chunks_exact(2) {
-				// ATA PIO works by writing one 16-bit word at a time, 
-				// so one 16-bit write covers two bytes of the buffer.
-				let word = (chunk[1] as u16) << 8 | (chunk[0] as u16);
-				unsafe { self.data.write(word); }
-			}
-			buffer_offset += SECTOR_SIZE_IN_BYTES;
-		}
-		self.wait_for_data_done().map_err(|_| "error after data write")?;
-
-		// Flush the drive's cache after each write command
-		let cache_flush_cmd = if using_lba_28 { AtaCommand::CacheFlush } else { AtaCommand::CacheFlushExt };
-		unsafe { self.command.write(cache_flush_cmd as u8) };
-
-		self.wait_for_data_done().map_err(|_| "error after cache flush after data write")?;
-		Ok(sector_count)
+		using_lba_28
 	}
 
 	/// Issues an ATA identify command to probe the drive
@@ -538,11 +878,16 @@ impl AtaBus {
 pub struct AtaDrive {
 	/// A reference to the bus that this drive sits on,
 	/// shared with the other AtaDrive that also sits on this bus.
-	bus: Arc<Mutex<AtaBus>>,
+	/// The lock is held for the entirety of each transfer, including while waiting for a DMA transfer's interrupt,
+	/// so it puts other tasks to sleep rather than having them spin.
+	bus: Arc<MutexSleep<AtaBus>>,
 	/// Data that represents the characteristics of the drive. 
 	identify_data: AtaIdentifyData,
 	/// Whether this drive is a master or slave on the bus.
 	master_slave: BusDriveSelect,
+	/// Whether reads and writes to this drive can use bus master DMA,
+	/// i.e., both the drive and its bus support DMA.
+	dma: bool,
 }
 
 impl AtaDrive {
@@ -552,11 +897,11 @@ impl AtaDrive {
 	/// Since two drives (one master and one slave) may exist on one IDE bus (sharing the same data and control BAR),
 	/// the caller must specify *which* one to search for. 
 	/// The caller can look for both by calling this twice: once with `which = Master` and once with `which = Slave`.
-	fn new(bus: Arc<Mutex<AtaBus>>, which: BusDriveSelect) -> Result<AtaDrive, &'static str> {
+	fn new(bus: Arc<MutexSleep<AtaBus>>, which: BusDriveSelect) -> Result<AtaDrive, &'static str> {
 		// Issue a preliminary software reset of the bus to clear out lingering errors.
-		bus.lock().software_reset(); 
+		bus.lock()?.software_reset(); 
 		// Then use an identify command to see if the drive exists.
-		let identify_data = bus.lock().identify_drive(which)?;
+		let identify_data = bus.lock()?.identify_drive(which)?;
 
 		// Check to see that the drive supports LBA,
 		// because we don't support the ancient CHS (cylinder-head-sector) addressing scheme.
@@ -564,10 +909,14 @@ impl AtaDrive {
 			return Err("drive is an ancient CHS device that doesn't support LBA addressing mode, but we don't support CHS.");
 		}
 
+		// Bit 8 of the capabilities indicates that the drive supports DMA.
+		let dma = identify_data.capabilities & 0x100 != 0 && bus.lock()?.dma.is_some();
+
 		Ok(AtaDrive {
 			bus, 
 			identify_data,
 			master_slave: which,
+			dma,
 		})
 	}
 
@@ -604,7 +953,7 @@ impl AtaDrive {
 			return Err("AtaDrive::read_pio(): cannot read more sectors than the drive's max");
 		}
 		
-		self.bus.lock().read_pio(buffer, self.master_slave, lba_start, sector_count)
+		self.bus.lock()?.read_pio(buffer, self.master_slave, lba_start, sector_count)
 	}
 
 	/// Writes data from the provided `buffer` to this drive, starting at the given `offset_in_sectors` into the drive.
@@ -640,7 +989,67 @@ impl AtaDrive {
 			return Err("AtaDrive::write_pio(): cannot write more sectors than the drive's max");
 		}
 
-		self.bus.lock().write_pio(buffer, self.master_slave, lba_start, sector_count)
+		self.bus.lock()?.write_pio(buffer, self.master_slave, lba_start, sector_count)
+	}
+
+	/// Reads data from this drive starting at the given `offset_in_sectors` into the provided `buffer`,
+	/// using bus master DMA instead of port I/O.
+	/// 
+	/// The requirements on the `buffer` and `offset_in_sectors` are the same as for [`read_pio()`](#method.read_pio).
+	/// Large reads are split into multiple DMA transfers, one for each time the bus's DMA buffer is filled.
+	/// 
+	/// Returns the number of sectors (*not bytes*) that were successfully read from the drive,
+	/// or an error if this drive doesn't support DMA.
+	pub fn read_dma(&mut self, buffer: &mut [u8], offset_in_sectors: usize) -> Result<usize, &'static str> {
+		if !self.dma {
+			return Err("AtaDrive::read_dma(): drive doesn't support DMA");
+		}
+		if buffer.len() % SECTOR_SIZE_IN_BYTES != 0 {
+			return Err("The buffer length must be a multiple of sector size (512) bytes. ATA drives can only read at sector granularity.");
+		}
+		if offset_in_sectors + (buffer.len() / SECTOR_SIZE_IN_BYTES) > self.size_in_sectors() {
+			return Err("offset_in_sectors and buffer length were out of bounds");
+		}
+
+		let mut sectors_read = 0;
+		for chunk in buffer.chunks_mut(DMA_BUFFER_SIZE_IN_BYTES) {
+			let sector_count = chunk.len() / SECTOR_SIZE_IN_BYTES;
+			sectors_read += self.bus.lock()?.read_dma(chunk, self.master_slave, offset_in_sectors + sectors_read, sector_count)?;
+		}
+		Ok(sectors_read)
+	}
+
+	/// Writes data from the provided `buffer` to this drive, starting at the given `offset_in_sectors` into the drive,
+	/// using bus master DMA instead of port I/O.
+	/// 
+	/// The requirements on the `buffer` and `offset_in_sectors` are the same as for [`write_pio()`](#method.write_pio).
+	/// Large writes are split into multiple DMA transfers, one for each time the bus's DMA buffer is filled.
+	/// 
+	/// Returns the number of sectors (*not bytes*) that were successfully written to the drive,
+	/// or an error if this drive doesn't support DMA.
+	pub fn write_dma(&mut self, buffer: &[u8], offset_in_sectors: usize) -> Result<usize, &'static str> {
+		if !self.dma {
+			return Err("AtaDrive::write_dma(): drive doesn't support DMA");
+		}
+		if buffer.len() % SECTOR_SIZE_IN_BYTES != 0 {
+			return Err("The buffer length must be a multiple of sector size (512) bytes. ATA drives can only write at sector granularity.");
+		}
+		if offset_in_sectors + (buffer.len() / SECTOR_SIZE_IN_BYTES) > self.size_in_sectors() {
+			return Err("offset_in_sectors and buffer length were out of bounds");
+		}
+
+		let mut sectors_written = 0;
+		for chunk in buffer.chunks(DMA_BUFFER_SIZE_IN_BYTES) {
+			let sector_count = chunk.len() / SECTOR_SIZE_IN_BYTES;
+			sectors_written += self.bus.lock()?.write_dma(chunk, self.master_slave, offset_in_sectors + sectors_written, sector_count)?;
+		}
+		Ok(sectors_written)
+	}
+
+	/// Returns `true` if reads and writes to this drive use bus master DMA, 
+	/// or `false` if they use the slower PIO mode.
+	pub fn is_dma_enabled(&self) -> bool {
+		self.dma
 	}
 
 
@@ -656,11 +1065,19 @@ impl AtaDrive {
 
 impl StorageDevice for AtaDrive {
 	fn read_sectors(&mut self, buffer: &mut [u8], offset_in_sectors: usize) -> Result<usize, &'static str> {
-		self.read_pio(buffer, offset_in_sectors)
+		if self.dma {
+			self.read_dma(buffer, offset_in_sectors)
+		} else {
+			self.read_pio(buffer, offset_in_sectors)
+		}
 	}
 
     fn write_sectors(&mut self, buffer: &[u8], offset_in_sectors: usize) -> Result<usize, &'static str> {
-		self.write_pio(buffer, offset_in_sectors)
+		if self.dma {
+			self.write_dma(buffer, offset_in_sectors)
+		} else {
+			self.write_pio(buffer, offset_in_sectors)
+		}
 	}
 
 	/// Returns the number of sectors in this drive.
@@ -724,11 +1141,46 @@ impl IdeController {
 			}
 		};
 
-		// TODO: use the BAR4 for DMA in the future
-		let _bus_master_base = pci_device.bars[4]; 
+		// Both channels raise legacy IRQs 14 and 15, which are used to signal the completion of DMA transfers.
+		let primary_irq_registered = interrupts::register_interrupt(PIC_MASTER_OFFSET + PRIMARY_CHANNEL_IRQ, primary_channel_handler)
+			.map_err(|_| error!("IdeController::new(): failed to register interrupt handler for primary ATA channel"))
+			.is_ok();
+		let secondary_irq_registered = interrupts::register_interrupt(PIC_MASTER_OFFSET + SECONDARY_CHANNEL_IRQ, secondary_channel_handler)
+			.map_err(|_| error!("IdeController::new(): failed to register interrupt handler for secondary ATA channel"))
+			.is_ok();
+
+		let mut primary_bus = AtaBus::new(primary_bus_data_port, primary_bus_control_port);
+		let mut secondary_bus = AtaBus::new(secondary_bus_data_port, secondary_bus_control_port);
+
+		// BAR4 holds the I/O port base of the bus master registers, which are used for DMA.
+		// Bit 7 of the prog IF indicates bus mastering support; bits 0 and 2 indicate whether 
+		// a channel is in native mode, which uses PCI interrupts instead of the legacy IRQs above.
+		let bus_master_base = pci_device.bars[4];
+		let dma_supported = pci_device.prog_if & 0x80 != 0 
+			&& pci_device.prog_if & 0x05 == 0 
+			&& bus_master_base & 0x1 != 0 
+			&& bus_master_base & !0x3 != 0;
+		if dma_supported {
+			pci_device.pci_set_command_bus_master_bit();
+			let bus_master_base = (bus_master_base & !0x3) as u16;
+			if primary_irq_registered {
+				primary_bus.dma = BusMasterDma::new(bus_master_base, &PRIMARY_CHANNEL_INTERRUPT)
+					.map_err(|e| error!("IdeController::new(): failed to set up DMA for primary ATA channel: {}", e))
+					.ok();
+			}
+			if secondary_irq_registered {
+				secondary_bus.dma = BusMasterDma::new(bus_master_base + SECONDARY_CHANNEL_BUS_MASTER_OFFSET, &SECONDARY_CHANNEL_INTERRUPT)
+					.map_err(|e| error!("IdeController::new(): failed to set up DMA for secondary ATA channel: {}", e))
+					.ok();
+			}
+		} else {
+			warn!("ATA drive controller at {} doesn't support bus master DMA, falling back to PIO (prog IF: {:#X}, BAR4: {:#X})",
+				pci_device.location, pci_device.prog_if, bus_master_base
+			);
+		}
 
-		let primary_bus = Arc::new(Mutex::new(AtaBus::new(primary_bus_data_port, primary_bus_control_port)));
-		let secondary_bus = Arc::new(Mutex::new(AtaBus::new(secondary_bus_data_port, secondary_bus_control_port)));
+		let primary_bus = Arc::new(MutexSleep::new(primary_bus));
+		let secondary_bus = Arc::new(MutexSleep::new(secondary_bus));
 
 		let primary_master   = AtaDrive::new(Arc::clone(&primary_bus), BusDriveSelect::Master);
 		let primary_slave    = AtaDrive::new(primary_bus, BusDriveSelect::Slave);
@@ -737,7 +1189,7 @@ impl IdeController {
 		
 		let drive_fmt = |drive: &Result<AtaDrive, &str>| -> String {
 			match drive {
-				Ok(d)  => format!("drive initialized, size: {} sectors", d.size_in_sectors()),
+				Ok(d)  => format!("drive initialized, size: {} sectors, {}", d.size_in_sectors(), if d.is_dma_enabled() { "DMA" } else { "PIO" }),
 				Err(e) => format!("{}", e),
 			}
 		};
@@ -976,7 +1428,9 @@ pub struct AtaIdentifyData {
 impl AtaIdentifyData {
 	/// Converts the given byte array, which should be the result of an ATA identify command,
 	/// into a struct that contains the identified details of an ATA drive.
-	fn new(arr: [u8; SECTOR_SIZE_IN_BYTES])-> AtaIdentifyData {
+	/// 
+	/// This is also used for SATA drives, which respond to the identify command with the same data.
+	pub fn new(arr: [u8; SECTOR_SIZE_IN_BYTES])-> AtaIdentifyData {
 		let mut identify_data: AtaIdentifyData = unsafe { core::mem::transmute(arr) };
 		Self::flip_bytes(&mut identify_data.serial_number.0);
 		Self::flip_bytes(&mut identify_data.firmware_version.0);
@@ -0,0 +1,322 @@
+//! A bounded, write-back cache of the blocks of a single storage device.
+//!
+//! Cached blocks are evicted using the CLOCK algorithm (an approximation of LRU):
+//! each cached block has a "referenced" bit that is set whenever it is accessed,
+//! and a "clock hand" sweeps over all cached blocks, giving each referenced block a second chance
+//! by clearing its bit, and evicting the first block whose bit was already clear.
+//! Modified (dirty) blocks are written back to the storage device before they are evicted.
+
+use core::ops::Range;
+use alloc::vec::Vec;
+use hashbrown::HashMap;
+use storage_device::{StorageDevice, StorageDeviceRef};
+
+
+/// The maximum number of blocks that are read from or written to the storage device in a single transfer.
+pub(crate) const MAX_TRANSFER_BLOCKS: usize = 128;
+
+
+/// A block from a storage device stored in a cache.
+/// This currently includes the actual owned cached content as a vector of bytes on the heap,
+/// in addition to the `CacheState` of the cached item.
+#[derive(Debug)]
+struct CachedBlock {
+    block: Vec<u8>,
+    state: CacheState,
+    /// Whether this block has been accessed since the clock hand last swept over it.
+    referenced: bool,
+}
+
+/// The states of an item in the cache, following the MSI cache coherence protocol.
+#[derive(Debug, PartialEq, Eq)]
+enum CacheState {
+    /// Dirty: the cached item has been modified more recently than the backing store,
+    /// so it must be flushed at a future time to guarantee data correctness and consistency.
+    /// A `Modified` cached item **cannot** be safely dropped from the cache.
+    /// A `Modified` cached item can be safely read from or overwritten without going to the backing store.
+    Modified,
+    /// Clean: the cached item and the backing store are in sync; they have the same value.
+    /// A `Shared` cached item can be safely dropped from the cache.
+    Shared,
+    /// The cached item is out-of-date and should not be read from,
+    /// as the backing storage has a more recent copy than the cache.
+    /// Therefore, if a read of an `Invalid` cached item is requested,
+    /// it must be re-read from the backing storage.
+    /// An `Invalid` item can still be overwritten in the cache without going to the backing store.
+    /// An `Invalid` item can be safely dropped from the cache.
+    Invalid,
+}
+
+
+/// A cache that holds up to `capacity` blocks of one storage device.
+///
+/// A `BlockCache` is shared by all `BlockIo`s over the same storage device, such that they stay coherent.
+/// All dirty blocks are written back to the storage device when the cache is dropped.
+pub(crate) struct BlockCache {
+    /// The storage device whose blocks are cached.
+    device: StorageDeviceRef,
+    /// The size of each block, which is the sector size of the storage device.
+    block_size: usize,
+    /// The maximum number of blocks that can be cached at once.
+    capacity: usize,
+    /// A map from block number to cached block.
+    blocks: HashMap<usize, CachedBlock>,
+    /// The block numbers of all cached blocks, in the order that the clock hand sweeps over them.
+    clock: Vec<usize>,
+    /// The index into `clock` of the next block to be considered for eviction.
+    hand: usize,
+}
+
+impl BlockCache {
+    /// Creates a new empty cache for the given storage `device` that holds up to `capacity` blocks.
+    ///
+    /// The `capacity` is rounded up such that the cache can hold at least two full transfers.
+    pub(crate) fn new(device: StorageDeviceRef, capacity: usize) -> BlockCache {
+        let block_size = device.lock().sector_size_in_bytes();
+        let capacity = core::cmp::max(capacity, 2 * MAX_TRANSFER_BLOCKS);
+        BlockCache {
+            device,
+            block_size,
+            capacity,
+            blocks: HashMap::new(),
+            clock: Vec::with_capacity(capacity),
+            hand: 0,
+        }
+    }
+
+    /// Returns the storage device whose blocks are cached.
+    pub(crate) fn device(&self) -> &StorageDeviceRef {
+        &self.device
+    }
+
+    /// Returns the contents of the given `block`, reading it from the storage device if it isn't cached.
+    ///
+    /// If the block must be read, the following blocks up to (but excluding) `read_ahead_end`
+    /// are read in the same transfer and cached as well, unless they are already cached.
+    pub(crate) fn read_block(
+        &mut self,
+        locked_device: &mut dyn StorageDevice,
+        block: usize,
+        read_ahead_end: usize,
+    ) -> Result<&[u8], &'static str> {
+        let is_valid = match self.blocks.get_mut(&block) {
+            Some(cached_block) if cached_block.state != CacheState::Invalid => {
+                cached_block.referenced = true;
+                true
+            }
+            _ => false,
+        };
+        if !is_valid {
+            self.fill(locked_device, block, read_ahead_end)?;
+        }
+        self.blocks.get(&block).map(|b| &b.block[..]).ok_or("BUG: block was evicted right after it was read")
+    }
+
+    /// Overwrites `data.len()` bytes of the given `block` starting at `offset_in_block` with `data`,
+    /// and marks the block as modified.
+    ///
+    /// The block is only read from the storage device if it isn't cached and is only partially overwritten.
+    pub(crate) fn write_block(
+        &mut self,
+        locked_device: &mut dyn StorageDevice,
+        block: usize,
+        offset_in_block: usize,
+        data: &[u8],
+    ) -> Result<(), &'static str> {
+        if offset_in_block == 0 && data.len() == self.block_size {
+            // We're overwriting the entire block, so no need to read it first
+            if let Some(cached_block) = self.blocks.get_mut(&block) {
+                cached_block.block.copy_from_slice(data);
+                cached_block.state = CacheState::Modified;
+                cached_block.referenced = true;
+                return Ok(());
+            }
+            return self.insert(locked_device, block, data.to_vec(), CacheState::Modified, true);
+        }
+
+        // We're only partially writing to this block, so we need to read the old block first.
+        self.read_block(locked_device, block, block + 1)?;
+        let cached_block = self.blocks.get_mut(&block).ok_or("BUG: block was evicted right after it was read")?;
+        cached_block.block[offset_in_block .. offset_in_block + data.len()].copy_from_slice(data);
+        cached_block.state = CacheState::Modified;
+        Ok(())
+    }
+
+    /// Writes back the given `block` if it is cached and modified.
+    /// If `block` is `None`, all modified blocks in the cache are written back.
+    ///
+    /// Contiguous modified blocks are written back together in as few transfers as possible.
+    pub(crate) fn flush(&mut self, locked_device: &mut dyn StorageDevice, block: Option<usize>) -> Result<(), &'static str> {
+        let dirty_blocks: Vec<usize> = match block {
+            Some(bn) => self.blocks.get(&bn)
+                .filter(|b| b.state == CacheState::Modified)
+                .map(|_| bn)
+                .into_iter()
+                .collect(),
+            None => self.blocks.iter()
+                .filter(|(_, b)| b.state == CacheState::Modified)
+                .map(|(bn, _)| *bn)
+                .collect(),
+        };
+        self.write_back(locked_device, dirty_blocks)
+    }
+
+    /// Writes back all cached and modified blocks in the given range of `blocks`.
+    pub(crate) fn flush_range(&mut self, locked_device: &mut dyn StorageDevice, blocks: Range<usize>) -> Result<(), &'static str> {
+        let dirty_blocks: Vec<usize> = self.blocks.iter()
+            .filter(|(bn, b)| blocks.contains(*bn) && b.state == CacheState::Modified)
+            .map(|(bn, _)| *bn)
+            .collect();
+        self.write_back(locked_device, dirty_blocks)
+    }
+
+    /// Writes back the given modified `dirty_blocks`,
+    /// combining contiguous blocks into as few transfers as possible.
+    fn write_back(&mut self, locked_device: &mut dyn StorageDevice, mut dirty_blocks: Vec<usize>) -> Result<(), &'static str> {
+        dirty_blocks.sort_unstable();
+        let mut start = 0;
+        while start < dirty_blocks.len() {
+            let mut end = start + 1;
+            while end < dirty_blocks.len()
+                && end - start < MAX_TRANSFER_BLOCKS
+                && dirty_blocks[end] == dirty_blocks[end - 1] + 1
+            {
+                end += 1;
+            }
+            self.write_back_run(locked_device, dirty_blocks[start], end - start)?;
+            start = end;
+        }
+        Ok(())
+    }
+
+    /// Marks all cached blocks in the given range of `blocks` as invalid,
+    /// such that they are read again from the storage device the next time they are accessed.
+    ///
+    /// This discards any modifications to those blocks that haven't yet been written back.
+    pub(crate) fn invalidate_range(&mut self, blocks: Range<usize>) {
+        for (_, cached_block) in self.blocks.iter_mut().filter(|(bn, _)| blocks.contains(*bn)) {
+            cached_block.state = CacheState::Invalid;
+        }
+    }
+
+    /// Reads the given `block` and the following uncached blocks up to `read_ahead_end` from the storage device
+    /// in a single transfer, and inserts them all into the cache.
+    fn fill(&mut self, locked_device: &mut dyn StorageDevice, block: usize, read_ahead_end: usize) -> Result<(), &'static str> {
+        let device_end = locked_device.size_in_sectors();
+        let max_end = core::cmp::min(core::cmp::min(read_ahead_end, device_end), block + MAX_TRANSFER_BLOCKS);
+        let mut end = block + 1;
+        // Stop at the first cached block, since it may be newer than the block on the storage device.
+        while end < max_end && !self.blocks.contains_key(&end) {
+            end += 1;
+        }
+
+        let mut buffer = vec![0u8; (end - block) * self.block_size];
+        let sectors_read = locked_device.read_sectors(&mut buffer, block)?;
+        if sectors_read != end - block {
+            return Err("storage device read fewer blocks than requested");
+        }
+        trace!("BlockCache::fill(): read blocks {}..{} (requested block {})", block, end, block);
+
+        // Insert the read-ahead blocks first and without their referenced bit,
+        // such that they are evicted before the requested block if they end up never being used.
+        let mut chunks = buffer.chunks(self.block_size);
+        let requested = chunks.next().ok_or("BUG: read an empty range of blocks")?.to_vec();
+        for (bn, chunk) in (block + 1 ..).zip(chunks) {
+            self.insert(locked_device, bn, chunk.to_vec(), CacheState::Shared, false)?;
+        }
+        match self.blocks.get_mut(&block) {
+            Some(cached_block) => {
+                // an `Invalid` block is re-read in place
+                cached_block.block = requested;
+                cached_block.state = CacheState::Shared;
+                cached_block.referenced = true;
+                Ok(())
+            }
+            None => self.insert(locked_device, block, requested, CacheState::Shared, true),
+        }
+    }
+
+    /// Inserts the given uncached `block` into the cache, evicting another block first if the cache is full.
+    fn insert(
+        &mut self,
+        locked_device: &mut dyn StorageDevice,
+        block: usize,
+        data: Vec<u8>,
+        state: CacheState,
+        referenced: bool,
+    ) -> Result<(), &'static str> {
+        if self.clock.len() < self.capacity {
+            self.clock.push(block);
+        } else {
+            let slot = self.evict(locked_device)?;
+            self.clock[slot] = block;
+        }
+        self.blocks.insert(block, CachedBlock { block: data, state, referenced });
+        Ok(())
+    }
+
+    /// Advances the clock hand until it finds a block that hasn't been referenced recently,
+    /// then writes that block back (if needed) and removes it from the cache.
+    ///
+    /// Returns the index into `clock` of the evicted block, which is free to be reused.
+    fn evict(&mut self, locked_device: &mut dyn StorageDevice) -> Result<usize, &'static str> {
+        // Every block is visited at most twice: once to clear its referenced bit, and once to evict it.
+        for _ in 0 .. 2 * self.clock.len() {
+            let slot = self.hand;
+            self.hand = (self.hand + 1) % self.clock.len();
+            let bn = self.clock[slot];
+            let is_dirty = match self.blocks.get_mut(&bn) {
+                Some(cached_block) if cached_block.referenced => {
+                    cached_block.referenced = false;
+                    continue;
+                }
+                Some(cached_block) => cached_block.state == CacheState::Modified,
+                None => false,
+            };
+            if is_dirty {
+                // Write back the subsequent dirty blocks too, since they're likely to be evicted soon.
+                let mut count = 1;
+                while count < MAX_TRANSFER_BLOCKS
+                    && self.blocks.get(&(bn + count)).map_or(false, |b| b.state == CacheState::Modified)
+                {
+                    count += 1;
+                }
+                self.write_back_run(locked_device, bn, count)?;
+            }
+            self.blocks.remove(&bn);
+            return Ok(slot);
+        }
+        Err("BUG: BlockCache couldn't find a block to evict")
+    }
+
+    /// Writes the `count` contiguous cached blocks starting at `first_block` back to the storage device
+    /// in a single transfer, and marks them as clean.
+    fn write_back_run(&mut self, locked_device: &mut dyn StorageDevice, first_block: usize, count: usize) -> Result<(), &'static str> {
+        let mut buffer = Vec::with_capacity(count * self.block_size);
+        for bn in first_block .. first_block + count {
+            let cached_block = self.blocks.get(&bn).ok_or("BUG: tried to write back an uncached block")?;
+            buffer.extend_from_slice(&cached_block.block);
+        }
+        let sectors_written = locked_device.write_sectors(&buffer, first_block)?;
+        if sectors_written != count {
+            return Err("storage device wrote fewer blocks than requested");
+        }
+        for bn in first_block .. first_block + count {
+            if let Some(cached_block) = self.blocks.get_mut(&bn) {
+                cached_block.state = CacheState::Shared;
+            }
+        }
+        trace!("BlockCache: wrote back blocks {}..{}", first_block, first_block + count);
+        Ok(())
+    }
+}
+
+impl Drop for BlockCache {
+    fn drop(&mut self) {
+        let device = self.device.clone();
+        let mut locked_device = device.lock();
+        if let Err(e) = self.flush(&mut *locked_device, None) {
+            error!("BlockCache::drop(): failed to write back dirty blocks: {}", e);
+        }
+    }
+}
@@ -3,52 +3,236 @@
 //! For example, these wrappers can expose a storage device that transfers 512-byte blocks at a time
 //! as a device that can transfer arbitrary bytes at a time (as little as one byte). 
 //! 
+//! # Caching
+//! All `BlockIo`s over the same `StorageDeviceRef` share a single bounded cache of that device's blocks,
+//! such that they stay coherent with each other.
+//! A `BlockIo` over a region of another storage device, e.g., a partition, uses the cache of that underlying device,
+//! such that it also stays coherent with `BlockIo`s over the whole device or over overlapping regions of it.
+//! The cache evicts blocks using the CLOCK algorithm (an approximation of LRU),
+//! and uses a *write-back* policy: writes only modify the cached blocks,
+//! which are written back to the storage device when they are evicted, when they are explicitly flushed,
+//! when the cache is dropped, or periodically by the background write-back task started in [`init()`](fn.init.html).
+//! 
+//! Transfers that bypass `BlockIo`, e.g., those issued by a `block_queue`, can be kept coherent with the cache
+//! by issuing them through [`transfer_uncached()`](fn.transfer_uncached.html).
+//! 
+//! Sequential reads are detected, upon which the following blocks are read ahead of time
+//! in the same transfer, with a read-ahead window that grows as long as the reads remain sequential.
+//! 
 //! # Limitations
 //! Currently, the `BlockIo` struct is hardcoded to use a `StorageDevice` reference,
 //! when in reality it should just use anything that implements traits like `BlockReader + BlockWriter`. 
 //! 
-//! The read and write functions are implemented such that if the backing storage device
-//! needs to be accessed, it is done so by transferring only one block at a time. 
-//! This is quite inefficient, and we should instead transfer multiple blocks at once. 
-//! 
 //! Cached blocks are stored as vectors of bytes on the heap, 
 //! we should do something else such as separate mapped regions. 
-//! Cached blocks cannot yet be dropped to relieve memory pressure. 
 
 #![no_std]
 
 #[macro_use] extern crate alloc;
 #[macro_use] extern crate log;
+#[macro_use] extern crate lazy_static;
+extern crate spin;
 extern crate hashbrown;
 extern crate storage_device;
+extern crate spawn;
+extern crate sleep;
+
+mod cache;
 
-use alloc::vec::Vec;
-use hashbrown::{
-    HashMap,
-    hash_map::Entry,
+use core::ops::Range;
+use core::time::Duration;
+use alloc::{
+    string::String,
+    sync::{Arc, Weak},
+    vec::Vec,
 };
+use spin::Mutex;
 use storage_device::{StorageDevice, StorageDeviceRef, BlockBounds};
+use cache::BlockCache;
+
+
+/// The default maximum number of blocks held in the cache of each storage device,
+/// e.g., 4 MiB for a device with 512-byte sectors.
+pub const DEFAULT_CACHE_CAPACITY_IN_BLOCKS: usize = 8192;
+
+/// The number of blocks that are read ahead upon the first sequential read.
+const MIN_READ_AHEAD_BLOCKS: usize = 8;
+/// The maximum number of blocks that are read ahead, to which the read-ahead window grows
+/// as long as reads remain sequential.
+const MAX_READ_AHEAD_BLOCKS: usize = cache::MAX_TRANSFER_BLOCKS;
+
+/// The interval at which the background write-back task writes all dirty blocks back to their storage devices.
+const WRITE_BACK_INTERVAL: Duration = Duration::from_secs(5);
+
+
+lazy_static! {
+    /// The caches of all storage devices that currently have a `BlockIo`,
+    /// keyed by the address of the storage device.
+    /// 
+    /// Only storage devices that aren't a region of another storage device have a cache,
+    /// see [`underlying_device()`](fn.underlying_device.html).
+    /// 
+    /// A cache only lives as long as the `BlockIo`s that use it,
+    /// and since each cache holds a reference to its storage device, 
+    /// the address of a storage device whose cache is still alive cannot be reused. 
+    static ref BLOCK_CACHES: Mutex<Vec<(usize, Weak<Mutex<BlockCache>>)>> = Mutex::new(Vec::new());
+}
+
+/// Returns the address of the given storage device, which uniquely identifies it while it exists.
+fn device_address(device: &StorageDeviceRef) -> usize {
+    &**device as *const _ as *const u8 as usize
+}
+
+/// Follows the chain of underlying storage devices from the given `device` (e.g., from a partition to its disk)
+/// and returns the storage device at the end of that chain,
+/// along with the sector on it at which the given `device` starts.
+fn underlying_device(device: &StorageDeviceRef) -> (StorageDeviceRef, usize) {
+    let mut device = Arc::clone(device);
+    let mut first_block = 0;
+    loop {
+        let underlying = device.lock().underlying_device();
+        match underlying {
+            Some((underlying, offset)) => {
+                device = underlying;
+                first_block += offset;
+            }
+            None => return (device, first_block),
+        }
+    }
+}
+
+/// Returns the cache shared by all `BlockIo`s over the given storage `device`,
+/// creating it if it doesn't yet exist.
+/// 
+/// The given `device` must not be a region of another storage device.
+fn get_or_create_cache(device: StorageDeviceRef) -> Arc<Mutex<BlockCache>> {
+    let address = device_address(&device);
+    let mut caches = BLOCK_CACHES.lock();
+    // remove the entries of caches that have been dropped
+    caches.retain(|(_, cache)| cache.upgrade().is_some());
+    if let Some(cache) = caches.iter().find(|(addr, _)| *addr == address).and_then(|(_, cache)| cache.upgrade()) {
+        return cache;
+    }
+    let cache = Arc::new(Mutex::new(BlockCache::new(device, DEFAULT_CACHE_CAPACITY_IN_BLOCKS)));
+    caches.push((address, Arc::downgrade(&cache)));
+    cache
+}
+
+/// Returns the cache shared by all `BlockIo`s over the given storage `device`, if any currently exist.
+/// 
+/// The given `device` must not be a region of another storage device.
+fn get_cache(device: &StorageDeviceRef) -> Option<Arc<Mutex<BlockCache>>> {
+    let address = device_address(device);
+    BLOCK_CACHES.lock().iter()
+        .find(|(addr, _)| *addr == address)
+        .and_then(|(_, cache)| cache.upgrade())
+}
+
+/// Invokes the given `transfer` function on the locked storage `device` to read or write the given range of `sectors`
+/// directly, bypassing the cache of `BlockIo`s over that device while keeping it coherent with the transfer.
+/// 
+/// Before the transfer, the modified cached blocks in that range are written back, such that the device holds their latest contents.
+/// After a write (`is_write` is `true`), the cached blocks in that range are invalidated,
+/// such that they are read again from the storage device the next time they are accessed.
+/// No `BlockIo` can access the cache of the device during the transfer.
+/// 
+/// If the `device` is a region of another storage device, e.g., a partition,
+/// the `sectors` are relative to the start of that region, just like the offsets given to `transfer`.
+pub fn transfer_uncached<F, R>(device: &StorageDeviceRef, sectors: Range<usize>, is_write: bool, transfer: F) -> Result<R, &'static str>
+    where F: FnOnce(&mut (dyn StorageDevice + Send)) -> Result<R, &'static str>
+{
+    let (underlying_device, first_block) = underlying_device(device);
+    let cache = match get_cache(&underlying_device) {
+        Some(cache) => cache,
+        None => return transfer(&mut *device.lock()),
+    };
+    let blocks = first_block + sectors.start .. first_block + sectors.end;
+    let mut locked_cache = cache.lock();
+    locked_cache.flush_range(&mut *underlying_device.lock(), blocks.clone())?;
+    let result = transfer(&mut *device.lock());
+    // The written sectors may have been partially written even if the transfer failed.
+    if is_write {
+        locked_cache.invalidate_range(blocks);
+    }
+    result
+}
+
+/// Writes all dirty blocks in all caches back to their storage devices.
+/// 
+/// A failure to write back one cache is logged but doesn't prevent the others from being written back;
+/// the last error is returned.
+pub fn flush_all() -> Result<(), &'static str> {
+    // Don't hold the lock on the list of caches while writing back, as that may take a while.
+    let caches: Vec<Arc<Mutex<BlockCache>>> = BLOCK_CACHES.lock().iter()
+        .filter_map(|(_, cache)| cache.upgrade())
+        .collect();
+    let mut result = Ok(());
+    for cache in caches {
+        let mut locked_cache = cache.lock();
+        let device = Arc::clone(locked_cache.device());
+        let mut locked_device = device.lock();
+        if let Err(e) = locked_cache.flush(&mut *locked_device, None) {
+            error!("block_io::flush_all(): failed to write back dirty blocks: {}", e);
+            result = Err(e);
+        }
+    }
+    result
+}
+
+/// Spawns the background task that periodically writes dirty blocks back to their storage devices,
+/// see [`flush_all()`](fn.flush_all.html).
+pub fn init() -> Result<(), &'static str> {
+    spawn::new_task_builder(write_back_loop, ())
+        .name(String::from("block_io_write_back"))
+        .spawn()?;
+    Ok(())
+}
+
+/// The entry point of the background write-back task.
+fn write_back_loop(_: ()) -> Result<(), &'static str> {
+    loop {
+        sleep::sleep(WRITE_BACK_INTERVAL)?;
+        // errors are already logged by `flush_all()`, and may be transient
+        let _ = flush_all();
+    }
+}
+
 
 /// A wrapper around a `StorageDevice` that supports reads and writes of arbitrary byte lengths
 /// (down to a single byte) by issuing commands to the underlying storage device.
 /// This is needed because most storage devices only allow reads/writes of larger blocks, 
 /// e.g., a 512-byte sector or 4KB cluster.  
 /// 
-/// It also contains a cache for the blocks in the backing storage device,
+/// It also uses a cache for the blocks in the backing storage device,
 /// in order to improve performance by avoiding actual storage device access.
+/// That cache is shared with all other `BlockIo`s over the same storage device.
 pub struct BlockIo {
-    /// The cache of blocks (sectors) read from the storage device,
-    /// a map from sector number to data byte array.
-    cache: BlockCache, 
-    /// The underlying storage device from where the blocks are read/written.
+    /// The storage device that this `BlockIo` was created for, which determines the bounds of its transfers.
     device: StorageDeviceRef,
+    /// The first block of `device` on the underlying storage device whose blocks are cached,
+    /// which is `0` unless `device` is a region of another storage device, e.g., a partition.
+    first_block: usize,
+    /// The cache of blocks (sectors) read from the underlying storage device,
+    /// which also holds the underlying storage device from where the blocks are read/written.
+    cache: Arc<Mutex<BlockCache>>,
+    /// The block after the last block accessed by the previous read, used to detect sequential reads.
+    next_sequential_block: usize,
+    /// The number of blocks to read ahead upon the next sequential read that misses in the cache.
+    read_ahead_blocks: usize,
 }
 impl BlockIo {
     /// Creates a new `BlockIo` device 
+    /// 
+    /// If the given `storage_device` is a region of another storage device, e.g., a partition,
+    /// the new `BlockIo` uses the cache of that underlying storage device.
     pub fn new(storage_device: StorageDeviceRef) -> BlockIo {
+        let (underlying_device, first_block) = underlying_device(&storage_device);
         BlockIo {
-            cache: HashMap::new(),
-            device: storage_device, 
+            device: storage_device,
+            first_block,
+            cache: get_or_create_cache(underlying_device),
+            next_sequential_block: 0,
+            read_ahead_blocks: 0,
         }
     }
 
@@ -58,19 +242,39 @@ impl BlockIo {
 	/// Returns the number of bytes that were successfully read from the drive
 	/// and copied into the given `buffer`.
     /// 
-    /// The read blocks will be cached in this `BlockIo` struct to accelerate future storage device access.
+    /// The read blocks will be cached to accelerate future storage device access.
+    /// If this read continues where the previous read left off, the following blocks are read ahead as well.
     pub fn read(&mut self, buffer: &mut [u8], offset: usize) -> Result<usize, &'static str> {
-        let mut locked_device = self.device.lock();
-        let BlockBounds { range, first_block_offset, .. } = locked_device.block_bounds(buffer.len(), offset)?;
+        let (BlockBounds { range, first_block_offset, .. }, size_in_blocks) = {
+            let locked_device = self.device.lock();
+            (locked_device.block_bounds(offset, buffer.len())?, locked_device.size_in_sectors())
+        };
+        let mut cache = self.cache.lock();
+        let device = Arc::clone(cache.device());
+        let mut locked_device = device.lock();
         let block_size_in_bytes = locked_device.sector_size_in_bytes();
 
-        // Read the actual data, one block at a time.
+        // A read that starts in the block where the previous read ended (or the one after it) is sequential.
+        let is_sequential = range.start == self.next_sequential_block || range.start + 1 == self.next_sequential_block;
+        self.read_ahead_blocks = if !is_sequential {
+            0
+        } else if self.read_ahead_blocks == 0 {
+            MIN_READ_AHEAD_BLOCKS
+        } else {
+            core::cmp::min(self.read_ahead_blocks * 2, MAX_READ_AHEAD_BLOCKS)
+        };
+        self.next_sequential_block = range.end;
+        // don't read ahead past the end of this `BlockIo`'s storage device
+        let read_ahead_end = self.first_block + core::cmp::min(range.end + self.read_ahead_blocks, size_in_blocks);
+
+        // Copy the data out of the cache, one block at a time. 
+        // Blocks that aren't cached are read from the storage device in larger transfers.
 		let mut src_offset = first_block_offset; 
 		let mut dest_offset = 0;
 		for block_num in range {
 			// don't copy past the end of `buffer`
 			let num_bytes_to_copy = core::cmp::min(block_size_in_bytes - src_offset, buffer.len() - dest_offset);
-            let block_bytes = Self::read_block(&mut self.cache, &mut *locked_device, block_num)?;
+            let block_bytes = cache.read_block(&mut *locked_device, self.first_block + block_num, read_ahead_end)?;
 			buffer[dest_offset .. (dest_offset + num_bytes_to_copy)].copy_from_slice(&block_bytes[src_offset .. (src_offset + num_bytes_to_copy)]);
 			trace!("BlockIo::read(): for block {}, copied bytes into buffer[{}..{}] from block[{}..{}]",
 				block_num, dest_offset, dest_offset + num_bytes_to_copy, src_offset, src_offset + num_bytes_to_copy,
@@ -87,12 +291,15 @@ impl BlockIo {
 	/// 
 	/// Returns the number of bytes that were successfully written to the storage device.
     /// 
-    /// The written blocks will be cached in this `BlockIo` struct to accelerate future storage device access.
-    /// Currently, we use a *write-through* cache policy,
-    /// in which the blocks are written directly to the cache and the backing storage device immediately.
+    /// The written blocks will be cached to accelerate future storage device access.
+    /// Currently, we use a *write-back* cache policy,
+    /// in which the blocks are only written to the cache and marked as dirty,
+    /// and are written to the backing storage device at a later point, see [`flush()`](#method.flush).
     pub fn write(&mut self, buffer: &[u8], offset: usize) -> Result<usize, &'static str> {
-        let mut locked_device = self.device.lock();
-        let block_bounds = locked_device.block_bounds(buffer.len(), offset)?;
+        let block_bounds = self.device.lock().block_bounds(offset, buffer.len())?;
+        let mut cache = self.cache.lock();
+        let device = Arc::clone(cache.device());
+        let mut locked_device = device.lock();
         let block_size_in_bytes = locked_device.sector_size_in_bytes();
 
         // // A write transfer (and a read too) can be broken down into three parts: 
@@ -106,27 +313,7 @@ impl BlockIo {
 
 		for block_num in block_bounds.range {
 			let num_bytes_to_copy = core::cmp::min(block_size_in_bytes - dest_offset, buffer.len() - src_offset);
-
-            let buffer_to_write = if num_bytes_to_copy == block_size_in_bytes {
-                // We're overwriting the entire block, so no need to read it first
-                (&buffer[src_offset .. (src_offset + num_bytes_to_copy)]).to_vec()
-            } else {
-                // We're only partially writing to this block, so we need to read the old block first.
-                let old_block = Self::read_block(&mut self.cache, &mut *locked_device, block_num)?;
-                let mut new_block_contents = old_block.to_vec();
-                let overwrite_offset = dest_offset % block_size_in_bytes;
-                new_block_contents[overwrite_offset .. (overwrite_offset + num_bytes_to_copy)]
-                    .copy_from_slice(&buffer[src_offset .. (src_offset + num_bytes_to_copy)]);
-                new_block_contents
-            };
-
-            let mut new_cached_block = CachedBlock {
-                block: buffer_to_write,
-                state: CacheState::Modified,
-            };
-            // Currently using a write-through policy right now, so flush the block immediately
-            Self::flush_block(&mut *locked_device, block_num, &mut new_cached_block)?;
-            self.cache.insert(block_num, new_cached_block);
+            cache.write_block(&mut *locked_device, self.first_block + block_num, dest_offset, &buffer[src_offset .. (src_offset + num_bytes_to_copy)])?;
 			trace!("BlockIo::write(): for block {}, copied bytes from buffer[{}..{}] to block[{}..{}]",
 				block_num, src_offset, src_offset + num_bytes_to_copy, dest_offset, dest_offset + num_bytes_to_copy,
 			);
@@ -140,107 +327,13 @@ impl BlockIo {
     /// Flushes the given block to the backing storage device. 
     /// If the `block_to_flush` is None, all blocks in the entire cache
     /// will be written back to the storage device.
+    /// 
+    /// Because the cache is shared, this also flushes blocks written by other `BlockIo`s over the same storage device
+    /// (or over the storage device that it is a region of).
     pub fn flush(&mut self, block_num: Option<usize>) -> Result<(), &'static str> {
-        let mut locked_device = self.device.lock();
-        if let Some(bn) = block_num {
-            // Flush just one block
-            if let Some(cached_block) = self.cache.get_mut(&bn) {
-                Self::flush_block(&mut *locked_device, bn, cached_block)?;
-            }
-            // If the block wasn't in the cache, do nothing.
-        }
-        else {
-            // Flush all blocks
-            for (bn, cached_block) in self.cache.iter_mut() {
-                Self::flush_block(&mut *locked_device, *bn, cached_block)?;
-            }
-        }
-        Ok(())
+        let mut cache = self.cache.lock();
+        let device = Arc::clone(cache.device());
+        let mut locked_device = device.lock();
+        cache.flush(&mut *locked_device, block_num.map(|block| self.first_block + block))
     }
-
-    /// An internal function that first checks the cache for a specific block
-    /// in order to avoid reading from the storage device.
-    /// If that block exists in the cache, it is copied into the buffer. 
-    /// If not, it is read from the storage device into the cache, and then copied into the buffer.
-    fn read_block<'c>(cache: &'c mut BlockCache, locked_device: &mut dyn StorageDevice, block: usize) -> Result<&'c [u8], &'static str> {
-        match cache.entry(block) {
-            Entry::Occupied(occ) => {
-                // An existing entry in the cache can be used directly (without going to the backing store)
-                // if it's in the `Modified` or `Shared` state.
-                // But if it's in the `Invalid` state, we have to re-read the block from the storage device.
-                let cached_block = occ.into_mut();
-                match cached_block.state {
-                    CacheState::Modified | CacheState::Shared => Ok(&cached_block.block),
-                    CacheState::Invalid => {
-                        locked_device.read_sectors(&mut cached_block.block, block)?;
-                        cached_block.state = CacheState::Shared;
-                        Ok(&cached_block.block)
-                    }
-                }
-            }
-            Entry::Vacant(vacant) => {
-                // A vacant entry will be read from the backing storage device,
-                // so it will always start out in the `Shared` state.
-                let mut v = vec![0; locked_device.sector_size_in_bytes()];
-                locked_device.read_sectors(&mut v, block)?;
-                let cb = CachedBlock {
-                    block: v,
-                    state: CacheState::Shared,
-                };
-                let cached_block = vacant.insert(cb);
-                Ok(&cached_block.block)
-            }
-        }
-    }
-
-    /// An internal function that writes out the given `cached_block`
-    /// to the given locked `StorageDevice` if the cached block is in the `Modified` state.
-    fn flush_block(locked_device: &mut dyn StorageDevice, block_num: usize, cached_block: &mut CachedBlock) -> Result<(), &'static str> {
-        // we only need to actually write blocks in the `Modified` state.
-        match cached_block.state {
-            CacheState::Shared | CacheState::Invalid => { },
-            CacheState::Modified => {
-                locked_device.write_sectors(&cached_block.block, block_num)?;
-                cached_block.state = CacheState::Shared;
-            }
-        }
-        Ok(())
-    }
-}
-
-
-
-/// A block from a storage device stored in a cache.
-/// This currently includes the actual owned cached content as a vector of bytes on the heap,
-/// in addition to the `CacheState` of the cached item.
-/// 
-/// TODO: allow non-dirty blocks to be freed (reclaimed) upon memory pressure. 
-#[derive(Debug)]
-struct CachedBlock {
-    block: Vec<u8>,
-    state: CacheState,
-}
-
-type BlockCache = HashMap<usize, CachedBlock>;
-
-
-/// The states of an item in the cache, following the MSI cache coherence protocol.
-#[derive(Debug)]
-#[allow(dead_code)]
-enum CacheState {
-    /// Dirty: the cached item has been modified more recently than the backing store,
-    /// so it must be flushed at a future time to guarantee data correctness and consistency.
-    /// A `Modified` cached item **cannot** be safely dropped from the cache.
-    /// A `Modified` cached item can be safely read from or overwritten without going to the backing store.  
-    Modified,
-    /// Clean: the cached item and the backing store are in sync; they have the same value.
-    /// A `Shared` cached item can be safely dropped from the cache.
-    Shared,
-    /// The cached item is out-of-date and should not be read from,
-    /// as the backing storage has a more recent copy than the cache.
-    /// Therefore, if a read of an `Invalid` cached item is requested,
-    /// it must be re-read from the backing storage.
-    /// An `Invalid` item can still be overwritten in the cache without going to the backing store. 
-    /// An `Invalid` item can be safely dropped from the cache.
-    Invalid,  
 }
@@ -0,0 +1,410 @@
+//! An asynchronous request queue for storage devices.
+//!
+//! The `StorageDevice` methods `read_sectors()` and `write_sectors()` are synchronous,
+//! so the calling task holds the device's lock for the whole transfer.
+//! A [`RequestQueue`](struct.RequestQueue.html) instead lets tasks submit read and write requests and continue computing,
+//! after which they can wait on the returned [`Completion`](struct.Completion.html) handle,
+//! or have a callback invoked once the request has completed.
+//!
+//! Each storage device has at most one request queue, see [`request_queue()`](fn.request_queue.html),
+//! whose dispatcher task issues the pending requests to the device one transfer at a time:
+//! * Requests are issued in elevator order, i.e., in order of ascending sectors from the last issued sector,
+//!   wrapping around to the lowest sector once there are no more requests beyond it.
+//!   However, a request is never issued before an earlier-submitted request that it overlaps with,
+//!   if either of them is a write.
+//! * Requests of the same kind for adjacent sectors are merged into a single transfer.
+//!
+//! The device is only locked for the duration of each transfer, so other users of the same `StorageDeviceRef`
+//! (e.g., a `BlockIo`) can still access the device in between.
+//! Each transfer is kept coherent with the cache of any `BlockIo`s over the device:
+//! modified cached blocks are written back before they are transferred, and written blocks are invalidated in the cache.
+//!
+//! # Example
+//! ```rust
+//! let queue = block_queue::request_queue(&storage_device)?;
+//! let completion = queue.submit(BlockRequest::read(vec![0; 4096], 8))?;
+//! // ... do other work while the sectors are being read ...
+//! let buffer = completion.wait()?;
+//! ```
+
+#![no_std]
+
+#[macro_use] extern crate alloc;
+#[macro_use] extern crate log;
+#[macro_use] extern crate lazy_static;
+extern crate spin;
+extern crate storage_device;
+extern crate block_io;
+extern crate spawn;
+extern crate wait_queue;
+
+use core::ops::Range;
+use alloc::{
+    boxed::Box,
+    sync::Arc,
+    vec::Vec,
+};
+use spin::Mutex;
+use storage_device::StorageDeviceRef;
+use wait_queue::{WaitQueue, WaitError};
+
+
+/// The maximum size of a single transfer that adjacent requests are merged into.
+/// A single request that is larger than this is still issued as one transfer.
+const MAX_MERGED_TRANSFER_BYTES: usize = 128 * 1024;
+
+
+lazy_static! {
+    /// The request queues of all storage devices that have one, keyed by the address of the storage device.
+    ///
+    /// Since each request queue holds a reference to its storage device,
+    /// the address of a storage device that has a request queue cannot be reused.
+    static ref REQUEST_QUEUES: Mutex<Vec<(usize, Arc<RequestQueue>)>> = Mutex::new(Vec::new());
+}
+
+/// Returns the address of the given storage device, which uniquely identifies it while it exists.
+fn device_address(device: &StorageDeviceRef) -> usize {
+    &**device as *const _ as *const u8 as usize
+}
+
+/// Returns the request queue of the given storage `device`,
+/// creating it and spawning its dispatcher task if it doesn't yet exist.
+pub fn request_queue(device: &StorageDeviceRef) -> Result<Arc<RequestQueue>, &'static str> {
+    let address = device_address(device);
+    let mut queues = REQUEST_QUEUES.lock();
+    if let Some((_, queue)) = queues.iter().find(|(addr, _)| *addr == address) {
+        return Ok(Arc::clone(queue));
+    }
+    let queue = Arc::new(RequestQueue::new(Arc::clone(device)));
+    spawn::new_task_builder(dispatch_loop, Arc::clone(&queue))
+        .name(format!("block_queue_{:#X}", address))
+        .spawn()?;
+    queues.push((address, Arc::clone(&queue)));
+    Ok(queue)
+}
+
+
+/// Whether a request reads from or writes to the storage device.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum RequestKind {
+    Read,
+    Write,
+}
+
+/// A request to read or write a contiguous range of sectors on a storage device.
+#[derive(Debug)]
+pub struct BlockRequest {
+    kind: RequestKind,
+    offset_in_sectors: usize,
+    buffer: Vec<u8>,
+}
+impl BlockRequest {
+    /// Creates a request to read sectors starting at `offset_in_sectors` into the given `buffer`.
+    /// The length of the `buffer` governs how many sectors are read,
+    /// and must be an even multiple of the storage device's sector size.
+    pub fn read(buffer: Vec<u8>, offset_in_sectors: usize) -> BlockRequest {
+        BlockRequest { kind: RequestKind::Read, offset_in_sectors, buffer }
+    }
+
+    /// Creates a request to write the given `buffer` to sectors starting at `offset_in_sectors`.
+    /// The length of the `buffer` governs how many sectors are written,
+    /// and must be an even multiple of the storage device's sector size.
+    pub fn write(buffer: Vec<u8>, offset_in_sectors: usize) -> BlockRequest {
+        BlockRequest { kind: RequestKind::Write, offset_in_sectors, buffer }
+    }
+
+    /// Returns whether this request reads or writes.
+    pub fn kind(&self) -> RequestKind {
+        self.kind
+    }
+
+    /// Returns the first sector that this request reads or writes.
+    pub fn offset_in_sectors(&self) -> usize {
+        self.offset_in_sectors
+    }
+
+    /// Returns the buffer that this request reads into or writes from.
+    pub fn buffer(&self) -> &[u8] {
+        &self.buffer
+    }
+}
+
+
+/// The result of a completed request: its buffer upon success, which holds the read data for a read request.
+pub type RequestResult = Result<Vec<u8>, &'static str>;
+
+/// The shared state of a `Completion` handle, which the dispatcher task fills in once the request has completed.
+struct CompletionState {
+    result: Mutex<Option<RequestResult>>,
+    waiters: WaitQueue,
+}
+
+/// A handle for a submitted request that can be used to wait for the request to complete.
+pub struct Completion {
+    state: Arc<CompletionState>,
+}
+impl Completion {
+    /// Returns `true` if the request has completed, in which case [`wait()`](#method.wait) won't block.
+    pub fn is_complete(&self) -> bool {
+        self.state.result.lock().is_some()
+    }
+
+    /// Blocks the current task until the request has completed, and returns its result.
+    pub fn wait(self) -> RequestResult {
+        let state = &self.state;
+        match state.waiters.wait_until(&|| state.result.lock().take()) {
+            Ok(result) => result,
+            Err(WaitError::NoCurrentTask) => Err("block_queue: cannot wait for a request without a current task"),
+            Err(_) => Err("block_queue: failed to wait for a request to complete"),
+        }
+    }
+}
+
+/// How the submitter of a request is told that the request has completed.
+enum Notifier {
+    Completion(Arc<CompletionState>),
+    Callback(Box<dyn FnOnce(RequestResult) + Send>),
+}
+impl Notifier {
+    fn complete(self, result: RequestResult) {
+        match self {
+            Notifier::Completion(state) => {
+                *state.result.lock() = Some(result);
+                state.waiters.notify_one();
+            }
+            Notifier::Callback(callback) => callback(result),
+        }
+    }
+}
+
+
+/// A request that has been submitted but not yet issued to the storage device.
+struct PendingRequest {
+    request: BlockRequest,
+    /// The range of sectors that the request reads or writes.
+    sectors: Range<usize>,
+    notifier: Notifier,
+}
+impl PendingRequest {
+    /// Returns `true` if this request must not be reordered with respect to the `other` request,
+    /// i.e., if they overlap and at least one of them is a write.
+    fn conflicts_with(&self, other: &PendingRequest) -> bool {
+        let overlaps = self.sectors.start < other.sectors.end && other.sectors.start < self.sectors.end;
+        overlaps && (self.request.kind == RequestKind::Write || other.request.kind == RequestKind::Write)
+    }
+
+    fn complete(self, result: Result<(), &'static str>) {
+        let buffer = self.request.buffer;
+        self.notifier.complete(result.map(|_| buffer));
+    }
+}
+
+/// The requests that are waiting to be issued by the dispatcher task.
+struct PendingRequests {
+    /// The pending requests in the order they were submitted.
+    requests: Vec<PendingRequest>,
+    /// The sector after the last sector of the previous transfer, from which the elevator continues.
+    head_position: usize,
+}
+impl PendingRequests {
+    /// Returns `true` if the request at the given `index` can be issued now,
+    /// i.e., if it doesn't conflict with any request that was submitted before it.
+    fn can_issue(&self, index: usize) -> bool {
+        let request = &self.requests[index];
+        self.requests[.. index].iter().all(|earlier| !earlier.conflicts_with(request))
+    }
+
+    /// Returns the index of the issuable request with the lowest first sector among those accepted by the `filter`.
+    fn lowest_issuable<F: Fn(&PendingRequest) -> bool>(&self, filter: F) -> Option<usize> {
+        (0 .. self.requests.len())
+            .filter(|&i| filter(&self.requests[i]) && self.can_issue(i))
+            .min_by_key(|&i| self.requests[i].sectors.start)
+    }
+
+    /// Removes and returns the requests that should be issued next, which are merged into a single transfer.
+    /// Returns `None` if there are no pending requests.
+    fn take_next_batch(&mut self) -> Option<Vec<PendingRequest>> {
+        let head_position = self.head_position;
+        let first = self.lowest_issuable(|r| r.sectors.start >= head_position)
+            .or_else(|| self.lowest_issuable(|_| true))?;
+        let first = self.requests.remove(first);
+        let kind = first.request.kind;
+        let mut end = first.sectors.end;
+        let mut total_bytes = first.request.buffer.len();
+        let mut batch = vec![first];
+
+        // Append requests of the same kind that start where the transfer currently ends.
+        loop {
+            let next = (0 .. self.requests.len()).find(|&i| {
+                let r = &self.requests[i];
+                r.request.kind == kind
+                    && r.sectors.start == end
+                    && total_bytes + r.request.buffer.len() <= MAX_MERGED_TRANSFER_BYTES
+                    && self.can_issue(i)
+            });
+            match next {
+                Some(i) => {
+                    let next = self.requests.remove(i);
+                    end = next.sectors.end;
+                    total_bytes += next.request.buffer.len();
+                    batch.push(next);
+                }
+                None => break,
+            }
+        }
+        self.head_position = end;
+        Some(batch)
+    }
+}
+
+
+/// A queue of read and write requests for a single storage device,
+/// which are issued to the device by a dedicated dispatcher task.
+///
+/// See the [crate-level documentation](index.html) for the order in which requests are issued.
+pub struct RequestQueue {
+    device: StorageDeviceRef,
+    sector_size_in_bytes: usize,
+    size_in_sectors: usize,
+    pending: Mutex<PendingRequests>,
+    /// The dispatcher task waits on this for requests to be submitted.
+    dispatcher_waiters: WaitQueue,
+}
+impl RequestQueue {
+    fn new(device: StorageDeviceRef) -> RequestQueue {
+        let (sector_size_in_bytes, size_in_sectors) = {
+            let locked_device = device.lock();
+            (locked_device.sector_size_in_bytes(), locked_device.size_in_sectors())
+        };
+        RequestQueue {
+            device,
+            sector_size_in_bytes,
+            size_in_sectors,
+            pending: Mutex::new(PendingRequests {
+                requests: Vec::new(),
+                head_position: 0,
+            }),
+            dispatcher_waiters: WaitQueue::new(),
+        }
+    }
+
+    /// Returns the storage device that this queue issues requests to.
+    pub fn device(&self) -> &StorageDeviceRef {
+        &self.device
+    }
+
+    /// Returns the number of submitted requests that haven't yet been issued to the storage device.
+    pub fn pending_count(&self) -> usize {
+        self.pending.lock().requests.len()
+    }
+
+    /// Submits the given `request`, returning a handle that can be used to wait for it to complete.
+    ///
+    /// Returns an error if the request is invalid, e.g., if its buffer length isn't a multiple of the sector size
+    /// or it extends past the end of the storage device.
+    pub fn submit(&self, request: BlockRequest) -> Result<Completion, &'static str> {
+        let state = Arc::new(CompletionState {
+            result: Mutex::new(None),
+            waiters: WaitQueue::new(),
+        });
+        self.enqueue(request, Notifier::Completion(Arc::clone(&state)))?;
+        Ok(Completion { state })
+    }
+
+    /// Submits the given `request`, upon whose completion the given `callback` is invoked with the request's result.
+    ///
+    /// The `callback` runs in the context of this queue's dispatcher task,
+    /// so it should be short and must not wait for other requests on this queue to complete.
+    ///
+    /// Returns an error without invoking the `callback` if the request is invalid, see [`submit()`](#method.submit).
+    pub fn submit_with_callback<F>(&self, request: BlockRequest, callback: F) -> Result<(), &'static str>
+        where F: FnOnce(RequestResult) + Send + 'static
+    {
+        self.enqueue(request, Notifier::Callback(Box::new(callback)))
+    }
+
+    fn enqueue(&self, request: BlockRequest, notifier: Notifier) -> Result<(), &'static str> {
+        let length = request.buffer.len();
+        if length == 0 || length % self.sector_size_in_bytes != 0 {
+            return Err("block_queue: the buffer length must be a non-zero multiple of the sector size");
+        }
+        let sectors = request.offset_in_sectors .. request.offset_in_sectors + length / self.sector_size_in_bytes;
+        if sectors.end > self.size_in_sectors {
+            return Err("block_queue: request extends past the end of the storage device");
+        }
+        self.pending.lock().requests.push(PendingRequest { request, sectors, notifier });
+        self.dispatcher_waiters.notify_one();
+        Ok(())
+    }
+
+    /// Issues the given batch of requests for adjacent sectors to the storage device in one transfer,
+    /// and then completes each request.
+    fn issue(&self, mut batch: Vec<PendingRequest>) {
+        if batch.len() == 1 {
+            let mut pending = batch.remove(0);
+            let result = self.transfer(pending.request.kind, &mut pending.request.buffer, pending.sectors.start);
+            pending.complete(result);
+            return;
+        }
+
+        let kind = batch[0].request.kind;
+        let mut merged_buffer = match kind {
+            RequestKind::Read => vec![0; batch.iter().map(|p| p.request.buffer.len()).sum()],
+            RequestKind::Write => {
+                let mut merged_buffer = Vec::new();
+                for pending in &batch {
+                    merged_buffer.extend_from_slice(&pending.request.buffer);
+                }
+                merged_buffer
+            }
+        };
+        match self.transfer(kind, &mut merged_buffer, batch[0].sectors.start) {
+            Ok(()) => {
+                let mut offset = 0;
+                for mut pending in batch {
+                    let length = pending.request.buffer.len();
+                    if kind == RequestKind::Read {
+                        pending.request.buffer.copy_from_slice(&merged_buffer[offset .. offset + length]);
+                    }
+                    offset += length;
+                    pending.complete(Ok(()));
+                }
+            }
+            Err(e) => {
+                // Retry each request on its own, such that one bad request doesn't fail the others.
+                warn!("block_queue: merged {:?} transfer of {} requests failed ({}), retrying them individually", kind, batch.len(), e);
+                for pending in batch {
+                    self.issue(vec![pending]);
+                }
+            }
+        }
+    }
+
+    /// Transfers the given `buffer` to or from the storage device, holding the device's lock only for this transfer.
+    ///
+    /// The transfer goes through [`block_io::transfer_uncached()`], which keeps it coherent with the cache of `BlockIo`s.
+    ///
+    /// [`block_io::transfer_uncached()`]: ../block_io/fn.transfer_uncached.html
+    fn transfer(&self, kind: RequestKind, buffer: &mut [u8], offset_in_sectors: usize) -> Result<(), &'static str> {
+        let sectors = offset_in_sectors .. offset_in_sectors + buffer.len() / self.sector_size_in_bytes;
+        let sectors = block_io::transfer_uncached(&self.device, sectors, kind == RequestKind::Write, |locked_device| match kind {
+            RequestKind::Read => locked_device.read_sectors(buffer, offset_in_sectors),
+            RequestKind::Write => locked_device.write_sectors(buffer, offset_in_sectors),
+        })?;
+        if sectors * self.sector_size_in_bytes < buffer.len() {
+            return Err("block_queue: the storage device transferred fewer sectors than requested");
+        }
+        Ok(())
+    }
+}
+
+
+/// The entry point of a request queue's dispatcher task,
+/// which issues the queue's pending requests to its storage device forever.
+fn dispatch_loop(queue: Arc<RequestQueue>) -> Result<(), &'static str> {
+    loop {
+        let batch = queue.dispatcher_waiters.wait_until(&|| queue.pending.lock().take_next_batch())
+            .map_err(|_| "block_queue: dispatcher task failed to wait for requests")?;
+        queue.issue(batch);
+    }
+}
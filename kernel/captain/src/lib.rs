@@ -37,12 +37,19 @@ extern crate task;
 extern crate interrupts;
 extern crate acpi;
 extern crate device_manager;
+extern crate devfs;
+extern crate sys_fs;
+extern crate block_io;
+extern crate fat32;
+extern crate ext2;
+extern crate mount_table;
 extern crate e1000;
 extern crate scheduler;
 #[cfg(mirror_log_to_vga)] #[macro_use] extern crate print;
 extern crate first_application;
 extern crate exceptions_full;
 extern crate network_manager;
+extern crate dhcp_client;
 extern crate window_manager;
 extern crate multiple_heaps;
 #[cfg(simd_personality)] extern crate simd_personality;
@@ -127,7 +134,28 @@ pub fn init(
 
     // initialize the rest of our drivers
     device_manager::init(key_producer, mouse_producer)?;
+    dhcp_client::init()?;
     task_fs::init()?;
+    devfs::init()?;
+    sys_fs::init()?;
+    block_io::init()?;
+    fat32::init()?;
+    ext2::init()?;
+
+    // persist crate object files on the first writable disk filesystem that has a namespaces directory
+    for mount in mount_table::get_mounts() {
+        if mount.options.read_only {
+            continue;
+        }
+        let disk_namespaces_dir = mount.root().lock().get_dir(mod_mgmt::NAMESPACES_DIRECTORY_NAME);
+        if let Some(dir) = disk_namespaces_dir {
+            info!("Persisting crate object files in {:?}", mount.mountpoint);
+            if let Err(e) = mod_mgmt::set_disk_namespaces_directory(dir) {
+                error!("Couldn't use the namespaces directory on {:?}: {}", mount.mountpoint, e);
+            }
+            break;
+        }
+    }
 
 
     // Before we start running applications, we need to unmap the identity-mapped section of the kernel's page tables, at PML4[0].
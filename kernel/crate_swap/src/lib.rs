@@ -45,7 +45,7 @@ use mod_mgmt::{
     StrongSectionRef,
     WeakDependent,
 };
-use path::Path;
+use path::{Path, PathBuf};
 use by_address::ByAddress;
 
 
@@ -232,7 +232,7 @@ pub fn swap_crates(
         let reexport_new_symbols_as_old = *reexport_new_symbols_as_old;
 
         // Populate the list of new crate names for future usage.
-        let new_crate_name = crate_name_from_path(&Path::new(new_crate_object_file.lock().get_name())).to_string();
+        let new_crate_name = crate_name_from_path(Path::new(&new_crate_object_file.lock().get_name())).to_string();
         new_crate_names.push(new_crate_name.clone());
 
         // Get a reference to the old crate that is currently loaded into the `old_namespace`.
@@ -660,7 +660,7 @@ pub fn swap_crates(
             // FIXME: currently we use a hack to determine which namespace this freshly-loaded crate should be added to,
             //        based on which directory its object file 
             {
-                let objfile_path = Path::new(new_crate_ref.lock_as_ref().object_file.lock().get_absolute_path());
+                let objfile_path = PathBuf::from(new_crate_ref.lock_as_ref().object_file.lock().get_absolute_path());
                 if objfile_path.components().skip(1).next() == Some(mod_mgmt::CrateType::Kernel.default_namespace_name()) {
                     let new_target_ns = this_namespace.recursive_namespace().unwrap_or(this_namespace);
                     #[cfg(not(loscd_eval))]
@@ -746,6 +746,17 @@ pub fn swap_crates(
         }
     }
 
+    // Persist the swapped crate object files to the disk directories that back the namespace directories, if any,
+    // such that the swapped crates are used after a reboot too. 
+    // The swap itself has already completed, so a failure here is logged rather than returned.
+    for req in swap_requests.iter() {
+        for namespace_dir in &[req.new_namespace.dir(), req.old_namespace.dir()] {
+            if let Err(e) = namespace_dir.persist() {
+                error!("swap_crates(): couldn't persist the crate object files in namespace directory {:?}: {}", namespace_dir, e);
+            }
+        }
+    }
+
     if cache_old_crates {
         #[cfg(not(loscd_eval))]
         {
@@ -967,7 +978,7 @@ impl SwapRequest {
                     let mut matching_files = CrateNamespace::get_crate_object_files_starting_with(&old_namespace, ocn);
                     if matching_files.len() == 1 {
                         let (old_crate_file, real_old_namespace) = matching_files.remove(0);
-                        let old_crate_file_path = Path::new(old_crate_file.lock().get_name());
+                        let old_crate_file_path = PathBuf::from(old_crate_file.lock().get_name());
                         let old_crate_full_name = crate_name_from_path(&old_crate_file_path).to_string();
                         (Some(old_crate_full_name), real_old_namespace)
                     } else {
@@ -1059,9 +1070,9 @@ pub enum InvalidSwapRequest {
     /// along with the `CrateNamespace` in which they were found. 
     OldCrateNotFound(Option<String>, Arc<CrateNamespace>, Vec<(String, Arc<CrateNamespace>)>),
     /// The given absolute `Path` for the new crate object file could not be resolved.
-    NewCrateAbsolutePathNotFound(Path),
+    NewCrateAbsolutePathNotFound(PathBuf),
     /// The given `Path` for the new crate object file was not an absolute path, as expected.
-    NewCratePathNotAbsolute(Path),
+    NewCratePathNotAbsolute(PathBuf),
     /// A single crate object file could not be found by matching the given prefix `String`
     /// within the given new `CrateNamespace` (which was searched recursively).
     /// Either zero or multiple crate object files matched the prefix,
@@ -0,0 +1,376 @@
+//! A virtual filesystem that exposes devices as files in the `/dev` directory, similar to the one in Linux,
+//! such that generic tools that operate on files can also operate on devices.
+//!
+//! The `/dev` directory contains:
+//! * a block device file for each storage device and each of its partitions, e.g., `disk0` and `disk0p1`,
+//!   which are named as in the `storage_manager` and read and written through a [`BlockIo`],
+//! * the `com1` character device file, which reads the bytes that the COM1 serial port has received
+//!   and writes bytes to it,
+//! * the `fb0` file, which holds the pixels on the screen and can be used as a memory mapping,
+//!   if the display is in graphics mode.
+//!
+//! Since storage devices can be added at any time, the file for each storage device is created when it is first looked up,
+//! and is then kept for as long as the `/dev` directory exists, such that all of its users share a single `BlockIo`.
+//! The framebuffer file also exists for as long as the `/dev` directory does, so that the screen's memory is only mapped once.
+//!
+//! [`BlockIo`]: ../block_io/struct.BlockIo.html
+
+#![no_std]
+
+#[macro_use] extern crate log;
+extern crate alloc;
+extern crate spin;
+extern crate fs_node;
+extern crate memory;
+extern crate root;
+extern crate vfs_node;
+extern crate mount_table;
+extern crate storage_manager;
+extern crate storage_device;
+extern crate block_io;
+extern crate serial_port;
+extern crate framebuffer;
+
+
+use alloc::string::{String, ToString};
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+use spin::Mutex;
+use fs_node::{DirRef, WeakDirRef, Directory, FileOrDir, File, FileRef, FsNode, Metadata, NodeKind, Permissions};
+use memory::MappedPages;
+use vfs_node::VFSDirectory;
+use mount_table::{FileSystem, MountOptions};
+use storage_device::StorageDeviceRef;
+use block_io::BlockIo;
+use framebuffer::AlphaPixel;
+
+
+/// The name of the VFS directory that exposes devices in the root.
+pub const DEV_DIRECTORY_NAME: &str = "dev";
+/// The absolute path of the devices directory, which is currently below the root.
+pub const DEV_DIRECTORY_PATH: &str = "/dev";
+/// The name of the file for the COM1 serial port.
+pub const SERIAL_PORT_FILE_NAME: &str = "com1";
+/// The name of the file for the screen's framebuffer.
+pub const FRAMEBUFFER_FILE_NAME: &str = "fb0";
+
+
+/// Initializes the devices virtual filesystem and mounts it on a new directory within the root directory.
+pub fn init() -> Result<(), &'static str> {
+    DevFs::new()?;
+    Ok(())
+}
+
+
+/// The devfs as a whole, which is what gets recorded in the mount table.
+struct DevFileSystem {
+    root: DirRef,
+}
+
+impl FileSystem for DevFileSystem {
+    fn root(&self) -> DirRef {
+        self.root.clone()
+    }
+}
+
+
+/// The top level directory that contains all device files.
+/// This directory exists in the root directory.
+pub struct DevFs {
+    /// A weak reference to this directory itself, which is the parent of all device files.
+    self_ref: Weak<Mutex<DevFs>>,
+    /// The file for the screen's framebuffer, which doesn't exist if the display isn't in graphics mode.
+    framebuffer: Option<FileRef>,
+    /// The files for the storage devices that have been looked up so far, along with their names.
+    /// This is behind a lock because files are created upon lookup, which only has immutable access to this directory.
+    block_device_files: Mutex<Vec<(String, FileRef)>>,
+}
+
+impl DevFs {
+    fn new() -> Result<DirRef, &'static str> {
+        let mountpoint = VFSDirectory::new(String::from(DEV_DIRECTORY_NAME), root::get_root())?;
+        let devfs = Arc::new(Mutex::new(DevFs {
+            self_ref: Weak::new(),
+            framebuffer: None,
+            block_device_files: Mutex::new(Vec::new()),
+        }));
+        {
+            let mut locked_devfs = devfs.lock();
+            locked_devfs.self_ref = Arc::downgrade(&devfs);
+            locked_devfs.framebuffer = match FramebufferFile::new(Arc::downgrade(&devfs) as WeakDirRef) {
+                Ok(file) => Some(Arc::new(Mutex::new(file)) as FileRef),
+                Err(e) => {
+                    warn!("devfs: not exposing the framebuffer: {}", e);
+                    None
+                }
+            };
+        }
+
+        let dir_ref = devfs as DirRef;
+        let filesystem = Arc::new(DevFileSystem { root: dir_ref.clone() });
+        mount_table::mount_filesystem(String::from("devfs"), String::from("devfs"), filesystem, &mountpoint, MountOptions::default())
+    }
+}
+
+impl FsNode for DevFs {
+    fn get_absolute_path(&self) -> String {
+        String::from(DEV_DIRECTORY_PATH)
+    }
+
+    fn get_name(&self) -> String {
+        String::from(DEV_DIRECTORY_NAME)
+    }
+
+    fn get_parent_dir(&self) -> Option<DirRef> {
+        Some(root::get_root().clone())
+    }
+
+    fn set_parent_dir(&mut self, _new_parent: WeakDirRef) {
+        // do nothing
+    }
+}
+
+impl Directory for DevFs {
+    fn insert(&mut self, _node: FileOrDir) -> Result<Option<FileOrDir>, &'static str> {
+        Err("cannot insert node into DevFs")
+    }
+
+    fn get(&self, name: &str) -> Option<FileOrDir> {
+        let parent = self.self_ref.clone() as WeakDirRef;
+        if name == SERIAL_PORT_FILE_NAME {
+            let file = SerialPortFile { parent };
+            return Some(FileOrDir::File(Arc::new(Mutex::new(file))));
+        }
+        if name == FRAMEBUFFER_FILE_NAME {
+            return self.framebuffer.clone().map(FileOrDir::File);
+        }
+        let mut block_device_files = self.block_device_files.lock();
+        if let Some((_, file)) = block_device_files.iter().find(|(n, _)| n == name) {
+            return Some(FileOrDir::File(file.clone()));
+        }
+        let device = storage_manager::find_storage_device(name)?;
+        let file = Arc::new(Mutex::new(BlockDeviceFile::new(name.to_string(), parent, device))) as FileRef;
+        block_device_files.push((name.to_string(), file.clone()));
+        Some(FileOrDir::File(file))
+    }
+
+    /// Returns the names of all device files, starting with those of the storage devices.
+    fn list(&self) -> Vec<String> {
+        let mut children: Vec<String> = storage_manager::named_storage_devices().into_iter()
+            .map(|(name, _device)| name)
+            .collect();
+        children.push(String::from(SERIAL_PORT_FILE_NAME));
+        if self.framebuffer.is_some() {
+            children.push(String::from(FRAMEBUFFER_FILE_NAME));
+        }
+        children
+    }
+
+    fn remove(&mut self, _node: &FileOrDir) -> Option<FileOrDir> {
+        None
+    }
+}
+
+
+/// Returns the metadata of a device file of the given `kind` and `size`, which has no timestamps.
+fn device_metadata(kind: NodeKind, size: usize) -> Metadata {
+    Metadata {
+        kind,
+        size,
+        created: None,
+        modified: None,
+        accessed: None,
+        permissions: Permissions::read_write(),
+    }
+}
+
+
+/// A block device file that reads and writes the contents of a storage device or partition.
+pub struct BlockDeviceFile {
+    name: String,
+    parent: WeakDirRef,
+    /// The size in bytes of the storage device.
+    size: usize,
+    /// Reads and writes need mutable access to the `BlockIo`, but `File::read()` only has immutable access to this file.
+    block_io: Mutex<BlockIo>,
+}
+
+impl BlockDeviceFile {
+    fn new(name: String, parent: WeakDirRef, device: StorageDeviceRef) -> BlockDeviceFile {
+        let size = {
+            let locked_device = device.lock();
+            locked_device.size_in_sectors() * locked_device.sector_size_in_bytes()
+        };
+        BlockDeviceFile {
+            name,
+            parent,
+            size,
+            block_io: Mutex::new(BlockIo::new(device)),
+        }
+    }
+}
+
+impl File for BlockDeviceFile {
+    fn read(&self, buffer: &mut [u8], offset: usize) -> Result<usize, &'static str> {
+        if offset > self.size {
+            return Err("read offset exceeds device size");
+        }
+        let read_bytes = core::cmp::min(self.size - offset, buffer.len());
+        if read_bytes == 0 {
+            return Ok(0);
+        }
+        self.block_io.lock().read(&mut buffer[..read_bytes], offset)
+    }
+
+    fn write(&mut self, buffer: &[u8], offset: usize) -> Result<usize, &'static str> {
+        let end = offset.checked_add(buffer.len()).ok_or("write offset is too large")?;
+        if end > self.size {
+            return Err("write extends past the end of the device");
+        }
+        if buffer.is_empty() {
+            return Ok(0);
+        }
+        self.block_io.lock().write(buffer, offset)
+    }
+
+    fn size(&self) -> usize {
+        self.size
+    }
+
+    fn as_mapping(&self) -> Result<&MappedPages, &'static str> {
+        Err("cannot treat a storage device as a memory mapped region")
+    }
+
+    fn metadata(&self) -> Metadata {
+        device_metadata(NodeKind::BlockDevice, self.size)
+    }
+}
+
+impl FsNode for BlockDeviceFile {
+    fn get_name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn get_parent_dir(&self) -> Option<DirRef> {
+        self.parent.upgrade()
+    }
+
+    fn set_parent_dir(&mut self, _new_parent: WeakDirRef) {
+        // do nothing
+    }
+}
+
+
+/// A character device file for the COM1 serial port, which ignores offsets.
+///
+/// Reading it never blocks: it returns the bytes that the serial port has already received, if any.
+pub struct SerialPortFile {
+    parent: WeakDirRef,
+}
+
+impl File for SerialPortFile {
+    fn read(&self, buffer: &mut [u8], _offset: usize) -> Result<usize, &'static str> {
+        Ok(serial_port::read_bytes(buffer))
+    }
+
+    fn write(&mut self, buffer: &[u8], _offset: usize) -> Result<usize, &'static str> {
+        serial_port::write_bytes(buffer);
+        Ok(buffer.len())
+    }
+
+    fn size(&self) -> usize {
+        0
+    }
+
+    fn as_mapping(&self) -> Result<&MappedPages, &'static str> {
+        Err("cannot treat a serial port as a memory mapped region")
+    }
+
+    fn metadata(&self) -> Metadata {
+        device_metadata(NodeKind::CharDevice, 0)
+    }
+}
+
+impl FsNode for SerialPortFile {
+    fn get_name(&self) -> String {
+        String::from(SERIAL_PORT_FILE_NAME)
+    }
+
+    fn get_parent_dir(&self) -> Option<DirRef> {
+        self.parent.upgrade()
+    }
+
+    fn set_parent_dir(&mut self, _new_parent: WeakDirRef) {
+        // do nothing
+    }
+}
+
+
+/// A file that holds the pixels on the screen, i.e., the memory of the VESA display device,
+/// which is mapped separately from the window manager's final framebuffer.
+///
+/// Each pixel is an `AlphaPixel`, and the pixels are laid out row by row.
+pub struct FramebufferFile {
+    parent: WeakDirRef,
+    mapped_pages: MappedPages,
+    /// The size in bytes of the screen's pixels, which may be less than the size of the `mapped_pages`.
+    size: usize,
+}
+
+impl FramebufferFile {
+    fn new(parent: WeakDirRef) -> Result<FramebufferFile, &'static str> {
+        let (mapped_pages, size) = framebuffer::map_final_buffer::<AlphaPixel>()?;
+        Ok(FramebufferFile {
+            parent,
+            mapped_pages,
+            size,
+        })
+    }
+}
+
+impl File for FramebufferFile {
+    fn read(&self, buffer: &mut [u8], offset: usize) -> Result<usize, &'static str> {
+        if offset > self.size {
+            return Err("read offset exceeds framebuffer size");
+        }
+        let read_bytes = core::cmp::min(self.size - offset, buffer.len());
+        buffer[..read_bytes].copy_from_slice(self.mapped_pages.as_slice(offset, read_bytes)?);
+        Ok(read_bytes)
+    }
+
+    fn write(&mut self, buffer: &[u8], offset: usize) -> Result<usize, &'static str> {
+        if offset > self.size {
+            return Err("write offset exceeds framebuffer size");
+        }
+        // the screen can't grow, so anything beyond its end is not written
+        let write_bytes = core::cmp::min(self.size - offset, buffer.len());
+        self.mapped_pages.as_slice_mut(offset, write_bytes)?.copy_from_slice(&buffer[..write_bytes]);
+        Ok(write_bytes)
+    }
+
+    fn size(&self) -> usize {
+        self.size
+    }
+
+    fn as_mapping(&self) -> Result<&MappedPages, &'static str> {
+        Ok(&self.mapped_pages)
+    }
+
+    fn metadata(&self) -> Metadata {
+        device_metadata(NodeKind::CharDevice, self.size)
+    }
+}
+
+impl FsNode for FramebufferFile {
+    fn get_name(&self) -> String {
+        String::from(FRAMEBUFFER_FILE_NAME)
+    }
+
+    fn get_parent_dir(&self) -> Option<DirRef> {
+        self.parent.upgrade()
+    }
+
+    fn set_parent_dir(&mut self, _new_parent: WeakDirRef) {
+        // do nothing
+    }
+}
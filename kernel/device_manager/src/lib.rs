@@ -4,6 +4,8 @@
 #[macro_use] extern crate log;
 extern crate event_types;
 extern crate e1000;
+extern crate virtio;
+extern crate virtio_net;
 extern crate memory;
 extern crate apic;
 extern crate acpi;
@@ -23,16 +25,6 @@ use ethernet_smoltcp_device::EthernetNetworkInterface;
 use network_manager::add_to_network_interfaces;
 
 
-/// A randomly chosen IP address that must be outside of the DHCP range.. // TODO FIXME: use DHCP to acquire IP
-const DEFAULT_LOCAL_IP: &'static str = "10.0.2.15/24"; // the default QEMU user-slirp network gives IP addresses of "10.0.2.*"
-// const DEFAULT_LOCAL_IP: &'static str = "192.168.1.252/24"; // home router reserved IP
-// const DEFAULT_LOCAL_IP: &'static str = "10.42.0.91/24"; // rice net IP
-
-/// Standard home router address. // TODO FIXME: use DHCP to acquire gateway IP
-const DEFAULT_GATEWAY_IP: [u8; 4] = [10, 0, 2, 2]; // the default QEMU user-slirp networking gateway IP
-// const DEFAULT_GATEWAY_IP: [u8; 4] = [192, 168, 1, 1]; // the default gateway for our TAP-based bridge
-// const DEFAULT_GATEWAY_IP: [u8; 4] = [10, 42, 0, 1]; // rice net gateway ip
-
 /// This is for early-stage initialization of things like VGA, ACPI, (IO)APIC, etc.
 pub fn early_init(kernel_mmi: &mut MemoryManagementInfo) -> Result<(), &'static str> {
     // First, initialize the local apic info.
@@ -82,10 +74,17 @@ pub fn init(key_producer: Queue<Event>, mouse_producer: Queue<Event>) -> Result<
             if dev.vendor_id == e1000::INTEL_VEND && dev.device_id == e1000::E1000_DEV {
                 info!("e1000 PCI device found at: {:?}", dev.location);
                 let e1000_nic_ref = e1000::E1000Nic::init(dev)?;
-                let e1000_interface = EthernetNetworkInterface::new_ipv4_interface(e1000_nic_ref, DEFAULT_LOCAL_IP, &DEFAULT_GATEWAY_IP)?;
+                let e1000_interface = EthernetNetworkInterface::new_dhcp_interface(e1000_nic_ref)?;
                 add_to_network_interfaces(e1000_interface);
                 continue;
             }
+            if virtio::device_type(dev) == Some(virtio::DEVICE_TYPE_NETWORK) {
+                info!("virtio network PCI device found at: {:?}", dev.location);
+                let virtio_net_nic_ref = virtio_net::VirtioNetNic::init(dev)?;
+                let virtio_net_interface = EthernetNetworkInterface::new_dhcp_interface(virtio_net_nic_ref)?;
+                add_to_network_interfaces(virtio_net_interface);
+                continue;
+            }
             // here: check for and initialize other ethernet cards
         }
 
@@ -0,0 +1,474 @@
+//! A DHCP client (RFC 2131) that acquires an IPv4 address for each network interface,
+//! along with its subnet mask, default gateway and DNS servers, and renews that lease before it expires.
+//!
+//! [`init()`](fn.init.html) spawns one client task per network interface that doesn't have a static IP address.
+//! Such an interface starts out with the unspecified address `0.0.0.0/0` as a placeholder,
+//! which the client replaces with the leased address once the server acknowledges it.
+//!
+//! Each task goes through the usual DHCP states:
+//! * **Selecting**: broadcast a DISCOVER and wait for an OFFER,
+//! * **Requesting**: broadcast a REQUEST for the offered address and wait for an ACK (or a NAK, which restarts selection),
+//! * **Bound**: sleep until the renewal time (T1),
+//! * **Renewing**: unicast a REQUEST to the server that granted the lease, until the rebinding time (T2),
+//! * **Rebinding**: broadcast a REQUEST to any server, until the lease expires and the client starts over.
+//!
+//! # Note
+//! There is no demultiplexing of received packets across different `SocketSet`s,
+//! so while a DHCP exchange is in progress, the client polls the interface with its own `SocketSet`
+//! and packets destined for other sockets may be dropped.
+//! This only happens at boot and for a few seconds whenever a lease is renewed.
+
+#![no_std]
+
+#[macro_use] extern crate log;
+#[macro_use] extern crate alloc;
+#[macro_use] extern crate lazy_static;
+extern crate spin;
+extern crate smoltcp;
+extern crate network_manager;
+extern crate hpet;
+#[macro_use] extern crate smoltcp_helper;
+extern crate sleep;
+extern crate spawn;
+
+mod message;
+mod packet;
+
+pub use message::{DhcpMessage, MessageType};
+
+use core::cmp::{max, min};
+use core::time::Duration;
+use alloc::vec::Vec;
+use spin::Mutex;
+use hpet::get_hpet;
+use smoltcp::{
+    socket::{SocketSet, RawSocket, RawSocketBuffer, RawPacketMetadata},
+    wire::{EthernetAddress, IpAddress, IpCidr, IpProtocol, IpVersion, Ipv4Address, Ipv4Cidr},
+};
+use network_manager::{NetworkInterfaceRef, NETWORK_INTERFACES};
+use smoltcp_helper::poll_iface;
+use sleep::{Instant, sleep, sleep_until};
+use message::{CLIENT_PORT, SERVER_PORT};
+
+
+/// How often the interface is polled while waiting for a reply from a server.
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+/// How long to wait for a reply to the first DISCOVER or REQUEST, which doubles after each unanswered attempt.
+const INITIAL_RETRANSMIT_TIMEOUT: Duration = Duration::from_secs(4);
+/// The upper bound on the time to wait for a reply while acquiring a lease.
+const MAX_RETRANSMIT_TIMEOUT: Duration = Duration::from_secs(64);
+/// The minimum time between two attempts to renew or rebind a lease.
+const MIN_RENEWAL_RETRY: Duration = Duration::from_secs(60);
+
+/// The maximum size of a packet that the client's raw socket can send or receive.
+const MAX_PACKET_SIZE: usize = 1500;
+/// The number of received packets that the client's raw socket can buffer.
+const RECEIVE_BUFFER_PACKETS: usize = 4;
+
+lazy_static! {
+    /// The current lease of each network interface that has one, keyed by the interface's Ethernet address.
+    static ref LEASES: Mutex<Vec<(EthernetAddress, Lease)>> = Mutex::new(Vec::new());
+}
+
+
+/// An IPv4 address lease granted by a DHCP server, along with the other configuration it provided.
+#[derive(Debug, Clone)]
+pub struct Lease {
+    /// The leased IP address and the prefix length of its subnet.
+    pub address: Ipv4Cidr,
+    /// The default gateway, if the server provided one.
+    pub router: Option<Ipv4Address>,
+    /// The DNS servers, in order of preference.
+    pub dns_servers: Vec<Ipv4Address>,
+    /// The server that granted this lease.
+    pub server: Ipv4Address,
+    /// When the lease was acquired, i.e., when the request for it was sent.
+    pub acquired: Instant,
+    /// How long after `acquired` the lease should be renewed with the server that granted it (T1).
+    pub renewal_time: Duration,
+    /// How long after `acquired` the lease should be rebound with any server (T2).
+    pub rebinding_time: Duration,
+    /// How long after `acquired` the lease expires.
+    pub lease_time: Duration,
+}
+
+impl Lease {
+    /// Creates a lease from the given ACK message, in reply to a request sent at the given time.
+    ///
+    /// If the ACK doesn't identify its server, e.g., when renewing a lease, the given `fallback_server` is used instead.
+    fn from_ack(ack: &DhcpMessage, acquired: Instant, fallback_server: Option<Ipv4Address>) -> Result<Lease, &'static str> {
+        let server = ack.server_id.or(fallback_server).ok_or("dhcp_client: ACK did not contain a server identifier")?;
+        let lease_time = ack.lease_time.ok_or("dhcp_client: ACK did not contain a lease time")?;
+        // By default, T1 is half of the lease time and T2 is 7/8 of the lease time.
+        let renewal_time = ack.renewal_time.unwrap_or(lease_time / 2);
+        let rebinding_time = ack.rebinding_time.unwrap_or((lease_time as u64 * 7 / 8) as u32);
+        let prefix_len = ack.subnet_mask
+            .map(|mask| u32::from_be_bytes(mask.0).count_ones() as u8)
+            .unwrap_or_else(|| default_prefix_len(ack.yiaddr));
+
+        Ok(Lease {
+            address: Ipv4Cidr::new(ack.yiaddr, prefix_len),
+            router: ack.router,
+            dns_servers: ack.dns_servers.clone(),
+            server,
+            acquired,
+            renewal_time: Duration::from_secs(renewal_time as u64),
+            rebinding_time: Duration::from_secs(rebinding_time as u64),
+            lease_time: Duration::from_secs(lease_time as u64),
+        })
+    }
+
+    /// Returns the time at which this lease should be renewed.
+    pub fn renew_at(&self) -> Instant {
+        self.acquired + self.renewal_time
+    }
+
+    /// Returns the time at which this lease should be rebound.
+    pub fn rebind_at(&self) -> Instant {
+        self.acquired + self.rebinding_time
+    }
+
+    /// Returns the time at which this lease expires.
+    pub fn expires_at(&self) -> Instant {
+        self.acquired + self.lease_time
+    }
+}
+
+
+/// Spawns a DHCP client task for each network interface that doesn't yet have an IPv4 address,
+/// i.e., one that was created without a static IP address.
+pub fn init() -> Result<(), &'static str> {
+    let ifaces: Vec<NetworkInterfaceRef> = NETWORK_INTERFACES.lock().iter().cloned().collect();
+    for iface in ifaces {
+        let (needs_dhcp, mac) = {
+            let iface_locked = iface.lock();
+            (!has_ipv4_address(iface_locked.ip_addrs()), iface_locked.ethernet_addr())
+        };
+        if needs_dhcp {
+            spawn::new_task_builder(dhcp_task, iface)
+                .name(format!("dhcp_client_{}", mac))
+                .spawn()?;
+        }
+    }
+    Ok(())
+}
+
+/// Returns the current lease of the given network interface, if it has one.
+pub fn get_lease(iface: &NetworkInterfaceRef) -> Option<Lease> {
+    let mac = iface.lock().ethernet_addr();
+    LEASES.lock().iter()
+        .find(|(addr, _)| *addr == mac)
+        .map(|(_, lease)| lease.clone())
+}
+
+/// Returns the DNS servers provided by the DHCP servers of all network interfaces, without duplicates.
+pub fn dns_servers() -> Vec<Ipv4Address> {
+    let mut servers: Vec<Ipv4Address> = Vec::new();
+    for (_, lease) in LEASES.lock().iter() {
+        for server in &lease.dns_servers {
+            if !servers.contains(server) {
+                servers.push(*server);
+            }
+        }
+    }
+    servers
+}
+
+/// The entry point of a DHCP client task, which acquires a lease for the given network interface
+/// and keeps renewing it forever, acquiring a new lease whenever the current one is lost.
+///
+/// Errors while acquiring or renewing a lease, e.g., failing to send a message, may be transient,
+/// so they are logged and the failed step is retried after a delay that doubles after each consecutive error.
+pub fn dhcp_task(iface: NetworkInterfaceRef) -> Result<(), &'static str> {
+    let client = DhcpClient::new(iface);
+    let mut lease: Option<Lease> = None;
+    let mut retry_delay = INITIAL_RETRANSMIT_TIMEOUT;
+    loop {
+        let result = match lease {
+            None => client.acquire().map(Some),
+            Some(ref current) => sleep_until(current.renew_at()).and_then(|_| client.renew(current)),
+        };
+        match result {
+            Ok(Some(new_lease)) => {
+                lease = Some(new_lease);
+                retry_delay = INITIAL_RETRANSMIT_TIMEOUT;
+            }
+            Ok(None) => {
+                if let Some(lost) = lease.take() {
+                    warn!("dhcp_client: lost lease for {} on interface {}", lost.address, client.mac);
+                    client.unconfigure(&lost);
+                }
+            }
+            Err(e) => {
+                warn!("dhcp_client: error on interface {}, retrying in {:?}: {}", client.mac, retry_delay, e);
+                sleep(retry_delay)?;
+                retry_delay = min(retry_delay * 2, MAX_RETRANSMIT_TIMEOUT);
+            }
+        }
+    }
+}
+
+
+/// A DHCP client for a single network interface.
+pub struct DhcpClient {
+    iface: NetworkInterfaceRef,
+    mac: EthernetAddress,
+}
+
+impl DhcpClient {
+    /// Creates a new DHCP client for the given network interface.
+    pub fn new(iface: NetworkInterfaceRef) -> DhcpClient {
+        let mac = iface.lock().ethernet_addr();
+        DhcpClient { iface, mac }
+    }
+
+    /// Acquires a new lease by going through the selecting and requesting states,
+    /// retrying until a server grants a usable lease, and then configures the interface with it.
+    pub fn acquire(&self) -> Result<Lease, &'static str> {
+        let mut timeout = INITIAL_RETRANSMIT_TIMEOUT;
+        loop {
+            let xid = self.new_xid()?;
+            let mut discover = DhcpMessage::new(MessageType::Discover, xid, self.mac);
+            discover.broadcast = true;
+            let offer = self.transact(&discover, Ipv4Address::UNSPECIFIED, Ipv4Address::BROADCAST, timeout,
+                |reply| reply.message_type == MessageType::Offer,
+            )?;
+            let offer = match offer {
+                Some(offer) => offer,
+                None => {
+                    debug!("dhcp_client: no DHCP offer received on interface {} after {:?}, retrying", self.mac, timeout);
+                    timeout = min(timeout * 2, MAX_RETRANSMIT_TIMEOUT);
+                    continue;
+                }
+            };
+            let server = match offer.server_id {
+                Some(server) => server,
+                None => {
+                    warn!("dhcp_client: ignoring DHCP offer of {} without a server identifier", offer.yiaddr);
+                    continue;
+                }
+            };
+
+            let mut request = DhcpMessage::new(MessageType::Request, xid, self.mac);
+            request.broadcast = true;
+            request.requested_ip = Some(offer.yiaddr);
+            request.server_id = Some(server);
+            let sent = Instant::now();
+            let reply = self.transact(&request, Ipv4Address::UNSPECIFIED, Ipv4Address::BROADCAST, timeout,
+                |reply| is_ack_or_nak(reply) && reply.server_id == Some(server),
+            )?;
+            match reply {
+                Some(ref ack) if ack.message_type == MessageType::Ack => match Lease::from_ack(ack, sent, Some(server)) {
+                    Ok(lease) => {
+                        self.configure(&lease)?;
+                        return Ok(lease);
+                    }
+                    // An unusable ACK is treated as if no reply was received.
+                    Err(e) => {
+                        warn!("dhcp_client: ignoring unusable DHCP ACK from server {}: {}", server, e);
+                        timeout = min(timeout * 2, MAX_RETRANSMIT_TIMEOUT);
+                    }
+                },
+                Some(_nak) => warn!("dhcp_client: server {} declined the request for {}", server, offer.yiaddr),
+                None => timeout = min(timeout * 2, MAX_RETRANSMIT_TIMEOUT),
+            }
+        }
+    }
+
+    /// Renews the given `lease` with the server that granted it until the rebinding time,
+    /// and then with any server until the lease expires, re-configuring the interface with the renewed lease.
+    ///
+    /// Returns `None` if the lease expired without being renewed or if a server declined to renew it.
+    pub fn renew(&self, lease: &Lease) -> Result<Option<Lease>, &'static str> {
+        let address = lease.address.address();
+        loop {
+            let now = Instant::now();
+            if now >= lease.expires_at() {
+                return Ok(None);
+            }
+            let rebinding = now >= lease.rebind_at();
+            let (dst, next_state_at) = if rebinding {
+                (Ipv4Address::BROADCAST, lease.expires_at())
+            } else {
+                (lease.server, lease.rebind_at())
+            };
+
+            let mut request = DhcpMessage::new(MessageType::Request, self.new_xid()?, self.mac);
+            request.ciaddr = address;
+            let reply = self.transact(&request, address, dst, INITIAL_RETRANSMIT_TIMEOUT, is_ack_or_nak)?;
+            match reply {
+                Some(ref ack) if ack.message_type == MessageType::Ack => match Lease::from_ack(ack, now, Some(lease.server)) {
+                    Ok(renewed) => {
+                        self.configure(&renewed)?;
+                        return Ok(Some(renewed));
+                    }
+                    // An unusable ACK is treated as if no reply was received.
+                    Err(e) => warn!("dhcp_client: ignoring unusable DHCP ACK to renew {}: {}", address, e),
+                },
+                Some(_nak) => return Ok(None),
+                None => { }
+            }
+
+            // Wait half of the remaining time until the next state before retrying, down to a minimum.
+            let retry_at = now + max((next_state_at - now) / 2, MIN_RENEWAL_RETRY);
+            sleep_until(min(retry_at, next_state_at))?;
+        }
+    }
+
+    /// Sends the given DHCP message from the `src` address to the `dst` address,
+    /// and then waits up to `timeout` for a reply from a server with the same transaction ID
+    /// for which the given `is_reply` function returns `true`.
+    fn transact<F: Fn(&DhcpMessage) -> bool>(
+        &self,
+        msg: &DhcpMessage,
+        src: Ipv4Address,
+        dst: Ipv4Address,
+        timeout: Duration,
+        is_reply: F,
+    ) -> Result<Option<DhcpMessage>, &'static str> {
+        let rx_buffer = RawSocketBuffer::new(
+            vec![RawPacketMetadata::EMPTY; RECEIVE_BUFFER_PACKETS],
+            vec![0; RECEIVE_BUFFER_PACKETS * MAX_PACKET_SIZE],
+        );
+        let tx_buffer = RawSocketBuffer::new(vec![RawPacketMetadata::EMPTY], vec![0; MAX_PACKET_SIZE]);
+        let raw_socket = RawSocket::new(IpVersion::Ipv4, IpProtocol::Udp, rx_buffer, tx_buffer);
+        let mut sockets = SocketSet::new(vec![]);
+        let handle = sockets.add(raw_socket);
+
+        let packet = packet::build_udp_packet(src, dst, CLIENT_PORT, SERVER_PORT, &msg.emit());
+        sockets.get::<RawSocket>(handle).send_slice(&packet).map_err(|_e| {
+            error!("dhcp_client: couldn't send DHCP {:?} message: {:?}", msg.message_type, _e);
+            "dhcp_client: couldn't send DHCP message"
+        })?;
+
+        let startup_time = hpet_ticks!();
+        let deadline = Instant::now() + timeout;
+        loop {
+            poll_iface(&self.iface, &mut sockets, startup_time)?;
+            {
+                let mut socket = sockets.get::<RawSocket>(handle);
+                while let Ok(received) = socket.recv() {
+                    if let Some(reply) = self.parse_reply(received, msg.xid) {
+                        if is_reply(&reply) {
+                            return Ok(Some(reply));
+                        }
+                    }
+                }
+            }
+            if Instant::now() >= deadline {
+                return Ok(None);
+            }
+            sleep(POLL_INTERVAL)?;
+        }
+    }
+
+    /// Parses the given received IPv4 `packet` as a DHCP reply to this client with the given transaction ID.
+    fn parse_reply(&self, packet: &[u8], xid: u32) -> Option<DhcpMessage> {
+        let datagram = packet::parse_udp_packet(packet)?;
+        if datagram.src_port != SERVER_PORT || datagram.dst_port != CLIENT_PORT {
+            return None;
+        }
+        let reply = match DhcpMessage::parse(datagram.payload) {
+            Ok(reply) => reply,
+            Err(e) => {
+                debug!("dhcp_client: ignoring invalid DHCP message from {}: {}", datagram.src_addr, e);
+                return None;
+            }
+        };
+        if reply.xid != xid || reply.chaddr != self.mac {
+            return None;
+        }
+        Some(reply)
+    }
+
+    /// Configures the interface with the address and default gateway of the given `lease`.
+    fn configure(&self, lease: &Lease) -> Result<(), &'static str> {
+        {
+            let mut iface = self.iface.lock();
+            if !set_ipv4_address(&mut *iface, lease.address) {
+                return Err("dhcp_client: network interface has no IPv4 address to configure");
+            }
+            if let Some(router) = lease.router {
+                iface.routes_mut().add_default_ipv4_route(router).map_err(|_e| {
+                    error!("dhcp_client: couldn't set default gateway {}: {:?}", router, _e);
+                    "dhcp_client: couldn't set default gateway"
+                })?;
+            }
+        }
+
+        info!("dhcp_client: interface {} acquired address {} from server {} (gateway: {:?}, DNS servers: {:?}) for {:?}",
+            self.mac, lease.address, lease.server, lease.router, lease.dns_servers, lease.lease_time,
+        );
+        let mut leases = LEASES.lock();
+        leases.retain(|(mac, _)| *mac != self.mac);
+        leases.push((self.mac, lease.clone()));
+        Ok(())
+    }
+
+    /// Removes the address of the given `lease` from the interface, replacing it with the unspecified address,
+    /// along with the default route through the lease's gateway, if any.
+    fn unconfigure(&self, lease: &Lease) {
+        {
+            let mut iface = self.iface.lock();
+            set_ipv4_address(&mut *iface, Ipv4Cidr::new(Ipv4Address::UNSPECIFIED, 0));
+            if let Some(router) = lease.router {
+                let default_route = IpCidr::new(IpAddress::v4(0, 0, 0, 0), 0);
+                iface.routes_mut().update(|routes| {
+                    // Don't remove a default route that was since set to a different gateway.
+                    if routes.get(&default_route).map_or(false, |route| route.via_router == IpAddress::Ipv4(router)) {
+                        routes.remove(&default_route);
+                    }
+                });
+            }
+        }
+        LEASES.lock().retain(|(mac, _)| *mac != self.mac);
+    }
+
+    /// Returns a new transaction ID, which should differ across clients and across reboots.
+    fn new_xid(&self) -> Result<u32, &'static str> {
+        let ticks = hpet_ticks!();
+        let mac = self.mac.0;
+        Ok((ticks as u32) ^ u32::from_be_bytes([mac[2], mac[3], mac[4], mac[5]]))
+    }
+}
+
+
+/// Returns `true` if the given message is an ACK or a NAK.
+fn is_ack_or_nak(msg: &DhcpMessage) -> bool {
+    msg.message_type == MessageType::Ack || msg.message_type == MessageType::Nak
+}
+
+/// Returns `true` if any of the given IP addresses is a specified IPv4 address.
+fn has_ipv4_address(addrs: &[IpCidr]) -> bool {
+    addrs.iter().any(|addr| match addr {
+        IpCidr::Ipv4(cidr) => !cidr.address().is_unspecified(),
+        _ => false,
+    })
+}
+
+/// Replaces the first IPv4 address of the given interface with `address`.
+/// Returns `false` if the interface has no IPv4 address to replace.
+fn set_ipv4_address(iface: &mut dyn network_manager::NetworkInterface, address: Ipv4Cidr) -> bool {
+    let mut replaced = false;
+    iface.update_ip_addrs(&mut |addrs| {
+        let existing = addrs.iter_mut().find(|addr| match addr {
+            IpCidr::Ipv4(_) => true,
+            _ => false,
+        });
+        if let Some(existing) = existing {
+            *existing = IpCidr::Ipv4(address);
+            replaced = true;
+        }
+    });
+    replaced
+}
+
+/// Returns the prefix length of the classful network of the given address,
+/// for use when a server doesn't provide a subnet mask.
+fn default_prefix_len(addr: Ipv4Address) -> u8 {
+    match addr.0[0] {
+        0 ..= 127 => 8,
+        128 ..= 191 => 16,
+        _ => 24,
+    }
+}
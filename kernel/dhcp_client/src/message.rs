@@ -0,0 +1,289 @@
+//! Encoding and decoding of DHCP messages (RFC 2131), which are BOOTP messages (RFC 951)
+//! whose `options` field starts with the DHCP magic cookie and contains a DHCP message type option (RFC 2132).
+//!
+//! Only the options that this client sends or uses are supported; all other options are ignored when parsing.
+
+use alloc::vec::Vec;
+use smoltcp::wire::{EthernetAddress, Ipv4Address};
+
+/// The UDP port that DHCP servers listen on.
+pub const SERVER_PORT: u16 = 67;
+/// The UDP port that DHCP clients listen on.
+pub const CLIENT_PORT: u16 = 68;
+
+const OP_BOOTREQUEST: u8 = 1;
+const OP_BOOTREPLY: u8 = 2;
+const HTYPE_ETHERNET: u8 = 1;
+const FLAG_BROADCAST: u16 = 0x8000;
+
+/// The size of the fixed-length BOOTP header that precedes the magic cookie and options.
+const HEADER_SIZE: usize = 236;
+/// The offset of the client hardware address field (`chaddr`), which is 16 bytes long.
+const CHADDR_OFFSET: usize = 28;
+const MAGIC_COOKIE: [u8; 4] = [99, 130, 83, 99];
+/// Some older servers drop BOOTP messages that are shorter than this.
+const MIN_MESSAGE_SIZE: usize = 300;
+
+const OPT_PAD: u8 = 0;
+const OPT_SUBNET_MASK: u8 = 1;
+const OPT_ROUTER: u8 = 3;
+const OPT_DNS_SERVER: u8 = 6;
+const OPT_REQUESTED_IP: u8 = 50;
+const OPT_LEASE_TIME: u8 = 51;
+const OPT_MESSAGE_TYPE: u8 = 53;
+const OPT_SERVER_ID: u8 = 54;
+const OPT_PARAMETER_REQUEST_LIST: u8 = 55;
+const OPT_RENEWAL_TIME: u8 = 58;
+const OPT_REBINDING_TIME: u8 = 59;
+const OPT_CLIENT_ID: u8 = 61;
+const OPT_END: u8 = 255;
+
+/// The options that a client asks the server to include in its replies.
+const REQUESTED_PARAMETERS: [u8; 6] = [
+    OPT_SUBNET_MASK, OPT_ROUTER, OPT_DNS_SERVER, OPT_LEASE_TIME, OPT_RENEWAL_TIME, OPT_REBINDING_TIME,
+];
+
+
+/// The type of a DHCP message, as given in the DHCP message type option.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MessageType {
+    Discover,
+    Offer,
+    Request,
+    Decline,
+    Ack,
+    Nak,
+    Release,
+    Inform,
+}
+
+impl MessageType {
+    fn from_u8(value: u8) -> Option<MessageType> {
+        match value {
+            1 => Some(MessageType::Discover),
+            2 => Some(MessageType::Offer),
+            3 => Some(MessageType::Request),
+            4 => Some(MessageType::Decline),
+            5 => Some(MessageType::Ack),
+            6 => Some(MessageType::Nak),
+            7 => Some(MessageType::Release),
+            8 => Some(MessageType::Inform),
+            _ => None,
+        }
+    }
+
+    fn to_u8(self) -> u8 {
+        match self {
+            MessageType::Discover => 1,
+            MessageType::Offer    => 2,
+            MessageType::Request  => 3,
+            MessageType::Decline  => 4,
+            MessageType::Ack      => 5,
+            MessageType::Nak      => 6,
+            MessageType::Release  => 7,
+            MessageType::Inform   => 8,
+        }
+    }
+
+    /// Returns `true` if messages of this type are sent by a client, `false` if they are sent by a server.
+    fn is_from_client(self) -> bool {
+        match self {
+            MessageType::Offer | MessageType::Ack | MessageType::Nak => false,
+            _ => true,
+        }
+    }
+}
+
+
+/// A DHCP message, containing the BOOTP header fields and DHCP options that this client cares about.
+#[derive(Debug, Clone)]
+pub struct DhcpMessage {
+    /// The type of this message.
+    pub message_type: MessageType,
+    /// The transaction ID chosen by the client, which the server echoes in its replies.
+    pub xid: u32,
+    /// The seconds elapsed since the client began acquiring or renewing a lease.
+    pub secs: u16,
+    /// Whether the client asks the server to broadcast its replies,
+    /// because it cannot yet receive unicast IP packets.
+    pub broadcast: bool,
+    /// The client's current IP address, only used when the client is renewing or rebinding a lease.
+    pub ciaddr: Ipv4Address,
+    /// The IP address that the server offers or assigns to the client.
+    pub yiaddr: Ipv4Address,
+    /// The hardware address of the client.
+    pub chaddr: EthernetAddress,
+    /// The IP address that the client requests, when selecting an offer.
+    pub requested_ip: Option<Ipv4Address>,
+    /// The IP address that identifies the server.
+    pub server_id: Option<Ipv4Address>,
+    /// The subnet mask of the assigned IP address.
+    pub subnet_mask: Option<Ipv4Address>,
+    /// The default gateway, i.e., the first router listed by the server.
+    pub router: Option<Ipv4Address>,
+    /// The DNS servers, in order of preference.
+    pub dns_servers: Vec<Ipv4Address>,
+    /// The duration of the lease, in seconds.
+    pub lease_time: Option<u32>,
+    /// The time after which the client should renew its lease (T1), in seconds since the lease was acquired.
+    pub renewal_time: Option<u32>,
+    /// The time after which the client should rebind its lease (T2), in seconds since the lease was acquired.
+    pub rebinding_time: Option<u32>,
+}
+
+impl DhcpMessage {
+    /// Creates a new client message of the given type with all optional fields empty.
+    pub fn new(message_type: MessageType, xid: u32, chaddr: EthernetAddress) -> DhcpMessage {
+        DhcpMessage {
+            message_type,
+            xid,
+            secs: 0,
+            broadcast: false,
+            ciaddr: Ipv4Address::UNSPECIFIED,
+            yiaddr: Ipv4Address::UNSPECIFIED,
+            chaddr,
+            requested_ip: None,
+            server_id: None,
+            subnet_mask: None,
+            router: None,
+            dns_servers: Vec::new(),
+            lease_time: None,
+            renewal_time: None,
+            rebinding_time: None,
+        }
+    }
+
+    /// Encodes this message into its wire format, i.e., the payload of a UDP datagram.
+    ///
+    /// Client messages also include the client identifier and parameter request list options.
+    pub fn emit(&self) -> Vec<u8> {
+        let mut buf = vec![0u8; HEADER_SIZE];
+        buf[0] = if self.message_type.is_from_client() { OP_BOOTREQUEST } else { OP_BOOTREPLY };
+        buf[1] = HTYPE_ETHERNET;
+        buf[2] = self.chaddr.0.len() as u8;
+        buf[4..8].copy_from_slice(&self.xid.to_be_bytes());
+        buf[8..10].copy_from_slice(&self.secs.to_be_bytes());
+        let flags = if self.broadcast { FLAG_BROADCAST } else { 0 };
+        buf[10..12].copy_from_slice(&flags.to_be_bytes());
+        buf[12..16].copy_from_slice(&self.ciaddr.0);
+        buf[16..20].copy_from_slice(&self.yiaddr.0);
+        buf[CHADDR_OFFSET .. CHADDR_OFFSET + 6].copy_from_slice(&self.chaddr.0);
+        buf.extend_from_slice(&MAGIC_COOKIE);
+
+        buf.extend_from_slice(&[OPT_MESSAGE_TYPE, 1, self.message_type.to_u8()]);
+        if self.message_type.is_from_client() {
+            buf.extend_from_slice(&[OPT_CLIENT_ID, 7, HTYPE_ETHERNET]);
+            buf.extend_from_slice(&self.chaddr.0);
+        }
+        emit_address_option(&mut buf, OPT_REQUESTED_IP, self.requested_ip);
+        emit_address_option(&mut buf, OPT_SERVER_ID, self.server_id);
+        emit_address_option(&mut buf, OPT_SUBNET_MASK, self.subnet_mask);
+        emit_address_option(&mut buf, OPT_ROUTER, self.router);
+        if !self.dns_servers.is_empty() {
+            buf.extend_from_slice(&[OPT_DNS_SERVER, (self.dns_servers.len() * 4) as u8]);
+            for server in &self.dns_servers {
+                buf.extend_from_slice(&server.0);
+            }
+        }
+        emit_time_option(&mut buf, OPT_LEASE_TIME, self.lease_time);
+        emit_time_option(&mut buf, OPT_RENEWAL_TIME, self.renewal_time);
+        emit_time_option(&mut buf, OPT_REBINDING_TIME, self.rebinding_time);
+        if self.message_type.is_from_client() {
+            buf.extend_from_slice(&[OPT_PARAMETER_REQUEST_LIST, REQUESTED_PARAMETERS.len() as u8]);
+            buf.extend_from_slice(&REQUESTED_PARAMETERS);
+        }
+        buf.push(OPT_END);
+
+        if buf.len() < MIN_MESSAGE_SIZE {
+            buf.resize(MIN_MESSAGE_SIZE, OPT_PAD);
+        }
+        buf
+    }
+
+    /// Parses a DHCP message from the given `payload` of a UDP datagram.
+    pub fn parse(payload: &[u8]) -> Result<DhcpMessage, &'static str> {
+        if payload.len() < HEADER_SIZE + MAGIC_COOKIE.len() {
+            return Err("dhcp_client: message is too short");
+        }
+        if payload[1] != HTYPE_ETHERNET || payload[2] != 6 {
+            return Err("dhcp_client: message is not for an Ethernet hardware address");
+        }
+        if payload[HEADER_SIZE .. HEADER_SIZE + MAGIC_COOKIE.len()] != MAGIC_COOKIE {
+            return Err("dhcp_client: message has an invalid magic cookie");
+        }
+
+        let mut message_type = None;
+        let mut msg = DhcpMessage::new(
+            MessageType::Discover,
+            read_u32(&payload[4..8]),
+            EthernetAddress::from_bytes(&payload[CHADDR_OFFSET .. CHADDR_OFFSET + 6]),
+        );
+        msg.secs = u16::from_be_bytes([payload[8], payload[9]]);
+        msg.broadcast = u16::from_be_bytes([payload[10], payload[11]]) & FLAG_BROADCAST != 0;
+        msg.ciaddr = Ipv4Address::from_bytes(&payload[12..16]);
+        msg.yiaddr = Ipv4Address::from_bytes(&payload[16..20]);
+
+        let mut options = &payload[HEADER_SIZE + MAGIC_COOKIE.len() ..];
+        while let Some((&kind, rest)) = options.split_first() {
+            match kind {
+                OPT_PAD => {
+                    options = rest;
+                    continue;
+                }
+                OPT_END => break,
+                _ => { }
+            }
+            let (&len, rest) = rest.split_first().ok_or("dhcp_client: option is missing its length")?;
+            let len = len as usize;
+            if rest.len() < len {
+                return Err("dhcp_client: option extends past the end of the message");
+            }
+            let (data, rest) = rest.split_at(len);
+            options = rest;
+
+            match (kind, len) {
+                (OPT_MESSAGE_TYPE, 1)   => message_type = MessageType::from_u8(data[0]),
+                (OPT_SUBNET_MASK, 4)    => msg.subnet_mask = Some(Ipv4Address::from_bytes(data)),
+                (OPT_REQUESTED_IP, 4)   => msg.requested_ip = Some(Ipv4Address::from_bytes(data)),
+                (OPT_SERVER_ID, 4)      => msg.server_id = Some(Ipv4Address::from_bytes(data)),
+                (OPT_LEASE_TIME, 4)     => msg.lease_time = Some(read_u32(data)),
+                (OPT_RENEWAL_TIME, 4)   => msg.renewal_time = Some(read_u32(data)),
+                (OPT_REBINDING_TIME, 4) => msg.rebinding_time = Some(read_u32(data)),
+                (OPT_ROUTER, _) if len >= 4 => msg.router = Some(Ipv4Address::from_bytes(&data[..4])),
+                (OPT_DNS_SERVER, _) => {
+                    msg.dns_servers = data.chunks(4)
+                        .filter(|chunk| chunk.len() == 4)
+                        .map(Ipv4Address::from_bytes)
+                        .collect();
+                }
+                _ => { } // ignore all other options
+            }
+        }
+
+        msg.message_type = message_type.ok_or("dhcp_client: message has no valid message type option")?;
+        let expected_op = if msg.message_type.is_from_client() { OP_BOOTREQUEST } else { OP_BOOTREPLY };
+        if payload[0] != expected_op {
+            return Err("dhcp_client: message's BOOTP op code doesn't match its DHCP message type");
+        }
+        Ok(msg)
+    }
+}
+
+
+fn emit_address_option(buf: &mut Vec<u8>, kind: u8, addr: Option<Ipv4Address>) {
+    if let Some(addr) = addr {
+        buf.extend_from_slice(&[kind, 4]);
+        buf.extend_from_slice(&addr.0);
+    }
+}
+
+fn emit_time_option(buf: &mut Vec<u8>, kind: u8, seconds: Option<u32>) {
+    if let Some(seconds) = seconds {
+        buf.extend_from_slice(&[kind, 4]);
+        buf.extend_from_slice(&seconds.to_be_bytes());
+    }
+}
+
+fn read_u32(bytes: &[u8]) -> u32 {
+    u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
@@ -0,0 +1,137 @@
+//! Building and parsing of the IPv4 and UDP headers around a DHCP message.
+//!
+//! A DHCP client must send and receive UDP datagrams before its interface has an IP address,
+//! which smoltcp's UDP sockets cannot do, so we use a raw IPv4 socket and handle these headers ourselves.
+
+use alloc::vec::Vec;
+use smoltcp::wire::Ipv4Address;
+
+const IPV4_HEADER_SIZE: usize = 20;
+const UDP_HEADER_SIZE: usize = 8;
+const IPV4_VERSION: u8 = 4;
+const PROTOCOL_UDP: u8 = 17;
+const DEFAULT_TTL: u8 = 64;
+
+
+/// A UDP datagram received in an IPv4 packet.
+pub struct UdpDatagram<'a> {
+    pub src_addr: Ipv4Address,
+    pub dst_addr: Ipv4Address,
+    pub src_port: u16,
+    pub dst_port: u16,
+    pub payload: &'a [u8],
+}
+
+/// Builds an IPv4 packet containing a UDP datagram with the given `payload`, including valid checksums.
+pub fn build_udp_packet(
+    src_addr: Ipv4Address,
+    dst_addr: Ipv4Address,
+    src_port: u16,
+    dst_port: u16,
+    payload: &[u8],
+) -> Vec<u8> {
+    let udp_len = UDP_HEADER_SIZE + payload.len();
+    let total_len = IPV4_HEADER_SIZE + udp_len;
+    let mut packet = vec![0u8; total_len];
+
+    {
+        let ip = &mut packet[..IPV4_HEADER_SIZE];
+        ip[0] = (IPV4_VERSION << 4) | (IPV4_HEADER_SIZE / 4) as u8;
+        ip[2..4].copy_from_slice(&(total_len as u16).to_be_bytes());
+        ip[8] = DEFAULT_TTL;
+        ip[9] = PROTOCOL_UDP;
+        ip[12..16].copy_from_slice(&src_addr.0);
+        ip[16..20].copy_from_slice(&dst_addr.0);
+        let checksum = checksum(0, ip);
+        ip[10..12].copy_from_slice(&checksum.to_be_bytes());
+    }
+
+    {
+        let udp = &mut packet[IPV4_HEADER_SIZE..];
+        udp[0..2].copy_from_slice(&src_port.to_be_bytes());
+        udp[2..4].copy_from_slice(&dst_port.to_be_bytes());
+        udp[4..6].copy_from_slice(&(udp_len as u16).to_be_bytes());
+        udp[UDP_HEADER_SIZE..].copy_from_slice(payload);
+        let mut checksum = checksum(pseudo_header_sum(src_addr, dst_addr, udp_len), udp);
+        // a checksum of zero means "no checksum", so it is sent as all ones instead
+        if checksum == 0 {
+            checksum = 0xffff;
+        }
+        udp[6..8].copy_from_slice(&checksum.to_be_bytes());
+    }
+
+    packet
+}
+
+/// Parses the given IPv4 `packet` and returns the UDP datagram it contains.
+///
+/// Returns `None` if the packet is malformed, is fragmented, doesn't contain a UDP datagram,
+/// or if either of its checksums is invalid.
+pub fn parse_udp_packet(packet: &[u8]) -> Option<UdpDatagram> {
+    if packet.len() < IPV4_HEADER_SIZE || packet[0] >> 4 != IPV4_VERSION {
+        return None;
+    }
+    let header_len = ((packet[0] & 0x0F) as usize) * 4;
+    let total_len = u16::from_be_bytes([packet[2], packet[3]]) as usize;
+    if header_len < IPV4_HEADER_SIZE || total_len < header_len + UDP_HEADER_SIZE || total_len > packet.len() {
+        return None;
+    }
+    let more_fragments = packet[6] & 0x20 != 0;
+    let fragment_offset = u16::from_be_bytes([packet[6] & 0x1F, packet[7]]);
+    if more_fragments || fragment_offset != 0 || packet[9] != PROTOCOL_UDP {
+        return None;
+    }
+    if checksum(0, &packet[..header_len]) != 0 {
+        return None;
+    }
+    let src_addr = Ipv4Address::from_bytes(&packet[12..16]);
+    let dst_addr = Ipv4Address::from_bytes(&packet[16..20]);
+
+    let udp = &packet[header_len .. total_len];
+    let udp_len = u16::from_be_bytes([udp[4], udp[5]]) as usize;
+    if udp_len < UDP_HEADER_SIZE || udp_len > udp.len() {
+        return None;
+    }
+    let udp = &udp[..udp_len];
+    let has_checksum = udp[6] != 0 || udp[7] != 0;
+    if has_checksum && checksum(pseudo_header_sum(src_addr, dst_addr, udp_len), udp) != 0 {
+        return None;
+    }
+
+    Some(UdpDatagram {
+        src_addr,
+        dst_addr,
+        src_port: u16::from_be_bytes([udp[0], udp[1]]),
+        dst_port: u16::from_be_bytes([udp[2], udp[3]]),
+        payload: &udp[UDP_HEADER_SIZE..],
+    })
+}
+
+
+/// Returns the (not yet folded) sum of the IPv4 pseudo-header used in the UDP checksum.
+fn pseudo_header_sum(src_addr: Ipv4Address, dst_addr: Ipv4Address, udp_len: usize) -> u32 {
+    let mut pseudo_header = [0u8; 12];
+    pseudo_header[0..4].copy_from_slice(&src_addr.0);
+    pseudo_header[4..8].copy_from_slice(&dst_addr.0);
+    pseudo_header[9] = PROTOCOL_UDP;
+    pseudo_header[10..12].copy_from_slice(&(udp_len as u16).to_be_bytes());
+    sum_words(0, &pseudo_header)
+}
+
+/// Computes the Internet checksum (RFC 1071) of the given `data`, starting from the given `initial` sum.
+/// Verifying data that includes a valid checksum yields zero.
+fn checksum(initial: u32, data: &[u8]) -> u16 {
+    let mut sum = sum_words(initial, data);
+    while sum >> 16 != 0 {
+        sum = (sum & 0xFFFF) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+/// Adds the given `data` as big-endian 16-bit words to the given `sum`, padding an odd last byte with zero.
+fn sum_words(sum: u32, data: &[u8]) -> u32 {
+    data.chunks(2).fold(sum, |sum, word| {
+        let word = if word.len() == 2 { u16::from_be_bytes([word[0], word[1]]) } else { (word[0] as u16) << 8 };
+        sum + word as u32
+    })
+}
@@ -0,0 +1,322 @@
+//! A DNS stub resolver, which translates host names into IP addresses
+//! by sending recursive queries over UDP to a nameserver, using a smoltcp UDP socket.
+//!
+//! A host name is resolved by checking the following, in order:
+//! 1. the static hosts table, which initially maps `localhost` to the loopback addresses
+//!    and can be changed with [`add_host()`](fn.add_host.html) and [`remove_host()`](fn.remove_host.html),
+//! 2. the cache of previous answers, each of which is kept for its time-to-live (but no longer than an hour),
+//! 3. the nameservers, which can be set with [`set_nameservers()`](fn.set_nameservers.html).
+//!    If none are set, the DNS servers provided by DHCP are used instead.
+//!
+//! Only A records (IPv4 addresses) and AAAA records (IPv6 addresses) can be queried.
+
+#![no_std]
+
+#[macro_use] extern crate log;
+#[macro_use] extern crate alloc;
+#[macro_use] extern crate lazy_static;
+extern crate spin;
+extern crate rand;
+extern crate smoltcp;
+extern crate network_manager;
+extern crate hpet;
+#[macro_use] extern crate smoltcp_helper;
+extern crate sleep;
+extern crate dhcp_client;
+
+mod message;
+
+pub use message::RecordType;
+
+use core::str::FromStr;
+use core::time::Duration;
+use alloc::{
+    collections::BTreeMap,
+    string::String,
+    vec::Vec,
+};
+use spin::Mutex;
+use rand::{
+    SeedableRng,
+    RngCore,
+    rngs::SmallRng
+};
+use hpet::get_hpet;
+use smoltcp::{
+    socket::{SocketSet, UdpSocket, UdpSocketBuffer, UdpPacketMetadata},
+    wire::{IpAddress, IpEndpoint, Ipv4Address, Ipv6Address},
+};
+use network_manager::NetworkInterfaceRef;
+use smoltcp_helper::{STARTING_FREE_PORT, poll_iface};
+use sleep::{Instant, sleep};
+use message::{Response, ResponseCode};
+
+
+/// The UDP port that nameservers listen on.
+pub const DNS_PORT: u16 = 53;
+
+/// How long to wait for a response from a nameserver before trying the next one.
+const QUERY_TIMEOUT: Duration = Duration::from_secs(2);
+/// How many times each nameserver is queried before giving up.
+const QUERY_ATTEMPTS: usize = 2;
+/// How often the interface is polled while waiting for a response.
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+/// The maximum size of a DNS message sent over UDP.
+const MAX_MESSAGE_SIZE: usize = 512;
+
+/// The maximum number of answers kept in the cache.
+const MAX_CACHE_ENTRIES: usize = 64;
+/// The maximum time that an answer is kept in the cache, regardless of its time-to-live.
+const MAX_CACHE_TTL: Duration = Duration::from_secs(60 * 60);
+
+lazy_static! {
+    /// The nameservers that queries are sent to, in order of preference.
+    static ref NAMESERVERS: Mutex<Vec<IpAddress>> = Mutex::new(Vec::new());
+
+    /// The static hosts table, which maps a host name to its addresses without querying a nameserver.
+    static ref HOSTS: Mutex<BTreeMap<String, Vec<IpAddress>>> = {
+        let mut hosts = BTreeMap::new();
+        hosts.insert(
+            String::from("localhost"),
+            vec![IpAddress::Ipv4(Ipv4Address::new(127, 0, 0, 1)), IpAddress::Ipv6(Ipv6Address::LOOPBACK)],
+        );
+        Mutex::new(hosts)
+    };
+
+    /// The cache of answers from nameservers, keyed by the queried host name and record type.
+    static ref CACHE: Mutex<BTreeMap<(String, RecordType), CacheEntry>> = Mutex::new(BTreeMap::new());
+}
+
+/// An answer from a nameserver that is kept in the cache until it expires.
+struct CacheEntry {
+    addresses: Vec<IpAddress>,
+    expires_at: Instant,
+}
+
+
+/// Sets the nameservers that queries are sent to, in order of preference.
+/// If the given list is empty, the DNS servers provided by DHCP are used.
+pub fn set_nameservers(nameservers: Vec<IpAddress>) {
+    *NAMESERVERS.lock() = nameservers;
+}
+
+/// Returns the nameservers that queries are currently sent to, in order of preference.
+pub fn nameservers() -> Vec<IpAddress> {
+    let nameservers = NAMESERVERS.lock().clone();
+    if !nameservers.is_empty() {
+        return nameservers;
+    }
+    dhcp_client::dns_servers().into_iter().map(IpAddress::Ipv4).collect()
+}
+
+/// Adds the given `address` for the host `name` to the static hosts table.
+pub fn add_host(name: &str, address: IpAddress) {
+    let mut hosts = HOSTS.lock();
+    let addresses = hosts.entry(normalize_name(name)).or_insert_with(Vec::new);
+    if !addresses.contains(&address) {
+        addresses.push(address);
+    }
+}
+
+/// Removes the host `name` from the static hosts table, returning its addresses, if it was present.
+pub fn remove_host(name: &str) -> Option<Vec<IpAddress>> {
+    HOSTS.lock().remove(&normalize_name(name))
+}
+
+/// Removes all answers from the cache, such that subsequent lookups will query a nameserver.
+pub fn clear_cache() {
+    CACHE.lock().clear();
+}
+
+
+/// Resolves the given host `name` into its IP addresses, preferring IPv4 addresses
+/// and only looking up IPv6 addresses if the name has no IPv4 addresses.
+///
+/// A `name` that is already an IP address is returned as is.
+///
+/// # Arguments
+/// * `iface`: the network interface used to query the nameservers.
+/// * `name`: the host name to resolve, e.g., `example.com`.
+pub fn resolve(iface: &NetworkInterfaceRef, name: &str) -> Result<Vec<IpAddress>, &'static str> {
+    if let Ok(address) = IpAddress::from_str(name) {
+        return Ok(vec![address]);
+    }
+    let ipv4_addresses = lookup(iface, name, RecordType::A)?;
+    if !ipv4_addresses.is_empty() {
+        return Ok(ipv4_addresses);
+    }
+    let ipv6_addresses = lookup(iface, name, RecordType::Aaaa)?;
+    if ipv6_addresses.is_empty() {
+        return Err("dns_resolver: host name has no addresses");
+    }
+    Ok(ipv6_addresses)
+}
+
+/// Looks up the addresses of the given type for the host `name`,
+/// using the hosts table, the cache or the nameservers, in that order.
+///
+/// Returns an empty list if the name exists but has no addresses of the given type.
+pub fn lookup(iface: &NetworkInterfaceRef, name: &str, record_type: RecordType) -> Result<Vec<IpAddress>, &'static str> {
+    let name = normalize_name(name);
+    if let Some(addresses) = HOSTS.lock().get(&name) {
+        let addresses: Vec<IpAddress> = addresses.iter()
+            .filter(|addr| is_of_type(addr, record_type))
+            .cloned()
+            .collect();
+        if !addresses.is_empty() {
+            return Ok(addresses);
+        }
+    }
+    if let Some(addresses) = get_cached(&name, record_type) {
+        return Ok(addresses);
+    }
+
+    let nameservers = nameservers();
+    if nameservers.is_empty() {
+        return Err("dns_resolver: no nameservers are configured");
+    }
+    let mut rng = SmallRng::seed_from_u64(hpet_ticks!());
+    let mut error = "dns_resolver: no response from any nameserver";
+    for _attempt in 0 .. QUERY_ATTEMPTS {
+        for nameserver in &nameservers {
+            match query(iface, &mut rng, *nameserver, &name, record_type) {
+                Ok(Some(response)) => match response.code {
+                    ResponseCode::NoError => {
+                        if response.truncated {
+                            warn!("dns_resolver: response from {} for {:?} was truncated", nameserver, name);
+                        }
+                        insert_cached(name, record_type, &response);
+                        return Ok(response.addresses);
+                    }
+                    ResponseCode::NameError => return Err("dns_resolver: no such host name"),
+                    code => {
+                        warn!("dns_resolver: nameserver {} couldn't answer the query for {:?}: {:?}", nameserver, name, code);
+                        error = "dns_resolver: nameserver couldn't answer the query";
+                    }
+                },
+                Ok(None) => debug!("dns_resolver: query to nameserver {} for {:?} timed out", nameserver, name),
+                Err(e) => {
+                    warn!("dns_resolver: couldn't query nameserver {} for {:?}: {}", nameserver, name, e);
+                    error = e;
+                }
+            }
+        }
+    }
+    Err(error)
+}
+
+
+/// Sends a query for the records of the given type for the host `name` to the given `nameserver`,
+/// and waits for its response. Returns `None` if no response arrived in time.
+fn query(
+    iface: &NetworkInterfaceRef,
+    rng: &mut SmallRng,
+    nameserver: IpAddress,
+    name: &str,
+    record_type: RecordType,
+) -> Result<Option<Response>, &'static str> {
+    let id = rng.next_u32() as u16;
+    let local_port = STARTING_FREE_PORT + (rng.next_u32() as u16 % (u16::max_value() - STARTING_FREE_PORT));
+    let query = message::encode_query(id, name, record_type)?;
+
+    let rx_buffer = UdpSocketBuffer::new(vec![UdpPacketMetadata::EMPTY; 4], vec![0; 4 * MAX_MESSAGE_SIZE]);
+    let tx_buffer = UdpSocketBuffer::new(vec![UdpPacketMetadata::EMPTY], vec![0; MAX_MESSAGE_SIZE]);
+    let mut sockets = SocketSet::new(vec![]);
+    let udp_handle = sockets.add(UdpSocket::new(rx_buffer, tx_buffer));
+    let remote_endpoint = IpEndpoint::new(nameserver, DNS_PORT);
+    {
+        let mut socket = sockets.get::<UdpSocket>(udp_handle);
+        socket.bind(local_port).map_err(|_e| {
+            error!("dns_resolver: couldn't bind UDP socket to port {}: {:?}", local_port, _e);
+            "dns_resolver: couldn't bind UDP socket"
+        })?;
+        socket.send_slice(&query, remote_endpoint).map_err(|_e| {
+            error!("dns_resolver: couldn't send query to {}: {:?}", remote_endpoint, _e);
+            "dns_resolver: couldn't send query"
+        })?;
+    }
+
+    let startup_time = hpet_ticks!();
+    let deadline = Instant::now() + QUERY_TIMEOUT;
+    loop {
+        poll_iface(iface, &mut sockets, startup_time)?;
+        {
+            let mut socket = sockets.get::<UdpSocket>(udp_handle);
+            while let Ok((data, source)) = socket.recv() {
+                if source != remote_endpoint {
+                    continue;
+                }
+                match message::parse_response(data, record_type) {
+                    Ok(ref response) if response.id != id => { }
+                    Ok(response) => return Ok(Some(response)),
+                    Err(e) => warn!("dns_resolver: ignoring invalid response from {}: {}", source, e),
+                }
+            }
+        }
+        if Instant::now() >= deadline {
+            return Ok(None);
+        }
+        sleep(POLL_INTERVAL)?;
+    }
+}
+
+/// Returns the cached addresses of the given type for the host `name`, if they haven't expired.
+fn get_cached(name: &str, record_type: RecordType) -> Option<Vec<IpAddress>> {
+    let mut cache = CACHE.lock();
+    let key = (String::from(name), record_type);
+    let expired = match cache.get(&key) {
+        Some(entry) if Instant::now() < entry.expires_at => return Some(entry.addresses.clone()),
+        Some(_) => true,
+        None => false,
+    };
+    if expired {
+        cache.remove(&key);
+    }
+    None
+}
+
+/// Caches the addresses in the given `response` for their time-to-live.
+/// If the cache is full, expired answers and then the answer that expires soonest are evicted.
+fn insert_cached(name: String, record_type: RecordType, response: &Response) {
+    if response.addresses.is_empty() || response.ttl == 0 {
+        return;
+    }
+    let now = Instant::now();
+    let ttl = core::cmp::min(Duration::from_secs(response.ttl as u64), MAX_CACHE_TTL);
+    let mut cache = CACHE.lock();
+    if cache.len() >= MAX_CACHE_ENTRIES {
+        let expired: Vec<(String, RecordType)> = cache.iter()
+            .filter(|(_, entry)| entry.expires_at <= now)
+            .map(|(key, _)| key.clone())
+            .collect();
+        for key in expired {
+            cache.remove(&key);
+        }
+    }
+    if cache.len() >= MAX_CACHE_ENTRIES {
+        let soonest = cache.iter()
+            .min_by_key(|(_, entry)| entry.expires_at)
+            .map(|(key, _)| key.clone());
+        if let Some(key) = soonest {
+            cache.remove(&key);
+        }
+    }
+    cache.insert((name, record_type), CacheEntry {
+        addresses: response.addresses.clone(),
+        expires_at: now + ttl,
+    });
+}
+
+/// Host names are case-insensitive and may end with a dot, so we store them in lowercase without it.
+fn normalize_name(name: &str) -> String {
+    name.trim_end_matches('.').to_ascii_lowercase()
+}
+
+/// Returns `true` if the given address is of the kind that the given record type holds.
+fn is_of_type(address: &IpAddress, record_type: RecordType) -> bool {
+    match (address, record_type) {
+        (IpAddress::Ipv4(_), RecordType::A) => true,
+        (IpAddress::Ipv6(_), RecordType::Aaaa) => true,
+        _ => false,
+    }
+}
@@ -0,0 +1,191 @@
+//! Encoding of DNS queries and parsing of DNS responses (RFC 1035),
+//! supporting only what a stub resolver needs: one question per query,
+//! and answers with A records (IPv4 addresses) or AAAA records (IPv6 addresses, RFC 3596).
+
+use alloc::vec::Vec;
+use smoltcp::wire::{IpAddress, Ipv4Address, Ipv6Address};
+
+const HEADER_SIZE: usize = 12;
+/// The flag in the header that marks a message as a response rather than a query.
+const FLAG_RESPONSE: u16 = 0x8000;
+/// The flag in the header that asks the nameserver to resolve the query recursively.
+const FLAG_RECURSION_DESIRED: u16 = 0x0100;
+/// The flag in the header that marks a response as truncated, i.e., it didn't fit into a UDP datagram.
+const FLAG_TRUNCATED: u16 = 0x0200;
+const RCODE_MASK: u16 = 0x000F;
+
+const CLASS_IN: u16 = 1;
+const TYPE_A: u16 = 1;
+const TYPE_AAAA: u16 = 28;
+
+const MAX_LABEL_LEN: usize = 63;
+const MAX_NAME_LEN: usize = 253;
+/// The top two bits of a length byte that mark it as the start of a compression pointer rather than a label.
+const POINTER_MASK: u8 = 0xC0;
+
+
+/// The type of DNS record to query for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum RecordType {
+    /// An IPv4 address.
+    A,
+    /// An IPv6 address.
+    Aaaa,
+}
+
+impl RecordType {
+    fn to_u16(self) -> u16 {
+        match self {
+            RecordType::A => TYPE_A,
+            RecordType::Aaaa => TYPE_AAAA,
+        }
+    }
+}
+
+/// The response code of a DNS response.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResponseCode {
+    NoError,
+    FormatError,
+    ServerFailure,
+    /// The queried domain name does not exist.
+    NameError,
+    NotImplemented,
+    Refused,
+    Other(u8),
+}
+
+impl ResponseCode {
+    fn from_u8(value: u8) -> ResponseCode {
+        match value {
+            0 => ResponseCode::NoError,
+            1 => ResponseCode::FormatError,
+            2 => ResponseCode::ServerFailure,
+            3 => ResponseCode::NameError,
+            4 => ResponseCode::NotImplemented,
+            5 => ResponseCode::Refused,
+            other => ResponseCode::Other(other),
+        }
+    }
+}
+
+/// The parts of a DNS response that a stub resolver cares about.
+#[derive(Debug, Clone)]
+pub struct Response {
+    /// The ID of the query that this is a response to.
+    pub id: u16,
+    /// Whether the nameserver successfully answered the query.
+    pub code: ResponseCode,
+    /// Whether the response was truncated, in which case `addresses` may be incomplete.
+    pub truncated: bool,
+    /// The addresses in all answer records of the queried type.
+    pub addresses: Vec<IpAddress>,
+    /// The smallest time-to-live of those answer records, in seconds.
+    pub ttl: u32,
+}
+
+
+/// Encodes a recursive query with the given `id` for the records of the given type for the domain `name`.
+pub fn encode_query(id: u16, name: &str, record_type: RecordType) -> Result<Vec<u8>, &'static str> {
+    let name = name.trim_end_matches('.');
+    if name.is_empty() || name.len() > MAX_NAME_LEN {
+        return Err("dns_resolver: invalid domain name length");
+    }
+
+    let mut buf = Vec::with_capacity(HEADER_SIZE + name.len() + 6);
+    buf.extend_from_slice(&id.to_be_bytes());
+    buf.extend_from_slice(&FLAG_RECURSION_DESIRED.to_be_bytes());
+    buf.extend_from_slice(&1u16.to_be_bytes()); // one question
+    buf.extend_from_slice(&[0; 6]); // no answer, authority or additional records
+
+    for label in name.split('.') {
+        if label.is_empty() || label.len() > MAX_LABEL_LEN {
+            return Err("dns_resolver: invalid label length in domain name");
+        }
+        buf.push(label.len() as u8);
+        buf.extend_from_slice(label.as_bytes());
+    }
+    buf.push(0);
+    buf.extend_from_slice(&record_type.to_u16().to_be_bytes());
+    buf.extend_from_slice(&CLASS_IN.to_be_bytes());
+    Ok(buf)
+}
+
+/// Parses the given DNS response to a query for records of the given type.
+pub fn parse_response(msg: &[u8], record_type: RecordType) -> Result<Response, &'static str> {
+    if msg.len() < HEADER_SIZE {
+        return Err("dns_resolver: response is too short");
+    }
+    let id = read_u16(msg, 0)?;
+    let flags = read_u16(msg, 2)?;
+    if flags & FLAG_RESPONSE == 0 {
+        return Err("dns_resolver: message is not a response");
+    }
+    let question_count = read_u16(msg, 4)?;
+    let answer_count = read_u16(msg, 6)?;
+
+    let mut offset = HEADER_SIZE;
+    for _ in 0 .. question_count {
+        offset = skip_name(msg, offset)? + 4; // the question's type and class
+    }
+
+    let mut addresses = Vec::new();
+    let mut ttl = u32::max_value();
+    for _ in 0 .. answer_count {
+        offset = skip_name(msg, offset)?;
+        let rtype = read_u16(msg, offset)?;
+        let class = read_u16(msg, offset + 2)?;
+        let record_ttl = read_u32(msg, offset + 4)?;
+        let data_len = read_u16(msg, offset + 8)? as usize;
+        let data_start = offset + 10;
+        let data = msg.get(data_start .. data_start + data_len).ok_or("dns_resolver: record extends past the end of the response")?;
+        offset = data_start + data_len;
+
+        if class != CLASS_IN || rtype != record_type.to_u16() {
+            continue; // e.g., a CNAME record that leads to the requested records
+        }
+        let address = match (record_type, data_len) {
+            (RecordType::A, 4) => IpAddress::Ipv4(Ipv4Address::from_bytes(data)),
+            (RecordType::Aaaa, 16) => IpAddress::Ipv6(Ipv6Address::from_bytes(data)),
+            _ => return Err("dns_resolver: address record has an invalid length"),
+        };
+        addresses.push(address);
+        ttl = core::cmp::min(ttl, record_ttl);
+    }
+
+    Ok(Response {
+        id,
+        code: ResponseCode::from_u8((flags & RCODE_MASK) as u8),
+        truncated: flags & FLAG_TRUNCATED != 0,
+        addresses,
+        ttl: if ttl == u32::max_value() { 0 } else { ttl },
+    })
+}
+
+
+/// Returns the offset just past the (possibly compressed) domain name that starts at `offset`.
+fn skip_name(msg: &[u8], mut offset: usize) -> Result<usize, &'static str> {
+    loop {
+        let len = *msg.get(offset).ok_or("dns_resolver: domain name extends past the end of the response")?;
+        if len & POINTER_MASK == POINTER_MASK {
+            // a pointer to the rest of the name elsewhere in the message, which ends this name
+            return Ok(offset + 2);
+        }
+        if len == 0 {
+            return Ok(offset + 1);
+        }
+        offset += 1 + len as usize;
+    }
+}
+
+fn read_u16(msg: &[u8], offset: usize) -> Result<u16, &'static str> {
+    msg.get(offset .. offset + 2)
+        .map(|b| u16::from_be_bytes([b[0], b[1]]))
+        .ok_or("dns_resolver: response ended unexpectedly")
+}
+
+fn read_u32(msg: &[u8], offset: usize) -> Result<u32, &'static str> {
+    msg.get(offset .. offset + 4)
+        .map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+        .ok_or("dns_resolver: response ended unexpectedly")
+}
@@ -0,0 +1,100 @@
+//! Helpers that are shared by device drivers.
+//!
+//! These include mapping a device's memory-mapped registers, finding them via a PCI device's BARs,
+//! and waiting for a device, either by blocking until its interrupt handler wakes up the current task
+//! or by polling it.
+
+#![no_std]
+
+extern crate irq_safety;
+extern crate memory;
+extern crate pci;
+extern crate pit_clock;
+extern crate wait_queue;
+
+use core::{
+    ops::DerefMut,
+    time::Duration,
+};
+use memory::{EntryFlags, FrameRange, MappedPages, PhysicalAddress, allocate_pages, get_frame_allocator_ref, get_kernel_mmi_ref};
+use pci::PciDevice;
+use wait_queue::{WaitQueue, WaitError};
+
+
+/// The flags used to map a device's registers and the memory that a device accesses directly,
+/// which must not be cached.
+pub const DEVICE_MAPPING_FLAGS: EntryFlags = EntryFlags::from_bits_truncate(
+    EntryFlags::PRESENT.bits() |
+    EntryFlags::WRITABLE.bits() |
+    EntryFlags::NO_CACHE.bits() |
+    EntryFlags::NO_EXECUTE.bits()
+);
+
+/// How often [`poll()`](fn.poll.html) checks its condition, in microseconds.
+const POLL_INTERVAL_MICROS: u32 = 10;
+
+
+/// Returns the physical address that the memory BAR at the given `index` of the given PCI device points to.
+/// A 64-bit address takes up two BARs, the second of which holds its upper 32 bits.
+pub fn bar_address(pci_device: &PciDevice, index: usize) -> Result<PhysicalAddress, &'static str> {
+    let bar_value = *pci_device.bars.get(index).ok_or("driver_utils: invalid BAR index")?;
+    if bar_value & 0x1 != 0 {
+        return Err("driver_utils: expected a memory BAR, found an I/O BAR");
+    }
+    let mut address = (bar_value & !0xF) as usize;
+    // bits 1-2 of a memory BAR give the size of its address
+    if (bar_value >> 1) & 0x3 == 0x2 {
+        let upper = *pci_device.bars.get(index + 1).ok_or("driver_utils: 64-bit BAR is missing its upper half")?;
+        address |= (upper as usize) << 32;
+    }
+    PhysicalAddress::new(address)
+}
+
+/// Maps the given physical memory region of a device, e.g., a region of its registers,
+/// with the [`DEVICE_MAPPING_FLAGS`](constant.DEVICE_MAPPING_FLAGS.html).
+pub fn map_device_memory(phys_addr: PhysicalAddress, size_in_bytes: usize) -> Result<MappedPages, &'static str> {
+    let frames = FrameRange::from_phys_addr(phys_addr, size_in_bytes);
+    let pages = allocate_pages(frames.size_in_frames()).ok_or("driver_utils: couldn't allocate pages for device memory")?;
+    let kernel_mmi_ref = get_kernel_mmi_ref().ok_or("driver_utils: KERNEL_MMI was not yet initialized")?;
+    let fa = get_frame_allocator_ref().ok_or("driver_utils: couldn't get the frame allocator")?;
+    let mapped_pages = kernel_mmi_ref.lock().page_table.map_allocated_pages_to(
+        pages,
+        frames,
+        DEVICE_MAPPING_FLAGS,
+        fa.lock().deref_mut(),
+    )?;
+    Ok(mapped_pages)
+}
+
+/// Calls the given `condition` every few microseconds until it returns `Some`,
+/// giving up and returning `None` after `timeout_ms` milliseconds.
+///
+/// This busy-waits, so it should only be used when a device's interrupts can't be waited for.
+pub fn poll<R, F: FnMut() -> Option<R>>(timeout_ms: u32, mut condition: F) -> Option<R> {
+    let iterations = timeout_ms * (1000 / POLL_INTERVAL_MICROS);
+    for _ in 0 .. iterations {
+        if let Some(result) = condition() {
+            return Some(result);
+        }
+        let _ = pit_clock::pit_wait(POLL_INTERVAL_MICROS);
+    }
+    condition()
+}
+
+/// Waits until the given `condition` returns `Some`, giving up and returning `None` after the given `timeout`.
+///
+/// If `waiters` is given and interrupts are enabled, the current task blocks on it
+/// until the device's interrupt handler wakes it up.
+/// Otherwise, e.g., during early boot when there is no task to block, this [`poll`](fn.poll.html)s the `condition` instead.
+pub fn wait_or_poll<R>(waiters: Option<&WaitQueue>, timeout: Duration, condition: &dyn Fn() -> Option<R>) -> Option<R> {
+    if let Some(waiters) = waiters {
+        if irq_safety::interrupts_enabled() {
+            match waiters.wait_until_timeout(condition, timeout) {
+                Ok(result) => return Some(result),
+                Err(WaitError::Timeout) => return None,
+                Err(_) => { }
+            }
+        }
+    }
+    poll(timeout.as_millis() as u32, condition)
+}
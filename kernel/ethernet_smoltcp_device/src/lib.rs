@@ -21,7 +21,7 @@ use smoltcp::{
     socket::SocketSet,
     time::Instant,
     phy::DeviceCapabilities,
-    wire::{EthernetAddress, IpAddress, IpCidr, Ipv4Address},
+    wire::{EthernetAddress, IpAddress, IpCidr, Ipv4Address, Ipv4Cidr},
     iface::{EthernetInterface, EthernetInterfaceBuilder, NeighborCache, Routes},
 };
 use network_interface_card::NetworkInterfaceCard;
@@ -61,6 +61,10 @@ impl<N: NetworkInterfaceCard + 'static> NetworkInterface for EthernetNetworkInte
         self.iface.has_ip_addr(addr)
     }
 
+    fn update_ip_addrs(&mut self, f: &mut dyn FnMut(&mut [IpCidr])) {
+        self.iface.update_ip_addrs(|addrs| f(&mut **addrs))
+    }
+
     fn routes(&self) -> &Routes<'static> {
         self.iface.routes()
     }
@@ -79,7 +83,8 @@ impl<N: NetworkInterfaceCard + 'static > EthernetNetworkInterface<N> {
     /// * `gateway_ip`: the IP of this network interface's local gateway (access point, router). If `None`, will be discovered via DHCP.
     /// 
     /// # Note
-    /// Currently, `static_ip` and `gateway_ip` are required because we don't yet support DHCP.
+    /// If `static_ip` is `None`, the interface is given the unspecified IPv4 address `0.0.0.0/0` as a placeholder,
+    /// which allows the `dhcp_client` to send packets from it and later replace it with an acquired address.
     /// 
     pub fn new<G: Into<IpAddress>>(
         nic: &'static MutexIrqSafe<N>,
@@ -88,21 +93,22 @@ impl<N: NetworkInterfaceCard + 'static > EthernetNetworkInterface<N> {
     ) -> Result<EthernetNetworkInterface<N>, &'static str> 
     {
         // here, we have to create the iface for the first time because it didn't yet exist
-        let static_ip = static_ip.ok_or("static_ip is currently required because we do not yet support DHCP")?;
-        let ip_addrs = vec![static_ip];
+        let ip_addrs = vec![static_ip.unwrap_or_else(|| IpCidr::Ipv4(Ipv4Cidr::new(Ipv4Address::UNSPECIFIED, 0)))];
 
         let mut routes = Routes::new(BTreeMap::new());
-        let res = match gateway_ip.ok_or("gateway_ip is currently required because we do not yet support DHCP")?.into() {
-            IpAddress::Ipv4(ipv4) => routes.add_default_ipv4_route(ipv4),
-            IpAddress::Ipv6(ipv6) => routes.add_default_ipv6_route(ipv6),
-            _ => {
-                return Err("gateway_ip must be an Ipv4Address or an Ipv6Address");
-            }
-        };
-        res.map_err(|_e| {
-            error!("ethernet_smoltcp_device(): couldn't set default gateway IP address: {:?}", _e);
-            "couldn't set default gateway IP address"
-        })?;
+        if let Some(gateway_ip) = gateway_ip {
+            let res = match gateway_ip.into() {
+                IpAddress::Ipv4(ipv4) => routes.add_default_ipv4_route(ipv4),
+                IpAddress::Ipv6(ipv6) => routes.add_default_ipv6_route(ipv6),
+                _ => {
+                    return Err("gateway_ip must be an Ipv4Address or an Ipv6Address");
+                }
+            };
+            res.map_err(|_e| {
+                error!("ethernet_smoltcp_device(): couldn't set default gateway IP address: {:?}", _e);
+                "couldn't set default gateway IP address"
+            })?;
+        }
 
         let device = EthernetDevice::new(nic);
         let hardware_mac_addr = EthernetAddress(nic.lock().mac_address());
@@ -119,8 +125,8 @@ impl<N: NetworkInterfaceCard + 'static > EthernetNetworkInterface<N> {
         )
     }
 
-    /// Creates a new ethernet network interface with an ipv4 gateway address.
-    /// The static and gateway ips are provided because DHCP is not enabled.
+    /// Creates a new ethernet network interface with a static IP address and an ipv4 gateway address,
+    /// which will not be configured via DHCP.
     /// 
     /// # Arguments
     /// * `nic_ref`: a reference to an initialized Ethernet NIC, which must implement the `NetworkInterfaceCard` trait.
@@ -137,6 +143,18 @@ impl<N: NetworkInterfaceCard + 'static > EthernetNetworkInterface<N> {
 
         Self::new(nic_ref, Some(static_ip), Some(gateway_ip))
     }
+
+    /// Creates a new ethernet network interface without an IP address or a gateway, 
+    /// both of which should be acquired later via DHCP, e.g., by the `dhcp_client`.
+    /// 
+    /// # Arguments
+    /// * `nic_ref`: a reference to an initialized Ethernet NIC, which must implement the `NetworkInterfaceCard` trait.
+    pub fn new_dhcp_interface(
+        nic_ref: &'static MutexIrqSafe<N>,
+    ) -> Result<EthernetNetworkInterface<N>, &'static str> 
+    {
+        Self::new::<Ipv4Address>(nic_ref, None, None)
+    }
 }
 
 
@@ -0,0 +1,676 @@
+//! Read-only support for the ext2 filesystem, built atop a `StorageDevice` via `block_io::BlockIo`.
+//!
+//! An ext2 volume is exposed through the standard `fs_node` traits:
+//! * [`Ext2Directory`](struct.Ext2Directory.html) implements the `Directory` trait,
+//! * [`Ext2File`](struct.Ext2File.html) implements the `File` trait,
+//! * [`Ext2Symlink`](struct.Ext2Symlink.html) implements the `Symlink` trait.
+//!
+//! The root directory of a volume can be mounted on any directory in the VFS tree using [`mount()`](fn.mount.html),
+//! or by name via `mount_table::mount()` once [`init()`](fn.init.html) has registered the `ext2` filesystem type.
+//! Since this driver cannot modify a volume, it must always be mounted read-only.
+//!
+//! Just like in the `fat32` crate, nodes are created lazily when they are first accessed through their parent directory,
+//! after which the parent keeps them cached, such that there is only ever one node object for a given directory entry.
+//!
+//! # Limitations
+//! * Only regular files, directories and symbolic links are supported; device files, FIFOs and sockets are hidden.
+//! * Hard links to the same inode from different directories are represented by different node objects.
+//! * Filesystems that use incompatible ext3/ext4 features, e.g., extents or a journal that needs recovery,
+//!   cannot be mounted.
+
+#![no_std]
+
+#[macro_use] extern crate alloc;
+#[macro_use] extern crate log;
+extern crate spin;
+extern crate memory;
+extern crate fs_node;
+extern crate root;
+extern crate vfs_node;
+extern crate mount_table;
+extern crate block_io;
+extern crate storage_device;
+extern crate storage_manager;
+
+mod structures;
+
+use alloc::{
+    collections::BTreeMap,
+    string::String,
+    sync::{Arc, Weak},
+    vec::Vec,
+};
+use spin::{Mutex, Once};
+use memory::{MappedPages, EntryFlags};
+use fs_node::{DirRef, WeakDirRef, FileRef, SymlinkRef, Directory, File, Symlink, FileOrDir, FsNode, Metadata, NodeKind, Permissions, Timestamp};
+use vfs_node::VFSDirectory;
+use mount_table::{FileSystem, FileSystemRef, FileSystemStats, MountOptions};
+use block_io::BlockIo;
+use storage_device::StorageDeviceRef;
+use structures::*;
+
+
+/// The name under which this filesystem type is registered in the mount table.
+pub const FS_TYPE_NAME: &str = "ext2";
+
+/// The error returned by all operations that would modify an ext2 filesystem.
+const READ_ONLY_ERROR: &str = "ext2: filesystem is read-only";
+
+
+/// Returns `true` if the given storage device contains an ext2 filesystem that this driver can read.
+///
+/// The superblock is read through the block cache, which may hold changes that haven't been written back yet.
+pub fn is_ext2(device: &StorageDeviceRef) -> bool {
+    let mut raw_superblock = [0u8; SUPERBLOCK_SIZE];
+    match BlockIo::new(Arc::clone(device)).read(&mut raw_superblock, SUPERBLOCK_OFFSET) {
+        Ok(SUPERBLOCK_SIZE) => Superblock::parse(&raw_superblock).is_ok(),
+        _ => false,
+    }
+}
+
+/// Mounts the ext2 filesystem on the given storage `device` on top of the given `mountpoint` directory,
+/// and records it in the mount table with the given `source` name.
+///
+/// The `options` must specify a read-only mount.
+///
+/// Returns the root directory of the mounted filesystem.
+pub fn mount(device: StorageDeviceRef, source: &str, mountpoint: &DirRef, options: MountOptions) -> Result<DirRef, &'static str> {
+    let volume = Ext2Volume::new(device, mountpoint, &options)?;
+    mount_table::mount_filesystem(String::from(source), String::from(FS_TYPE_NAME), volume, mountpoint, options)
+}
+
+/// The `MountFn` for the `ext2` filesystem type,
+/// which expects the `source` to be the name of a storage device, e.g., `"disk0"` or `"disk0p1"`.
+fn mount_storage_device(source: &str, mountpoint: &DirRef, options: &MountOptions) -> Result<FileSystemRef, &'static str> {
+    let device = storage_manager::find_storage_device(source).ok_or("ext2: no storage device with that name")?;
+    if !is_ext2(&device) {
+        return Err("ext2: storage device does not contain a supported ext2 filesystem");
+    }
+    Ext2Volume::new(device, mountpoint, options)
+}
+
+/// Registers the `ext2` filesystem type with the mount table,
+/// then finds all storage devices and partitions that contain an ext2 filesystem
+/// and mounts each of them read-only on a new directory in the root directory called `ext2_<N>`.
+///
+/// A failure to mount one device is logged but does not prevent other devices from being mounted.
+pub fn init() -> Result<(), &'static str> {
+    mount_table::register_filesystem_type(FS_TYPE_NAME, mount_storage_device)?;
+
+    let mut count = 0;
+    for (source, device) in storage_manager::named_storage_devices() {
+        if !is_ext2(&device) {
+            continue;
+        }
+        let name = format!("ext2_{}", count);
+        let options = MountOptions { read_only: true, ..Default::default() };
+        let result = VFSDirectory::new(name.clone(), root::get_root())
+            .and_then(|mountpoint| mount(device, &source, &mountpoint, options));
+        match result {
+            Ok(_) => count += 1,
+            Err(e) => error!("ext2::init(): failed to mount ext2 filesystem on {}: {}", source, e),
+        }
+    }
+    Ok(())
+}
+
+
+/// A mounted ext2 volume, as recorded in the mount table.
+pub struct Ext2Volume {
+    fs: Ext2FsRef,
+    root: DirRef,
+}
+
+impl Ext2Volume {
+    /// Creates a new `Ext2Volume` for the given storage `device`,
+    /// whose root directory has the same name and parent directory as the given `mountpoint`.
+    fn new(device: StorageDeviceRef, mountpoint: &DirRef, options: &MountOptions) -> Result<FileSystemRef, &'static str> {
+        if !options.read_only {
+            return Err("ext2: filesystems can only be mounted read-only (use the \"ro\" option)");
+        }
+        let fs = Ext2FileSystem::new(device)?;
+        let (name, parent) = {
+            let locked_mountpoint = mountpoint.lock();
+            let parent = locked_mountpoint.get_parent_dir().ok_or("ext2: mountpoint has no parent directory")?;
+            (locked_mountpoint.get_name(), parent)
+        };
+        let fs_ref = Arc::new(Mutex::new(fs));
+        let root_inode = fs_ref.lock().read_inode(ROOT_INODE)?;
+        if root_inode.kind() != InodeKind::Directory {
+            return Err("ext2: root inode is not a directory");
+        }
+        let root = Ext2Directory::new_node(name, Arc::downgrade(&parent), fs_ref.clone(), root_inode)?;
+        Ok(Arc::new(Ext2Volume {
+            fs: fs_ref,
+            root: root as DirRef,
+        }))
+    }
+}
+
+impl FileSystem for Ext2Volume {
+    fn root(&self) -> DirRef {
+        self.root.clone()
+    }
+
+    fn stats(&self) -> Option<FileSystemStats> {
+        let fs = self.fs.lock();
+        // The per-group free block counts are more up-to-date than the one in the superblock.
+        let free_blocks = fs.groups.iter().map(|g| g.free_blocks_count as usize).sum();
+        Some(FileSystemStats {
+            block_size: fs.superblock.block_size,
+            total_blocks: fs.superblock.blocks_count as usize,
+            free_blocks,
+        })
+    }
+}
+
+
+/// A mounted ext2 filesystem, which holds the volume's geometry
+/// and performs all accesses to inodes and data blocks.
+pub struct Ext2FileSystem {
+    /// The byte-granular I/O interface to the underlying storage device.
+    io: BlockIo,
+    superblock: Superblock,
+    /// The descriptors of all block groups, which locate each group's inode table.
+    groups: Vec<GroupDescriptor>,
+}
+
+/// A reference to a mounted ext2 filesystem, shared by all of its files and directories.
+pub type Ext2FsRef = Arc<Mutex<Ext2FileSystem>>;
+
+impl Ext2FileSystem {
+    /// Reads the superblock and block group descriptors of the given `device`
+    /// and initializes a new `Ext2FileSystem` from them.
+    pub fn new(device: StorageDeviceRef) -> Result<Ext2FileSystem, &'static str> {
+        let device_size = device.lock().size_in_bytes();
+        let mut io = BlockIo::new(device);
+        let mut raw_superblock = [0u8; SUPERBLOCK_SIZE];
+        if io.read(&mut raw_superblock, SUPERBLOCK_OFFSET)? != SUPERBLOCK_SIZE {
+            return Err("couldn't read the ext2 superblock");
+        }
+        let superblock = Superblock::parse(&raw_superblock)?;
+
+        // The group descriptor table starts in the block right after the superblock.
+        // A corrupt block count could make the table huge, so it must fit on the device before it is read.
+        let group_count = superblock.group_count();
+        let table_offset = (superblock.first_data_block as usize + 1) * superblock.block_size;
+        let table_size = group_count * GROUP_DESCRIPTOR_SIZE;
+        if table_offset + table_size > device_size {
+            return Err("ext2: block group descriptor table extends past the end of the storage device");
+        }
+        let mut raw_groups = vec![0u8; table_size];
+        if io.read(&mut raw_groups, table_offset)? != raw_groups.len() {
+            return Err("couldn't read the ext2 block group descriptors");
+        }
+        let groups = raw_groups.chunks_exact(GROUP_DESCRIPTOR_SIZE).map(GroupDescriptor::parse).collect();
+
+        debug!("Found ext2 volume {:?}: {} blocks of {} bytes, {} inodes, {} block groups",
+            superblock.volume_name, superblock.blocks_count, superblock.block_size, superblock.inodes_count, group_count
+        );
+        Ok(Ext2FileSystem { io, superblock, groups })
+    }
+
+    /// Returns the size of one block in bytes.
+    pub fn block_size(&self) -> usize {
+        self.superblock.block_size
+    }
+
+    /// Reads the inode with the given `number` from its block group's inode table.
+    fn read_inode(&mut self, number: u32) -> Result<Inode, &'static str> {
+        if number == 0 || number > self.superblock.inodes_count {
+            return Err("ext2: inode number out of bounds");
+        }
+        let index = (number - 1) as usize;
+        let inodes_per_group = self.superblock.inodes_per_group as usize;
+        let group = self.groups.get(index / inodes_per_group).ok_or("ext2: inode is in a nonexistent block group")?;
+        let offset = self.block_offset(group.inode_table)? + (index % inodes_per_group) * self.superblock.inode_size;
+        let mut raw = vec![0u8; self.superblock.inode_size];
+        if self.io.read(&mut raw, offset)? != raw.len() {
+            return Err("ext2: couldn't read inode");
+        }
+        Ok(Inode::parse(&raw, self.superblock.has_large_files()))
+    }
+
+    /// Returns the byte offset of the given `block`, after checking that it lies within the filesystem.
+    fn block_offset(&self, block: u32) -> Result<usize, &'static str> {
+        if block >= self.superblock.blocks_count {
+            return Err("ext2: block number out of bounds");
+        }
+        Ok(block as usize * self.superblock.block_size)
+    }
+
+    /// Returns the entry at the given `index` of the indirect block `block`,
+    /// or `0` if the indirect block itself is a hole.
+    fn read_indirect(&mut self, block: u32, index: usize) -> Result<u32, &'static str> {
+        if block == 0 {
+            return Ok(0);
+        }
+        let offset = self.block_offset(block)? + index * 4;
+        let mut entry = [0u8; 4];
+        self.io.read(&mut entry, offset)?;
+        Ok(read_u32(&entry, 0))
+    }
+
+    /// Returns the block that holds the data at the given logical block `index` of the given `inode`,
+    /// or `0` if that part of the inode is a hole (i.e., is all zeros).
+    fn block_address(&mut self, inode: &Inode, index: usize) -> Result<u32, &'static str> {
+        let per_block = self.superblock.block_size / 4;
+        let mut index = index;
+        if index < DIRECT_BLOCKS {
+            return Ok(inode.block[index]);
+        }
+        index -= DIRECT_BLOCKS;
+        if index < per_block {
+            return self.read_indirect(inode.block[DIRECT_BLOCKS], index);
+        }
+        index -= per_block;
+        if index < per_block * per_block {
+            let indirect = self.read_indirect(inode.block[DIRECT_BLOCKS + 1], index / per_block)?;
+            return self.read_indirect(indirect, index % per_block);
+        }
+        index -= per_block * per_block;
+        if index < per_block * per_block * per_block {
+            let doubly_indirect = self.read_indirect(inode.block[DIRECT_BLOCKS + 2], index / (per_block * per_block))?;
+            let indirect = self.read_indirect(doubly_indirect, (index / per_block) % per_block)?;
+            return self.read_indirect(indirect, index % per_block);
+        }
+        Err("ext2: block index exceeds the maximum file size")
+    }
+
+    /// Reads the contents of the given `inode` starting at the given byte `offset` into the given `buffer`.
+    ///
+    /// Runs of contiguous blocks are read in a single transfer, and holes are filled with zeros.
+    /// Returns the number of bytes read, which is less than the length of `buffer` if it extends past the end of the inode.
+    fn read_data(&mut self, inode: &Inode, buffer: &mut [u8], offset: usize) -> Result<usize, &'static str> {
+        if !inode.uses_block_pointers() {
+            return Err("ext2: inodes with extents or inline data are not supported");
+        }
+        let size = inode.size as usize;
+        if offset > size {
+            return Err("read offset exceeds file size");
+        }
+        let count = core::cmp::min(size - offset, buffer.len());
+        let block_size = self.superblock.block_size;
+
+        let mut copied = 0;
+        while copied < count {
+            let position = offset + copied;
+            let block = self.block_address(inode, position / block_size)?;
+            let offset_in_block = position % block_size;
+            let mut length = core::cmp::min(block_size - offset_in_block, count - copied);
+            if block == 0 {
+                for b in &mut buffer[copied .. copied + length] {
+                    *b = 0;
+                }
+                copied += length;
+                continue;
+            }
+            // extend the transfer across the following blocks, as long as they are contiguous on disk
+            let mut next_block = block + 1;
+            while copied + length < count {
+                let next_index = (position + length) / block_size;
+                if self.block_address(inode, next_index)? != next_block {
+                    break;
+                }
+                length = core::cmp::min(length + block_size, count - copied);
+                next_block += 1;
+            }
+            let disk_offset = self.block_offset(block)? + offset_in_block;
+            // make sure that the last block of the run lies within the filesystem too
+            self.block_offset(next_block - 1)?;
+            if self.io.read(&mut buffer[copied .. copied + length], disk_offset)? != length {
+                return Err("ext2: couldn't read file data");
+            }
+            copied += length;
+        }
+        Ok(count)
+    }
+
+    /// Reads the target path of the symbolic link with the given `inode`.
+    fn read_symlink_target(&mut self, inode: &Inode) -> Result<String, &'static str> {
+        let size = inode.size as usize;
+        let target = if inode.is_fast_symlink(self.superblock.block_size) {
+            let mut bytes = inode.block_bytes();
+            if size > bytes.len() {
+                return Err("ext2: fast symbolic link is too long");
+            }
+            bytes.truncate(size);
+            bytes
+        } else {
+            // A slow symbolic link's target is stored in a single block, which guards against a corrupt size.
+            if size > self.superblock.block_size || inode.size > u64::from(inode.sectors) * 512 {
+                return Err("ext2: symbolic link target exceeds its allocated block");
+            }
+            let mut data = vec![0u8; size];
+            if self.read_data(inode, &mut data, 0)? != size {
+                return Err("ext2: couldn't read symbolic link target");
+            }
+            data
+        };
+        String::from_utf8(target).map_err(|_| "ext2: symbolic link target is not valid UTF-8")
+    }
+
+    /// Reads all of the entries in the directory with the given `inode`.
+    ///
+    /// If the directory entries don't record the kind of inode they refer to, each inode is read to find out.
+    fn read_dir(&mut self, inode: &Inode) -> Result<Vec<DirEntry>, &'static str> {
+        // A directory can't be larger than the blocks allocated to it, which guards against a corrupt size.
+        if inode.size > u64::from(inode.sectors) * 512 {
+            return Err("ext2: directory size exceeds its allocated blocks");
+        }
+        let size = inode.size as usize;
+        let block_size = self.superblock.block_size;
+        let has_file_types = self.superblock.has_file_types();
+
+        // Directory entries never cross a block boundary, so the directory is read and parsed one block at a time.
+        let mut entries = Vec::new();
+        let mut block = vec![0u8; block_size];
+        let mut offset = 0;
+        while offset < size {
+            let length = core::cmp::min(block_size, size - offset);
+            if self.read_data(inode, &mut block[.. length], offset)? != length {
+                return Err("ext2: couldn't read directory data");
+            }
+            entries.extend(parse_dir_entries(&block[.. length], block_size, has_file_types)?);
+            offset += length;
+        }
+        for entry in entries.iter_mut().filter(|e| e.kind.is_none()) {
+            entry.kind = Some(self.read_inode(entry.inode)?.kind());
+        }
+        Ok(entries)
+    }
+}
+
+
+/// Converts the given number of seconds since the Unix epoch into a calendar date and time (in UTC).
+/// A time of `0` means that the time wasn't recorded.
+fn timestamp_from_unix(seconds: u32) -> Option<Timestamp> {
+    if seconds == 0 {
+        return None;
+    }
+    let days = (seconds / 86_400) as i64;
+    let seconds_of_day = seconds % 86_400;
+    // This converts days since the epoch into a proleptic Gregorian date,
+    // using eras of 400 years that start on March 1st, such that the leap day is the last day of a year.
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let day_of_era = z - era * 146_097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 { month_index + 3 } else { month_index - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    Some(Timestamp {
+        year: year as u16,
+        month: month as u8,
+        day: day as u8,
+        hour: (seconds_of_day / 3600) as u8,
+        minute: (seconds_of_day / 60 % 60) as u8,
+        second: (seconds_of_day % 60) as u8,
+    })
+}
+
+/// Returns the metadata of the given `inode`, which is always read-only.
+fn inode_metadata(inode: &Inode) -> Metadata {
+    let (kind, size) = match inode.kind() {
+        InodeKind::Directory => (NodeKind::Directory, 0),
+        InodeKind::Symlink => (NodeKind::Symlink, inode.size as usize),
+        _ => (NodeKind::File, inode.size as usize),
+    };
+    Metadata {
+        kind,
+        size,
+        // ext2 doesn't record when an inode was created; its `ctime` is when the inode was last changed.
+        created: None,
+        modified: timestamp_from_unix(inode.mtime),
+        accessed: timestamp_from_unix(inode.atime),
+        permissions: Permissions::read_only(),
+    }
+}
+
+
+/// A file, directory or symbolic link node that has been created for an entry in an `Ext2Directory`.
+#[derive(Clone)]
+enum Ext2Node {
+    File(Arc<Mutex<Ext2File>>),
+    Dir(Arc<Mutex<Ext2Directory>>),
+    Symlink(Arc<Mutex<Ext2Symlink>>),
+}
+
+impl Ext2Node {
+    fn as_file_or_dir(&self) -> FileOrDir {
+        match self {
+            Ext2Node::File(f) => FileOrDir::File(f.clone() as FileRef),
+            Ext2Node::Dir(d) => FileOrDir::Dir(d.clone() as DirRef),
+            Ext2Node::Symlink(s) => FileOrDir::Symlink(s.clone() as SymlinkRef),
+        }
+    }
+}
+
+
+/// A directory in an ext2 filesystem.
+pub struct Ext2Directory {
+    /// The name of this directory. For the root directory, this is the name it was mounted with.
+    name: String,
+    /// The directory that contains this directory.
+    parent: WeakDirRef,
+    /// The filesystem that this directory belongs to.
+    fs: Ext2FsRef,
+    /// This directory's inode.
+    inode: Inode,
+    /// A weak reference to this directory itself, used as the parent of its child nodes.
+    self_ref: Weak<Mutex<Ext2Directory>>,
+    /// The entries for regular files, directories and symbolic links in this directory,
+    /// read from disk when this directory was created.
+    entries: Vec<DirEntry>,
+    /// The nodes that have been created for the entries in this directory, keyed by entry name.
+    children: Mutex<BTreeMap<String, Ext2Node>>,
+}
+
+impl Ext2Directory {
+    /// Creates a new directory node for the given directory `inode`, reading in all of its entries.
+    fn new_node(name: String, parent: WeakDirRef, fs: Ext2FsRef, inode: Inode) -> Result<Arc<Mutex<Ext2Directory>>, &'static str> {
+        let mut entries = fs.lock().read_dir(&inode)?;
+        entries.retain(|e| e.kind.is_some() && e.kind != Some(InodeKind::Other));
+        let dir = Arc::new(Mutex::new(Ext2Directory {
+            name,
+            parent,
+            fs,
+            inode,
+            self_ref: Weak::new(),
+            entries,
+            children: Mutex::new(BTreeMap::new()),
+        }));
+        dir.lock().self_ref = Arc::downgrade(&dir);
+        Ok(dir)
+    }
+
+    /// Returns the node for the entry with the given `name`, creating it if necessary.
+    fn node_for_entry(&self, name: &str) -> Result<Option<Ext2Node>, &'static str> {
+        let entry = match self.entries.iter().find(|e| e.name == name) {
+            Some(entry) => entry,
+            None => return Ok(None),
+        };
+        let mut children = self.children.lock();
+        if let Some(node) = children.get(name) {
+            return Ok(Some(node.clone()));
+        }
+
+        let inode = self.fs.lock().read_inode(entry.inode)?;
+        let parent = self.self_ref.clone() as WeakDirRef;
+        let node = match inode.kind() {
+            InodeKind::Directory => Ext2Node::Dir(Ext2Directory::new_node(entry.name.clone(), parent, self.fs.clone(), inode)?),
+            InodeKind::RegularFile => Ext2Node::File(Arc::new(Mutex::new(Ext2File {
+                name: entry.name.clone(),
+                parent,
+                fs: self.fs.clone(),
+                inode,
+                mapping: Once::new(),
+            }))),
+            InodeKind::Symlink => {
+                let target = self.fs.lock().read_symlink_target(&inode)?;
+                Ext2Node::Symlink(Arc::new(Mutex::new(Ext2Symlink {
+                    name: entry.name.clone(),
+                    parent,
+                    inode,
+                    target,
+                })))
+            }
+            _ => return Err("ext2: directory entry's file type doesn't match its inode"),
+        };
+        children.insert(entry.name.clone(), node.clone());
+        Ok(Some(node))
+    }
+}
+
+impl Directory for Ext2Directory {
+    fn get(&self, name: &str) -> Option<FileOrDir> {
+        match self.node_for_entry(name) {
+            Ok(node) => node.map(|n| n.as_file_or_dir()),
+            Err(e) => {
+                error!("Ext2Directory::get(): error getting {:?}: {}", name, e);
+                None
+            }
+        }
+    }
+
+    fn insert(&mut self, _node: FileOrDir) -> Result<Option<FileOrDir>, &'static str> {
+        Err(READ_ONLY_ERROR)
+    }
+
+    fn remove(&mut self, node: &FileOrDir) -> Option<FileOrDir> {
+        error!("Ext2Directory::remove(): cannot remove {:?}: {}", node.get_name(), READ_ONLY_ERROR);
+        None
+    }
+
+    fn list(&self) -> Vec<String> {
+        self.entries.iter().map(|e| e.name.clone()).collect()
+    }
+
+    fn metadata(&self) -> Metadata {
+        inode_metadata(&self.inode)
+    }
+}
+
+impl FsNode for Ext2Directory {
+    fn get_name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn get_parent_dir(&self) -> Option<DirRef> {
+        self.parent.upgrade()
+    }
+
+    fn set_parent_dir(&mut self, new_parent: WeakDirRef) {
+        self.parent = new_parent;
+    }
+}
+
+
+/// A regular file in an ext2 filesystem.
+pub struct Ext2File {
+    /// The name of this file.
+    name: String,
+    /// The directory that contains this file.
+    parent: WeakDirRef,
+    /// The filesystem that this file belongs to.
+    fs: Ext2FsRef,
+    /// This file's inode.
+    inode: Inode,
+    /// A copy of this file's entire contents in memory, which is only created the first time it is requested
+    /// via `as_mapping()`, e.g., when this file is loaded as a crate object file.
+    mapping: Once<Result<MappedPages, &'static str>>,
+}
+
+impl Ext2File {
+    /// Reads this file's entire contents into a new memory mapping.
+    fn map_contents(&self) -> Result<MappedPages, &'static str> {
+        let size = self.size();
+        let mut mapped_pages = memory::create_mapping(size, EntryFlags::WRITABLE)?;
+        {
+            let contents: &mut [u8] = mapped_pages.as_slice_mut(0, size)?;
+            if self.fs.lock().read_data(&self.inode, contents, 0)? != size {
+                return Err("ext2: couldn't read the entire file");
+            }
+        }
+        Ok(mapped_pages)
+    }
+}
+
+impl File for Ext2File {
+    fn read(&self, buffer: &mut [u8], offset: usize) -> Result<usize, &'static str> {
+        self.fs.lock().read_data(&self.inode, buffer, offset)
+    }
+
+    fn write(&mut self, _buffer: &[u8], _offset: usize) -> Result<usize, &'static str> {
+        Err(READ_ONLY_ERROR)
+    }
+
+    fn size(&self) -> usize {
+        self.inode.size as usize
+    }
+
+    fn as_mapping(&self) -> Result<&MappedPages, &'static str> {
+        self.mapping.call_once(|| self.map_contents()).as_ref().map_err(|e| *e)
+    }
+
+    fn metadata(&self) -> Metadata {
+        inode_metadata(&self.inode)
+    }
+
+    fn truncate(&mut self, _size: usize) -> Result<(), &'static str> {
+        Err(READ_ONLY_ERROR)
+    }
+}
+
+impl FsNode for Ext2File {
+    fn get_name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn get_parent_dir(&self) -> Option<DirRef> {
+        self.parent.upgrade()
+    }
+
+    fn set_parent_dir(&mut self, new_parent: WeakDirRef) {
+        self.parent = new_parent;
+    }
+}
+
+
+/// A symbolic link in an ext2 filesystem.
+pub struct Ext2Symlink {
+    /// The name of this symbolic link.
+    name: String,
+    /// The directory that contains this symbolic link.
+    parent: WeakDirRef,
+    /// This symbolic link's inode.
+    inode: Inode,
+    /// The path that this symbolic link points to, read from disk when this node was created.
+    target: String,
+}
+
+impl Symlink for Ext2Symlink {
+    fn target(&self) -> String {
+        self.target.clone()
+    }
+
+    fn metadata(&self) -> Metadata {
+        inode_metadata(&self.inode)
+    }
+}
+
+impl FsNode for Ext2Symlink {
+    fn get_name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn get_parent_dir(&self) -> Option<DirRef> {
+        self.parent.upgrade()
+    }
+
+    fn set_parent_dir(&mut self, new_parent: WeakDirRef) {
+        self.parent = new_parent;
+    }
+}
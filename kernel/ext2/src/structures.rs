@@ -0,0 +1,324 @@
+//! The on-disk structures of an ext2 filesystem: the superblock, block group descriptors, inodes and directory entries.
+//!
+//! All fields are stored in little-endian byte order.
+
+use alloc::{
+    string::String,
+    vec::Vec,
+};
+
+
+/// The byte offset of the superblock from the start of the storage device, regardless of the block size.
+pub const SUPERBLOCK_OFFSET: usize = 1024;
+/// The size of the superblock in bytes.
+pub const SUPERBLOCK_SIZE: usize = 1024;
+/// The magic number that identifies an ext2 (or ext3/ext4) superblock.
+const EXT2_MAGIC: u16 = 0xEF53;
+/// The revision level of the original ext2 format, which has fixed-size inodes.
+const GOOD_OLD_REVISION: u32 = 0;
+/// The size of an inode in filesystems with the original revision level.
+const GOOD_OLD_INODE_SIZE: usize = 128;
+
+/// The incompatible feature flag indicating that directory entries record the type of the file they refer to.
+pub const FEATURE_INCOMPAT_FILETYPE: u32 = 0x0002;
+/// The incompatible feature flag indicating that block group metadata may be placed anywhere,
+/// which doesn't matter when reading because the group descriptors still point to it.
+const FEATURE_INCOMPAT_FLEX_BG: u32 = 0x0200;
+/// All incompatible features that this driver understands.
+/// A filesystem that uses any other incompatible feature (e.g., extents or a journal that needs recovery) cannot be read.
+const SUPPORTED_INCOMPAT_FEATURES: u32 = FEATURE_INCOMPAT_FILETYPE | FEATURE_INCOMPAT_FLEX_BG;
+/// The read-only compatible feature flag indicating that regular files may be larger than 4 GiB,
+/// in which case the upper 32 bits of their size are stored in the inode.
+const FEATURE_RO_COMPAT_LARGE_FILE: u32 = 0x0002;
+
+/// The size of a block group descriptor.
+pub const GROUP_DESCRIPTOR_SIZE: usize = 32;
+
+/// The inode number of the root directory.
+pub const ROOT_INODE: u32 = 2;
+/// The number of direct block pointers in an inode,
+/// which are followed by one singly-, one doubly- and one triply-indirect block pointer.
+pub const DIRECT_BLOCKS: usize = 12;
+/// The total number of block pointers in an inode.
+const BLOCK_POINTERS: usize = DIRECT_BLOCKS + 3;
+
+/// The mask for the file type bits of an inode's mode.
+const MODE_TYPE_MASK: u16 = 0xF000;
+const MODE_DIRECTORY: u16 = 0x4000;
+const MODE_REGULAR_FILE: u16 = 0x8000;
+const MODE_SYMLINK: u16 = 0xA000;
+/// The inode flag indicating that the inode's blocks are mapped by an extent tree (ext4), not block pointers.
+const INODE_FLAG_EXTENTS: u32 = 0x0008_0000;
+/// The inode flag indicating that the inode's data is stored inline in the inode itself (ext4).
+const INODE_FLAG_INLINE_DATA: u32 = 0x1000_0000;
+
+/// The size of the fixed part of a directory entry, which is followed by its name.
+pub const DIR_ENTRY_HEADER_SIZE: usize = 8;
+/// The file types recorded in directory entries when the `FILETYPE` feature is used.
+pub const DIR_ENTRY_TYPE_UNKNOWN: u8 = 0;
+const DIR_ENTRY_TYPE_REGULAR_FILE: u8 = 1;
+const DIR_ENTRY_TYPE_DIRECTORY: u8 = 2;
+const DIR_ENTRY_TYPE_SYMLINK: u8 = 7;
+
+
+/// The fields of the superblock that are relevant to a read-only ext2 driver.
+#[derive(Debug)]
+pub struct Superblock {
+    pub inodes_count: u32,
+    pub blocks_count: u32,
+    pub free_blocks_count: u32,
+    /// The block that contains the superblock, which is `1` for 1 KiB blocks and `0` otherwise.
+    pub first_data_block: u32,
+    pub block_size: usize,
+    pub blocks_per_group: u32,
+    pub inodes_per_group: u32,
+    pub inode_size: usize,
+    pub feature_incompat: u32,
+    pub feature_ro_compat: u32,
+    /// The name of the volume, which may be empty.
+    pub volume_name: String,
+}
+
+impl Superblock {
+    /// Parses and validates the given raw superblock, which must be `SUPERBLOCK_SIZE` bytes long.
+    pub fn parse(raw: &[u8]) -> Result<Superblock, &'static str> {
+        if raw.len() < SUPERBLOCK_SIZE || read_u16(raw, 56) != EXT2_MAGIC {
+            return Err("ext2: invalid superblock magic number");
+        }
+        let log_block_size = read_u32(raw, 24);
+        // Block sizes range from 1 KiB to 64 KiB.
+        if log_block_size > 6 {
+            return Err("ext2: invalid block size");
+        }
+        let rev_level = read_u32(raw, 76);
+        let (inode_size, feature_incompat, feature_ro_compat) = if rev_level == GOOD_OLD_REVISION {
+            (GOOD_OLD_INODE_SIZE, 0, 0)
+        } else {
+            (read_u16(raw, 88) as usize, read_u32(raw, 96), read_u32(raw, 100))
+        };
+        let sb = Superblock {
+            inodes_count: read_u32(raw, 0),
+            blocks_count: read_u32(raw, 4),
+            free_blocks_count: read_u32(raw, 12),
+            first_data_block: read_u32(raw, 20),
+            block_size: 1024 << log_block_size,
+            blocks_per_group: read_u32(raw, 32),
+            inodes_per_group: read_u32(raw, 40),
+            inode_size,
+            feature_incompat,
+            feature_ro_compat,
+            volume_name: parse_name(&raw[120 .. 136]),
+        };
+        if sb.blocks_per_group == 0 || sb.inodes_per_group == 0 || sb.blocks_count <= sb.first_data_block {
+            return Err("ext2: invalid block group geometry");
+        }
+        // Each block group's block and inode bitmaps fit in a single block,
+        // and together the block groups must hold all inodes.
+        let bits_per_block = 8 * sb.block_size as u64;
+        if u64::from(sb.blocks_per_group) > bits_per_block || u64::from(sb.inodes_per_group) > bits_per_block
+            || u64::from(sb.inodes_per_group) * sb.group_count() as u64 != u64::from(sb.inodes_count)
+        {
+            return Err("ext2: invalid block group geometry");
+        }
+        if sb.inode_size < GOOD_OLD_INODE_SIZE || sb.inode_size > sb.block_size || !sb.inode_size.is_power_of_two() {
+            return Err("ext2: invalid inode size");
+        }
+        if sb.feature_incompat & !SUPPORTED_INCOMPAT_FEATURES != 0 {
+            error!("ext2: unsupported incompatible features {:#X}", sb.feature_incompat & !SUPPORTED_INCOMPAT_FEATURES);
+            return Err("ext2: filesystem uses unsupported features");
+        }
+        Ok(sb)
+    }
+
+    /// Returns the number of block groups in this filesystem.
+    pub fn group_count(&self) -> usize {
+        let data_blocks = (self.blocks_count - self.first_data_block) as usize;
+        let blocks_per_group = self.blocks_per_group as usize;
+        (data_blocks + blocks_per_group - 1) / blocks_per_group
+    }
+
+    /// Returns `true` if directory entries record the type of the file they refer to.
+    pub fn has_file_types(&self) -> bool {
+        self.feature_incompat & FEATURE_INCOMPAT_FILETYPE != 0
+    }
+
+    /// Returns `true` if regular files may be larger than 4 GiB.
+    pub fn has_large_files(&self) -> bool {
+        self.feature_ro_compat & FEATURE_RO_COMPAT_LARGE_FILE != 0
+    }
+}
+
+
+/// The fields of a block group descriptor that are relevant to a read-only ext2 driver.
+#[derive(Debug, Clone)]
+pub struct GroupDescriptor {
+    /// The first block of this group's inode table.
+    pub inode_table: u32,
+    pub free_blocks_count: u16,
+}
+
+impl GroupDescriptor {
+    /// Parses the given raw group descriptor, which must be `GROUP_DESCRIPTOR_SIZE` bytes long.
+    pub fn parse(raw: &[u8]) -> GroupDescriptor {
+        GroupDescriptor {
+            inode_table: read_u32(raw, 8),
+            free_blocks_count: read_u16(raw, 12),
+        }
+    }
+}
+
+
+/// The type of file that an inode describes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InodeKind {
+    RegularFile,
+    Directory,
+    Symlink,
+    /// A device file, FIFO or socket, none of which are supported.
+    Other,
+}
+
+/// An inode, which holds the metadata of a file or directory and the locations of its data blocks.
+#[derive(Debug, Clone)]
+pub struct Inode {
+    pub mode: u16,
+    pub size: u64,
+    /// The time of the last access, in seconds since the Unix epoch.
+    pub atime: u32,
+    /// The time of the last modification of the contents, in seconds since the Unix epoch.
+    pub mtime: u32,
+    pub flags: u32,
+    /// The number of 512-byte sectors allocated to this inode, including its extended attribute block.
+    pub sectors: u32,
+    /// The block that holds this inode's extended attributes, or `0` if it has none.
+    pub file_acl: u32,
+    /// The direct block pointers, followed by the singly-, doubly- and triply-indirect block pointers.
+    pub block: [u32; BLOCK_POINTERS],
+}
+
+impl Inode {
+    /// Parses the given raw inode, which must be at least 128 bytes long.
+    /// The upper 32 bits of a regular file's size are only used if `large_files` is `true`.
+    pub fn parse(raw: &[u8], large_files: bool) -> Inode {
+        let mut block = [0u32; BLOCK_POINTERS];
+        for (i, b) in block.iter_mut().enumerate() {
+            *b = read_u32(raw, 40 + i * 4);
+        }
+        let mode = read_u16(raw, 0);
+        let size_low = u64::from(read_u32(raw, 4));
+        // For directories, the upper field is instead the (unused) directory ACL.
+        let size_high = if large_files && mode & MODE_TYPE_MASK == MODE_REGULAR_FILE {
+            u64::from(read_u32(raw, 108))
+        } else {
+            0
+        };
+        Inode {
+            mode,
+            size: size_low | (size_high << 32),
+            atime: read_u32(raw, 8),
+            mtime: read_u32(raw, 16),
+            flags: read_u32(raw, 32),
+            sectors: read_u32(raw, 28),
+            file_acl: read_u32(raw, 104),
+            block,
+        }
+    }
+
+    pub fn kind(&self) -> InodeKind {
+        match self.mode & MODE_TYPE_MASK {
+            MODE_REGULAR_FILE => InodeKind::RegularFile,
+            MODE_DIRECTORY => InodeKind::Directory,
+            MODE_SYMLINK => InodeKind::Symlink,
+            _ => InodeKind::Other,
+        }
+    }
+
+    /// Returns `true` if this inode is a symbolic link whose target is stored in place of its block pointers
+    /// rather than in a data block, which is the case for targets shorter than 60 bytes.
+    pub fn is_fast_symlink(&self, block_size: usize) -> bool {
+        let attribute_sectors = if self.file_acl != 0 { (block_size / 512) as u32 } else { 0 };
+        self.kind() == InodeKind::Symlink && self.sectors == attribute_sectors
+    }
+
+    /// Returns the raw bytes of this inode's block pointers, which hold the target of a fast symbolic link.
+    pub fn block_bytes(&self) -> Vec<u8> {
+        self.block.iter().flat_map(|b| b.to_le_bytes().to_vec()).collect()
+    }
+
+    /// Returns `true` if this inode's data blocks are mapped by block pointers,
+    /// as opposed to an ext4 extent tree or inline data, neither of which are supported.
+    pub fn uses_block_pointers(&self) -> bool {
+        self.flags & (INODE_FLAG_EXTENTS | INODE_FLAG_INLINE_DATA) == 0
+    }
+}
+
+
+/// One entry in a directory, which links a name to an inode.
+#[derive(Debug, Clone)]
+pub struct DirEntry {
+    pub inode: u32,
+    pub name: String,
+    /// The kind of the inode, or `None` if the directory entry doesn't record it.
+    pub kind: Option<InodeKind>,
+}
+
+/// Parses the raw contents of an entire directory into a list of its entries,
+/// skipping unused entries and the `.` and `..` entries.
+///
+/// If `has_file_types` is `true`, the type of each entry's inode is taken from the entry itself.
+pub fn parse_dir_entries(data: &[u8], block_size: usize, has_file_types: bool) -> Result<Vec<DirEntry>, &'static str> {
+    let mut entries = Vec::new();
+    let mut offset = 0;
+    while offset + DIR_ENTRY_HEADER_SIZE <= data.len() {
+        let inode = read_u32(data, offset);
+        let rec_len = read_u16(data, offset + 4) as usize;
+        let name_len = data[offset + 6] as usize;
+        let file_type = data[offset + 7];
+        // An entry must be aligned, hold its name, and not cross a block boundary or the end of the data.
+        if rec_len < DIR_ENTRY_HEADER_SIZE || rec_len % 4 != 0
+            || DIR_ENTRY_HEADER_SIZE + name_len > rec_len
+            || offset + rec_len > data.len()
+            || offset / block_size != (offset + rec_len - 1) / block_size
+        {
+            return Err("ext2: corrupt directory entry");
+        }
+        let name = &data[offset + DIR_ENTRY_HEADER_SIZE .. offset + DIR_ENTRY_HEADER_SIZE + name_len];
+        // An inode number of 0 marks an unused entry.
+        if inode != 0 && name != b"." && name != b".." {
+            let kind = if has_file_types {
+                match file_type {
+                    DIR_ENTRY_TYPE_UNKNOWN => None,
+                    DIR_ENTRY_TYPE_REGULAR_FILE => Some(InodeKind::RegularFile),
+                    DIR_ENTRY_TYPE_DIRECTORY => Some(InodeKind::Directory),
+                    DIR_ENTRY_TYPE_SYMLINK => Some(InodeKind::Symlink),
+                    _ => Some(InodeKind::Other),
+                }
+            } else {
+                None
+            };
+            entries.push(DirEntry {
+                inode,
+                name: String::from_utf8_lossy(name).into_owned(),
+                kind,
+            });
+        }
+        offset += rec_len;
+    }
+    Ok(entries)
+}
+
+/// Parses the given null-padded name.
+fn parse_name(bytes: &[u8]) -> String {
+    let len = bytes.iter().position(|&b| b == 0).unwrap_or_else(|| bytes.len());
+    String::from_utf8_lossy(&bytes[.. len]).into_owned()
+}
+
+/// Reads the little-endian `u16` at the given `offset` in `data`.
+pub fn read_u16(data: &[u8], offset: usize) -> u16 {
+    u16::from(data[offset]) | (u16::from(data[offset + 1]) << 8)
+}
+
+/// Reads the little-endian `u32` at the given `offset` in `data`.
+pub fn read_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from(read_u16(data, offset)) | (u32::from(read_u16(data, offset + 2)) << 16)
+}
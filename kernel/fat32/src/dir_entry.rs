@@ -0,0 +1,325 @@
+//! Parsing and encoding of on-disk FAT directory entries,
+//! both regular 8.3 short entries and VFAT long file name (LFN) entries.
+
+use core::cmp::Ordering;
+use alloc::{
+    string::String,
+    vec::Vec,
+};
+use fs_node::Timestamp;
+
+/// The size in bytes of a single directory entry.
+pub const DIR_ENTRY_SIZE: usize = 32;
+
+/// The first name byte of a deleted (free) directory entry.
+pub const DELETED_ENTRY_MARKER: u8 = 0xE5;
+/// The first name byte of the entry following the last used entry in a directory.
+pub const END_OF_DIRECTORY_MARKER: u8 = 0x00;
+
+/// The number of UCS-2 characters stored in one long file name entry.
+const LFN_CHARS_PER_ENTRY: usize = 13;
+/// The byte offsets of the 13 UCS-2 characters within a long file name entry.
+const LFN_CHAR_OFFSETS: [usize; LFN_CHARS_PER_ENTRY] = [1, 3, 5, 7, 9, 14, 16, 18, 20, 22, 24, 28, 30];
+/// The bit in a long file name entry's ordinal that marks it as the last (first physical) one.
+const LFN_LAST_ENTRY_FLAG: u8 = 0x40;
+/// The maximum length of a long file name, in UCS-2 characters.
+pub const MAX_LONG_NAME_LENGTH: usize = 255;
+
+/// Bit in the reserved `NTRes` byte indicating that the base of the short name is lowercase.
+const NT_LOWERCASE_BASE: u8 = 0x08;
+/// Bit in the reserved `NTRes` byte indicating that the extension of the short name is lowercase.
+const NT_LOWERCASE_EXT: u8 = 0x10;
+
+bitflags! {
+    /// The attributes of a FAT directory entry.
+    pub struct FileAttributes: u8 {
+        const READ_ONLY = 0x01;
+        const HIDDEN    = 0x02;
+        const SYSTEM    = 0x04;
+        const VOLUME_ID = 0x08;
+        const DIRECTORY = 0x10;
+        const ARCHIVE   = 0x20;
+        /// The combination of attributes (`READ_ONLY | HIDDEN | SYSTEM | VOLUME_ID`) that marks a long file name entry.
+        const LONG_NAME = 0x0F;
+    }
+}
+
+
+/// A regular (8.3) directory entry, decoded from its 32-byte on-disk form.
+#[derive(Debug, Clone)]
+pub struct ShortEntry {
+    /// The space-padded 8-byte base name followed by the space-padded 3-byte extension.
+    pub name: [u8; 11],
+    pub attributes: FileAttributes,
+    /// The `NTRes` byte, which holds the lowercase flags used by Windows.
+    pub nt_reserved: u8,
+    pub create_time_tenths: u8,
+    pub create_time: u16,
+    pub create_date: u16,
+    pub access_date: u16,
+    pub first_cluster: u32,
+    pub write_time: u16,
+    pub write_date: u16,
+    pub file_size: u32,
+}
+
+impl ShortEntry {
+    /// Creates a new entry with the given short `name` and `attributes`, timestamped with the given FAT `date` and `time`.
+    pub fn new(name: [u8; 11], attributes: FileAttributes, first_cluster: u32, date: u16, time: u16) -> ShortEntry {
+        ShortEntry {
+            name,
+            attributes,
+            nt_reserved: 0,
+            create_time_tenths: 0,
+            create_time: time,
+            create_date: date,
+            access_date: date,
+            first_cluster,
+            write_time: time,
+            write_date: date,
+            file_size: 0,
+        }
+    }
+
+    /// Decodes a short entry from the given 32 bytes.
+    pub fn parse(raw: &[u8]) -> ShortEntry {
+        let mut name = [0u8; 11];
+        name.copy_from_slice(&raw[0..11]);
+        // A leading 0x05 byte is used to represent an actual 0xE5 character in the name.
+        if name[0] == 0x05 {
+            name[0] = DELETED_ENTRY_MARKER;
+        }
+        ShortEntry {
+            name,
+            attributes: FileAttributes::from_bits_truncate(raw[11]),
+            nt_reserved: raw[12],
+            create_time_tenths: raw[13],
+            create_time: read_u16(raw, 14),
+            create_date: read_u16(raw, 16),
+            access_date: read_u16(raw, 18),
+            first_cluster: ((read_u16(raw, 20) as u32) << 16) | (read_u16(raw, 26) as u32),
+            write_time: read_u16(raw, 22),
+            write_date: read_u16(raw, 24),
+            file_size: read_u32(raw, 28),
+        }
+    }
+
+    /// Encodes this short entry into its 32-byte on-disk form.
+    pub fn to_bytes(&self) -> [u8; DIR_ENTRY_SIZE] {
+        let mut raw = [0u8; DIR_ENTRY_SIZE];
+        raw[0..11].copy_from_slice(&self.name);
+        if raw[0] == DELETED_ENTRY_MARKER {
+            raw[0] = 0x05;
+        }
+        raw[11] = self.attributes.bits();
+        raw[12] = self.nt_reserved;
+        raw[13] = self.create_time_tenths;
+        write_u16(&mut raw, 14, self.create_time);
+        write_u16(&mut raw, 16, self.create_date);
+        write_u16(&mut raw, 18, self.access_date);
+        write_u16(&mut raw, 20, (self.first_cluster >> 16) as u16);
+        write_u16(&mut raw, 22, self.write_time);
+        write_u16(&mut raw, 24, self.write_date);
+        write_u16(&mut raw, 26, self.first_cluster as u16);
+        write_u32(&mut raw, 28, self.file_size);
+        raw
+    }
+
+    /// Returns true if this entry is a directory.
+    pub fn is_dir(&self) -> bool {
+        self.attributes.contains(FileAttributes::DIRECTORY)
+    }
+
+    /// Returns true if this entry is the volume label rather than a real file or directory.
+    pub fn is_volume_label(&self) -> bool {
+        self.attributes.contains(FileAttributes::VOLUME_ID) && !self.is_dir()
+    }
+
+    /// Returns true if this is the special `.` or `..` entry found in every subdirectory.
+    pub fn is_dot_entry(&self) -> bool {
+        &self.name == b".          " || &self.name == b"..         "
+    }
+
+    /// Returns the human-readable form of the short name, e.g., `"README.TXT"`,
+    /// honoring the lowercase flags set by Windows and Linux.
+    pub fn display_name(&self) -> String {
+        let lower_base = self.nt_reserved & NT_LOWERCASE_BASE != 0;
+        let lower_ext = self.nt_reserved & NT_LOWERCASE_EXT != 0;
+        let mut s = String::new();
+        for &b in self.name[0..8].iter().take_while(|&&b| b != b' ') {
+            s.push(if lower_base { b.to_ascii_lowercase() } else { b } as char);
+        }
+        let mut ext = self.name[8..11].iter().take_while(|&&b| b != b' ').peekable();
+        if ext.peek().is_some() {
+            s.push('.');
+            for &b in ext {
+                s.push(if lower_ext { b.to_ascii_lowercase() } else { b } as char);
+            }
+        }
+        s
+    }
+
+    /// Computes the checksum of this entry's short name, which is stored in each of its long name entries.
+    pub fn checksum(&self) -> u8 {
+        self.name.iter().fold(0u8, |sum, &b| ((sum & 1) << 7).wrapping_add(sum >> 1).wrapping_add(b))
+    }
+}
+
+
+/// Returns true if the given 32-byte raw entry is a long file name entry.
+pub fn is_long_name_entry(raw: &[u8]) -> bool {
+    raw[11] & FileAttributes::LONG_NAME.bits() == FileAttributes::LONG_NAME.bits()
+}
+
+/// One decoded long file name entry.
+pub struct LongEntry {
+    /// The sequence number of this entry (starting at 1), without the "last entry" flag.
+    pub order: u8,
+    /// Whether this is the last logical (and first physical) entry of the long name.
+    pub is_last: bool,
+    pub checksum: u8,
+    /// The (up to) 13 UCS-2 characters held in this entry, excluding any terminator and padding.
+    pub chars: Vec<u16>,
+}
+
+impl LongEntry {
+    /// Decodes a long file name entry from the given 32 bytes.
+    pub fn parse(raw: &[u8]) -> LongEntry {
+        let chars = LFN_CHAR_OFFSETS.iter()
+            .map(|&off| read_u16(raw, off))
+            .take_while(|&c| c != 0x0000 && c != 0xFFFF)
+            .collect();
+        LongEntry {
+            order: raw[0] & !LFN_LAST_ENTRY_FLAG,
+            is_last: raw[0] & LFN_LAST_ENTRY_FLAG != 0,
+            checksum: raw[13],
+            chars,
+        }
+    }
+}
+
+/// Encodes the given long `name` into the sequence of long name entries that must be written
+/// immediately before its short entry, in on-disk order (the last logical entry comes first).
+pub fn long_name_entries(name: &str, checksum: u8) -> Vec<[u8; DIR_ENTRY_SIZE]> {
+    let ucs2: Vec<u16> = name.encode_utf16().collect();
+    let count = (ucs2.len() + LFN_CHARS_PER_ENTRY - 1) / LFN_CHARS_PER_ENTRY;
+    let mut entries = Vec::with_capacity(count);
+    for i in (0..count).rev() {
+        let mut raw = [0u8; DIR_ENTRY_SIZE];
+        raw[0] = (i + 1) as u8 | if i == count - 1 { LFN_LAST_ENTRY_FLAG } else { 0 };
+        raw[11] = FileAttributes::LONG_NAME.bits();
+        raw[13] = checksum;
+        for (j, &off) in LFN_CHAR_OFFSETS.iter().enumerate() {
+            let idx = i * LFN_CHARS_PER_ENTRY + j;
+            // the name is terminated by a single 0x0000, and then padded with 0xFFFF
+            let c = match idx.cmp(&ucs2.len()) {
+                Ordering::Less    => ucs2[idx],
+                Ordering::Equal   => 0x0000,
+                Ordering::Greater => 0xFFFF,
+            };
+            write_u16(&mut raw, off, c);
+        }
+        entries.push(raw);
+    }
+    entries
+}
+
+
+/// If the given `name` can be stored exactly as an 8.3 short name (uppercase ASCII only),
+/// returns that short name, in which case no long name entries are needed.
+pub fn exact_short_name(name: &str) -> Option<[u8; 11]> {
+    let (base, ext) = match name.rfind('.') {
+        Some(i) => (&name[..i], &name[i + 1..]),
+        None => (name, ""),
+    };
+    if base.is_empty() || base.len() > 8 || ext.len() > 3 || (name.ends_with('.') && ext.is_empty()) {
+        return None;
+    }
+    let valid = |c: u8| is_valid_short_name_char(c) && !c.is_ascii_lowercase();
+    if !base.bytes().all(valid) || !ext.bytes().all(valid) {
+        return None;
+    }
+    let mut short = [b' '; 11];
+    short[..base.len()].copy_from_slice(base.as_bytes());
+    short[8..8 + ext.len()].copy_from_slice(ext.as_bytes());
+    Some(short)
+}
+
+/// Generates a short name for the given long `name` in the form of `BASE~N.EXT`,
+/// using the given numeric `tail` to distinguish it from other entries with similar names.
+pub fn generate_short_name(name: &str, tail: usize) -> [u8; 11] {
+    let (base, ext) = match name.rfind('.') {
+        Some(i) if i > 0 => (&name[..i], &name[i + 1..]),
+        _ => (name, ""),
+    };
+    let sanitize = |s: &str| -> Vec<u8> {
+        s.bytes()
+            .filter(|&c| c != b' ' && c != b'.')
+            .map(|c| if is_valid_short_name_char(c) { c.to_ascii_uppercase() } else { b'_' })
+            .collect()
+    };
+    let base = sanitize(base);
+    let ext = sanitize(ext);
+
+    let tail_str = format!("~{}", tail);
+    let base_len = core::cmp::min(base.len(), 8 - tail_str.len());
+    let mut short = [b' '; 11];
+    short[..base_len].copy_from_slice(&base[..base_len]);
+    short[base_len..base_len + tail_str.len()].copy_from_slice(tail_str.as_bytes());
+    let ext_len = core::cmp::min(ext.len(), 3);
+    short[8..8 + ext_len].copy_from_slice(&ext[..ext_len]);
+    short
+}
+
+/// Returns true if the given byte may appear in a short (8.3) name.
+fn is_valid_short_name_char(c: u8) -> bool {
+    c.is_ascii_alphanumeric() || b"$%'-_@~`!(){}^#&".contains(&c)
+}
+
+
+/// Encodes the given calendar date into the FAT date format.
+/// FAT dates start at 1980, so earlier years are clamped to 1980.
+pub fn encode_date(year: u16, month: u8, day: u8) -> u16 {
+    let year = if year < 1980 { 0 } else { year - 1980 };
+    (year << 9) | ((month as u16 & 0x0F) << 5) | (day as u16 & 0x1F)
+}
+
+/// Encodes the given time of day into the FAT time format, which has a 2-second granularity.
+pub fn encode_time(hours: u8, minutes: u8, seconds: u8) -> u16 {
+    ((hours as u16 & 0x1F) << 11) | ((minutes as u16 & 0x3F) << 5) | ((seconds as u16 / 2) & 0x1F)
+}
+
+/// Decodes the given FAT `date` and `time` into a `Timestamp`.
+/// Returns `None` if the date is zero, which means that it was never set.
+pub fn decode_timestamp(date: u16, time: u16) -> Option<Timestamp> {
+    if date == 0 {
+        return None;
+    }
+    Some(Timestamp {
+        year: 1980 + (date >> 9),
+        month: ((date >> 5) & 0x0F) as u8,
+        day: (date & 0x1F) as u8,
+        hour: (time >> 11) as u8,
+        minute: ((time >> 5) & 0x3F) as u8,
+        second: ((time & 0x1F) * 2) as u8,
+    })
+}
+
+
+pub fn read_u16(buf: &[u8], offset: usize) -> u16 {
+    (buf[offset] as u16) | ((buf[offset + 1] as u16) << 8)
+}
+
+pub fn read_u32(buf: &[u8], offset: usize) -> u32 {
+    (read_u16(buf, offset) as u32) | ((read_u16(buf, offset + 2) as u32) << 16)
+}
+
+pub fn write_u16(buf: &mut [u8], offset: usize, value: u16) {
+    buf[offset] = value as u8;
+    buf[offset + 1] = (value >> 8) as u8;
+}
+
+pub fn write_u32(buf: &mut [u8], offset: usize, value: u32) {
+    write_u16(buf, offset, value as u16);
+    write_u16(buf, offset + 2, (value >> 16) as u16);
+}
@@ -0,0 +1,1400 @@
+//! Support for the FAT32 filesystem, built atop a `StorageDevice` via `block_io::BlockIo`.
+//!
+//! A FAT32 volume is exposed through the standard `fs_node` traits:
+//! * [`FatDirectory`](struct.FatDirectory.html) implements the `Directory` trait,
+//! * [`FatFile`](struct.FatFile.html) implements the `File` trait.
+//!
+//! The root directory of a volume can be mounted on any directory in the VFS tree using [`mount()`](fn.mount.html),
+//! or by name via `mount_table::mount()` once [`init()`](fn.init.html) has registered the `fat32` filesystem type.
+//!
+//! Nodes are created lazily when they are first accessed through their parent directory,
+//! after which the parent keeps them cached.
+//! Thus, there is only ever one `FatDirectory` or `FatFile` object for a given on-disk entry,
+//! which keeps multiple users of the same file or directory coherent.
+//!
+//! Both short (8.3) names and VFAT long file names are supported.
+//! Name lookups are case-insensitive, as is customary for FAT filesystems.
+//!
+//! # Limitations
+//! * Because the `Directory::insert()` method accepts an existing node from any filesystem,
+//!   inserting a node into a `FatDirectory` creates a new on-disk file or directory
+//!   with the same name and **copies** the contents of the given node into it.
+//!   Thus, the node that was passed in is not itself part of the FAT volume.
+//!   Callers that want to access the new node should re-obtain it with `Directory::get()`.
+//! * FAT12 and FAT16 volumes are not supported.
+
+#![no_std]
+
+#[macro_use] extern crate alloc;
+#[macro_use] extern crate log;
+#[macro_use] extern crate bitflags;
+extern crate spin;
+extern crate memory;
+extern crate fs_node;
+extern crate root;
+extern crate vfs_node;
+extern crate mount_table;
+extern crate block_io;
+extern crate storage_device;
+extern crate storage_manager;
+
+mod dir_entry;
+
+use alloc::{
+    collections::BTreeMap,
+    string::String,
+    sync::{Arc, Weak},
+    vec::Vec,
+};
+use spin::Mutex;
+use memory::MappedPages;
+use fs_node::{DirRef, WeakDirRef, FileRef, Directory, File, FileOrDir, FsNode, Metadata, NodeKind, Permissions, Timestamp};
+use vfs_node::VFSDirectory;
+use mount_table::{FileSystem, FileSystemRef, FileSystemStats, MountOptions};
+use block_io::BlockIo;
+use storage_device::StorageDeviceRef;
+use dir_entry::*;
+
+pub use dir_entry::FileAttributes;
+
+
+/// The name under which this filesystem type is registered in the mount table.
+pub const FS_TYPE_NAME: &str = "fat32";
+
+/// The size in bytes of the boot sector, which contains the BIOS Parameter Block (BPB).
+const BOOT_SECTOR_SIZE: usize = 512;
+/// The mask for the valid bits of a FAT32 entry; the top 4 bits are reserved.
+const FAT_ENTRY_MASK: u32 = 0x0FFF_FFFF;
+/// Any FAT entry value at or above this marks the end of a cluster chain.
+const END_OF_CHAIN_MIN: u32 = 0x0FFF_FFF8;
+/// The value written into a FAT entry to mark the end of a cluster chain.
+const END_OF_CHAIN: u32 = 0x0FFF_FFFF;
+/// The FAT entry value of a bad cluster.
+const BAD_CLUSTER: u32 = 0x0FFF_FFF7;
+/// The FAT entry value of a free cluster.
+const FREE_CLUSTER: u32 = 0;
+/// The first valid data cluster number; clusters 0 and 1 are reserved.
+const FIRST_DATA_CLUSTER: u32 = 2;
+
+/// The signatures at the start of the FSInfo sector, at offset 484, and at the end of the sector.
+const FSINFO_LEAD_SIGNATURE: u32 = 0x4161_5252;
+const FSINFO_STRUCT_SIGNATURE: u32 = 0x6141_7272;
+/// The offsets of the free cluster count and next free cluster hint within the FSInfo sector.
+const FSINFO_FREE_COUNT_OFFSET: usize = 488;
+const FSINFO_NEXT_FREE_OFFSET: usize = 492;
+/// The value of an unknown free cluster count or next free cluster hint in the FSInfo sector.
+const FSINFO_UNKNOWN: u32 = 0xFFFF_FFFF;
+
+/// Characters that are never permitted in a FAT long file name.
+const INVALID_NAME_CHARS: &[char] = &['"', '*', '/', ':', '<', '>', '?', '\\', '|'];
+
+
+/// Returns `true` if the given storage device contains a FAT32 filesystem.
+///
+/// The boot sector is read through the block cache, which may hold changes that haven't been written back yet.
+pub fn is_fat32(device: &StorageDeviceRef) -> bool {
+    let mut boot_sector = [0u8; BOOT_SECTOR_SIZE];
+    match BlockIo::new(Arc::clone(device)).read(&mut boot_sector, 0) {
+        Ok(BOOT_SECTOR_SIZE) => BiosParameterBlock::parse(&boot_sector).is_ok(),
+        _ => false,
+    }
+}
+
+/// Mounts the FAT32 filesystem on the given storage `device` on top of the given `mountpoint` directory,
+/// and records it in the mount table with the given `source` name.
+///
+/// Returns the root directory of the mounted filesystem.
+pub fn mount(device: StorageDeviceRef, source: &str, mountpoint: &DirRef, options: MountOptions) -> Result<DirRef, &'static str> {
+    let volume = FatVolume::new(device, mountpoint, &options)?;
+    mount_table::mount_filesystem(String::from(source), String::from(FS_TYPE_NAME), volume, mountpoint, options)
+}
+
+/// The `MountFn` for the `fat32` filesystem type,
+/// which expects the `source` to be the name of a storage device, e.g., `"disk0"`.
+fn mount_storage_device(source: &str, mountpoint: &DirRef, options: &MountOptions) -> Result<FileSystemRef, &'static str> {
+    let device = storage_manager::find_storage_device(source).ok_or("FAT32: no storage device with that name")?;
+    if !is_fat32(&device) {
+        return Err("FAT32: storage device does not contain a FAT32 filesystem");
+    }
+    FatVolume::new(device, mountpoint, options)
+}
+
+/// Registers the `fat32` filesystem type with the mount table,
+/// then finds all storage devices and partitions that contain a FAT32 filesystem
+/// and mounts each of them on a new directory in the root directory called `fat32_<N>`.
+///
+/// A failure to mount one device is logged but does not prevent other devices from being mounted.
+pub fn init() -> Result<(), &'static str> {
+    mount_table::register_filesystem_type(FS_TYPE_NAME, mount_storage_device)?;
+
+    let mut count = 0;
+    for (source, device) in storage_manager::named_storage_devices() {
+        if !is_fat32(&device) {
+            continue;
+        }
+        let name = format!("fat32_{}", count);
+        let result = VFSDirectory::new(name.clone(), root::get_root())
+            .and_then(|mountpoint| mount(device, &source, &mountpoint, MountOptions::default()));
+        match result {
+            Ok(_) => count += 1,
+            Err(e) => error!("fat32::init(): failed to mount FAT32 filesystem on {}: {}", source, e),
+        }
+    }
+    Ok(())
+}
+
+
+/// A mounted FAT32 volume, as recorded in the mount table.
+pub struct FatVolume {
+    fs: FatFsRef,
+    root: DirRef,
+}
+
+impl FatVolume {
+    /// Creates a new `FatVolume` for the given storage `device`,
+    /// whose root directory has the same name and parent directory as the given `mountpoint`.
+    fn new(device: StorageDeviceRef, mountpoint: &DirRef, options: &MountOptions) -> Result<FileSystemRef, &'static str> {
+        let mut fs = FatFileSystem::new(device)?;
+        fs.read_only = options.read_only;
+        let (name, parent) = {
+            let locked_mountpoint = mountpoint.lock();
+            let parent = locked_mountpoint.get_parent_dir().ok_or("FAT32: mountpoint has no parent directory")?;
+            (locked_mountpoint.get_name(), parent)
+        };
+        let root_cluster = fs.root_cluster;
+        let fs_ref = Arc::new(Mutex::new(fs));
+        let root = FatDirectory::new_node(name, Arc::downgrade(&parent), fs_ref.clone(), root_cluster, None, None)?;
+        Ok(Arc::new(FatVolume {
+            fs: fs_ref,
+            root: root as DirRef,
+        }))
+    }
+}
+
+impl FileSystem for FatVolume {
+    fn root(&self) -> DirRef {
+        self.root.clone()
+    }
+
+    fn stats(&self) -> Option<FileSystemStats> {
+        let mut fs = self.fs.lock();
+        match fs.count_free_clusters() {
+            Ok(free_clusters) => Some(FileSystemStats {
+                block_size: fs.cluster_size,
+                total_blocks: fs.cluster_count as usize,
+                free_blocks: free_clusters as usize,
+            }),
+            Err(e) => {
+                error!("FatVolume::stats(): couldn't count free clusters: {}", e);
+                None
+            }
+        }
+    }
+}
+
+
+/// The fields of the BIOS Parameter Block (in the boot sector) that are relevant to a FAT32 driver.
+struct BiosParameterBlock {
+    bytes_per_sector: usize,
+    sectors_per_cluster: usize,
+    reserved_sector_count: usize,
+    num_fats: usize,
+    total_sectors: usize,
+    sectors_per_fat: usize,
+    root_cluster: u32,
+    fs_info_sector: usize,
+}
+
+impl BiosParameterBlock {
+    /// Parses and validates the BPB contained in the given boot `sector`.
+    fn parse(sector: &[u8]) -> Result<BiosParameterBlock, &'static str> {
+        if sector.len() < BOOT_SECTOR_SIZE || sector[510] != 0x55 || sector[511] != 0xAA {
+            return Err("boot sector signature was invalid");
+        }
+        let bytes_per_sector = read_u16(sector, 11) as usize;
+        let sectors_per_cluster = sector[13] as usize;
+        let root_entry_count = read_u16(sector, 17);
+        let total_sectors_16 = read_u16(sector, 19) as usize;
+        let sectors_per_fat_16 = read_u16(sector, 22);
+        let total_sectors_32 = read_u32(sector, 32) as usize;
+
+        if !bytes_per_sector.is_power_of_two() || bytes_per_sector < 512 || bytes_per_sector > 4096 {
+            return Err("invalid bytes per sector in BPB");
+        }
+        if sectors_per_cluster == 0 || !sectors_per_cluster.is_power_of_two() {
+            return Err("invalid sectors per cluster in BPB");
+        }
+        // FAT12/FAT16 volumes have a fixed-size root directory and a 16-bit FAT size.
+        if root_entry_count != 0 || sectors_per_fat_16 != 0 {
+            return Err("not a FAT32 volume (found FAT12 or FAT16)");
+        }
+        let bpb = BiosParameterBlock {
+            bytes_per_sector,
+            sectors_per_cluster,
+            reserved_sector_count: read_u16(sector, 14) as usize,
+            num_fats: sector[16] as usize,
+            total_sectors: if total_sectors_16 != 0 { total_sectors_16 } else { total_sectors_32 },
+            sectors_per_fat: read_u32(sector, 36) as usize,
+            root_cluster: read_u32(sector, 44),
+            fs_info_sector: read_u16(sector, 48) as usize,
+        };
+        if bpb.reserved_sector_count == 0 || bpb.num_fats == 0 || bpb.sectors_per_fat == 0 || bpb.total_sectors == 0 {
+            return Err("invalid FAT32 BPB");
+        }
+        if bpb.root_cluster < FIRST_DATA_CLUSTER {
+            return Err("invalid root cluster in FAT32 BPB");
+        }
+        Ok(bpb)
+    }
+}
+
+
+/// A mounted FAT32 filesystem, which holds the volume's geometry
+/// and performs all accesses to the FAT (file allocation table) and data clusters.
+pub struct FatFileSystem {
+    /// The byte-granular I/O interface to the underlying storage device.
+    io: BlockIo,
+    /// The size of one cluster in bytes.
+    cluster_size: usize,
+    /// The number of copies of the FAT, all of which are kept in sync.
+    num_fats: usize,
+    /// The byte offset of the first FAT.
+    fat_start: usize,
+    /// The size in bytes of each FAT copy.
+    fat_size: usize,
+    /// The byte offset of the first data cluster (cluster 2).
+    data_start: usize,
+    /// The number of data clusters in this volume.
+    cluster_count: u32,
+    /// The first cluster of the root directory.
+    root_cluster: u32,
+    /// The byte offset of the FSInfo sector, if it exists.
+    fs_info_offset: Option<usize>,
+    /// The cluster from which to start searching for a free cluster.
+    next_free_hint: u32,
+    /// Whether the free cluster count in the FSInfo sector has been marked as unknown
+    /// due to this driver modifying the FAT.
+    fs_info_invalidated: bool,
+    /// Whether this volume was mounted read-only, in which case all modifications are rejected.
+    read_only: bool,
+}
+
+/// A reference to a mounted FAT32 filesystem, shared by all of its files and directories.
+pub type FatFsRef = Arc<Mutex<FatFileSystem>>;
+
+impl FatFileSystem {
+    /// Reads the boot sector of the given `device` and initializes a new `FatFileSystem` from it.
+    pub fn new(device: StorageDeviceRef) -> Result<FatFileSystem, &'static str> {
+        let mut io = BlockIo::new(device);
+        let mut boot_sector = [0u8; BOOT_SECTOR_SIZE];
+        if io.read(&mut boot_sector, 0)? != BOOT_SECTOR_SIZE {
+            return Err("couldn't read the FAT32 boot sector");
+        }
+        let bpb = BiosParameterBlock::parse(&boot_sector)?;
+
+        let fat_start = bpb.reserved_sector_count * bpb.bytes_per_sector;
+        let fat_size = bpb.sectors_per_fat * bpb.bytes_per_sector;
+        let data_start_sector = bpb.reserved_sector_count + bpb.num_fats * bpb.sectors_per_fat;
+        if data_start_sector >= bpb.total_sectors {
+            return Err("FAT32 volume has no data region");
+        }
+        let cluster_count = ((bpb.total_sectors - data_start_sector) / bpb.sectors_per_cluster) as u32;
+        // The FAT must be large enough to hold an entry for each cluster.
+        let cluster_count = core::cmp::min(cluster_count, (fat_size / 4) as u32 - FIRST_DATA_CLUSTER);
+
+        let mut fs = FatFileSystem {
+            io,
+            cluster_size: bpb.sectors_per_cluster * bpb.bytes_per_sector,
+            num_fats: bpb.num_fats,
+            fat_start,
+            fat_size,
+            data_start: data_start_sector * bpb.bytes_per_sector,
+            cluster_count,
+            root_cluster: bpb.root_cluster,
+            fs_info_offset: None,
+            next_free_hint: FIRST_DATA_CLUSTER,
+            fs_info_invalidated: false,
+            read_only: false,
+        };
+
+        // The FSInfo sector is optional, but provides a hint about where free clusters are.
+        if bpb.fs_info_sector != 0 && bpb.fs_info_sector != 0xFFFF && bpb.fs_info_sector < bpb.reserved_sector_count {
+            let offset = bpb.fs_info_sector * bpb.bytes_per_sector;
+            let mut fs_info = [0u8; BOOT_SECTOR_SIZE];
+            fs.io.read(&mut fs_info, offset)?;
+            if read_u32(&fs_info, 0) == FSINFO_LEAD_SIGNATURE && read_u32(&fs_info, 484) == FSINFO_STRUCT_SIGNATURE {
+                fs.fs_info_offset = Some(offset);
+                let hint = read_u32(&fs_info, FSINFO_NEXT_FREE_OFFSET);
+                if fs.is_valid_cluster(hint) {
+                    fs.next_free_hint = hint;
+                }
+            }
+        }
+
+        if !fs.is_valid_cluster(fs.root_cluster) {
+            return Err("FAT32 root cluster is out of bounds");
+        }
+        debug!("Found FAT32 volume: {} clusters of {} bytes, {} FATs, root cluster {}",
+            fs.cluster_count, fs.cluster_size, fs.num_fats, fs.root_cluster
+        );
+        Ok(fs)
+    }
+
+    /// Returns the size in bytes of one cluster, the allocation unit of this filesystem.
+    pub fn cluster_size(&self) -> usize {
+        self.cluster_size
+    }
+
+    /// Returns an error if this volume was mounted read-only.
+    fn check_writable(&self) -> Result<(), &'static str> {
+        if self.read_only {
+            Err("FAT32: filesystem is mounted read-only")
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Counts the free clusters in this volume by scanning the entire FAT.
+    fn count_free_clusters(&mut self) -> Result<u32, &'static str> {
+        let mut free_clusters = 0;
+        let mut buffer = vec![0u8; self.cluster_size];
+        let mut offset = FIRST_DATA_CLUSTER as usize * 4;
+        let end = (self.cluster_count + FIRST_DATA_CLUSTER) as usize * 4;
+        while offset < end {
+            let count = core::cmp::min(buffer.len(), end - offset);
+            self.io.read(&mut buffer[..count], self.fat_start + offset)?;
+            free_clusters += buffer[..count].chunks(4)
+                .filter(|entry| read_u32(entry, 0) & FAT_ENTRY_MASK == FREE_CLUSTER)
+                .count() as u32;
+            offset += count;
+        }
+        Ok(free_clusters)
+    }
+
+    /// Returns true if the given `cluster` is a valid data cluster number in this volume.
+    fn is_valid_cluster(&self, cluster: u32) -> bool {
+        cluster >= FIRST_DATA_CLUSTER && cluster < self.cluster_count + FIRST_DATA_CLUSTER
+    }
+
+    /// Returns the byte offset on disk of the start of the given data `cluster`.
+    fn cluster_offset(&self, cluster: u32) -> usize {
+        self.data_start + (cluster - FIRST_DATA_CLUSTER) as usize * self.cluster_size
+    }
+
+    /// Reads the FAT entry for the given `cluster`, i.e., the next cluster in its chain.
+    fn read_fat_entry(&mut self, cluster: u32) -> Result<u32, &'static str> {
+        let mut entry = [0u8; 4];
+        self.io.read(&mut entry, self.fat_start + cluster as usize * 4)?;
+        Ok(read_u32(&entry, 0) & FAT_ENTRY_MASK)
+    }
+
+    /// Writes the given `value` into the FAT entry for the given `cluster`, in every copy of the FAT.
+    /// The reserved top 4 bits of the existing entry are preserved.
+    fn write_fat_entry(&mut self, cluster: u32, value: u32) -> Result<(), &'static str> {
+        self.invalidate_fs_info()?;
+        let mut entry = [0u8; 4];
+        self.io.read(&mut entry, self.fat_start + cluster as usize * 4)?;
+        let new_value = (read_u32(&entry, 0) & !FAT_ENTRY_MASK) | (value & FAT_ENTRY_MASK);
+        write_u32(&mut entry, 0, new_value);
+        for i in 0 .. self.num_fats {
+            self.io.write(&entry, self.fat_start + i * self.fat_size + cluster as usize * 4)?;
+        }
+        Ok(())
+    }
+
+    /// Marks the free cluster count in the FSInfo sector as unknown, since this driver doesn't track it.
+    /// This only happens once, upon the first modification of the FAT.
+    fn invalidate_fs_info(&mut self) -> Result<(), &'static str> {
+        if self.fs_info_invalidated {
+            return Ok(());
+        }
+        self.fs_info_invalidated = true;
+        if let Some(offset) = self.fs_info_offset {
+            let mut unknown = [0u8; 4];
+            write_u32(&mut unknown, 0, FSINFO_UNKNOWN);
+            self.io.write(&unknown, offset + FSINFO_FREE_COUNT_OFFSET)?;
+        }
+        Ok(())
+    }
+
+    /// Returns the list of clusters in the chain that begins with the given `first_cluster`.
+    /// A `first_cluster` of `0` represents an empty chain, e.g., an empty file.
+    fn cluster_chain(&mut self, first_cluster: u32) -> Result<Vec<u32>, &'static str> {
+        let mut chain = Vec::new();
+        let mut cluster = first_cluster;
+        if cluster == FREE_CLUSTER {
+            return Ok(chain);
+        }
+        loop {
+            if !self.is_valid_cluster(cluster) {
+                error!("FAT32: invalid cluster {:#X} in chain starting at {:#X}", cluster, first_cluster);
+                return Err("FAT32: found invalid cluster in cluster chain");
+            }
+            chain.push(cluster);
+            // a chain can't be longer than the number of clusters, so it must have a cycle
+            if chain.len() > self.cluster_count as usize {
+                return Err("FAT32: cluster chain contains a cycle");
+            }
+            let next = self.read_fat_entry(cluster)?;
+            if next >= END_OF_CHAIN_MIN {
+                return Ok(chain);
+            }
+            if next == BAD_CLUSTER || next == FREE_CLUSTER {
+                return Err("FAT32: cluster chain was corrupted");
+            }
+            cluster = next;
+        }
+    }
+
+    /// Finds a free cluster, marks it as the end of a chain,
+    /// and appends it to the chain ending with the given `prev_cluster`, if any.
+    /// If `zero` is true, the contents of the new cluster are cleared.
+    fn allocate_cluster(&mut self, prev_cluster: Option<u32>, zero: bool) -> Result<u32, &'static str> {
+        let start = if self.is_valid_cluster(self.next_free_hint) { self.next_free_hint } else { FIRST_DATA_CLUSTER };
+        let end = self.cluster_count + FIRST_DATA_CLUSTER;
+        let mut found = None;
+        for cluster in (start .. end).chain(FIRST_DATA_CLUSTER .. start) {
+            if self.read_fat_entry(cluster)? == FREE_CLUSTER {
+                found = Some(cluster);
+                break;
+            }
+        }
+        let cluster = found.ok_or("FAT32: no free clusters remain, the volume is full")?;
+        self.write_fat_entry(cluster, END_OF_CHAIN)?;
+        if let Some(prev) = prev_cluster {
+            self.write_fat_entry(prev, cluster)?;
+        }
+        self.next_free_hint = cluster + 1;
+        if zero {
+            let zeros = vec![0u8; self.cluster_size];
+            let offset = self.cluster_offset(cluster);
+            self.io.write(&zeros, offset)?;
+        }
+        Ok(cluster)
+    }
+
+    /// Frees every cluster in the chain that begins with the given `first_cluster`.
+    fn free_chain(&mut self, first_cluster: u32) -> Result<(), &'static str> {
+        for cluster in self.cluster_chain(first_cluster)? {
+            self.write_fat_entry(cluster, FREE_CLUSTER)?;
+            if cluster < self.next_free_hint {
+                self.next_free_hint = cluster;
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads bytes from the data stored in the given cluster `chain`,
+    /// starting at the given byte `offset` from the beginning of the chain.
+    /// The length of the `buffer` determines the number of bytes read.
+    fn read_chain(&mut self, chain: &[u32], buffer: &mut [u8], offset: usize) -> Result<usize, &'static str> {
+        let mut copied = 0;
+        while copied < buffer.len() {
+            let pos = offset + copied;
+            let cluster = *chain.get(pos / self.cluster_size).ok_or("FAT32: read past the end of the cluster chain")?;
+            let offset_in_cluster = pos % self.cluster_size;
+            let count = core::cmp::min(self.cluster_size - offset_in_cluster, buffer.len() - copied);
+            let disk_offset = self.cluster_offset(cluster) + offset_in_cluster;
+            if self.io.read(&mut buffer[copied .. copied + count], disk_offset)? != count {
+                return Err("FAT32: failed to read from storage device");
+            }
+            copied += count;
+        }
+        Ok(copied)
+    }
+
+    /// Writes the given `buffer` into the data stored in the given cluster `chain`,
+    /// starting at the given byte `offset` from the beginning of the chain.
+    /// The chain must already be long enough to hold the written data.
+    fn write_chain(&mut self, chain: &[u32], buffer: &[u8], offset: usize) -> Result<usize, &'static str> {
+        let mut copied = 0;
+        while copied < buffer.len() {
+            let pos = offset + copied;
+            let cluster = *chain.get(pos / self.cluster_size).ok_or("FAT32: write past the end of the cluster chain")?;
+            let offset_in_cluster = pos % self.cluster_size;
+            let count = core::cmp::min(self.cluster_size - offset_in_cluster, buffer.len() - copied);
+            let disk_offset = self.cluster_offset(cluster) + offset_in_cluster;
+            if self.io.write(&buffer[copied .. copied + count], disk_offset)? != count {
+                return Err("FAT32: failed to write to storage device");
+            }
+            copied += count;
+        }
+        Ok(copied)
+    }
+
+    /// Returns the byte offset on disk of the given directory entry `slot` within the directory
+    /// whose clusters are given by `chain`.
+    fn slot_offset(&self, chain: &[u32], slot: usize) -> Result<usize, &'static str> {
+        let slots_per_cluster = self.cluster_size / DIR_ENTRY_SIZE;
+        let cluster = *chain.get(slot / slots_per_cluster).ok_or("FAT32: directory slot out of bounds")?;
+        Ok(self.cluster_offset(cluster) + (slot % slots_per_cluster) * DIR_ENTRY_SIZE)
+    }
+
+    /// Writes the given raw 32-byte directory `entry` at the given byte offset on disk.
+    fn write_dir_entry(&mut self, entry: &[u8; DIR_ENTRY_SIZE], disk_offset: usize) -> Result<(), &'static str> {
+        self.io.write(entry, disk_offset)?;
+        Ok(())
+    }
+
+    /// Reads the raw contents of the directory that begins at `first_cluster`.
+    /// Returns the directory's cluster chain and its contents.
+    fn read_dir_raw(&mut self, first_cluster: u32) -> Result<(Vec<u32>, Vec<u8>), &'static str> {
+        let chain = self.cluster_chain(first_cluster)?;
+        let mut data = vec![0u8; chain.len() * self.cluster_size];
+        self.read_chain(&chain, &mut data, 0)?;
+        Ok((chain, data))
+    }
+
+    /// Reads all of the entries in the directory that begins at `first_cluster`.
+    fn read_dir(&mut self, first_cluster: u32) -> Result<Vec<DirEntryInfo>, &'static str> {
+        let (_chain, data) = self.read_dir_raw(first_cluster)?;
+        Ok(parse_dir_entries(&data))
+    }
+
+    /// Frees all of the clusters used by the directory that begins at `first_cluster`,
+    /// including the clusters of every file and directory within it, recursively.
+    fn free_dir_tree(&mut self, first_cluster: u32, depth: usize) -> Result<(), &'static str> {
+        // a valid directory tree can't be deeper than the number of clusters
+        if depth > self.cluster_count as usize {
+            return Err("FAT32: directory tree contains a cycle");
+        }
+        for entry in self.read_dir(first_cluster)? {
+            if entry.short.is_dir() {
+                self.free_dir_tree(entry.short.first_cluster, depth + 1)?;
+            } else {
+                self.free_chain(entry.short.first_cluster)?;
+            }
+        }
+        self.free_chain(first_cluster)
+    }
+}
+
+
+/// The information about one file or directory entry in a FAT directory.
+#[derive(Clone)]
+struct DirEntryInfo {
+    /// The name of this entry, which is its long name if it has one, otherwise its short name.
+    name: String,
+    /// The short entry that contains the actual metadata of this entry.
+    short: ShortEntry,
+    /// The index of the first slot used by this entry, which is its first long name entry (if any).
+    first_slot: usize,
+    /// The total number of slots used by this entry, including the short entry.
+    /// The short entry is always in the last slot.
+    slot_count: usize,
+}
+
+impl DirEntryInfo {
+    /// The index of the slot that holds this entry's short entry.
+    fn short_slot(&self) -> usize {
+        self.first_slot + self.slot_count - 1
+    }
+}
+
+/// Parses the raw `data` of an entire directory into a list of its entries,
+/// skipping deleted entries, the volume label, and the `.` and `..` entries.
+fn parse_dir_entries(data: &[u8]) -> Vec<DirEntryInfo> {
+    let mut entries = Vec::new();
+    // the long name pieces seen so far, in on-disk order (which is the reverse of the logical order)
+    let mut lfn_pieces: Vec<Vec<u16>> = Vec::new();
+    let mut lfn_start = 0;
+    let mut lfn_checksum = 0;
+    let mut lfn_next_order = 0;
+
+    for (slot, raw) in data.chunks_exact(DIR_ENTRY_SIZE).enumerate() {
+        if raw[0] == END_OF_DIRECTORY_MARKER {
+            break;
+        }
+        if raw[0] == DELETED_ENTRY_MARKER {
+            lfn_pieces.clear();
+            continue;
+        }
+        if is_long_name_entry(raw) {
+            let lfn = LongEntry::parse(raw);
+            if lfn.is_last {
+                lfn_pieces.clear();
+                lfn_start = slot;
+                lfn_checksum = lfn.checksum;
+                lfn_next_order = lfn.order;
+            } else if lfn_pieces.is_empty() || lfn.checksum != lfn_checksum || lfn.order != lfn_next_order {
+                // an orphaned or out-of-order long name entry, ignore it
+                lfn_pieces.clear();
+                continue;
+            }
+            if lfn.order == 0 {
+                lfn_pieces.clear();
+                continue;
+            }
+            lfn_pieces.push(lfn.chars);
+            lfn_next_order = lfn.order - 1;
+            continue;
+        }
+
+        let short = ShortEntry::parse(raw);
+        if short.is_volume_label() || short.is_dot_entry() {
+            lfn_pieces.clear();
+            continue;
+        }
+        let has_valid_lfn = !lfn_pieces.is_empty() && lfn_next_order == 0 && lfn_checksum == short.checksum();
+        let (name, first_slot) = if has_valid_lfn {
+            let ucs2: Vec<u16> = lfn_pieces.iter().rev().flat_map(|p| p.iter().cloned()).collect();
+            (String::from_utf16_lossy(&ucs2), lfn_start)
+        } else {
+            (short.display_name(), slot)
+        };
+        lfn_pieces.clear();
+        entries.push(DirEntryInfo {
+            name,
+            short,
+            first_slot,
+            slot_count: slot - first_slot + 1,
+        });
+    }
+    entries
+}
+
+/// Returns the current time from the real-time clock, encoded as a FAT `(date, time)` pair.
+fn current_fat_timestamp() -> (u16, u16) {
+    let now = Timestamp::now();
+    (
+        encode_date(now.year, now.month, now.day),
+        encode_time(now.hour, now.minute, now.second),
+    )
+}
+
+/// Returns the metadata stored in the given `short` directory entry.
+/// The node is reported as read-only if the entry has the read-only attribute
+/// or if the filesystem it belongs to was mounted read-only.
+fn short_entry_metadata(short: &ShortEntry, fs_read_only: bool) -> Metadata {
+    let (kind, size) = if short.is_dir() {
+        (NodeKind::Directory, 0)
+    } else {
+        (NodeKind::File, short.file_size as usize)
+    };
+    let mut permissions = Permissions::read_write();
+    permissions.set_readonly(fs_read_only || short.attributes.contains(FileAttributes::READ_ONLY));
+    Metadata {
+        kind,
+        size,
+        created: decode_timestamp(short.create_date, short.create_time),
+        // FAT only records the date of the last access, not the time
+        modified: decode_timestamp(short.write_date, short.write_time),
+        accessed: decode_timestamp(short.access_date, 0),
+        permissions,
+    }
+}
+
+/// Returns `true` if the two given references point to the same object, regardless of their types.
+fn same_arc<T: ?Sized, U: ?Sized>(a: &Arc<T>, b: &Arc<U>) -> bool {
+    &**a as *const T as *const u8 == &**b as *const U as *const u8
+}
+
+/// Returns `true` if the two given nodes are the same node.
+fn same_node(a: &FileOrDir, b: &FileOrDir) -> bool {
+    match (a, b) {
+        (FileOrDir::File(a), FileOrDir::File(b)) => same_arc(a, b),
+        (FileOrDir::Dir(a), FileOrDir::Dir(b)) => same_arc(a, b),
+        _ => false,
+    }
+}
+
+/// Returns an error if the given `name` cannot be used for a file or directory in a FAT filesystem.
+fn validate_name(name: &str) -> Result<(), &'static str> {
+    if name.is_empty() || name == "." || name == ".." {
+        return Err("FAT32: invalid file name");
+    }
+    if name.encode_utf16().count() > MAX_LONG_NAME_LENGTH {
+        return Err("FAT32: file name is too long");
+    }
+    if name.chars().any(|c| (c as u32) < 0x20 || INVALID_NAME_CHARS.contains(&c)) {
+        return Err("FAT32: file name contains invalid characters");
+    }
+    Ok(())
+}
+
+
+/// A file or directory node that has been created for an entry in a `FatDirectory`.
+#[derive(Clone)]
+enum FatNode {
+    File(Arc<Mutex<FatFile>>),
+    Dir(Arc<Mutex<FatDirectory>>),
+}
+
+impl FatNode {
+    /// Marks this node and all nodes beneath it as removed from the filesystem,
+    /// and clears this node's parent directory.
+    /// Returns the first cluster of this node.
+    fn mark_removed(&self) -> u32 {
+        match self {
+            FatNode::File(f) => {
+                let mut file = f.lock();
+                file.removed = true;
+                file.parent = Weak::<Mutex<FatDirectory>>::new();
+                file.short.first_cluster
+            }
+            FatNode::Dir(d) => {
+                let mut dir = d.lock();
+                dir.removed = true;
+                dir.parent = Weak::<Mutex<FatDirectory>>::new();
+                let children = core::mem::replace(&mut *dir.children.lock(), BTreeMap::new());
+                for child in children.values() {
+                    child.mark_removed();
+                }
+                dir.first_cluster
+            }
+        }
+    }
+
+    /// Returns the up-to-date copy of this node's short directory entry.
+    fn short_entry(&self) -> Option<ShortEntry> {
+        match self {
+            FatNode::File(f) => Some(f.lock().short.clone()),
+            FatNode::Dir(d) => d.lock().short.clone(),
+        }
+    }
+
+    /// Points this node at the new entry that was written for it,
+    /// which has the given `name` and `short` entry located at `entry_offset` on disk.
+    fn set_entry(&self, name: String, short: ShortEntry, entry_offset: usize) {
+        match self {
+            FatNode::File(f) => {
+                let mut file = f.lock();
+                file.name = name;
+                file.short = short;
+                file.entry_offset = entry_offset;
+            }
+            FatNode::Dir(d) => {
+                let mut dir = d.lock();
+                dir.name = name;
+                dir.short = Some(short);
+                dir.entry_offset = Some(entry_offset);
+            }
+        }
+    }
+
+    fn as_file_or_dir(&self) -> FileOrDir {
+        match self {
+            FatNode::File(f) => FileOrDir::File(f.clone() as FileRef),
+            FatNode::Dir(d) => FileOrDir::Dir(d.clone() as DirRef),
+        }
+    }
+}
+
+
+/// A directory in a FAT32 filesystem.
+pub struct FatDirectory {
+    /// The name of this directory. For the root directory, this is the name it was mounted with.
+    name: String,
+    /// The directory that contains this directory.
+    parent: WeakDirRef,
+    /// The filesystem that this directory belongs to.
+    fs: FatFsRef,
+    /// The first cluster of this directory's contents.
+    first_cluster: u32,
+    /// The byte offset on disk of this directory's own short entry, or `None` for the root directory.
+    entry_offset: Option<usize>,
+    /// This directory's own short entry, or `None` for the root directory, which doesn't have one.
+    short: Option<ShortEntry>,
+    /// A weak reference to this directory itself, used as the parent of its child nodes.
+    self_ref: Weak<Mutex<FatDirectory>>,
+    /// The entries in this directory, read from disk when this directory was created.
+    entries: Vec<DirEntryInfo>,
+    /// The nodes that have been created for the entries in this directory, keyed by entry name.
+    children: Mutex<BTreeMap<String, FatNode>>,
+    /// Whether this directory has been removed from the filesystem.
+    removed: bool,
+}
+
+impl FatDirectory {
+    /// Creates a new directory node for the on-disk directory that begins at `first_cluster`,
+    /// reading in all of its entries.
+    fn new_node(
+        name: String,
+        parent: WeakDirRef,
+        fs: FatFsRef,
+        first_cluster: u32,
+        entry_offset: Option<usize>,
+        short: Option<ShortEntry>,
+    ) -> Result<Arc<Mutex<FatDirectory>>, &'static str> {
+        let entries = fs.lock().read_dir(first_cluster)?;
+        let dir = Arc::new(Mutex::new(FatDirectory {
+            name,
+            parent,
+            fs,
+            first_cluster,
+            entry_offset,
+            short,
+            self_ref: Weak::new(),
+            entries,
+            children: Mutex::new(BTreeMap::new()),
+            removed: false,
+        }));
+        dir.lock().self_ref = Arc::downgrade(&dir);
+        Ok(dir)
+    }
+
+    /// Returns the index of the entry that matches the given `name` (case-insensitively).
+    fn find_entry(&self, name: &str) -> Option<usize> {
+        self.entries.iter().position(|e| e.name.eq_ignore_ascii_case(name))
+    }
+
+    /// Returns the node for the entry at the given index, creating it if necessary.
+    fn node_for_entry(&self, index: usize) -> Result<FatNode, &'static str> {
+        let entry = &self.entries[index];
+        let mut children = self.children.lock();
+        if let Some(node) = children.get(&entry.name) {
+            return Ok(node.clone());
+        }
+
+        let entry_offset = {
+            let mut fs = self.fs.lock();
+            let chain = fs.cluster_chain(self.first_cluster)?;
+            fs.slot_offset(&chain, entry.short_slot())?
+        };
+        let parent = self.self_ref.clone() as WeakDirRef;
+        let node = if entry.short.is_dir() {
+            FatNode::Dir(FatDirectory::new_node(entry.name.clone(), parent, self.fs.clone(), entry.short.first_cluster, Some(entry_offset), Some(entry.short.clone()))?)
+        } else {
+            FatNode::File(Arc::new(Mutex::new(FatFile {
+                name: entry.name.clone(),
+                parent,
+                fs: self.fs.clone(),
+                short: entry.short.clone(),
+                entry_offset,
+                removed: false,
+            })))
+        };
+        children.insert(entry.name.clone(), node.clone());
+        Ok(node)
+    }
+
+    /// Creates a new entry in this directory with the given `name` and `attributes`
+    /// and writes it to disk, along with any long name entries it needs.
+    fn add_entry(&mut self, name: &str, attributes: FileAttributes, first_cluster: u32) -> Result<usize, &'static str> {
+        validate_name(name)?;
+        if self.find_entry(name).is_some() {
+            return Err("FAT32: an entry with that name already exists");
+        }
+        let (date, time) = current_fat_timestamp();
+        self.write_new_entry(name, ShortEntry::new([b' '; 11], attributes, first_cluster, date, time))
+    }
+
+    /// Writes a new entry called `name` to a free run of slots in this directory,
+    /// along with any long name entries it needs, and returns its index in `entries`.
+    /// 
+    /// All fields of the given `short` entry are kept except for its short name,
+    /// which is derived from `name` such that it is unique within this directory.
+    fn write_new_entry(&mut self, name: &str, mut short: ShortEntry) -> Result<usize, &'static str> {
+        // Use the name directly as a short name if possible, otherwise generate a unique short name.
+        let short_name_taken = |sn: &[u8; 11]| self.entries.iter().any(|e| &e.short.name == sn);
+        let (short_name, needs_lfn) = match exact_short_name(name) {
+            Some(sn) if !short_name_taken(&sn) => (sn, false),
+            _ => {
+                let sn = (1 .. 1_000_000)
+                    .map(|tail| generate_short_name(name, tail))
+                    .find(|sn| !short_name_taken(sn))
+                    .ok_or("FAT32: couldn't generate a unique short name")?;
+                (sn, true)
+            }
+        };
+        short.name = short_name;
+        let mut raw_entries = if needs_lfn { long_name_entries(name, short.checksum()) } else { Vec::new() };
+        raw_entries.push(short.to_bytes());
+        let slot_count = raw_entries.len();
+
+        let mut fs = self.fs.lock();
+        let (mut chain, data) = fs.read_dir_raw(self.first_cluster)?;
+
+        // Find a run of consecutive free slots that is long enough to hold all of the new entries.
+        let mut run_start = 0;
+        let mut run_length = 0;
+        for (slot, raw) in data.chunks_exact(DIR_ENTRY_SIZE).enumerate() {
+            if run_length == slot_count {
+                break;
+            }
+            if raw[0] == DELETED_ENTRY_MARKER || raw[0] == END_OF_DIRECTORY_MARKER {
+                if run_length == 0 {
+                    run_start = slot;
+                }
+                run_length += 1;
+            } else {
+                run_length = 0;
+            }
+        }
+        // If there isn't enough room, extend the directory with new (zeroed) clusters.
+        let slots_per_cluster = fs.cluster_size / DIR_ENTRY_SIZE;
+        while run_length < slot_count {
+            let last = chain.last().cloned();
+            let new_cluster = fs.allocate_cluster(last, true)?;
+            if run_length == 0 {
+                run_start = chain.len() * slots_per_cluster;
+            }
+            chain.push(new_cluster);
+            run_length += slots_per_cluster;
+        }
+
+        for (i, raw) in raw_entries.iter().enumerate() {
+            let offset = fs.slot_offset(&chain, run_start + i)?;
+            fs.write_dir_entry(raw, offset)?;
+        }
+        drop(fs);
+
+        self.entries.push(DirEntryInfo {
+            name: String::from(name),
+            short,
+            first_slot: run_start,
+            slot_count,
+        });
+        Ok(self.entries.len() - 1)
+    }
+
+    /// Creates a new empty file called `name` in this directory.
+    pub fn create_file(&mut self, name: &str) -> Result<FileRef, &'static str> {
+        if self.removed {
+            return Err("FAT32: directory has been removed");
+        }
+        self.fs.lock().check_writable()?;
+        let index = self.add_entry(name, FileAttributes::ARCHIVE, 0)?;
+        match self.node_for_entry(index)? {
+            FatNode::File(f) => Ok(f as FileRef),
+            FatNode::Dir(_) => Err("BUG: FAT32 created a directory instead of a file"),
+        }
+    }
+
+    /// Returns `true` if this directory is the given directory `dir` or is somewhere beneath it.
+    fn is_or_is_within(&self, dir: &DirRef) -> bool {
+        if let Some(this) = self.self_ref.upgrade() {
+            if same_arc(&this, dir) {
+                return true;
+            }
+        }
+        let mut ancestor = self.parent.upgrade();
+        while let Some(current) = ancestor {
+            if same_arc(&current, dir) {
+                return true;
+            }
+            ancestor = current.lock().get_parent_dir();
+            // the root directory is its own parent
+            if ancestor.as_ref().map_or(false, |parent| same_arc(parent, &current)) {
+                break;
+            }
+        }
+        false
+    }
+
+    /// Returns a name that no entry in this directory currently uses,
+    /// under which a node can be copied before it is renamed into place.
+    fn temporary_name(&self) -> String {
+        (0..).map(|i| format!("~insert{}.tmp", i))
+            .find(|name| self.find_entry(name).is_none())
+            .unwrap_or_else(|| String::from("~insert.tmp"))
+    }
+
+    /// Creates a new entry called `name` in this directory, and copies the contents of the given `node` into it.
+    fn copy_node(&mut self, name: &str, node: FileOrDir) -> Result<(), &'static str> {
+        match node {
+            FileOrDir::File(src) => {
+                let new_file = self.create_file(name)?;
+                let src = src.lock();
+                let mut buf = vec![0u8; self.fs.lock().cluster_size()];
+                let mut offset = 0;
+                while offset < src.size() {
+                    let count = src.read(&mut buf, offset)?;
+                    if count == 0 {
+                        break;
+                    }
+                    new_file.lock().write(&buf[..count], offset)?;
+                    offset += count;
+                }
+            }
+            FileOrDir::Dir(src) => {
+                let new_dir = self.create_dir(name)?;
+                let child_names = src.lock().list();
+                for child_name in child_names {
+                    let child = src.lock().get(&child_name);
+                    match child {
+                        Some(FileOrDir::Symlink(_)) => warn!("FAT32: skipping symbolic link {:?} while copying a directory", child_name),
+                        Some(child) => { new_dir.lock().insert(child)?; }
+                        None => { }
+                    }
+                }
+            }
+            FileOrDir::Symlink(_) => return Err("FAT32: symbolic links are not supported"),
+        }
+        Ok(())
+    }
+
+    /// Removes the entry with the given `name` from this directory,
+    /// freeing all of the clusters it used.
+    /// Returns the node that was removed, which is no longer usable.
+    fn remove_entry(&mut self, name: &str) -> Result<FileOrDir, &'static str> {
+        self.fs.lock().check_writable()?;
+        let index = self.find_entry(name).ok_or("FAT32: no such entry")?;
+        // make sure a node exists for the entry, so we can return it to the caller
+        let node = self.node_for_entry(index)?;
+        let entry = self.entries.remove(index);
+        self.children.lock().remove(&entry.name);
+
+        // The node's short entry is the most up-to-date, e.g., a file may have been written to.
+        let first_cluster = node.mark_removed();
+
+        self.delete_slots(&entry)?;
+        let mut fs = self.fs.lock();
+        if entry.short.is_dir() {
+            fs.free_dir_tree(first_cluster, 0)?;
+        } else {
+            fs.free_chain(first_cluster)?;
+        }
+        Ok(node.as_file_or_dir())
+    }
+
+    /// Marks all of the on-disk slots used by the given `entry` as deleted,
+    /// without freeing the clusters that it refers to.
+    fn delete_slots(&self, entry: &DirEntryInfo) -> Result<(), &'static str> {
+        let mut fs = self.fs.lock();
+        let chain = fs.cluster_chain(self.first_cluster)?;
+        for slot in entry.first_slot ..= entry.short_slot() {
+            let offset = fs.slot_offset(&chain, slot)?;
+            let mut raw = [0u8; DIR_ENTRY_SIZE];
+            fs.io.read(&mut raw, offset)?;
+            raw[0] = DELETED_ENTRY_MARKER;
+            fs.write_dir_entry(&raw, offset)?;
+        }
+        Ok(())
+    }
+}
+
+impl Directory for FatDirectory {
+    fn get(&self, name: &str) -> Option<FileOrDir> {
+        if self.removed {
+            return None;
+        }
+        let index = self.find_entry(name)?;
+        match self.node_for_entry(index) {
+            Ok(node) => Some(node.as_file_or_dir()),
+            Err(e) => {
+                error!("FatDirectory::get(): error getting {:?}: {}", name, e);
+                None
+            }
+        }
+    }
+
+    /// Creates a new file or directory in this FAT directory with the same name and contents as the given `node`.
+    /// Files are copied in their entirety, and directories are copied recursively.
+    /// If an entry with the same name already exists, it is removed and returned.
+    ///
+    /// The copy is first written under a temporary name and then renamed into place,
+    /// such that an existing entry is not lost if copying fails.
+    /// Inserting a node into the directory that already contains it,
+    /// or inserting a directory into itself or one of its subdirectories, is an error.
+    /// 
+    /// FAT cannot represent symbolic links, so inserting one is an error,
+    /// and symbolic links within a copied directory are skipped.
+    fn insert(&mut self, node: FileOrDir) -> Result<Option<FileOrDir>, &'static str> {
+        if self.removed {
+            return Err("FAT32: directory has been removed");
+        }
+        if let FileOrDir::Symlink(_) = node {
+            return Err("FAT32: symbolic links are not supported");
+        }
+        self.fs.lock().check_writable()?;
+        if let FileOrDir::Dir(ref src) = node {
+            if self.is_or_is_within(src) {
+                return Err("FAT32: cannot copy a directory into itself or one of its subdirectories");
+            }
+        }
+        // This must come after the check above, because getting the name of this directory itself would deadlock.
+        let name = node.get_name();
+        validate_name(&name)?;
+        if let Some(index) = self.find_entry(&name) {
+            if same_node(&self.node_for_entry(index)?.as_file_or_dir(), &node) {
+                return Err("FAT32: cannot insert a node into the directory that already contains it");
+            }
+        }
+
+        let temp_name = self.temporary_name();
+        if let Err(e) = self.copy_node(&temp_name, node) {
+            if self.find_entry(&temp_name).is_some() {
+                if let Err(remove_err) = self.remove_entry(&temp_name) {
+                    error!("FatDirectory::insert(): couldn't remove partial copy {:?}: {}", temp_name, remove_err);
+                }
+            }
+            return Err(e);
+        }
+        self.rename(&temp_name, &name)
+    }
+
+    fn list(&self) -> Vec<String> {
+        self.entries.iter().map(|e| e.name.clone()).collect()
+    }
+
+    /// Creates a new empty subdirectory called `name` in this directory.
+    fn create_dir(&mut self, name: &str) -> Result<DirRef, &'static str> {
+        if self.removed {
+            return Err("FAT32: directory has been removed");
+        }
+        self.fs.lock().check_writable()?;
+        // Every subdirectory starts with a cluster holding its "." and ".." entries.
+        let (date, time) = current_fat_timestamp();
+        let new_cluster = self.fs.lock().allocate_cluster(None, true)?;
+        let dot = ShortEntry::new(*b".          ", FileAttributes::DIRECTORY, new_cluster, date, time);
+        // The ".." entry of a directory in the root directory must point to cluster 0.
+        let parent_cluster = if self.entry_offset.is_none() { 0 } else { self.first_cluster };
+        let dotdot = ShortEntry::new(*b"..         ", FileAttributes::DIRECTORY, parent_cluster, date, time);
+        {
+            let mut fs = self.fs.lock();
+            let offset = fs.cluster_offset(new_cluster);
+            fs.write_dir_entry(&dot.to_bytes(), offset)?;
+            fs.write_dir_entry(&dotdot.to_bytes(), offset + DIR_ENTRY_SIZE)?;
+        }
+
+        let index = match self.add_entry(name, FileAttributes::DIRECTORY, new_cluster) {
+            Ok(i) => i,
+            Err(e) => {
+                let _ = self.fs.lock().free_chain(new_cluster);
+                return Err(e);
+            }
+        };
+        match self.node_for_entry(index)? {
+            FatNode::Dir(d) => Ok(d as DirRef),
+            FatNode::File(_) => Err("BUG: FAT32 created a file instead of a directory"),
+        }
+    }
+
+    /// Renames an entry in place by writing a new entry for it and then deleting the old one,
+    /// such that its contents are not copied and its timestamps are kept.
+    /// If another entry is already called `new_name`, it is removed and returned.
+    fn rename(&mut self, old_name: &str, new_name: &str) -> Result<Option<FileOrDir>, &'static str> {
+        if self.removed {
+            return Err("FAT32: directory has been removed");
+        }
+        self.fs.lock().check_writable()?;
+        validate_name(new_name)?;
+        let index = self.find_entry(old_name).ok_or("FAT32: no such entry")?;
+        if self.entries[index].name == new_name {
+            return Ok(None);
+        }
+        // make sure a node exists for the entry, so that it can be pointed at the new entry
+        let node = self.node_for_entry(index)?;
+        let short = node.short_entry().ok_or("BUG: FAT32 node for an entry had no short entry")?;
+
+        // A new name that only differs in case still refers to the entry being renamed.
+        let replaced = match self.find_entry(new_name) {
+            Some(other) if other != index => Some(self.remove_entry(new_name)?),
+            _ => None,
+        };
+
+        // Write the new entry before deleting the old one, such that the node is never lost from the disk.
+        let new_index = self.write_new_entry(new_name, short)?;
+        let old_index = self.find_entry(old_name).ok_or("FAT32: no such entry")?;
+        let old_entry = self.entries.remove(old_index);
+        self.delete_slots(&old_entry)?;
+
+        // the new entry was appended, so it moved down by one when the old entry was removed
+        let new_entry = &self.entries[new_index - 1];
+        let entry_offset = {
+            let mut fs = self.fs.lock();
+            let chain = fs.cluster_chain(self.first_cluster)?;
+            fs.slot_offset(&chain, new_entry.short_slot())?
+        };
+        node.set_entry(new_entry.name.clone(), new_entry.short.clone(), entry_offset);
+        let mut children = self.children.lock();
+        children.remove(&old_entry.name);
+        children.insert(new_entry.name.clone(), node);
+        Ok(replaced)
+    }
+
+    fn remove(&mut self, node: &FileOrDir) -> Option<FileOrDir> {
+        if self.removed {
+            return None;
+        }
+        match self.remove_entry(&node.get_name()) {
+            Ok(removed) => Some(removed),
+            Err(e) => {
+                error!("FatDirectory::remove(): error removing {:?}: {}", node.get_name(), e);
+                None
+            }
+        }
+    }
+
+    fn metadata(&self) -> Metadata {
+        let fs_read_only = self.fs.lock().read_only;
+        match self.short {
+            Some(ref short) => short_entry_metadata(short, fs_read_only),
+            None => {
+                // the root directory has no directory entry, and thus no timestamps
+                let mut permissions = Permissions::read_write();
+                permissions.set_readonly(fs_read_only);
+                Metadata {
+                    kind: NodeKind::Directory,
+                    size: 0,
+                    created: None,
+                    modified: None,
+                    accessed: None,
+                    permissions,
+                }
+            }
+        }
+    }
+}
+
+impl FsNode for FatDirectory {
+    fn get_name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn get_parent_dir(&self) -> Option<DirRef> {
+        self.parent.upgrade()
+    }
+
+    fn set_parent_dir(&mut self, new_parent: WeakDirRef) {
+        self.parent = new_parent;
+    }
+}
+
+
+/// A file in a FAT32 filesystem.
+pub struct FatFile {
+    /// The name of this file.
+    name: String,
+    /// The directory that contains this file.
+    parent: WeakDirRef,
+    /// The filesystem that this file belongs to.
+    fs: FatFsRef,
+    /// The up-to-date copy of this file's short directory entry,
+    /// which holds its size and first cluster.
+    short: ShortEntry,
+    /// The byte offset on disk of this file's short directory entry.
+    entry_offset: usize,
+    /// Whether this file has been removed from the filesystem.
+    removed: bool,
+}
+
+impl FatFile {
+    /// Writes this file's short entry back to its directory on disk.
+    fn write_entry(&self, fs: &mut FatFileSystem) -> Result<(), &'static str> {
+        fs.write_dir_entry(&self.short.to_bytes(), self.entry_offset)
+    }
+}
+
+impl File for FatFile {
+    fn read(&self, buffer: &mut [u8], offset: usize) -> Result<usize, &'static str> {
+        if self.removed {
+            return Err("FAT32: file has been removed");
+        }
+        let size = self.short.file_size as usize;
+        if offset > size {
+            return Err("read offset exceeds file size");
+        }
+        let count = core::cmp::min(size - offset, buffer.len());
+        if count == 0 {
+            return Ok(0);
+        }
+        let mut fs = self.fs.lock();
+        let chain = fs.cluster_chain(self.short.first_cluster)?;
+        fs.read_chain(&chain, &mut buffer[..count], offset)
+    }
+
+    fn write(&mut self, buffer: &[u8], offset: usize) -> Result<usize, &'static str> {
+        if self.removed {
+            return Err("FAT32: file has been removed");
+        }
+        if self.short.attributes.contains(FileAttributes::READ_ONLY) {
+            return Err("FAT32: file is read-only");
+        }
+        self.fs.lock().check_writable()?;
+        let size = self.short.file_size as usize;
+        if offset > size {
+            return Err("offset out of bounds");
+        }
+        let end = offset + buffer.len();
+        if end > u32::max_value() as usize {
+            return Err("FAT32: files cannot be larger than 4 GiB");
+        }
+        if buffer.is_empty() {
+            return Ok(0);
+        }
+
+        let mut fs = self.fs.lock();
+        let mut chain = fs.cluster_chain(self.short.first_cluster)?;
+        // allocate enough clusters to hold the new end of the file
+        let clusters_needed = (end + fs.cluster_size - 1) / fs.cluster_size;
+        while chain.len() < clusters_needed {
+            let last = chain.last().cloned();
+            let new_cluster = fs.allocate_cluster(last, false)?;
+            if chain.is_empty() {
+                self.short.first_cluster = new_cluster;
+            }
+            chain.push(new_cluster);
+        }
+        let written = fs.write_chain(&chain, buffer, offset)?;
+
+        let (date, time) = current_fat_timestamp();
+        self.short.file_size = core::cmp::max(size, end) as u32;
+        self.short.write_date = date;
+        self.short.write_time = time;
+        self.short.access_date = date;
+        self.short.attributes.insert(FileAttributes::ARCHIVE);
+        self.write_entry(&mut fs)?;
+        Ok(written)
+    }
+
+    fn size(&self) -> usize {
+        self.short.file_size as usize
+    }
+
+    fn as_mapping(&self) -> Result<&MappedPages, &'static str> {
+        Err("cannot treat a FatFile as a memory mapped region")
+    }
+
+    fn metadata(&self) -> Metadata {
+        short_entry_metadata(&self.short, self.fs.lock().read_only)
+    }
+
+    fn truncate(&mut self, size: usize) -> Result<(), &'static str> {
+        if self.removed {
+            return Err("FAT32: file has been removed");
+        }
+        if self.short.attributes.contains(FileAttributes::READ_ONLY) {
+            return Err("FAT32: file is read-only");
+        }
+        self.fs.lock().check_writable()?;
+        if size >= self.short.file_size as usize {
+            return Ok(());
+        }
+
+        let mut fs = self.fs.lock();
+        let chain = fs.cluster_chain(self.short.first_cluster)?;
+        let clusters_needed = (size + fs.cluster_size - 1) / fs.cluster_size;
+        if clusters_needed == 0 {
+            fs.free_chain(self.short.first_cluster)?;
+            self.short.first_cluster = FREE_CLUSTER;
+        } else if clusters_needed < chain.len() {
+            // end the chain at the last needed cluster, then free the rest of it
+            fs.write_fat_entry(chain[clusters_needed - 1], END_OF_CHAIN)?;
+            fs.free_chain(chain[clusters_needed])?;
+        }
+
+        let (date, time) = current_fat_timestamp();
+        self.short.file_size = size as u32;
+        self.short.write_date = date;
+        self.short.write_time = time;
+        self.short.access_date = date;
+        self.short.attributes.insert(FileAttributes::ARCHIVE);
+        self.write_entry(&mut fs)
+    }
+}
+
+impl FsNode for FatFile {
+    fn get_name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn get_parent_dir(&self) -> Option<DirRef> {
+        self.parent.upgrade()
+    }
+
+    fn set_parent_dir(&mut self, new_parent: WeakDirRef) {
+        self.parent = new_parent;
+    }
+}
@@ -0,0 +1,344 @@
+//! Open file handles, which access a `File` through a cursor, and tables of file descriptors that refer to them.
+//!
+//! An [`OpenFile`](struct.OpenFile.html) is created using [`OpenOptions`](struct.OpenOptions.html),
+//! which determine whether it may be read from and/or written to, similar to `std::fs::OpenOptions`.
+//! It keeps track of the current offset into its file, such that it can be used like any other stream
+//! via the `Read`, `Write` and `Seek` traits from `core_io`.
+//!
+//! Each `Task` has its own [`FileDescriptorTable`](struct.FileDescriptorTable.html),
+//! which maps small integers (file descriptors) to shared open file handles.
+//! A new `Task` starts out with a copy of its parent's table,
+//! so both of them refer to the same handles and thus share their cursors.
+
+#![no_std]
+
+#[macro_use] extern crate alloc;
+extern crate spin;
+extern crate core_io;
+extern crate fs_node;
+extern crate heapfile;
+extern crate path;
+
+use alloc::{
+    collections::BTreeMap,
+    string::{String, ToString},
+    sync::Arc,
+};
+use spin::Mutex;
+use core_io::{Read, Write, Seek, SeekFrom, Error, ErrorKind};
+use fs_node::{DirRef, FileRef, FileOrDir};
+use heapfile::HeapFile;
+use path::{Path, PATH_DELIMITER};
+
+
+/// A small integer that refers to an open file in a `FileDescriptorTable`.
+pub type FileDescriptor = usize;
+
+/// The lowest file descriptor that is handed out for open files.
+/// Descriptors `0`, `1` and `2` conventionally refer to stdin, stdout and stderr,
+/// which are provided by the `app_io` streams instead.
+pub const FIRST_FILE_DESCRIPTOR: FileDescriptor = 3;
+
+/// A reference to an open file handle, which can be shared among multiple file descriptors and tasks.
+pub type OpenFileRef = Arc<Mutex<OpenFile>>;
+
+
+/// The options that determine how a file is opened, i.e., its access mode.
+///
+/// By default, all options are `false`, so at least one of `read`, `write` or `append` must be enabled.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct OpenOptions {
+    read: bool,
+    write: bool,
+    append: bool,
+    truncate: bool,
+    create: bool,
+}
+
+impl OpenOptions {
+    /// Creates a new set of options with every option disabled.
+    pub fn new() -> OpenOptions {
+        OpenOptions::default()
+    }
+
+    /// Sets whether the file can be read from.
+    pub fn read(mut self, read: bool) -> OpenOptions {
+        self.read = read;
+        self
+    }
+
+    /// Sets whether the file can be written to.
+    pub fn write(mut self, write: bool) -> OpenOptions {
+        self.write = write;
+        self
+    }
+
+    /// Sets whether every write should be appended to the end of the file, regardless of the cursor.
+    /// This implies that the file can be written to.
+    pub fn append(mut self, append: bool) -> OpenOptions {
+        self.append = append;
+        self
+    }
+
+    /// Sets whether the existing contents of the file should be discarded when it is opened.
+    /// This requires the `write` option, and cannot be combined with the `append` option.
+    pub fn truncate(mut self, truncate: bool) -> OpenOptions {
+        self.truncate = truncate;
+        self
+    }
+
+    /// Sets whether a new empty file should be created if the file doesn't exist.
+    /// This requires either the `write` or the `append` option.
+    pub fn create(mut self, create: bool) -> OpenOptions {
+        self.create = create;
+        self
+    }
+
+    /// Opens the file at the given `path` with these options.
+    /// A relative `path` starts from the given `working_dir`.
+    ///
+    /// If the file doesn't exist and the `create` option is set,
+    /// a new file is created in the directory that the `path` leads to.
+    pub fn open(self, path: &Path, working_dir: &DirRef) -> Result<OpenFile, &'static str> {
+        self.validate()?;
+        let file = match path.get(working_dir) {
+            Some(FileOrDir::File(f)) => f,
+            Some(FileOrDir::Dir(_)) => return Err("cannot open a directory as a file"),
+            // `Path::get()` follows symbolic links, so this is unreachable
+            Some(FileOrDir::Symlink(_)) => return Err("cannot open a symbolic link as a file"),
+            None if self.create => create_file(path, working_dir)?,
+            None => return Err("file not found"),
+        };
+        self.open_file(file)
+    }
+
+    /// Opens the given `file` with these options.
+    /// The `create` option has no effect here, since the file already exists.
+    pub fn open_file(self, file: FileRef) -> Result<OpenFile, &'static str> {
+        self.validate()?;
+        if self.is_writable() {
+            let mut locked_file = file.lock();
+            if locked_file.metadata().permissions.readonly() {
+                return Err("cannot open a read-only file for writing");
+            }
+            if self.truncate {
+                locked_file.truncate(0)?;
+            }
+        }
+        Ok(OpenFile {
+            file,
+            offset: 0,
+            options: self,
+        })
+    }
+
+    /// Returns true if these options allow writing to a file.
+    fn is_writable(self) -> bool {
+        self.write || self.append
+    }
+
+    /// Returns an error if these options don't make sense together.
+    fn validate(self) -> Result<(), &'static str> {
+        if !self.read && !self.is_writable() {
+            return Err("a file must be opened for reading, writing or appending");
+        }
+        if self.truncate && (!self.write || self.append) {
+            return Err("truncating a file requires the write option without the append option");
+        }
+        if self.create && !self.is_writable() {
+            return Err("creating a file requires the write or append option");
+        }
+        Ok(())
+    }
+}
+
+
+/// A handle to an open file, which reads and writes the file at its current offset (its cursor)
+/// and advances the offset accordingly.
+pub struct OpenFile {
+    /// The underlying file.
+    file: FileRef,
+    /// The offset into the file at which the next read or write will occur.
+    offset: usize,
+    /// The options that this file was opened with.
+    options: OpenOptions,
+}
+
+impl OpenFile {
+    /// Returns the underlying file.
+    pub fn file(&self) -> &FileRef {
+        &self.file
+    }
+
+    /// Returns the current offset into the file, at which the next read or write will occur.
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    /// Returns the options that this file was opened with.
+    pub fn options(&self) -> OpenOptions {
+        self.options
+    }
+
+    /// Returns true if this file was opened for reading.
+    pub fn is_readable(&self) -> bool {
+        self.options.read
+    }
+
+    /// Returns true if this file was opened for writing or appending.
+    pub fn is_writable(&self) -> bool {
+        self.options.is_writable()
+    }
+}
+
+impl Read for OpenFile {
+    /// Reads from the file at the current offset, advancing the offset by the number of bytes read.
+    /// Returns `0` once the offset has reached the end of the file.
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+        if !self.is_readable() {
+            return Err(Error::new(ErrorKind::PermissionDenied, "file was not opened for reading"));
+        }
+        let file = self.file.lock();
+        if self.offset >= file.size() {
+            return Ok(0);
+        }
+        let count = file.read(buf, self.offset).map_err(|e| Error::new(ErrorKind::Other, e))?;
+        self.offset += count;
+        Ok(count)
+    }
+}
+
+impl Write for OpenFile {
+    /// Writes to the file at the current offset, or at the end of the file if it was opened for appending,
+    /// and advances the offset past the written bytes.
+    ///
+    /// If the offset was moved beyond the end of the file, the gap is filled with zeros.
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Error> {
+        if !self.is_writable() {
+            return Err(Error::new(ErrorKind::PermissionDenied, "file was not opened for writing"));
+        }
+        let mut file = self.file.lock();
+        let size = file.size();
+        if self.options.append {
+            self.offset = size;
+        }
+        if self.offset > size {
+            let zeros = vec![0u8; self.offset - size];
+            file.write(&zeros, size).map_err(|e| Error::new(ErrorKind::Other, e))?;
+        }
+        let count = file.write(buf, self.offset).map_err(|e| Error::new(ErrorKind::Other, e))?;
+        self.offset += count;
+        Ok(count)
+    }
+
+    /// Files are written directly, so there is nothing to flush.
+    fn flush(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+impl Seek for OpenFile {
+    /// Moves the offset of this open file, which may be beyond the end of the file.
+    /// Returns the new offset from the beginning of the file.
+    fn seek(&mut self, pos: SeekFrom) -> Result<u64, Error> {
+        let new_offset = match pos {
+            SeekFrom::Start(offset) => offset as i64,
+            SeekFrom::End(delta) => self.file.lock().size() as i64 + delta,
+            SeekFrom::Current(delta) => self.offset as i64 + delta,
+        };
+        if new_offset < 0 {
+            return Err(Error::new(ErrorKind::InvalidInput, "cannot seek to a negative offset"));
+        }
+        self.offset = new_offset as usize;
+        Ok(new_offset as u64)
+    }
+}
+
+
+/// A table of the files opened by a task, keyed by file descriptor.
+#[derive(Clone, Default)]
+pub struct FileDescriptorTable {
+    files: BTreeMap<FileDescriptor, OpenFileRef>,
+}
+
+impl FileDescriptorTable {
+    /// Creates a new empty table.
+    pub fn new() -> FileDescriptorTable {
+        FileDescriptorTable::default()
+    }
+
+    /// Adds the given open `file` to this table.
+    /// Returns the new file descriptor, which is the lowest one not currently in use.
+    pub fn insert(&mut self, file: OpenFile) -> FileDescriptor {
+        self.insert_ref(Arc::new(Mutex::new(file)))
+    }
+
+    /// Like [`insert()`](#method.insert), but adds an open file that may already be shared, e.g., by another table.
+    pub fn insert_ref(&mut self, file: OpenFileRef) -> FileDescriptor {
+        let mut fd = FIRST_FILE_DESCRIPTOR;
+        // the keys are sorted, so the first gap is the lowest unused descriptor
+        for &used_fd in self.files.range(FIRST_FILE_DESCRIPTOR ..).map(|(k, _)| k) {
+            if used_fd != fd {
+                break;
+            }
+            fd += 1;
+        }
+        self.files.insert(fd, file);
+        fd
+    }
+
+    /// Returns the open file that the given file descriptor refers to.
+    pub fn get(&self, fd: FileDescriptor) -> Option<OpenFileRef> {
+        self.files.get(&fd).cloned()
+    }
+
+    /// Adds a new file descriptor that refers to the same open file as the given `fd`, such that they share a cursor.
+    pub fn dup(&mut self, fd: FileDescriptor) -> Result<FileDescriptor, &'static str> {
+        let file = self.get(fd).ok_or("invalid file descriptor")?;
+        Ok(self.insert_ref(file))
+    }
+
+    /// Removes the given file descriptor from this table.
+    /// The file is closed once no other descriptor or task refers to it.
+    pub fn close(&mut self, fd: FileDescriptor) -> Result<(), &'static str> {
+        self.files.remove(&fd).map(|_| ()).ok_or("invalid file descriptor")
+    }
+
+    /// Returns an iterator over all file descriptors in this table and the open files they refer to.
+    pub fn iter(&self) -> impl Iterator<Item = (&FileDescriptor, &OpenFileRef)> {
+        self.files.iter()
+    }
+
+    /// Returns the number of file descriptors in this table.
+    pub fn len(&self) -> usize {
+        self.files.len()
+    }
+
+    /// Returns true if this table contains no file descriptors.
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty()
+    }
+}
+
+
+/// Creates a new empty file at the given `path`, whose parent directory must already exist.
+fn create_file(path: &Path, working_dir: &DirRef) -> Result<FileRef, &'static str> {
+    if path.ends_with(PATH_DELIMITER) {
+        return Err("cannot create a file at a directory path");
+    }
+    let (parent, name) = match path.rfind(PATH_DELIMITER) {
+        Some(index) => {
+            let parent_path = if index == 0 { PATH_DELIMITER.to_string() } else { path[.. index].to_string() };
+            let parent = match Path::new(&parent_path).get(working_dir) {
+                Some(FileOrDir::Dir(d)) => d,
+                _ => return Err("the parent directory of the new file doesn't exist"),
+            };
+            (parent, String::from(&path[index + 1 ..]))
+        }
+        None => (Arc::clone(working_dir), path.to_string()),
+    };
+    HeapFile::new(name.clone(), &parent)?;
+    // Some directories (e.g., on-disk filesystems) store a copy of the inserted file rather than the file itself.
+    let file = parent.lock().get_file(&name);
+    file.ok_or("the new file couldn't be found in its parent directory")
+}
@@ -12,7 +12,7 @@ extern crate path;
 
 use alloc::string::ToString;
 use mod_mgmt::CrateNamespace;
-use path::Path;
+use path::PathBuf;
 
 /// Starts the first applications that run in Theseus 
 /// by creating a new "default" application namespace
@@ -27,7 +27,7 @@ pub fn start() -> Result<(), &'static str> {
     let (shell_file, _ns) = CrateNamespace::get_crate_object_file_starting_with(&new_app_ns, "shell-")
         .ok_or("Couldn't find shell application in default app namespace")?;
 
-    let path = Path::new(shell_file.lock().get_absolute_path());
+    let path = PathBuf::from(shell_file.lock().get_absolute_path());
     info!("Starting first application: crate at {:?}", path);
     // Spawn the default shell
     spawn::new_application_task_builder(path, Some(new_app_ns))?
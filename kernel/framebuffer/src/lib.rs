@@ -24,25 +24,66 @@ pub use pixel::*;
 /// The final framebuffer represents the actual pixel content displayed on screen 
 /// because its memory is directly mapped to the VESA display device's underlying physical memory.
 pub fn init<P: Pixel>() -> Result<Framebuffer<P>, &'static str> {
-    // get the graphic mode information
-    let vesa_display_phys_start: PhysicalAddress;
-    let buffer_width: usize;
-    let buffer_height: usize;
-    {
-        let graphic_info = multicore_bringup::GRAPHIC_INFO.lock();
-        if graphic_info.physical_address == 0 {
-            return Err("Fail to get graphic mode infomation!");
-        }
-        vesa_display_phys_start = PhysicalAddress::new(graphic_info.physical_address as usize)?;
-        buffer_width = graphic_info.width as usize;
-        buffer_height = graphic_info.height as usize;
-    };
+    let (buffer_width, buffer_height, vesa_display_phys_start) = graphic_mode_info()?;
 
     // create and return the final framebuffer
     let framebuffer = Framebuffer::new(buffer_width, buffer_height, Some(vesa_display_phys_start))?;
     Ok(framebuffer)
 }
 
+/// Maps the memory of the VESA display device, i.e., the same memory as the final framebuffer,
+/// as raw bytes rather than as pixels, e.g., such that the screen can be exposed as a file.
+/// 
+/// Returns the mapped pages and the number of bytes in them that hold the screen's pixels of type `P`,
+/// which may be fewer than the size of the mapped pages.
+pub fn map_final_buffer<P: Pixel>() -> Result<(MappedPages, usize), &'static str> {
+    let (buffer_width, buffer_height, vesa_display_phys_start) = graphic_mode_info()?;
+    let size = buffer_width * buffer_height * core::mem::size_of::<P>();
+    let mapped_pages = map_buffer(size, Some(vesa_display_phys_start))?;
+    Ok((mapped_pages, size))
+}
+
+/// Returns the width and height in pixels of the screen and the physical address of the VESA display device's memory,
+/// as obtained during boot.
+fn graphic_mode_info() -> Result<(usize, usize, PhysicalAddress), &'static str> {
+    let graphic_info = multicore_bringup::GRAPHIC_INFO.lock();
+    if graphic_info.physical_address == 0 {
+        return Err("Fail to get graphic mode infomation!");
+    }
+    let vesa_display_phys_start = PhysicalAddress::new(graphic_info.physical_address as usize)?;
+    Ok((graphic_info.width as usize, graphic_info.height as usize, vesa_display_phys_start))
+}
+
+/// Maps `size` bytes of memory for a framebuffer, 
+/// which is the physical memory at the given `physical_address` if provided.
+/// See [`Framebuffer::new()`](struct.Framebuffer.html#method.new).
+fn map_buffer(size: usize, physical_address: Option<PhysicalAddress>) -> Result<MappedPages, &'static str> {
+    // get a reference to the kernel's memory mapping information
+    let kernel_mmi_ref = memory::get_kernel_mmi_ref().ok_or("KERNEL_MMI was not yet initialized!")?;
+    let allocator = get_frame_allocator_ref().ok_or("Couldn't get Frame Allocator")?;
+
+    let vesa_display_flags: EntryFlags =
+        EntryFlags::PRESENT | EntryFlags::WRITABLE | EntryFlags::GLOBAL | EntryFlags::NO_CACHE;
+
+    let pages = memory::allocate_pages_by_bytes(size).ok_or("could not allocate pages")?;
+
+    if let Some(address) = physical_address {
+        let frame = FrameRange::from_phys_addr(address, size);
+        kernel_mmi_ref.lock().page_table.map_allocated_pages_to(
+            pages,
+            frame,
+            vesa_display_flags,
+            allocator.lock().deref_mut()
+        )
+    } else {
+        kernel_mmi_ref.lock().page_table.map_allocated_pages(
+            pages,
+            vesa_display_flags,
+            allocator.lock().deref_mut()
+        )
+    }
+}
+
 /// A framebuffer is a region of memory interpreted as a 2-D array of pixels.
 /// The memory buffer is a rectangular region with a width and height.
 #[derive(Hash)]
@@ -64,31 +105,8 @@ impl<P: Pixel> Framebuffer<P> {
         height: usize,
         physical_address: Option<PhysicalAddress>,
     ) -> Result<Framebuffer<P>, &'static str> {
-        // get a reference to the kernel's memory mapping information
-        let kernel_mmi_ref = memory::get_kernel_mmi_ref().ok_or("KERNEL_MMI was not yet initialized!")?;
-        let allocator = get_frame_allocator_ref().ok_or("Couldn't get Frame Allocator")?;
-
-        let vesa_display_flags: EntryFlags =
-            EntryFlags::PRESENT | EntryFlags::WRITABLE | EntryFlags::GLOBAL | EntryFlags::NO_CACHE;
-
         let size = width * height * core::mem::size_of::<P>();
-        let pages = memory::allocate_pages_by_bytes(size).ok_or("could not allocate pages")?;
-
-        let mapped_framebuffer = if let Some(address) = physical_address {
-            let frame = FrameRange::from_phys_addr(address, size);
-            kernel_mmi_ref.lock().page_table.map_allocated_pages_to(
-                pages,
-                frame,
-                vesa_display_flags,
-                allocator.lock().deref_mut()
-            )?
-        } else {
-            kernel_mmi_ref.lock().page_table.map_allocated_pages(
-                pages,
-                vesa_display_flags,
-                allocator.lock().deref_mut()
-            )?
-        };
+        let mapped_framebuffer = map_buffer(size, physical_address)?;
 
         // obtain a slice reference to the framebuffer's memory
         let buffer = BoxRefMut::new(Box::new(mapped_framebuffer))
@@ -7,19 +7,30 @@
 //! Note that both File and Directory extend from FsNode, which is a trait that defines
 //! common methods for both Files and Directories to enhance code reuse 
 //! 
+//! A directory can also contain symbolic links, which extend from FsNode as well
+//! and merely hold the path of another node. They are followed by the `path` crate when resolving paths.
+//! 
 //! Some functions return an enum FileOrDir; this allows us to seamlessly call functions on the return types of
 //! other filesystem functions, and then we simply match on the FSnode to extract the concrete type
 //! to perform the desired function
+//! 
+//! Files and directories can also be watched, in which case they deliver an [`FsEvent`](enum.FsEvent.html)
+//! to each of their watchers whenever they change; see the `fs_watch` crate for receiving those events over a channel.
 
 #[macro_use] extern crate alloc;
 extern crate spin;
 extern crate memory;
+extern crate rtc;
 
+use core::cell::Cell;
+use core::fmt;
+use alloc::boxed::Box;
 use alloc::string::String;
 use alloc::vec::Vec;
 use spin::Mutex;
 use alloc::sync::{Arc, Weak};
 use memory::MappedPages;
+use rtc::RtcTime;
 
 
 /// A reference to any type that implements the Directory trait.
@@ -30,6 +41,8 @@ pub type WeakDirRef = Weak<Mutex<dyn Directory + Send>>;
 pub type FileRef = Arc<Mutex<dyn File + Send>>;
 /// A weak reference to any type that implements the File trait.
 pub type WeakFileRef = Weak<Mutex<dyn File + Send>>;
+/// A reference to any type that implements the Symlink trait.
+pub type SymlinkRef = Arc<Mutex<dyn Symlink + Send>>;
 
 
 /// A trait that covers any filesystem node, both files and directories.
@@ -60,6 +73,17 @@ pub trait FsNode {
     /// This is useful for ensuring correctness when inserting or remonving 
     /// files or directories from their parent directory.
     fn set_parent_dir(&mut self, new_parent: WeakDirRef);
+
+    /// Changes the name of this node to `new_name`.
+    /// 
+    /// This only changes the node itself, so it should only be used by the directory that contains this node,
+    /// i.e., when renaming or moving it. See [`Directory::rename()`](trait.Directory.html#method.rename)
+    /// and [`move_node()`](fn.move_node.html).
+    /// 
+    /// The default implementation returns an error, for nodes whose name cannot be changed.
+    fn set_name(&mut self, _new_name: String) -> Result<(), &'static str> {
+        Err("this node cannot be renamed")
+    }
 } 
 
 // Trait for files, implementors of File must also implement FsNode
@@ -76,6 +100,47 @@ pub trait File : FsNode {
 
     /// Returns a view of this file as an immutable memory-mapped region.
     fn as_mapping(&self) -> Result<&MappedPages, &'static str>;
+
+    /// Returns the metadata of this file, e.g., its size, timestamps and permissions.
+    fn metadata(&self) -> Metadata;
+
+    /// Shrinks this file to the given `size` in bytes, discarding all of its contents beyond that.
+    /// If this file is not larger than `size`, it is left unchanged.
+    /// 
+    /// The default implementation returns an error, for files that cannot be truncated.
+    fn truncate(&mut self, _size: usize) -> Result<(), &'static str> {
+        Err("this file cannot be truncated")
+    }
+
+    /// Registers the given `sink` to receive an event whenever this file is modified or renamed.
+    /// 
+    /// The default implementation returns an error, for files that cannot be watched.
+    fn watch(&mut self, _sink: Box<dyn FsEventSink>) -> Result<(), &'static str> {
+        Err("this file does not support watching")
+    }
+}
+
+/// Trait for symbolic links, implementors of Symlink must also implement FsNode
+pub trait Symlink : FsNode {
+    /// Returns the path that this symbolic link points to,
+    /// which is either absolute or relative to the directory that contains this link.
+    /// 
+    /// The target need not exist, in which case this link is dangling.
+    fn target(&self) -> String;
+
+    /// Returns the metadata of this symbolic link, whose size is the length of its target.
+    /// 
+    /// The default implementation reports a writable link without any timestamps.
+    fn metadata(&self) -> Metadata {
+        Metadata {
+            kind: NodeKind::Symlink,
+            size: self.target().len(),
+            created: None,
+            modified: None,
+            accessed: None,
+            permissions: Permissions::read_write(),
+        }
+    }
 }
 
 /// Trait for directories, implementors of Directory must also implement FsNode
@@ -99,6 +164,16 @@ pub trait Directory : FsNode {
         }
     }
 
+    /// Like [`get()`], but only looks for symbolic links matching the given `name` in this `Directory`.
+    /// 
+    /// Note that none of these methods follow symbolic links; use `path::Path::get()` for that.
+    fn get_symlink(&self, name: &str) -> Option<SymlinkRef> {
+        match self.get(name) {
+            Some(FileOrDir::Symlink(s)) => Some(s),
+            _ => None,
+        }
+    }
+
     /// Inserts the given new file or directory into this directory.
     /// If an existing node has the same name, that node is replaced and returned.
     /// 
@@ -118,6 +193,61 @@ pub trait Directory : FsNode {
 
     /// Lists the names of the nodes in this directory.
     fn list(&self) -> Vec<String>;
+
+    /// Renames the node called `old_name` in this directory to `new_name`, keeping the node itself intact.
+    /// If another node is already called `new_name`, that node is replaced and returned,
+    /// and its parent directory is cleared, just like [`insert()`](#method.insert).
+    /// 
+    /// To move a node into a different directory, use [`move_node()`](fn.move_node.html).
+    /// 
+    /// The default implementation returns an error, for directories whose contents cannot be renamed.
+    fn rename(&mut self, _old_name: &str, _new_name: &str) -> Result<Option<FileOrDir>, &'static str> {
+        Err("this directory does not support renaming")
+    }
+
+    /// Creates a new empty subdirectory called `name` in this directory and returns it.
+    /// 
+    /// Unlike creating a `VFSDirectory` and inserting it, this creates the subdirectory natively in this directory,
+    /// e.g., on disk, such that the returned directory is the one that actually exists in this directory.
+    /// 
+    /// The default implementation returns an error, for directories that cannot create their own subdirectories.
+    fn create_dir(&mut self, _name: &str) -> Result<DirRef, &'static str> {
+        Err("this directory does not support creating subdirectories")
+    }
+
+    /// Removes the node called `name` from this directory without destroying it and returns it,
+    /// such that it can be inserted into another directory.
+    /// The returned node's parent directory reference is cleared.
+    /// 
+    /// Unlike [`remove()`](#method.remove), this must not delete the node's contents,
+    /// so directories whose nodes cannot exist outside of them (e.g., those stored on disk)
+    /// keep the default implementation, which returns an error.
+    fn detach(&mut self, _name: &str) -> Result<FileOrDir, &'static str> {
+        Err(DETACH_UNSUPPORTED)
+    }
+
+    /// Registers the given `sink` to receive an event whenever a node is created in, removed from,
+    /// or renamed within this directory. Changes to the contents of the nodes in this directory are not reported;
+    /// those nodes must be watched individually.
+    /// 
+    /// The default implementation returns an error, for directories that cannot be watched.
+    fn watch(&mut self, _sink: Box<dyn FsEventSink>) -> Result<(), &'static str> {
+        Err("this directory does not support watching")
+    }
+
+    /// Returns the metadata of this directory.
+    /// 
+    /// The default implementation reports a writable directory without any timestamps.
+    fn metadata(&self) -> Metadata {
+        Metadata {
+            kind: NodeKind::Directory,
+            size: 0,
+            created: None,
+            modified: None,
+            accessed: None,
+            permissions: Permissions::read_write(),
+        }
+    }
 }
 
 /// Allows us to return a generic type that can be matched by the caller to extract the underlying type
@@ -125,6 +255,18 @@ pub trait Directory : FsNode {
 pub enum FileOrDir {
     File(FileRef),
     Dir(DirRef),
+    Symlink(SymlinkRef),
+}
+
+impl FileOrDir {
+    /// Returns the metadata of the underlying file or directory.
+    pub fn metadata(&self) -> Metadata {
+        match self {
+            FileOrDir::File(file) => file.lock().metadata(),
+            FileOrDir::Dir(dir) => dir.lock().metadata(),
+            FileOrDir::Symlink(symlink) => symlink.lock().metadata(),
+        }
+    }
 }
 
 // Allows us to call methods directly on an enum so we don't have to match on the underlying type
@@ -134,6 +276,7 @@ impl FsNode for FileOrDir {
         match self {
             FileOrDir::File(file) => file.lock().get_absolute_path(),
             FileOrDir::Dir(dir) => dir.lock().get_absolute_path(),
+            FileOrDir::Symlink(symlink) => symlink.lock().get_absolute_path(),
         }
     }
 
@@ -141,6 +284,7 @@ impl FsNode for FileOrDir {
         match self {
             FileOrDir::File(file) => file.lock().get_name(),
             FileOrDir::Dir(dir) => dir.lock().get_name(),
+            FileOrDir::Symlink(symlink) => symlink.lock().get_name(),
         }
     }
 
@@ -148,6 +292,7 @@ impl FsNode for FileOrDir {
         match self {
             FileOrDir::File(file) => file.lock().get_parent_dir(),
             FileOrDir::Dir(dir) => dir.lock().get_parent_dir(),
+            FileOrDir::Symlink(symlink) => symlink.lock().get_parent_dir(),
         }
     }
 
@@ -155,6 +300,332 @@ impl FsNode for FileOrDir {
         match self {
             FileOrDir::File(file) => file.lock().set_parent_dir(new_parent),
             FileOrDir::Dir(dir) => dir.lock().set_parent_dir(new_parent),
+            FileOrDir::Symlink(symlink) => symlink.lock().set_parent_dir(new_parent),
+        }
+    }
+
+    fn set_name(&mut self, new_name: String) -> Result<(), &'static str> {
+        match self {
+            FileOrDir::File(file) => file.lock().set_name(new_name),
+            FileOrDir::Dir(dir) => dir.lock().set_name(new_name),
+            FileOrDir::Symlink(symlink) => symlink.lock().set_name(new_name),
+        }
+    }
+}
+
+
+/// The error returned by the default implementation of [`Directory::detach()`](trait.Directory.html#method.detach),
+/// upon which callers of [`move_node()`](fn.move_node.html) can fall back to copying the node and removing the original.
+pub const DETACH_UNSUPPORTED: &str = "nodes cannot be moved out of this directory";
+
+/// Moves the node called `name` from the `source` directory into the `dest` directory,
+/// where it will be called `new_name`, and sets its parent directory to `dest`.
+/// If `dest` already contains a node called `new_name`, that node is replaced and returned.
+/// 
+/// If `source` and `dest` are the same directory, this simply renames the node using [`Directory::rename()`].
+/// Otherwise, the node is [`detach()`]ed from `source` and inserted into `dest`,
+/// with both directories locked throughout, such that no other task can observe the node in neither or both of them.
+/// If any step fails, the node is put back into `source` under its original name.
+/// 
+/// A directory cannot be moved into itself or any of its subdirectories.
+/// 
+/// The locks on both directories and on the node must not be held, because they will be acquired within this function.
+/// 
+/// [`Directory::rename()`]: trait.Directory.html#method.rename
+/// [`detach()`]: trait.Directory.html#method.detach
+pub fn move_node(source: &DirRef, name: &str, dest: &DirRef, new_name: &str) -> Result<Option<FileOrDir>, &'static str> {
+    if Arc::ptr_eq(source, dest) {
+        return source.lock().rename(name, new_name);
+    }
+    let node = source.lock().get(name).ok_or("no such file or directory")?;
+    if let FileOrDir::Dir(ref dir) = node {
+        if is_within(dest, dir) {
+            return Err("cannot move a directory into itself");
+        }
+    }
+
+    // Always lock the two directories in the same order to avoid deadlocking with a concurrent move.
+    let source_addr = &**source as *const Mutex<dyn Directory + Send> as *const u8 as usize;
+    let dest_addr = &**dest as *const Mutex<dyn Directory + Send> as *const u8 as usize;
+    let (mut locked_source, mut locked_dest) = if source_addr < dest_addr {
+        let s = source.lock();
+        (s, dest.lock())
+    } else {
+        let d = dest.lock();
+        (source.lock(), d)
+    };
+
+    let mut node = locked_source.detach(name)?;
+    let result = node.set_name(String::from(new_name)).and_then(|_| {
+        node.set_parent_dir(Arc::downgrade(dest));
+        locked_dest.insert(node.clone())
+    });
+    if result.is_err() {
+        // undo the move, which cannot fail because the node was just detached from `source`
+        let _ = node.set_name(String::from(name));
+        node.set_parent_dir(Arc::downgrade(source));
+        let _ = locked_source.insert(node);
+    }
+    result
+}
+
+/// Returns true if the given `dir` is the given `ancestor` directory or is beneath it.
+pub fn is_within(dir: &DirRef, ancestor: &DirRef) -> bool {
+    let mut curr_dir = Arc::clone(dir);
+    loop {
+        if Arc::ptr_eq(&curr_dir, ancestor) {
+            return true;
         }
+        let parent = match curr_dir.lock().get_parent_dir() {
+            Some(p) => p,
+            None => return false,
+        };
+        // Only the root directory is its own parent.
+        if Arc::ptr_eq(&parent, &curr_dir) {
+            return false;
+        }
+        curr_dir = parent;
+    }
+}
+
+
+/// The kind of a filesystem node.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NodeKind {
+    File,
+    Directory,
+    Symlink,
+    /// A file that gives access to a storage device.
+    BlockDevice,
+    /// A file that gives access to any other device, e.g., a serial port or the screen.
+    CharDevice,
+}
+
+/// Information about a filesystem node, as returned by `File::metadata()`, `Directory::metadata()` and `Symlink::metadata()`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Metadata {
+    /// Whether the node is a file, a directory, a symbolic link or a device file.
+    pub kind: NodeKind,
+    /// The size in bytes of the node's contents; this is `0` for most directories.
+    pub size: usize,
+    /// When the node was created, if known.
+    pub created: Option<Timestamp>,
+    /// When the node's contents were last modified, if known.
+    pub modified: Option<Timestamp>,
+    /// When the node's contents were last accessed, if known.
+    pub accessed: Option<Timestamp>,
+    /// The access permissions of the node.
+    pub permissions: Permissions,
+}
+
+impl Metadata {
+    /// Creates the metadata for a file with the given `size`, `timestamps` and `permissions`.
+    pub fn file(size: usize, timestamps: &Timestamps, permissions: Permissions) -> Metadata {
+        Metadata {
+            kind: NodeKind::File,
+            size,
+            created: Some(timestamps.created()),
+            modified: Some(timestamps.modified()),
+            accessed: Some(timestamps.accessed()),
+            permissions,
+        }
+    }
+
+    /// Returns true if this metadata describes a file.
+    pub fn is_file(&self) -> bool {
+        self.kind == NodeKind::File
+    }
+
+    /// Returns true if this metadata describes a directory.
+    pub fn is_dir(&self) -> bool {
+        self.kind == NodeKind::Directory
+    }
+
+    /// Returns true if this metadata describes a symbolic link.
+    pub fn is_symlink(&self) -> bool {
+        self.kind == NodeKind::Symlink
+    }
+
+    /// Returns true if this metadata describes a block or character device file.
+    pub fn is_device(&self) -> bool {
+        self.kind == NodeKind::BlockDevice || self.kind == NodeKind::CharDevice
+    }
+}
+
+/// The access permissions of a filesystem node.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Permissions {
+    readonly: bool,
+}
+
+impl Permissions {
+    /// Returns permissions that allow a node to be both read and written.
+    pub fn read_write() -> Permissions {
+        Permissions { readonly: false }
+    }
+
+    /// Returns permissions that only allow a node to be read.
+    pub fn read_only() -> Permissions {
+        Permissions { readonly: true }
+    }
+
+    /// Returns true if these permissions forbid writing to a node.
+    pub fn readonly(&self) -> bool {
+        self.readonly
+    }
+
+    /// Sets whether these permissions forbid writing to a node.
+    pub fn set_readonly(&mut self, readonly: bool) {
+        self.readonly = readonly;
+    }
+}
+
+/// A calendar date and time of day, as obtained from the real-time clock.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Timestamp {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+}
+
+impl Timestamp {
+    /// Returns the current date and time according to the real-time clock.
+    pub fn now() -> Timestamp {
+        Timestamp::from(rtc::read_rtc())
+    }
+}
+
+impl From<RtcTime> for Timestamp {
+    fn from(time: RtcTime) -> Timestamp {
+        Timestamp {
+            // the RTC only provides the last two digits of the year
+            year: 2000 + time.years as u16,
+            month: time.months,
+            day: time.days,
+            hour: time.hours,
+            minute: time.minutes,
+            second: time.seconds,
+        }
+    }
+}
+
+impl fmt::Display for Timestamp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:04}-{:02}-{:02} {:02}:{:02}:{:02}", self.year, self.month, self.day, self.hour, self.minute, self.second)
+    }
+}
+
+/// The creation, modification and access times of a node,
+/// which can be embedded into an implementation of the `File` trait to keep track of them.
+/// 
+/// The access time can be updated through an immutable reference,
+/// since `File::read()` only has immutable access to the file.
+#[derive(Debug, Clone)]
+pub struct Timestamps {
+    created: Timestamp,
+    modified: Timestamp,
+    accessed: Cell<Timestamp>,
+}
+
+impl Timestamps {
+    /// Creates a new set of timestamps, all of which are set to the current time.
+    pub fn new() -> Timestamps {
+        let now = Timestamp::now();
+        Timestamps {
+            created: now,
+            modified: now,
+            accessed: Cell::new(now),
+        }
+    }
+
+    /// Returns when the node was created.
+    pub fn created(&self) -> Timestamp {
+        self.created
+    }
+
+    /// Returns when the node was last modified.
+    pub fn modified(&self) -> Timestamp {
+        self.modified
+    }
+
+    /// Returns when the node was last accessed.
+    pub fn accessed(&self) -> Timestamp {
+        self.accessed.get()
+    }
+
+    /// Records that the node was just accessed.
+    pub fn mark_accessed(&self) {
+        self.accessed.set(Timestamp::now());
+    }
+
+    /// Records that the node was just modified, which also counts as an access.
+    pub fn mark_modified(&mut self) {
+        let now = Timestamp::now();
+        self.modified = now;
+        self.accessed.set(now);
+    }
+}
+
+impl Default for Timestamps {
+    fn default() -> Timestamps {
+        Timestamps::new()
+    }
+}
+
+
+/// A change to a watched file or directory.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FsEvent {
+    /// A node with the given name was created in or inserted into the watched directory,
+    /// possibly replacing an existing node with the same name.
+    Created(String),
+    /// The node with the given name was removed from the watched directory.
+    Removed(String),
+    /// The contents of the watched file were modified, e.g., written to or truncated.
+    Modified,
+    /// A node in the watched directory, or the watched file itself, was renamed from `from` to `to`.
+    Renamed { from: String, to: String },
+}
+
+/// A destination for the events of a watched file or directory, e.g., the sending end of a channel.
+pub trait FsEventSink : Send {
+    /// Delivers the given `event` to this sink.
+    /// 
+    /// Returns `false` if this sink no longer accepts any events, e.g., because its receiver was dropped,
+    /// in which case it is unregistered from the node that it was watching.
+    fn deliver(&self, event: FsEvent) -> bool;
+}
+
+/// The sinks that are watching a node,
+/// which can be embedded into an implementation of the `File` or `Directory` trait to support watching it.
+#[derive(Default)]
+pub struct Watchers {
+    sinks: Vec<Box<dyn FsEventSink>>,
+}
+
+impl Watchers {
+    /// Creates an empty set of watchers.
+    pub fn new() -> Watchers {
+        Watchers {
+            sinks: Vec::new(),
+        }
+    }
+
+    /// Registers the given `sink` to receive all future events.
+    pub fn add(&mut self, sink: Box<dyn FsEventSink>) {
+        self.sinks.push(sink);
+    }
+
+    /// Returns true if nothing is watching the node.
+    pub fn is_empty(&self) -> bool {
+        self.sinks.is_empty()
+    }
+
+    /// Delivers the given `event` to every sink, and unregisters those that no longer accept events.
+    pub fn notify(&mut self, event: FsEvent) {
+        self.sinks.retain(|sink| sink.deliver(event.clone()));
     }
 }
@@ -0,0 +1,80 @@
+//! Delivers the change events of watched files and directories over asynchronous channels.
+//!
+//! The `fs_node` crate defines how nodes are watched, i.e., by registering an [`FsEventSink`] with them,
+//! but cannot depend on any channel, because channels depend on task management, which depends on the filesystem.
+//! This crate connects the two, such that a task can simply block on the receiving end of a channel
+//! until a watched file or directory changes, rather than polling it.
+//!
+//! # Example
+//! ```rust,ignore
+//! let events = fs_watch::watch_dir(&namespace_dir)?;
+//! while let Ok(event) = events.receive() {
+//!     if let FsEvent::Created(name) = event {
+//!         info!("new crate object file: {}", name);
+//!     }
+//! }
+//! ```
+//!
+//! [`FsEventSink`]: ../fs_node/trait.FsEventSink.html
+
+#![no_std]
+
+#[macro_use] extern crate log;
+extern crate alloc;
+extern crate fs_node;
+extern crate async_channel;
+
+use alloc::boxed::Box;
+use async_channel::{Sender, Receiver, ChannelError};
+use fs_node::{DirRef, FileRef, FileOrDir, FsEvent, FsEventSink};
+
+
+/// The number of events that can be waiting in a watch channel before further events are dropped.
+pub const WATCH_CHANNEL_CAPACITY: usize = 64;
+
+
+/// Starts watching the given directory, returning the channel through which its events will be received.
+///
+/// The directory stops delivering events to the channel once the returned `Receiver` is dropped.
+pub fn watch_dir(dir: &DirRef) -> Result<Receiver<FsEvent>, &'static str> {
+    let (sender, receiver) = async_channel::new_channel(WATCH_CHANNEL_CAPACITY);
+    dir.lock().watch(Box::new(ChannelSink(sender)))?;
+    Ok(receiver)
+}
+
+/// Starts watching the given file, returning the channel through which its events will be received.
+///
+/// The file stops delivering events to the channel once the returned `Receiver` is dropped.
+pub fn watch_file(file: &FileRef) -> Result<Receiver<FsEvent>, &'static str> {
+    let (sender, receiver) = async_channel::new_channel(WATCH_CHANNEL_CAPACITY);
+    file.lock().watch(Box::new(ChannelSink(sender)))?;
+    Ok(receiver)
+}
+
+/// Starts watching the given file or directory; see [`watch_file()`](fn.watch_file.html)
+/// and [`watch_dir()`](fn.watch_dir.html).
+pub fn watch(node: &FileOrDir) -> Result<Receiver<FsEvent>, &'static str> {
+    match node {
+        FileOrDir::File(file) => watch_file(file),
+        FileOrDir::Dir(dir) => watch_dir(dir),
+        FileOrDir::Symlink(_) => Err("symbolic links cannot be watched"),
+    }
+}
+
+
+/// An event sink that sends each event through the sending end of a channel.
+struct ChannelSink(Sender<FsEvent>);
+
+impl FsEventSink for ChannelSink {
+    fn deliver(&self, event: FsEvent) -> bool {
+        // Never block here, because the watched node is locked while its events are delivered.
+        match self.0.try_send(event) {
+            Ok(()) => true,
+            Err((event, ChannelError::ChannelFull)) => {
+                warn!("fs_watch: dropping event {:?} because the watcher's channel is full", event);
+                true
+            }
+            Err(_) => false,
+        }
+    }
+}
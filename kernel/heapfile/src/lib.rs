@@ -13,12 +13,13 @@ extern crate fs_node;
 
 
 use alloc::{
+    boxed::Box,
     vec::Vec,
     sync::Arc,
     string::String,
 };
 use spin::Mutex;
-use fs_node::{FileOrDir, FileRef, DirRef, WeakDirRef, File, FsNode};
+use fs_node::{FileOrDir, FileRef, DirRef, WeakDirRef, File, FsNode, Metadata, Permissions, Timestamps, FsEvent, FsEventSink, Watchers};
 use memory::MappedPages;
 
 /// A file in memory that is backed by the heap, i.e., a `Vec`.
@@ -29,6 +30,10 @@ pub struct HeapFile {
     vec: Vec<u8>,
     /// The parent directory that contains this file.
     parent: WeakDirRef,
+    /// When this file was created, last modified and last accessed.
+    timestamps: Timestamps,
+    /// The sinks that are notified when this file is modified or renamed.
+    watchers: Watchers,
 }
 
 impl HeapFile {
@@ -45,6 +50,8 @@ impl HeapFile {
             name: name, 
             vec: vec, 
             parent: Arc::downgrade(parent), 
+            timestamps: Timestamps::new(),
+            watchers: Watchers::new(),
         };
         let file_ref = Arc::new(Mutex::new(hf)) as FileRef;
         parent.lock().insert(FileOrDir::File(file_ref.clone()))?;
@@ -59,7 +66,8 @@ impl File for HeapFile {
         }
         // read from the offset until the end of the file, but not more than the buffer length
         let read_bytes = core::cmp::min(self.vec.len() - offset, buffer.len());
-        buffer[..read_bytes].copy_from_slice(&self.vec[offset..read_bytes]); 
+        buffer[..read_bytes].copy_from_slice(&self.vec[offset .. offset + read_bytes]); 
+        self.timestamps.mark_accessed();
         Ok(read_bytes) 
     }
 
@@ -71,6 +79,8 @@ impl File for HeapFile {
         // optimization for first write of an empty HeapFile
         if self.vec.is_empty() {
             self.vec = buffer.to_vec();
+            self.timestamps.mark_modified();
+            self.watchers.notify(FsEvent::Modified);
             return Ok(buffer.len());
         }
         
@@ -89,6 +99,8 @@ impl File for HeapFile {
         else {
             // no reallocation needed
         }
+        self.timestamps.mark_modified();
+        self.watchers.notify(FsEvent::Modified);
         Ok(buffer.len())
     }
 
@@ -99,6 +111,24 @@ impl File for HeapFile {
     fn as_mapping(&self) -> Result<&MappedPages, &'static str> {
         Err("Mapping a HeapFile as a MappedPages object is unimplemented")
     }
+
+    fn metadata(&self) -> Metadata {
+        Metadata::file(self.vec.len(), &self.timestamps, Permissions::read_write())
+    }
+
+    fn truncate(&mut self, size: usize) -> Result<(), &'static str> {
+        if size < self.vec.len() {
+            self.vec.truncate(size);
+            self.timestamps.mark_modified();
+            self.watchers.notify(FsEvent::Modified);
+        }
+        Ok(())
+    }
+
+    fn watch(&mut self, sink: Box<dyn FsEventSink>) -> Result<(), &'static str> {
+        self.watchers.add(sink);
+        Ok(())
+    }
     
 }
 
@@ -114,4 +144,10 @@ impl FsNode for HeapFile {
     fn set_parent_dir(&mut self, new_parent: WeakDirRef) {
         self.parent = new_parent;
     }
+
+    fn set_name(&mut self, new_name: String) -> Result<(), &'static str> {
+        let old_name = core::mem::replace(&mut self.name, new_name);
+        self.watchers.notify(FsEvent::Renamed { from: old_name, to: self.name.clone() });
+        Ok(())
+    }
 }
@@ -0,0 +1,92 @@
+//! Parsing of cpio archives in the "new" portable ASCII format (`newc`),
+//! with either the `070701` magic number or the `070702` magic number, which adds a checksum that is ignored here.
+//!
+//! Each entry consists of a 110-byte header, followed by the NUL-terminated path of the entry and then its contents,
+//! where both the path and the contents are padded to a multiple of 4 bytes.
+//! The archive ends with an entry called `TRAILER!!!`.
+
+use alloc::string::ToString;
+use alloc::vec::Vec;
+use core::str;
+use super::{Entry, EntryKind};
+
+const HEADER_SIZE: usize = 110;
+const MAGIC_NEWC: &[u8] = b"070701";
+const MAGIC_CRC: &[u8] = b"070702";
+const TRAILER_NAME: &str = "TRAILER!!!";
+
+/// The index of each 8-digit hexadecimal field in the header, after the 6-byte magic number.
+const MODE_FIELD: usize = 1;
+const FILESIZE_FIELD: usize = 6;
+const NAMESIZE_FIELD: usize = 11;
+
+/// The bits of the mode field that specify the kind of the entry.
+const S_IFMT: usize = 0o170000;
+const S_IFDIR: usize = 0o040000;
+const S_IFREG: usize = 0o100000;
+const S_IFLNK: usize = 0o120000;
+
+
+/// Returns `true` if the given `archive` starts with a cpio header in the "new" ASCII format.
+pub fn is_cpio(archive: &[u8]) -> bool {
+    archive.starts_with(MAGIC_NEWC) || archive.starts_with(MAGIC_CRC)
+}
+
+/// Parses the entries of the given cpio `archive`.
+pub fn parse(archive: &[u8]) -> Result<Vec<Entry>, &'static str> {
+    let mut entries = Vec::new();
+    let mut offset = 0;
+    loop {
+        let header = archive.get(offset .. offset + HEADER_SIZE).ok_or("initramfs: cpio archive ended without a trailer")?;
+        if !is_cpio(header) {
+            return Err("initramfs: invalid magic number in cpio header");
+        }
+        let mode = hex_field(header, MODE_FIELD)?;
+        let file_size = hex_field(header, FILESIZE_FIELD)?;
+        let name_size = hex_field(header, NAMESIZE_FIELD)?;
+
+        // the name size includes the terminating NUL byte
+        let name_start = offset + HEADER_SIZE;
+        let name_bytes = archive.get(name_start .. name_start + name_size).ok_or("initramfs: cpio entry name extends past the end of the archive")?;
+        let name_bytes = name_bytes.split(|&b| b == 0).next().unwrap_or(name_bytes);
+        let path = str::from_utf8(name_bytes).map_err(|_e| "initramfs: cpio entry name was not valid UTF-8")?;
+        if path == TRAILER_NAME {
+            break;
+        }
+
+        let contents_start = round_up_to_4(name_start + name_size);
+        let contents = archive.get(contents_start .. contents_start + file_size).ok_or("initramfs: cpio entry extends past the end of the archive")?;
+
+        let kind = match mode & S_IFMT {
+            S_IFREG => EntryKind::File(contents),
+            S_IFDIR => EntryKind::Directory,
+            S_IFLNK => EntryKind::Symlink(str::from_utf8(contents).map_err(|_e| "initramfs: cpio symlink target was not valid UTF-8")?),
+            _ => EntryKind::Other,
+        };
+        entries.push(Entry { path: path.to_string(), kind });
+
+        offset = round_up_to_4(contents_start + file_size);
+    }
+    Ok(entries)
+}
+
+
+/// Parses the 8-digit hexadecimal field at the given `index` in the `header`.
+fn hex_field(header: &[u8], index: usize) -> Result<usize, &'static str> {
+    let start = MAGIC_NEWC.len() + index * 8;
+    let mut value = 0;
+    for &b in &header[start .. start + 8] {
+        let digit = match b {
+            b'0' ..= b'9' => b - b'0',
+            b'a' ..= b'f' => b - b'a' + 10,
+            b'A' ..= b'F' => b - b'A' + 10,
+            _ => return Err("initramfs: invalid hexadecimal number in cpio header"),
+        };
+        value = (value << 4) | digit as usize;
+    }
+    Ok(value)
+}
+
+fn round_up_to_4(offset: usize) -> usize {
+    (offset + 3) & !3
+}
@@ -0,0 +1,173 @@
+//! Support for an initial RAM filesystem (initramfs), an archive that the bootloader loads as a module
+//! and that is unpacked at boot into a tree of in-memory files and directories.
+//!
+//! This allows arbitrary files, e.g., configuration files, test data and scripts, to be bundled together
+//! into a single bootloader module rather than passing each one as a separate module.
+//!
+//! A module is treated as an initramfs archive if its name starts with [`INITRAMFS_MODULE_PREFIX`](constant.INITRAMFS_MODULE_PREFIX.html),
+//! e.g., `initramfs.tar`. The following archive formats are supported, which are detected from the archive's contents:
+//! * the POSIX ustar format (and the old v7 tar format), as created by `tar --format=ustar`,
+//! * the "new" portable ASCII cpio format, with or without checksums, as created by `cpio -o -H newc`.
+//!
+//! Regular files become `MemFile`s, directories become `VFSDirectory`s and symbolic links become `VFSSymlink`s.
+//! Other kinds of archive entries, e.g., device files and hard links, are skipped,
+//! as are entries within the [reserved directories](constant.RESERVED_ROOT_NAMES.html) of the root directory.
+
+#![no_std]
+
+#[macro_use] extern crate alloc;
+#[macro_use] extern crate log;
+extern crate memory;
+extern crate fs_node;
+extern crate vfs_node;
+extern crate memfs;
+extern crate root;
+
+mod tar;
+mod cpio;
+
+use core::ops::DerefMut;
+use alloc::string::{String, ToString};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use memory::{MemoryManagementInfo, MappedPages, EntryFlags, allocate_pages_by_bytes, get_frame_allocator_ref};
+use fs_node::{DirRef, FileOrDir};
+use vfs_node::{VFSDirectory, VFSSymlink};
+use memfs::MemFile;
+
+
+/// The prefix of the name of a bootloader module that is an initramfs archive, e.g., `initramfs.tar` or `initramfs.cpio`.
+pub const INITRAMFS_MODULE_PREFIX: &'static str = "initramfs";
+
+/// The names of the directories in the root directory that are created after the initramfs is unpacked,
+/// namely those of `devfs`, `sys_fs` and `task_fs`, each of which would replace an unpacked node with the same name.
+/// Archive entries at or below these names are skipped when unpacking into the root directory.
+pub const RESERVED_ROOT_NAMES: &[&str] = &["dev", "sys", "tasks"];
+
+/// Returns `true` if the bootloader module with the given `module_name` is an initramfs archive
+/// rather than a crate object file.
+pub fn is_initramfs_module(module_name: &str) -> bool {
+    module_name.starts_with(INITRAMFS_MODULE_PREFIX) && !module_name.contains('#')
+}
+
+
+/// The kind of an entry in an archive, along with its contents.
+#[derive(Debug)]
+pub enum EntryKind<'a> {
+    /// A regular file with the given contents.
+    File(&'a [u8]),
+    /// A directory.
+    Directory,
+    /// A symbolic link to the given target path.
+    Symlink(&'a str),
+    /// Any other kind of entry, e.g., a device file or a hard link, which is not unpacked.
+    Other,
+}
+
+/// An entry in an archive, i.e., a file, directory or symbolic link at a certain path.
+#[derive(Debug)]
+pub struct Entry<'a> {
+    /// The path of this entry, relative to the directory that the archive is unpacked into.
+    pub path: String,
+    /// What kind of entry this is, along with its contents.
+    pub kind: EntryKind<'a>,
+}
+
+
+/// Parses the given `archive`, which may be in any of the supported formats,
+/// and returns its entries in the order that they appear in the archive.
+pub fn parse(archive: &[u8]) -> Result<Vec<Entry>, &'static str> {
+    if cpio::is_cpio(archive) {
+        cpio::parse(archive)
+    } else if tar::is_tar(archive) {
+        tar::parse(archive)
+    } else {
+        Err("initramfs: unknown archive format, expected tar or cpio")
+    }
+}
+
+/// Unpacks the given `archive` into the `dest` directory, creating any missing directories along the way.
+/// Existing directories are reused, but existing files and symbolic links are replaced.
+/// If `dest` is the root directory, entries within the [reserved directories](constant.RESERVED_ROOT_NAMES.html) are skipped.
+///
+/// The `kernel_mmi` is used to map writable memory for each file, such that the unpacked files can be modified later.
+/// It is passed in explicitly because the initramfs is typically unpacked while the kernel's `MemoryManagementInfo` is locked.
+///
+/// Returns the number of entries that were unpacked.
+pub fn unpack(archive: &[u8], dest: &DirRef, kernel_mmi: &mut MemoryManagementInfo) -> Result<usize, &'static str> {
+    let is_root = Arc::ptr_eq(dest, root::get_root());
+    let mut unpacked = 0;
+    for entry in parse(archive)? {
+        let mut components: Vec<&str> = entry.path.split('/')
+            .filter(|c| !c.is_empty() && *c != ".")
+            .collect();
+        if components.contains(&"..") {
+            warn!("initramfs: skipping entry {:?} that is outside of the destination directory", entry.path);
+            continue;
+        }
+        if is_root && components.first().map_or(false, |c| RESERVED_ROOT_NAMES.contains(c)) {
+            warn!("initramfs: skipping entry {:?} in a reserved directory of the root directory", entry.path);
+            continue;
+        }
+        let name = match components.pop() {
+            Some(name) => name.to_string(),
+            None => continue, // the destination directory itself, e.g., "./"
+        };
+        let parent = get_or_create_dirs(dest, &components)?;
+
+        match entry.kind {
+            EntryKind::File(contents) => {
+                let mp = map_contents(contents, kernel_mmi)?;
+                MemFile::from_mapped_pages(mp, name, contents.len(), &parent)?;
+            }
+            EntryKind::Directory => {
+                get_or_create_dir(&parent, &name)?;
+            }
+            EntryKind::Symlink(target) => {
+                VFSSymlink::new(name, target.to_string(), &parent)?;
+            }
+            EntryKind::Other => {
+                warn!("initramfs: skipping entry {:?} of an unsupported kind", entry.path);
+                continue;
+            }
+        }
+        unpacked += 1;
+    }
+    Ok(unpacked)
+}
+
+
+/// Returns the directory at the end of the given path `components` relative to `dir`,
+/// creating each directory along the way that doesn't exist yet.
+fn get_or_create_dirs(dir: &DirRef, components: &[&str]) -> Result<DirRef, &'static str> {
+    let mut dir = dir.clone();
+    for component in components {
+        dir = get_or_create_dir(&dir, component)?;
+    }
+    Ok(dir)
+}
+
+/// Returns the directory called `name` in the given `parent` directory, creating it if it doesn't exist yet.
+fn get_or_create_dir(parent: &DirRef, name: &str) -> Result<DirRef, &'static str> {
+    let existing = parent.lock().get(name);
+    match existing {
+        Some(FileOrDir::Dir(dir)) => Ok(dir),
+        Some(_) => {
+            error!("initramfs: {:?} already exists in {:?} but is not a directory", name, parent.lock().get_absolute_path());
+            Err("initramfs: a path component in the archive is not a directory")
+        }
+        None => VFSDirectory::new(String::from(name), parent),
+    }
+}
+
+/// Copies the given `contents` of a file into newly-mapped writable pages.
+fn map_contents(contents: &[u8], kernel_mmi: &mut MemoryManagementInfo) -> Result<MappedPages, &'static str> {
+    if contents.is_empty() {
+        return Ok(MappedPages::empty());
+    }
+    let allocator = get_frame_allocator_ref().ok_or("couldn't get Frame Allocator")?;
+    let pages = allocate_pages_by_bytes(contents.len()).ok_or("couldn't allocate pages for initramfs file")?;
+    let mut mp = kernel_mmi.page_table.map_allocated_pages(pages, EntryFlags::WRITABLE, allocator.lock().deref_mut())?;
+    mp.as_slice_mut::<u8>(0, contents.len())?.copy_from_slice(contents);
+    Ok(mp)
+}
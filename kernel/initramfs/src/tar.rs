@@ -0,0 +1,110 @@
+//! Parsing of tar archives in the POSIX ustar format, which is also compatible with the old v7 tar format.
+//!
+//! A tar archive is a sequence of 512-byte blocks. Each entry consists of a header block
+//! followed by the entry's contents, padded to a multiple of the block size.
+//! The archive ends with (at least) one block of all zeros.
+
+use alloc::string::ToString;
+use alloc::vec::Vec;
+use core::str;
+use super::{Entry, EntryKind};
+
+const BLOCK_SIZE: usize = 512;
+
+const NAME: (usize, usize) = (0, 100);
+const SIZE: (usize, usize) = (124, 136);
+const CHECKSUM: (usize, usize) = (148, 156);
+const TYPEFLAG: usize = 156;
+const LINKNAME: (usize, usize) = (157, 257);
+const MAGIC: (usize, usize) = (257, 262);
+const PREFIX: (usize, usize) = (345, 500);
+
+const USTAR_MAGIC: &[u8] = b"ustar";
+
+
+/// Returns `true` if the given `archive` starts with a valid tar header block.
+pub fn is_tar(archive: &[u8]) -> bool {
+    archive.len() >= BLOCK_SIZE && checksum_is_valid(&archive[..BLOCK_SIZE])
+}
+
+/// Parses the entries of the given tar `archive`.
+pub fn parse(archive: &[u8]) -> Result<Vec<Entry>, &'static str> {
+    let mut entries = Vec::new();
+    let mut offset = 0;
+    while offset + BLOCK_SIZE <= archive.len() {
+        let header = &archive[offset .. offset + BLOCK_SIZE];
+        if header.iter().all(|&b| b == 0) {
+            // the end-of-archive marker
+            break;
+        }
+        if !checksum_is_valid(header) {
+            return Err("initramfs: invalid checksum in tar header");
+        }
+
+        let size = parse_octal(field(header, SIZE))?;
+        let contents_start = offset + BLOCK_SIZE;
+        let contents_end = contents_start.checked_add(size).ok_or("initramfs: tar entry size is too large")?;
+        let contents = archive.get(contents_start .. contents_end).ok_or("initramfs: tar entry extends past the end of the archive")?;
+
+        // In the ustar format, long paths are split into a prefix and a name.
+        let name = str_field(header, NAME)?;
+        let prefix = if &header[MAGIC.0 .. MAGIC.1] == USTAR_MAGIC { str_field(header, PREFIX)? } else { "" };
+        let path = if prefix.is_empty() { name.to_string() } else { format!("{}/{}", prefix, name) };
+
+        let kind = match header[TYPEFLAG] {
+            b'0' | b'\0' | b'7' => {
+                // old tar formats mark directories only by a trailing slash on a regular file entry
+                if path.ends_with('/') { EntryKind::Directory } else { EntryKind::File(contents) }
+            }
+            b'5' => EntryKind::Directory,
+            b'2' => EntryKind::Symlink(str_field(header, LINKNAME)?),
+            _ => EntryKind::Other,
+        };
+        entries.push(Entry { path, kind });
+
+        offset = contents_start + round_up_to_block(size);
+    }
+    Ok(entries)
+}
+
+
+/// Returns the bytes of the given `field` in the `header`, up to the first NUL byte, if any.
+fn field(header: &[u8], field: (usize, usize)) -> &[u8] {
+    let bytes = &header[field.0 .. field.1];
+    let len = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+    &bytes[..len]
+}
+
+/// Returns the given text `field` in the `header`.
+fn str_field(header: &[u8], f: (usize, usize)) -> Result<&str, &'static str> {
+    str::from_utf8(field(header, f)).map_err(|_e| "initramfs: tar header field was not valid UTF-8")
+}
+
+/// Parses an octal number, which may be surrounded by spaces.
+fn parse_octal(bytes: &[u8]) -> Result<usize, &'static str> {
+    let mut value: usize = 0;
+    for &b in bytes.iter().skip_while(|&&b| b == b' ').take_while(|&&b| b != b' ') {
+        if b < b'0' || b > b'7' {
+            return Err("initramfs: invalid octal number in tar header");
+        }
+        value = value.checked_mul(8).ok_or("initramfs: octal number in tar header is too large")? + (b - b'0') as usize;
+    }
+    Ok(value)
+}
+
+/// Returns `true` if the checksum stored in the given `header` block matches its contents,
+/// i.e., the sum of all header bytes with the checksum field itself treated as spaces.
+fn checksum_is_valid(header: &[u8]) -> bool {
+    let expected = match parse_octal(field(header, CHECKSUM)) {
+        Ok(checksum) => checksum,
+        Err(_e) => return false,
+    };
+    let actual: usize = header.iter().enumerate()
+        .map(|(i, &b)| if i >= CHECKSUM.0 && i < CHECKSUM.1 { b' ' as usize } else { b as usize })
+        .sum();
+    expected == actual
+}
+
+fn round_up_to_block(size: usize) -> usize {
+    (size + BLOCK_SIZE - 1) / BLOCK_SIZE * BLOCK_SIZE
+}
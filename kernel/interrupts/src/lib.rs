@@ -20,6 +20,7 @@ extern crate gdt;
 extern crate exceptions_early;
 extern crate pic;
 extern crate scheduler;
+extern crate sleep;
 extern crate keyboard;
 extern crate mouse;
 extern crate ps2;
@@ -147,8 +148,9 @@ fn set_handlers(idt: &mut Idt) {
     idt[0x2B].set_handler_fn(unimplemented_interrupt_handler);
     idt[0x2C].set_handler_fn(ps2_mouse_handler);
     idt[0x2D].set_handler_fn(unimplemented_interrupt_handler);
-    idt[0x2E].set_handler_fn(primary_ata_handler);
-    idt[0x2F].set_handler_fn(secondary_ata_handler);
+    // 0x2E and 0x2F (the primary and secondary ATA channels) are reserved for the ata driver, which replaces these handlers
+    idt[0x2E].set_handler_fn(default_handler(0x2E));
+    idt[0x2F].set_handler_fn(default_handler(0x2F));
 
     idt[apic::APIC_SPURIOUS_INTERRUPT_VECTOR as usize].set_handler_fn(apic_spurious_interrupt_handler); 
     idt[tlb_shootdown::TLB_SHOOTDOWN_IPI_IRQ as usize].set_handler_fn(ipi_handler);
@@ -185,8 +187,8 @@ pub fn init_handlers_pic() {
 pub fn register_interrupt(interrupt_num: u8, func: HandlerFunc) -> Result<(), &'static str> {
     let mut idt = IDT.lock();
 
-    // checks if the handler stored is the default apic handler which signifies that the interrupt hasn't been used yet
-    if idt[interrupt_num as usize].handler_eq(unimplemented_interrupt_handler) {
+    // checks if the handler stored is the default handler which signifies that the interrupt hasn't been used yet
+    if idt[interrupt_num as usize].handler_eq(default_handler(interrupt_num)) {
         idt[interrupt_num as usize].set_handler_fn(func);
         Ok(())
     }
@@ -224,7 +226,7 @@ pub fn deregister_interrupt(interrupt_num: u8, func: HandlerFunc) -> Result<(),
     // check if the handler stored is the same as the one provided
     // this is to make sure no other application can deregister your interrupt
     if idt[interrupt_num as usize].handler_eq(func) {
-        idt[interrupt_num as usize].set_handler_fn(unimplemented_interrupt_handler);
+        idt[interrupt_num as usize].set_handler_fn(default_handler(interrupt_num));
         Ok(())
     }
     else {
@@ -233,6 +235,26 @@ pub fn deregister_interrupt(interrupt_num: u8, func: HandlerFunc) -> Result<(),
     }
 }
 
+/// Returns `true` if a handler other than the default handler
+/// is currently registered for the given interrupt number.
+///
+/// Only meaningful for interrupts at or above 32, since lower numbers are CPU exceptions.
+pub fn is_interrupt_registered(interrupt_num: u8) -> bool {
+    !IDT.lock()[interrupt_num as usize].handler_eq(default_handler(interrupt_num))
+}
+
+/// Returns the handler that the given interrupt number has while no driver has registered it.
+///
+/// This is the unimplemented handler for all interrupts except those reserved for a specific driver,
+/// which `register_msi_interrupt()` must never hand out because that driver registers them later.
+fn default_handler(interrupt_num: u8) -> HandlerFunc {
+    match interrupt_num {
+        0x2E => primary_ata_handler,
+        0x2F => secondary_ata_handler,
+        _ => unimplemented_interrupt_handler,
+    }
+}
+
 /// Send an end of interrupt signal, which works for all types of interrupt chips (APIC, x2apic, PIC)
 /// irq arg is only used for PIC
 pub fn eoi(irq: Option<u8>) {
@@ -338,6 +360,9 @@ extern "x86-interrupt" fn lapic_timer_handler(_stack_frame: &mut ExceptionStackF
     // we must acknowledge the interrupt first before handling it because we switch tasks here, which doesn't return
     eoi(None); // None, because 0x22 IRQ cannot possibly be a PIC interrupt
     
+    // wake up any sleeping tasks whose deadline has passed, so they can be chosen by the scheduler below
+    sleep::timer_tick();
+
     scheduler::schedule();
 }
 
@@ -429,7 +454,7 @@ extern "x86-interrupt" fn pic_spurious_interrupt_handler(_stack_frame: &mut Exce
 // }
 
 
-/// 0x2E
+/// 0x2E, reserved for the primary ATA channel until the ata driver registers its own handler.
 extern "x86-interrupt" fn primary_ata_handler(_stack_frame: &mut ExceptionStackFrame ) {
     info!("Primary ATA Interrupt (0x2E)");
 
@@ -437,7 +462,7 @@ extern "x86-interrupt" fn primary_ata_handler(_stack_frame: &mut ExceptionStackF
 }
 
 
-/// 0x2F
+/// 0x2F, reserved for the secondary ATA channel until the ata driver registers its own handler.
 extern "x86-interrupt" fn secondary_ata_handler(_stack_frame: &mut ExceptionStackFrame ) {
     info!("Secondary ATA Interrupt (0x2F)");
     
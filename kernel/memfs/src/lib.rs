@@ -14,8 +14,9 @@ extern crate irq_safety;
 
 // use alloc::vec::Vec;
 use core::ops::DerefMut;
+use alloc::boxed::Box;
 use alloc::string::String;
-use fs_node::{DirRef, WeakDirRef, File, FsNode};
+use fs_node::{DirRef, WeakDirRef, File, FsNode, Metadata, Permissions, Timestamps, FsEvent, FsEventSink, Watchers};
 use memory::{MappedPages, get_kernel_mmi_ref, allocate_pages_by_bytes, get_frame_allocator_ref, EntryFlags};
 use alloc::sync::Arc;
 use spin::Mutex;
@@ -32,6 +33,10 @@ pub struct MemFile {
     mp: MappedPages,
     /// The parent directory that contains this file.
     parent: WeakDirRef,
+    /// When this file was created, last modified and last accessed.
+    timestamps: Timestamps,
+    /// The sinks that are notified when this file is modified or renamed.
+    watchers: Watchers,
 }
 
 impl MemFile {
@@ -48,11 +53,31 @@ impl MemFile {
             size: size, 
             mp: mapped_pages, 
             parent: Arc::downgrade(parent), 
+            timestamps: Timestamps::new(),
+            watchers: Watchers::new(),
         };
         let file_ref = Arc::new(Mutex::new(memfile)) as FileRef;
         parent.lock().insert(FileOrDir::File(file_ref.clone()))?; // adds the newly created file to the tree
         Ok(file_ref)
     }
+
+    /// Creates a new `MemFile` holding a copy of the given `contents`, which considers `parent` to be its parent directory
+    /// but is **not** inserted into it.
+    ///
+    /// This is useful for an in-memory copy of a file in a directory that cannot hold a `MemFile` itself,
+    /// e.g., a directory on disk, which stores its own copy of any file inserted into it.
+    pub fn new_detached(name: String, contents: &[u8], parent: &DirRef) -> Result<FileRef, &'static str> {
+        let mut memfile = MemFile {
+            name: name,
+            size: 0,
+            mp: MappedPages::empty(),
+            parent: Arc::downgrade(parent),
+            timestamps: Timestamps::new(),
+            watchers: Watchers::new(),
+        };
+        memfile.write(contents, 0)?;
+        Ok(Arc::new(Mutex::new(memfile)) as FileRef)
+    }
 }
 
 impl File for MemFile {
@@ -64,6 +89,7 @@ impl File for MemFile {
         // read from the offset until the end of the file, but not more than the buffer length
         let read_bytes = core::cmp::min(self.size - offset, buffer.len());
         buffer[..read_bytes].copy_from_slice(self.mp.as_slice(offset, read_bytes)?); 
+        self.timestamps.mark_accessed();
         Ok(read_bytes) 
     }
 
@@ -84,6 +110,8 @@ impl File for MemFile {
             if end > self.size { 
                 self.size = end; 
             }
+            self.timestamps.mark_modified();
+            self.watchers.notify(FsEvent::Modified);
             Ok(buffer.len()) // we wrote all of the requested bytes successfully
         } 
         // if not, we need to reallocate a new mapped pages 
@@ -125,6 +153,8 @@ impl File for MemFile {
             }
             self.mp = new_mapped_pages;
             self.size = end;
+            self.timestamps.mark_modified();
+            self.watchers.notify(FsEvent::Modified);
             Ok(buffer.len())
         }
     }
@@ -137,6 +167,34 @@ impl File for MemFile {
     fn as_mapping(&self) -> Result<&MappedPages, &'static str> {
         Ok(&self.mp)
     }
+
+    fn metadata(&self) -> Metadata {
+        // a file backed by existing non-writable pages, e.g., a crate object file, cannot be written to
+        let permissions = if !self.mp.flags().is_writable() && self.mp.size_in_bytes() != 0 {
+            Permissions::read_only()
+        } else {
+            Permissions::read_write()
+        };
+        Metadata::file(self.size, &self.timestamps, permissions)
+    }
+
+    fn truncate(&mut self, size: usize) -> Result<(), &'static str> {
+        if !self.mp.flags().is_writable() && self.mp.size_in_bytes() != 0 {
+            return Err("MemFile::truncate(): existing MappedPages were not writable");
+        }
+        // the underlying MappedPages are kept, so a later write can reuse them
+        if size < self.size {
+            self.size = size;
+            self.timestamps.mark_modified();
+            self.watchers.notify(FsEvent::Modified);
+        }
+        Ok(())
+    }
+
+    fn watch(&mut self, sink: Box<dyn FsEventSink>) -> Result<(), &'static str> {
+        self.watchers.add(sink);
+        Ok(())
+    }
     
 }
 
@@ -152,5 +210,11 @@ impl FsNode for MemFile {
     fn set_parent_dir(&mut self, new_parent: WeakDirRef) {
         self.parent = new_parent;
     }
+
+    fn set_name(&mut self, new_name: String) -> Result<(), &'static str> {
+        let old_name = core::mem::replace(&mut self.name, new_name);
+        self.watchers.notify(FsEvent::Renamed { from: old_name, to: self.name.clone() });
+        Ok(())
+    }
 }
 
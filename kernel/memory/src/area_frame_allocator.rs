@@ -34,6 +34,14 @@ impl<T: Clone> VectorArray<T> {
         }
     }
 
+    /// Returns the elements that are currently stored, regardless of whether they're in an array or a vector.
+    pub fn as_slice(&self) -> &[T] {
+        match self {
+            VectorArray::Array((count, arr)) => &arr[..*count],
+            VectorArray::Vector(v) => &v[..],
+        }
+    }
+
     // pub fn iter(&self) -> ::core::slice::Iter<T> {
     //     match self {
     //         &VectorArray::Array((_count, arr)) => arr.iter(),
@@ -56,6 +64,8 @@ pub struct AreaFrameAllocator {
     current_area: Option<PhysicalMemoryArea>,
     available: VectorArray<PhysicalMemoryArea>,
     occupied: VectorArray<PhysicalMemoryArea>,
+    /// The number of frames that have been allocated so far, including those wasted by `allocate_frames()`.
+    allocated_frames: usize,
 }
 
 impl AreaFrameAllocator {
@@ -70,6 +80,7 @@ impl AreaFrameAllocator {
             current_area: None,
             available: VectorArray::Array((avail_len, available)),
             occupied: VectorArray::Array((occ_len, occupied)),
+            allocated_frames: 0,
         };
         allocator.select_next_area();
         Ok(allocator)
@@ -109,6 +120,26 @@ impl AreaFrameAllocator {
         Ok(())
     }
 
+    /// Returns the total size in bytes of the usable memory areas that frames are allocated from,
+    /// which includes any parts of them that are also covered by occupied areas.
+    pub fn available_bytes(&self) -> usize {
+        self.available.as_slice().iter()
+            .filter(|area| area.typ == 1)
+            .map(|area| area.size_in_bytes)
+            .sum()
+    }
+
+    /// Returns the total size in bytes of the occupied memory areas, from which frames are never allocated.
+    pub fn occupied_bytes(&self) -> usize {
+        self.occupied.as_slice().iter().map(|area| area.size_in_bytes).sum()
+    }
+
+    /// Returns the number of frames that have been allocated so far.
+    /// Frames are currently never deallocated, so this number only grows.
+    pub fn allocated_frames(&self) -> usize {
+        self.allocated_frames
+    }
+
     fn select_next_area(&mut self) {
         self.current_area = match self.available {
             VectorArray::Array((len, ref arr)) => {
@@ -238,6 +269,7 @@ impl FrameAllocator for AreaFrameAllocator {
             } else {
                 // frame is unused, increment `next_free_frame` and return it
                 self.next_free_frame += 1;
+                self.allocated_frames += 1;
                 // trace!("AreaFrameAllocator: allocated frame {:?}", frame);
                 return Some(frame);
             }
@@ -22,6 +22,7 @@ extern crate path;
 extern crate memfs;
 extern crate cstr_core;
 extern crate hashbrown;
+extern crate initramfs;
 
 use core::{
     fmt,
@@ -46,7 +47,7 @@ use rustc_demangle::demangle;
 use qp_trie::{Trie, wrapper::BString};
 use fs_node::{FileOrDir, File, FileRef, DirRef};
 use vfs_node::VFSDirectory;
-use path::Path;
+use path::{Path, PathBuf};
 use memfs::MemFile;
 use hashbrown::HashMap;
 pub use crate_name_utils::{get_containing_crate_name, replace_containing_crate_name, crate_name_from_path};
@@ -75,6 +76,40 @@ pub fn get_namespaces_directory() -> Option<DirRef> {
     root::get_root().lock().get_dir(NAMESPACES_DIRECTORY_NAME)
 }
 
+/// The directory on a mounted disk filesystem that persists the crate object files of the namespaces.
+static DISK_NAMESPACES_DIRECTORY: Once<DirRef> = Once::new();
+
+/// Sets the directory on a mounted disk filesystem that persists the crate object files
+/// of the namespaces whose directories are in the top-level namespaces directory, e.g., `/namespaces/_applications`.
+/// This can only be set once.
+/// 
+/// Each such namespace directory is backed by the subdirectory of the given `dir` that has the same name, e.g., `_applications`;
+/// see [`NamespaceDir::persist()`](struct.NamespaceDir.html#method.persist).
+/// If that disk directory already exists, e.g., from a previous boot, the crate object files in it are added
+/// to the namespace directory, but only for crates that the namespace directory doesn't already have an object file for;
+/// the crate object files from the bootloader modules are never replaced or removed.
+pub fn set_disk_namespaces_directory(dir: DirRef) -> Result<(), &'static str> {
+    if DISK_NAMESPACES_DIRECTORY.try().is_some() {
+        return Err("the disk directory for namespaces was already set");
+    }
+    DISK_NAMESPACES_DIRECTORY.call_once(|| dir);
+
+    let namespaces_dir = get_namespaces_directory().ok_or("couldn't get the top-level namespaces directory")?;
+    let names = namespaces_dir.lock().list();
+    for name in names {
+        let namespace_dir = namespaces_dir.lock().get_dir(&name);
+        if let Some(namespace_dir) = namespace_dir {
+            NamespaceDir::new(namespace_dir).restore()?;
+        }
+    }
+    Ok(())
+}
+
+/// Returns the directory on a mounted disk filesystem that persists the crate object files of the namespaces, if one was set.
+pub fn get_disk_namespaces_directory() -> Option<&'static DirRef> {
+    DISK_NAMESPACES_DIRECTORY.try()
+}
+
 
 /// Create a new application `CrateNamespace` that uses the default application directory 
 /// and is structured atop the given `recursive_namespace`. 
@@ -144,9 +179,6 @@ fn parse_bootloader_modules_into_files(
     for m in boot_info.module_tags() {
         let size_in_bytes = (m.end_address() - m.start_address()) as usize;
         let frames = FrameRange::from_phys_addr(PhysicalAddress::new(m.start_address() as usize)?, size_in_bytes);
-        let (crate_type, prefix, file_name) = CrateType::from_module_name(m.name())?;
-        let dir_name = format!("{}{}", prefix, crate_type.default_namespace_name());
-        let name = String::from(file_name);
 
         let pages = allocate_pages_by_bytes(size_in_bytes).ok_or("Couldn't allocate virtual pages for bootloader module area")?;
         let mp = kernel_mmi.page_table.map_allocated_pages_to(
@@ -156,6 +188,20 @@ fn parse_bootloader_modules_into_files(
             fa.lock().deref_mut()
         )?;
 
+        // An initramfs archive is not a crate object file, so its contents are unpacked into the root directory instead.
+        // A malformed archive is skipped rather than preventing the crate object files from being loaded.
+        if initramfs::is_initramfs_module(m.name()) {
+            match mp.as_slice(0, size_in_bytes).and_then(|archive| initramfs::unpack(archive, root::get_root(), kernel_mmi)) {
+                Ok(num_entries) => info!("Unpacked {} entries from initramfs module {:?}", num_entries, m.name()),
+                Err(e) => error!("Couldn't unpack initramfs module {:?}, skipping it: {}", m.name(), e),
+            }
+            continue;
+        }
+
+        let (crate_type, prefix, file_name) = CrateType::from_module_name(m.name())?;
+        let dir_name = format!("{}{}", prefix, crate_type.default_namespace_name());
+        let name = String::from(file_name);
+
         // debug!("Module: {:?}, size {}, mp: {:?}", name, size_in_bytes, mp);
 
         let create_file = |dir: &DirRef| {
@@ -188,6 +234,9 @@ pub type SymbolMapIter<'a> = qp_trie::Iter<'a, &'a BString, &'a WeakSectionRef>;
 /// A wrapper around a `Directory` reference that offers special convenience functions
 /// for getting and inserting crate object files into a directory.  
 /// 
+/// The directory may be backed by a directory on disk that persists its crate object files,
+/// see [`persist()`](#method.persist).
+/// 
 /// Auto-derefs into a `DirRef`.
 #[derive(Clone)] 
 pub struct NamespaceDir(DirRef);
@@ -272,12 +321,155 @@ impl NamespaceDir {
     /// # Examples 
     /// * The file "k#keyboard-36be916209949cef.o" will be written to "./keyboard-36be916209949cef.o". 
     /// * The file "a#ps.o" will be placed into "./ps.o". 
+    /// 
+    /// If this directory is backed by a disk directory, the file is also written to the disk directory.
+    /// Failing to write it there is logged, but doesn't fail the write to this directory.
     pub fn write_crate_object_file(&self, crate_object_file_name: &str, content: &[u8]) -> Result<FileRef, &'static str> {
         let (_crate_type, _prefix, objfilename) = CrateType::from_module_name(crate_object_file_name)?;
         let cfile = MemFile::new(String::from(objfilename), &self.0)?;
         cfile.lock().write(content, 0)?;
+        if let Err(e) = self.persist_file(objfilename, content) {
+            error!("write_crate_object_file(): couldn't write {:?} to the disk directory of {:?}: {}", objfilename, self, e);
+        }
         Ok(cfile)
     }
+
+    /// Returns the directory on disk that backs this directory, if it exists.
+    /// 
+    /// Only directories in the top-level namespaces directory can be backed by a disk directory,
+    /// which is the one with the same name in the directory given to 
+    /// [`set_disk_namespaces_directory()`](fn.set_disk_namespaces_directory.html).
+    pub fn disk_dir(&self) -> Option<DirRef> {
+        let name = self.name_in_namespaces_directory()?;
+        let disk_dir = get_disk_namespaces_directory()?.lock().get_dir(&name);
+        disk_dir
+    }
+
+    /// Makes the disk directory that backs this directory hold the same crate object files as this directory,
+    /// such that they persist across reboots. 
+    /// The disk directory is created if it doesn't yet exist.
+    /// 
+    /// Only the files that the disk directory lacks are written to it, and the files that this directory lacks are removed from it.
+    /// Files are compared by name only, because the name of a crate object file includes the hash of the crate.
+    /// 
+    /// Does nothing if this directory cannot be backed by a disk directory.
+    pub fn persist(&self) -> Result<(), &'static str> {
+        match self.get_or_create_disk_dir()? {
+            Some(disk_dir) => sync_files(&self.0, &disk_dir),
+            None => Ok(()),
+        }
+    }
+
+    /// Writes a crate object file called `name` with the given `content` to the disk directory that backs this directory,
+    /// replacing the file with the same name in it, if any.
+    /// The disk directory is created if it doesn't yet exist.
+    /// 
+    /// Does nothing if this directory cannot be backed by a disk directory.
+    fn persist_file(&self, name: &str, content: &[u8]) -> Result<(), &'static str> {
+        if let Some(disk_dir) = self.get_or_create_disk_dir()? {
+            let copy = MemFile::new_detached(name.to_string(), content, &disk_dir)?;
+            disk_dir.lock().insert(FileOrDir::File(copy))?;
+        }
+        Ok(())
+    }
+
+    /// Adds copies of the crate object files in the disk directory that backs this directory, if it exists,
+    /// for each crate that this directory doesn't already have an object file for.
+    /// 
+    /// Files already in this directory, e.g., those from the bootloader modules, are never replaced or removed.
+    fn restore(&self) -> Result<(), &'static str> {
+        let disk_dir = match self.disk_dir() {
+            Some(disk_dir) => disk_dir,
+            None => return Ok(()),
+        };
+        let crate_names: BTreeSet<String> = file_names(&self.0).iter()
+            .map(|name| crate_name_of_file(name).to_string())
+            .collect();
+        for name in file_names(&disk_dir) {
+            if !crate_names.contains(crate_name_of_file(&name)) {
+                copy_file(&disk_dir, &name, &self.0)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the directory on disk that backs this directory, creating it if it doesn't yet exist,
+    /// or `None` if this directory cannot be backed by a disk directory.
+    fn get_or_create_disk_dir(&self) -> Result<Option<DirRef>, &'static str> {
+        let (name, disk_namespaces_dir) = match (self.name_in_namespaces_directory(), get_disk_namespaces_directory()) {
+            (Some(name), Some(disk_namespaces_dir)) => (name, disk_namespaces_dir),
+            _ => return Ok(None),
+        };
+        let mut locked_disk_namespaces_dir = disk_namespaces_dir.lock();
+        match locked_disk_namespaces_dir.get_dir(&name) {
+            Some(disk_dir) => Ok(Some(disk_dir)),
+            None => locked_disk_namespaces_dir.create_dir(&name).map(Some),
+        }
+    }
+
+    /// Returns the name of this directory if it is in the top-level namespaces directory.
+    fn name_in_namespaces_directory(&self) -> Option<String> {
+        let (name, parent) = {
+            let locked_dir = self.0.lock();
+            (locked_dir.get_name(), locked_dir.get_parent_dir()?)
+        };
+        if Arc::ptr_eq(&parent, &get_namespaces_directory()?) {
+            Some(name)
+        } else {
+            None
+        }
+    }
+}
+
+/// Makes the `dest` directory hold the same files as the `source` directory, comparing the files by name only.
+/// The files that only `dest` has are removed from it, and copies of the files that only `source` has are inserted into it.
+/// Subdirectories are ignored.
+fn sync_files(source: &DirRef, dest: &DirRef) -> Result<(), &'static str> {
+    let source_names = file_names(source);
+    let dest_names = file_names(dest);
+
+    for name in dest_names.difference(&source_names) {
+        let file = dest.lock().get_file(name).ok_or("BUG: file to be removed disappeared from directory")?;
+        let removed = dest.lock().remove(&FileOrDir::File(file));
+        if removed.is_none() {
+            error!("sync_files(): couldn't remove file {:?} from directory {:?}", name, dest.lock().get_absolute_path());
+            return Err("couldn't remove a crate object file from a namespace directory");
+        }
+    }
+
+    for name in source_names.difference(&dest_names) {
+        copy_file(source, name, dest)?;
+    }
+    Ok(())
+}
+
+/// Inserts a copy of the file called `name` in the `source` directory into the `dest` directory.
+fn copy_file(source: &DirRef, name: &str, dest: &DirRef) -> Result<(), &'static str> {
+    let content = {
+        let file = source.lock().get_file(name).ok_or("BUG: file to be copied disappeared from directory")?;
+        let locked_file = file.lock();
+        let mut content = vec![0u8; locked_file.size()];
+        let bytes_read = locked_file.read(&mut content, 0)?;
+        content.truncate(bytes_read);
+        content
+    };
+    let copy = MemFile::new_detached(name.to_string(), &content, dest)?;
+    dest.lock().insert(FileOrDir::File(copy))?;
+    Ok(())
+}
+
+/// Returns the name of the crate that the crate object file called `file_name` belongs to, without the crate's hash,
+/// e.g., `"keyboard"` for `"keyboard-36be916209949cef.o"`.
+fn crate_name_of_file(file_name: &str) -> &str {
+    crate_name_from_path(Path::new(file_name)).split(CRATE_HASH_DELIMITER).next().unwrap_or(file_name)
+}
+
+/// Returns the names of all files (not directories) in the given `dir`.
+fn file_names(dir: &DirRef) -> BTreeSet<String> {
+    let locked_dir = dir.lock();
+    locked_dir.list().into_iter()
+        .filter(|name| locked_dir.get_file(name).is_some())
+        .collect()
 }
 
 
@@ -289,7 +481,7 @@ pub enum IntoCrateObjectFile {
     /// A direct reference to the crate object file. This will be used as-is. 
     File(FileRef),
     /// An absolute path that points to the crate object file. 
-    AbsolutePath(Path),
+    AbsolutePath(PathBuf),
     /// A string prefix that will be used to search for the crate object file in the namespace.
     /// This must be able to uniquely identify a single crate object file in the namespace directory (recursively searched). 
     Prefix(String),
@@ -936,7 +1128,7 @@ impl CrateNamespace {
         
         let mapped_pages  = crate_file.as_mapping()?;
         let size_in_bytes = crate_file.size();
-        let abs_path      = Path::new(crate_file.get_absolute_path());
+        let abs_path      = PathBuf::from(crate_file.get_absolute_path());
         let crate_name    = crate_name_from_path(&abs_path).to_string();
 
         // First, check to make sure this crate hasn't already been loaded. 
@@ -1975,7 +2167,7 @@ impl CrateNamespace {
                 }
             };
                           
-            let potential_crate_file_path = Path::new(potential_crate_file.lock().get_absolute_path());
+            let potential_crate_file_path = PathBuf::from(potential_crate_file.lock().get_absolute_path());
             // Check to make sure this crate is not already loaded into this namespace (or its recursive namespace).
             if self.get_crate(crate_name_from_path(&potential_crate_file_path)).is_some() {
                 trace!("  (skipping already-loaded crate {:?})", potential_crate_file_path);
@@ -21,7 +21,7 @@ use cstr_core::CStr;
 use memory::{VirtualAddress, MappedPages};
 use crate_metadata::{LoadedCrate, StrongCrateRef, LoadedSection, StrongSectionRef, SectionType, Shndx};
 use hashbrown::HashMap;
-use path::Path;
+use path::PathBuf;
 use super::CrateNamespace;
 
 
@@ -76,7 +76,7 @@ pub fn parse_nano_core(
             .ok_or("couldn't find the expected \"nano_core\" kernel file"),
         text_pages, rodata_pages, data_pages
     );
-    let nano_core_file_path = Path::new(nano_core_file.lock().get_absolute_path());
+    let nano_core_file_path = PathBuf::from(nano_core_file.lock().get_absolute_path());
     debug!("parse_nano_core: trying to load and parse the nano_core file: {:?}", nano_core_file_path);
 
     let crate_name = String::from(NANO_CORE_CRATE_NAME);
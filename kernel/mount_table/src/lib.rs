@@ -0,0 +1,266 @@
+//! The mount table, which records every filesystem that is mounted into the VFS tree.
+//!
+//! A filesystem is mounted on top of an existing directory, its *mountpoint*,
+//! which is then covered by the root directory of the mounted filesystem.
+//! The mountpoint directory itself is left untouched, so it reappears once the filesystem is unmounted.
+//! Path lookups (see `path::Path::get()`) use [`resolve()`](fn.resolve.html)
+//! to cross from a mountpoint into the root directory of the filesystem mounted on it.
+//!
+//! The root directory of a mounted filesystem takes on the name and parent directory of its mountpoint,
+//! such that absolute paths and `..` work just like they do for regular directories.
+//!
+//! Filesystem types (e.g., `fat32`) can register a [`MountFn`](type.MountFn.html) under their name,
+//! which allows them to be mounted by name, e.g., from the `mount` application.
+//! Filesystems that are created by other means can be added to the table using
+//! [`mount_filesystem()`](fn.mount_filesystem.html).
+
+#![no_std]
+
+extern crate alloc;
+#[macro_use] extern crate log;
+#[macro_use] extern crate lazy_static;
+extern crate spin;
+extern crate fs_node;
+extern crate block_io;
+
+use core::fmt;
+use alloc::{
+    collections::BTreeMap,
+    string::{String, ToString},
+    sync::Arc,
+    vec::Vec,
+};
+use spin::Mutex;
+use fs_node::{DirRef, is_within};
+
+
+lazy_static! {
+    /// The list of all mounted filesystems, in the order they were mounted.
+    /// A later mount on the same mountpoint covers the earlier ones.
+    static ref MOUNT_TABLE: Mutex<Vec<Mount>> = Mutex::new(Vec::new());
+
+    /// The registered filesystem types, a map from the type's name to the function that mounts it.
+    static ref FILESYSTEM_TYPES: Mutex<BTreeMap<&'static str, MountFn>> = Mutex::new(BTreeMap::new());
+}
+
+
+/// Usage statistics of a mounted filesystem.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileSystemStats {
+    /// The size in bytes of one allocation block.
+    pub block_size: usize,
+    /// The total number of blocks in the filesystem.
+    pub total_blocks: usize,
+    /// The number of blocks that are not currently in use.
+    pub free_blocks: usize,
+}
+
+/// A filesystem instance that can be mounted into the VFS tree.
+pub trait FileSystem {
+    /// Returns the root directory of this filesystem.
+    fn root(&self) -> DirRef;
+
+    /// Returns the usage statistics of this filesystem,
+    /// or `None` if it doesn't have a fixed capacity, e.g., if its contents are generated on demand.
+    fn stats(&self) -> Option<FileSystemStats> {
+        None
+    }
+}
+
+/// A reference to a filesystem instance.
+pub type FileSystemRef = Arc<dyn FileSystem + Send + Sync>;
+
+/// The function that a filesystem type registers in order to be mountable by name.
+///
+/// The arguments are the source of the filesystem (e.g., a storage device name),
+/// the mountpoint directory, and the mount options.
+/// The root directory of the returned filesystem must have the same name and parent directory as the mountpoint.
+pub type MountFn = fn(&str, &DirRef, &MountOptions) -> Result<FileSystemRef, &'static str>;
+
+
+/// The options that a filesystem was mounted with.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MountOptions {
+    /// Whether modifications to the filesystem should be rejected.
+    pub read_only: bool,
+    /// All other options, which are only meaningful to the specific filesystem type.
+    pub other: Vec<String>,
+}
+
+impl MountOptions {
+    /// Parses a comma-separated list of mount options, e.g., `"ro,foo=bar"`.
+    pub fn parse(options: &str) -> MountOptions {
+        let mut mount_options = MountOptions::default();
+        for opt in options.split(',').map(|o| o.trim()).filter(|o| !o.is_empty()) {
+            match opt {
+                "ro" => mount_options.read_only = true,
+                "rw" => mount_options.read_only = false,
+                other => mount_options.other.push(other.to_string()),
+            }
+        }
+        mount_options
+    }
+}
+
+impl fmt::Display for MountOptions {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", if self.read_only { "ro" } else { "rw" })?;
+        for opt in &self.other {
+            write!(f, ",{}", opt)?;
+        }
+        Ok(())
+    }
+}
+
+
+/// An entry in the mount table.
+#[derive(Clone)]
+pub struct Mount {
+    /// Where the filesystem came from, e.g., the name of a storage device.
+    pub source: String,
+    /// The name of the filesystem type, e.g., `"fat32"`.
+    pub fs_type: String,
+    /// The absolute path of the mountpoint.
+    pub mountpoint: String,
+    /// The options that the filesystem was mounted with.
+    pub options: MountOptions,
+    /// The mounted filesystem itself.
+    pub filesystem: FileSystemRef,
+    /// The root directory of the mounted filesystem.
+    root: DirRef,
+    /// The directory that is covered by the mounted filesystem.
+    covered_dir: DirRef,
+}
+
+impl Mount {
+    /// Returns the root directory of the mounted filesystem.
+    pub fn root(&self) -> &DirRef {
+        &self.root
+    }
+
+    /// Returns the mountpoint directory, which is hidden while this filesystem is mounted.
+    pub fn covered_dir(&self) -> &DirRef {
+        &self.covered_dir
+    }
+}
+
+
+/// Registers a new filesystem type with the given `name`,
+/// such that it can be mounted using [`mount()`](fn.mount.html).
+pub fn register_filesystem_type(name: &'static str, mount_fn: MountFn) -> Result<(), &'static str> {
+    let mut fs_types = FILESYSTEM_TYPES.lock();
+    if fs_types.contains_key(name) {
+        return Err("a filesystem type with that name was already registered");
+    }
+    fs_types.insert(name, mount_fn);
+    Ok(())
+}
+
+/// Returns the names of all registered filesystem types.
+pub fn filesystem_types() -> Vec<&'static str> {
+    FILESYSTEM_TYPES.lock().keys().cloned().collect()
+}
+
+/// Mounts the given `source` as a filesystem of the registered type `fs_type` on top of the given `mountpoint`.
+///
+/// If another filesystem is already mounted on the `mountpoint`, the new one is mounted on top of it.
+///
+/// Returns the root directory of the newly-mounted filesystem.
+pub fn mount(source: &str, fs_type: &str, mountpoint: &DirRef, options: MountOptions) -> Result<DirRef, &'static str> {
+    let mount_fn = *FILESYSTEM_TYPES.lock().get(fs_type).ok_or("unknown filesystem type")?;
+    let mountpoint = resolve(mountpoint);
+    let filesystem = mount_fn(source, &mountpoint, &options)?;
+    mount_filesystem(source.to_string(), fs_type.to_string(), filesystem, &mountpoint, options)
+}
+
+/// Adds the given already-created `filesystem` to the mount table, mounting it on top of the given `mountpoint`.
+///
+/// The root directory of the `filesystem` must have the same name and parent directory as the `mountpoint`.
+/// If another filesystem is already mounted on the `mountpoint`, the new one is mounted on top of it.
+///
+/// Returns the root directory of the newly-mounted filesystem.
+pub fn mount_filesystem(
+    source: String,
+    fs_type: String,
+    filesystem: FileSystemRef,
+    mountpoint: &DirRef,
+    options: MountOptions,
+) -> Result<DirRef, &'static str> {
+    let mountpoint = resolve(mountpoint);
+    let root = filesystem.root();
+    if Arc::ptr_eq(&root, &mountpoint) {
+        return Err("cannot mount a filesystem on top of its own root directory");
+    }
+    let mountpoint_path = {
+        let locked_mountpoint = mountpoint.lock();
+        // Only the root directory is its own parent.
+        if locked_mountpoint.get_parent_dir().map_or(false, |p| Arc::ptr_eq(&p, &mountpoint)) {
+            return Err("cannot mount a filesystem on top of the root directory");
+        }
+        locked_mountpoint.get_absolute_path()
+    };
+
+    info!("Mounted {} filesystem from {:?} at {:?} ({})", fs_type, source, mountpoint_path, options);
+    MOUNT_TABLE.lock().push(Mount {
+        source,
+        fs_type,
+        mountpoint: mountpoint_path,
+        options,
+        filesystem,
+        root: Arc::clone(&root),
+        covered_dir: mountpoint,
+    });
+    Ok(root)
+}
+
+/// Unmounts the filesystem that is visible at the given directory,
+/// which can be either the root directory of a mounted filesystem or the mountpoint that it covers.
+///
+/// Before the filesystem is removed from the mount table, all dirty cached blocks are written back
+/// to their storage devices, so that none of its changes are lost.
+///
+/// Fails if another filesystem is mounted somewhere within the filesystem being unmounted,
+/// or if the cached blocks couldn't be written back.
+///
+/// Returns the entry that was removed from the mount table.
+pub fn unmount(dir: &DirRef) -> Result<Mount, &'static str> {
+    let root = resolve(dir);
+    // Don't hold the mount table lock while locking directories, since directories may use the mount table.
+    let mounts = get_mounts();
+    mounts.iter().find(|m| Arc::ptr_eq(&m.root, &root)).ok_or("no filesystem is mounted there")?;
+    if mounts.iter().any(|m| !Arc::ptr_eq(&m.root, &root) && is_within(&m.covered_dir, &root)) {
+        return Err("another filesystem is mounted within that filesystem");
+    }
+    block_io::flush_all()?;
+
+    let mut table = MOUNT_TABLE.lock();
+    let index = table.iter().position(|m| Arc::ptr_eq(&m.root, &root)).ok_or("no filesystem is mounted there")?;
+    let removed = table.remove(index);
+    info!("Unmounted {} filesystem from {:?}", removed.fs_type, removed.mountpoint);
+    Ok(removed)
+}
+
+/// Returns the directory that is visible at the given `dir`, i.e.,
+/// the root directory of the filesystem most recently mounted on it,
+/// or the given `dir` itself if nothing is mounted on it.
+pub fn resolve(dir: &DirRef) -> DirRef {
+    let table = MOUNT_TABLE.lock();
+    let mut curr_dir = Arc::clone(dir);
+    // Each mount covers the topmost directory at the time it was mounted, so stacked mounts form a chain.
+    while let Some(mount) = table.iter().find(|m| Arc::ptr_eq(&m.covered_dir, &curr_dir)) {
+        curr_dir = Arc::clone(&mount.root);
+    }
+    curr_dir
+}
+
+/// Returns the mount table entry for the filesystem whose root directory is visible at the given `dir`, if any.
+pub fn get_mount(dir: &DirRef) -> Option<Mount> {
+    let root = resolve(dir);
+    MOUNT_TABLE.lock().iter().find(|m| Arc::ptr_eq(&m.root, &root)).cloned()
+}
+
+/// Returns a copy of all entries in the mount table, in the order that they were mounted.
+pub fn get_mounts() -> Vec<Mount> {
+    MOUNT_TABLE.lock().clone()
+}
+
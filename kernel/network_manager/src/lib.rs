@@ -50,6 +50,14 @@ pub trait NetworkInterface {
     /// Check whether the interface has the given IP address assigned.
     fn has_ip_addr(&self, addr: IpAddress) -> bool;
 
+    /// Modify the IP addresses of the interface in place using the given function `f`,
+    /// e.g., to replace a placeholder address with one acquired via DHCP.
+    /// 
+    /// This is a thin wrapper around smoltcp's 
+    /// [`update_ip_addrs()`](https://docs.rs/smoltcp/0.5.0/smoltcp/iface/struct.EthernetInterface.html#method.update_ip_addrs) method,
+    /// so the number of IP addresses cannot be changed.
+    fn update_ip_addrs(&mut self, f: &mut dyn FnMut(&mut [IpCidr]));
+
     fn routes(&self) -> &Routes<'static>;
 
     fn routes_mut(&mut self) -> &mut Routes<'static>;
@@ -0,0 +1,790 @@
+//! Support for NVMe (NVM Express) controllers, which are typically SSDs attached via PCIe.
+//!
+//! The primary structs of interest are [`NvmeController`](struct.NvmeController.html),
+//! which implements `StorageController`, and [`NvmeNamespace`](struct.NvmeNamespace.html), which implements `StorageDevice`.
+//! Each namespace of an NVMe controller is a separate range of logical blocks, and thus acts as a separate drive.
+//!
+//! Commands are given to the controller through pairs of submission and completion queues in memory.
+//! The admin queue pair is used to set up the controller and identify its namespaces,
+//! while reads and writes are issued through I/O queue pairs.
+//! One I/O queue pair is created per processor core (as far as the controller allows),
+//! and each one's completion queue raises its own MSI-X interrupt on the core that it belongs to,
+//! such that tasks on different cores don't contend for the same queue.
+
+#![no_std]
+#![feature(abi_x86_interrupt)]
+
+#[macro_use] extern crate alloc;
+#[macro_use] extern crate log;
+#[macro_use] extern crate lazy_static;
+extern crate spin;
+extern crate irq_safety;
+extern crate volatile;
+extern crate x86_64;
+extern crate kernel_config;
+extern crate memory;
+extern crate pci;
+extern crate apic;
+extern crate interrupts;
+extern crate wait_queue;
+extern crate driver_utils;
+extern crate storage_device;
+
+mod regs;
+mod queue;
+
+use core::{
+    fmt,
+    sync::atomic::Ordering,
+    time::Duration,
+};
+use alloc::{
+    boxed::Box,
+    string::String,
+    sync::Arc,
+    vec::Vec,
+};
+use spin::Mutex;
+use irq_safety::MutexIrqSafe;
+use x86_64::structures::idt::ExceptionStackFrame;
+use kernel_config::memory::PAGE_SIZE;
+use memory::{MappedPages, PhysicalAddress, create_contiguous_mapping};
+use pci::{PciDevice, MSIX_CAPABILITY};
+use apic::InterruptChip;
+use storage_device::{StorageDevice, StorageDeviceRef, StorageController};
+use driver_utils::{DEVICE_MAPPING_FLAGS, bar_address, map_device_memory, poll};
+use regs::*;
+use queue::QueuePair;
+
+
+/// The number of entries in each admin queue.
+const ADMIN_QUEUE_DEPTH: u16 = 32;
+/// The number of entries in each I/O queue, which fills one page of submission queue entries.
+const IO_QUEUE_DEPTH: u16 = 64;
+/// The maximum number of I/O queue pairs created for a single controller.
+const MAX_IO_QUEUES: usize = 64;
+/// The size of each buffer that data is transferred through by I/O commands,
+/// which is the largest amount of data that is transferred by a single command.
+const DMA_BUFFER_SIZE_IN_BYTES: usize = 128 * 1024;
+/// The maximum number of unused DMA buffers that each I/O queue pair keeps for later commands.
+const MAX_POOLED_DMA_BUFFERS: usize = 4;
+
+/// How long to wait for a command to complete before giving up.
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(5);
+
+
+/// The commands that are given to the controller through the admin queue.
+#[derive(Copy, Clone, Debug)]
+#[repr(u8)]
+enum AdminCommand {
+    CreateIoSubmissionQueue = 0x01,
+    CreateIoCompletionQueue = 0x05,
+    Identify                = 0x06,
+    SetFeatures             = 0x09,
+}
+
+/// The commands of the NVM command set that are given to the controller through the I/O queues.
+#[derive(Copy, Clone, Debug)]
+#[repr(u8)]
+enum IoCommand {
+    /// Commit the data and metadata in the controller's volatile write cache to non-volatile media.
+    Flush = 0x00,
+    Write = 0x01,
+    Read  = 0x02,
+}
+
+/// The data structures returned by the identify command.
+#[derive(Copy, Clone, Debug)]
+#[repr(u32)]
+enum IdentifyCns {
+    Namespace           = 0x00,
+    Controller          = 0x01,
+    ActiveNamespaceList = 0x02,
+}
+
+/// The feature identifier of the number of I/O queues, used with the set features command.
+const FEATURE_NUMBER_OF_QUEUES: u32 = 0x07;
+
+
+lazy_static! {
+    /// The I/O queue pairs whose completion queues raise interrupts that are handled by `nvme_handler()`,
+    /// along with the APIC id of the core that each one's interrupts are sent to.
+    static ref INTERRUPT_TARGETS: MutexIrqSafe<Vec<(u8, Arc<QueuePair>)>> = MutexIrqSafe::new(Vec::new());
+}
+
+/// The interrupt handler shared by the completion queues of all NVMe controllers.
+///
+/// MSI-X interrupts don't need to be acknowledged by the controller,
+/// so this only wakes up the tasks waiting on the queues that belong to the current core.
+/// Those tasks take the completed commands from the queues themselves.
+extern "x86-interrupt" fn nvme_handler(_stack_frame: &mut ExceptionStackFrame) {
+    let me = apic::get_my_apic_id();
+    for &(core, ref queue) in INTERRUPT_TARGETS.lock().iter() {
+        if core == me {
+            while queue.waiters.notify_one() { }
+        }
+    }
+    interrupts::eoi(None);
+}
+
+
+/// The characteristics of a controller, as given by the identify command.
+#[derive(Debug)]
+struct IdentifyController {
+    serial_number: String,
+    model_number: String,
+    /// The maximum size of a single data transfer, given as a power of two of the minimum memory page size.
+    /// A value of `0` means there is no limit.
+    max_data_transfer_size: u8,
+    /// Whether the controller has a volatile write cache, which must be flushed to ensure that written data is stored.
+    volatile_write_cache: bool,
+    /// The maximum namespace id that the controller supports.
+    namespace_count: u32,
+}
+
+impl IdentifyController {
+    /// Parses the 4096-byte data structure returned by the identify controller command.
+    fn new(data: &[u8]) -> IdentifyController {
+        IdentifyController {
+            serial_number: ascii_field(&data[4 .. 24]),
+            model_number: ascii_field(&data[24 .. 64]),
+            max_data_transfer_size: data[77],
+            volatile_write_cache: data[525] & 0x1 != 0,
+            namespace_count: read_u32(data, 516),
+        }
+    }
+}
+
+
+/// An NVMe controller, along with the namespaces that it exposes as drives.
+#[derive(Debug)]
+pub struct NvmeController {
+    /// The state shared by the controller's namespaces.
+    shared: Arc<ControllerShared>,
+    /// The active namespaces of this controller, in order of their namespace id.
+    namespaces: Vec<NvmeNamespace>,
+    /// The `StorageDevice`s of the `namespaces`, each of which holds a clone of its namespace.
+    devices: Vec<NvmeNamespaceRef>,
+}
+
+/// The parts of a controller that are shared by all of its namespaces.
+#[derive(Debug)]
+struct ControllerShared {
+    /// The admin queue pair, which is only used while initializing the controller
+    /// but must stay allocated for as long as the controller is enabled.
+    _admin_queue: QueuePair,
+    io_queues: Vec<IoQueue>,
+}
+
+impl ControllerShared {
+    /// Returns the I/O queue pair that belongs to the current core.
+    ///
+    /// If the controller has fewer I/O queues than there are cores, some cores share a queue.
+    fn io_queue(&self) -> &IoQueue {
+        let me = apic::get_my_apic_id();
+        self.io_queues.iter()
+            .find(|io_queue| io_queue.core == me)
+            .unwrap_or(&self.io_queues[me as usize % self.io_queues.len()])
+    }
+}
+
+/// An I/O queue pair, along with the DMA buffers that the data of its commands is transferred through.
+#[derive(Debug)]
+struct IoQueue {
+    /// The APIC id of the core that this queue pair belongs to.
+    core: u8,
+    queue: Arc<QueuePair>,
+    /// The DMA buffers that aren't currently used by any command.
+    buffers: Mutex<Vec<DmaBuffer>>,
+}
+
+impl IoQueue {
+    /// Calls the given function `f` with a DMA buffer that no other command is using,
+    /// which is taken from this queue pair's unused buffers or newly allocated if there are none.
+    ///
+    /// If `f` fails, e.g., because a command timed out, the controller may still access the buffer later,
+    /// so it is leaked rather than reused or freed.
+    fn with_buffer<R, F>(&self, f: F) -> Result<R, &'static str>
+        where F: FnOnce(&mut DmaBuffer) -> Result<R, &'static str>
+    {
+        let pooled = self.buffers.lock().pop();
+        let mut buffer = match pooled {
+            Some(buffer) => buffer,
+            None => DmaBuffer::new()?,
+        };
+        let result = f(&mut buffer);
+        if result.is_ok() {
+            let mut buffers = self.buffers.lock();
+            if buffers.len() < MAX_POOLED_DMA_BUFFERS {
+                buffers.push(buffer);
+            }
+        } else {
+            core::mem::forget(buffer);
+        }
+        result
+    }
+}
+
+/// A physically-contiguous buffer that the data of a single I/O command is transferred through.
+struct DmaBuffer {
+    buffer: MappedPages,
+    /// The physical address of the `buffer`.
+    phys_addr: PhysicalAddress,
+    /// The list of physical region page (PRP) entries that describes every page of the `buffer` after the first,
+    /// which is needed for transfers larger than two pages.
+    _prp_list: MappedPages,
+    /// The physical address of the `prp_list`.
+    prp_list_phys_addr: PhysicalAddress,
+}
+
+impl fmt::Debug for DmaBuffer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("DmaBuffer")
+            .field("phys_addr", &self.phys_addr)
+            .field("prp_list_phys_addr", &self.prp_list_phys_addr)
+            .finish()
+    }
+}
+
+impl DmaBuffer {
+    fn new() -> Result<DmaBuffer, &'static str> {
+        let (buffer, phys_addr) = create_contiguous_mapping(DMA_BUFFER_SIZE_IN_BYTES, DEVICE_MAPPING_FLAGS)?;
+        let (mut prp_list, prp_list_phys_addr) = create_contiguous_mapping(PAGE_SIZE, DEVICE_MAPPING_FLAGS)?;
+        {
+            let entries = prp_list.as_slice_mut::<u64>(0, PAGE_SIZE / 8)?;
+            for (index, entry) in entries.iter_mut().take(DMA_BUFFER_SIZE_IN_BYTES / PAGE_SIZE - 1).enumerate() {
+                *entry = (phys_addr.value() + (index + 1) * PAGE_SIZE) as u64;
+            }
+        }
+        Ok(DmaBuffer { buffer, phys_addr, _prp_list: prp_list, prp_list_phys_addr })
+    }
+
+    /// Returns the two physical region page (PRP) entries of a command that transfers `length` bytes through this buffer.
+    fn prp_entries(&self, length: usize) -> (u64, u64) {
+        let prp2 = if length <= PAGE_SIZE {
+            0
+        } else if length <= 2 * PAGE_SIZE {
+            self.phys_addr.value() + PAGE_SIZE
+        } else {
+            self.prp_list_phys_addr.value()
+        };
+        (self.phys_addr.value() as u64, prp2 as u64)
+    }
+}
+
+impl NvmeController {
+    /// Creates a new instance of an NVMe controller based on the given PCI device,
+    /// resets and enables the controller, creates its I/O queues, and identifies its namespaces.
+    pub fn new(pci_device: &PciDevice) -> Result<NvmeController, &'static str> {
+        let mem_base = pci_device.determine_mem_base()?;
+        let mem_size = pci_device.determine_mem_size() as usize;
+
+        // set the bus mastering bit for this PciDevice, which allows it to use DMA
+        pci_device.pci_set_command_bus_master_bit();
+
+        let mut registers = Registers {
+            mapped_pages: map_device_memory(mem_base, mem_size)?,
+            doorbell_stride: 4,
+        };
+        let capabilities = registers.controller()?.cap.read();
+        if capabilities & CAP_CSS_NVM == 0 {
+            return Err("NVMe controller doesn't support the NVM command set");
+        }
+        // Theseus's pages are 4 KiB, which must be a supported memory page size of the controller.
+        if (capabilities >> CAP_MPSMIN_SHIFT) & CAP_MPSMIN_MASK != 0 {
+            return Err("NVMe controller doesn't support 4 KiB memory pages");
+        }
+        registers.doorbell_stride = 4 << ((capabilities >> CAP_DSTRD_SHIFT) & CAP_DSTRD_MASK);
+        let ready_timeout_ms = core::cmp::max(1, (capabilities >> CAP_TO_SHIFT) & CAP_TO_MASK) as u32 * 500;
+        let max_queue_depth = ((capabilities & CAP_MQES_MASK) + 1) as u16;
+
+        // Reset the controller by disabling it, which also deletes all of its queues.
+        let cc = registers.controller()?.cc.read();
+        registers.controller_mut()?.cc.write(cc & !CC_ENABLE);
+        let registers = Arc::new(MutexIrqSafe::new(registers));
+        wait_for_ready(&registers, false, ready_timeout_ms)?;
+
+        // Set up the admin queues, then enable the controller with 4 KiB pages and the NVM command set.
+        let admin_queue = QueuePair::new(0, core::cmp::min(ADMIN_QUEUE_DEPTH, max_queue_depth), Arc::clone(&registers), None)?;
+        {
+            let mut regs = registers.lock();
+            let regs = regs.controller_mut()?;
+            let depth = admin_queue.depth() as u32 - 1;
+            regs.aqa.write((depth << 16) | depth);
+            regs.asq.write(admin_queue.sq_phys_addr().value() as u64);
+            regs.acq.write(admin_queue.cq_phys_addr().value() as u64);
+            regs.cc.write(CC_ENABLE | CC_IOSQES | CC_IOCQES);
+        }
+        wait_for_ready(&registers, true, ready_timeout_ms)?;
+
+        let (identify_buffer, identify_buffer_phys_addr) = create_contiguous_mapping(PAGE_SIZE, DEVICE_MAPPING_FLAGS)?;
+        identify(&admin_queue, IdentifyCns::Controller, 0, identify_buffer_phys_addr)?;
+        let identify_data = IdentifyController::new(identify_buffer.as_slice(0, PAGE_SIZE)?);
+
+        // Ask for one I/O queue pair per core, each with its own MSI-X vector in addition to the admin queue's vector.
+        let cores: Vec<u8> = apic::get_lapics().iter().map(|(&apic_id, _)| apic_id).collect();
+        let msix = MsixTable::find(pci_device)?;
+        let mut queue_count = core::cmp::min(core::cmp::max(cores.len(), 1), MAX_IO_QUEUES);
+        if let Some(ref msix) = msix {
+            queue_count = core::cmp::min(queue_count, msix.size.saturating_sub(1));
+        }
+        let queue_count = set_queue_count(&admin_queue, core::cmp::max(queue_count, 1))?;
+
+        // Route each I/O queue's MSI-X vector to the core that the queue belongs to.
+        // Without MSI-X (or an APIC to receive it), the I/O queues are polled instead.
+        let interrupt_vectors = setup_interrupts(pci_device, msix.as_ref(), &registers, &cores, queue_count);
+
+        let io_queue_depth = core::cmp::min(IO_QUEUE_DEPTH, max_queue_depth);
+        let mut io_queues = Vec::with_capacity(queue_count);
+        for (index, interrupt_vector) in interrupt_vectors.into_iter().enumerate() {
+            let queue = Arc::new(QueuePair::new((index + 1) as u16, io_queue_depth, Arc::clone(&registers), interrupt_vector)?);
+            create_io_queue_pair(&admin_queue, &queue)?;
+            let core = cores.get(index).cloned().unwrap_or_else(apic::get_my_apic_id);
+            if interrupt_vector.is_some() {
+                INTERRUPT_TARGETS.lock().push((core, Arc::clone(&queue)));
+            }
+            io_queues.push(IoQueue { core, queue, buffers: Mutex::new(Vec::new()) });
+        }
+
+        // Identify the active namespaces, falling back to trying every namespace id if the controller can't list them.
+        let namespace_ids: Vec<u32> = match identify(&admin_queue, IdentifyCns::ActiveNamespaceList, 0, identify_buffer_phys_addr) {
+            Ok(_) => identify_buffer.as_slice::<u32>(0, PAGE_SIZE / 4)?.iter().cloned().take_while(|&id| id != 0).collect(),
+            Err(_) => (1 ..= identify_data.namespace_count).collect(),
+        };
+        let mut namespace_info = Vec::new();
+        for namespace_id in namespace_ids {
+            identify(&admin_queue, IdentifyCns::Namespace, namespace_id, identify_buffer_phys_addr)?;
+            let data = identify_buffer.as_slice::<u8>(0, PAGE_SIZE)?;
+            // inactive namespaces have a size of zero
+            let size_in_blocks = read_u64(data, 0) as usize;
+            if size_in_blocks == 0 {
+                continue;
+            }
+            // The formatted LBA size selects one of the LBA formats, each of which gives the block size as a power of two.
+            let lba_format = read_u32(data, 128 + 4 * (data[26] & 0xF) as usize);
+            let metadata_size = lba_format & 0xFFFF;
+            let block_size = 1usize << ((lba_format >> 16) & 0xFF);
+            if metadata_size != 0 || block_size < 512 || block_size > PAGE_SIZE {
+                warn!("NvmeController::new(): ignoring namespace {} with unsupported block size {} and metadata size {}",
+                    namespace_id, block_size, metadata_size
+                );
+                continue;
+            }
+            namespace_info.push((namespace_id, size_in_blocks, block_size));
+        }
+
+        let shared = Arc::new(ControllerShared { _admin_queue: admin_queue, io_queues });
+        let max_transfer_size = match identify_data.max_data_transfer_size {
+            0 => DMA_BUFFER_SIZE_IN_BYTES,
+            mdts => core::cmp::min(DMA_BUFFER_SIZE_IN_BYTES, PAGE_SIZE << mdts),
+        };
+        let namespaces: Vec<NvmeNamespace> = namespace_info.into_iter().map(|(namespace_id, size_in_blocks, block_size)| NvmeNamespace {
+            controller: Arc::clone(&shared),
+            namespace_id,
+            size_in_blocks,
+            block_size,
+            blocks_per_command: max_transfer_size / block_size,
+            flush_after_write: identify_data.volatile_write_cache,
+        }).collect();
+
+        info!("NVMe controller at {}: {:?} (serial {:?}), {} I/O queue(s), {} namespace(s) found",
+            pci_device.location, identify_data.model_number, identify_data.serial_number, queue_count, namespaces.len()
+        );
+        for namespace in namespaces.iter() {
+            info!("--> namespace {}: {} blocks of {} bytes", namespace.namespace_id, namespace.size_in_blocks, namespace.block_size);
+        }
+
+        let devices: Vec<NvmeNamespaceRef> = namespaces.iter().map(|n| Arc::new(Mutex::new(n.clone()))).collect();
+
+        Ok(NvmeController { shared, namespaces, devices })
+    }
+
+    /// Returns an `Iterator` over the `StorageDevice`s of all of the `NvmeNamespace`s of this controller.
+    pub fn iter(&self) -> impl Iterator<Item = &NvmeNamespaceRef> {
+        self.devices.iter()
+    }
+
+    /// Returns an `Iterator` over all of the `NvmeNamespace`s of this controller.
+    ///
+    /// Unlike the `StorageDevice`s returned by [`iter()`](#method.iter), which are locked for the whole of each transfer,
+    /// these namespaces can be cloned and then used by multiple tasks at once.
+    pub fn namespaces(&self) -> impl Iterator<Item = &NvmeNamespace> {
+        self.namespaces.iter()
+    }
+
+    /// Returns the number of I/O queue pairs that this controller's namespaces issue commands through.
+    pub fn io_queue_count(&self) -> usize {
+        self.shared.io_queues.len()
+    }
+}
+
+impl StorageController for NvmeController {
+    fn devices<'c>(&'c self) -> Box<(dyn Iterator<Item = StorageDeviceRef> + 'c)> {
+        Box::new(
+            self.iter().map(|namespace_ref| Arc::clone(namespace_ref) as StorageDeviceRef)
+        )
+    }
+}
+
+
+/// A namespace of an NVMe controller, i.e., a range of logical blocks that acts as a separate drive.
+///
+/// A namespace has no mutable state, so its transfers only need a shared reference to it.
+/// Each transfer is issued through the I/O queue pair of the current core, using a DMA buffer of its own,
+/// so transfers by tasks on different cores, or by several tasks on the same core, can be in flight at the same time.
+/// Clones of a namespace share its controller's queues.
+#[derive(Clone)]
+pub struct NvmeNamespace {
+    /// The state shared with the controller and its other namespaces.
+    controller: Arc<ControllerShared>,
+    namespace_id: u32,
+    /// The number of logical blocks in this namespace.
+    size_in_blocks: usize,
+    /// The size of a logical block in bytes.
+    block_size: usize,
+    /// The maximum number of blocks transferred by a single command.
+    blocks_per_command: usize,
+    /// Whether written data must be flushed from the controller's volatile write cache.
+    flush_after_write: bool,
+}
+
+impl fmt::Debug for NvmeNamespace {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("NvmeNamespace")
+            .field("namespace_id", &self.namespace_id)
+            .field("size_in_blocks", &self.size_in_blocks)
+            .field("block_size", &self.block_size)
+            .field("blocks_per_command", &self.blocks_per_command)
+            .field("flush_after_write", &self.flush_after_write)
+            .finish()
+    }
+}
+
+impl NvmeNamespace {
+    /// Returns the id of this namespace within its controller.
+    pub fn namespace_id(&self) -> u32 {
+        self.namespace_id
+    }
+
+    /// Reads blocks starting at `offset_in_blocks` into the given `buffer`,
+    /// whose length must be a multiple of the block size.
+    ///
+    /// Returns the number of blocks read.
+    pub fn read_blocks(&self, buffer: &mut [u8], offset_in_blocks: usize) -> Result<usize, &'static str> {
+        self.check_bounds(buffer.len(), offset_in_blocks)?;
+        let io_queue = self.controller.io_queue();
+        io_queue.with_buffer(|dma_buffer| {
+            let mut lba = offset_in_blocks;
+            for chunk in buffer.chunks_mut(self.blocks_per_command * self.block_size) {
+                let block_count = chunk.len() / self.block_size;
+                self.transfer(&io_queue.queue, dma_buffer, false, lba, block_count)?;
+                chunk.copy_from_slice(dma_buffer.buffer.as_slice(0, chunk.len())?);
+                lba += block_count;
+            }
+            Ok(lba - offset_in_blocks)
+        })
+    }
+
+    /// Writes the given `buffer` to blocks starting at `offset_in_blocks`,
+    /// whose length must be a multiple of the block size.
+    ///
+    /// Returns the number of blocks written.
+    pub fn write_blocks(&self, buffer: &[u8], offset_in_blocks: usize) -> Result<usize, &'static str> {
+        self.check_bounds(buffer.len(), offset_in_blocks)?;
+        let io_queue = self.controller.io_queue();
+        let written = io_queue.with_buffer(|dma_buffer| {
+            let mut lba = offset_in_blocks;
+            for chunk in buffer.chunks(self.blocks_per_command * self.block_size) {
+                let block_count = chunk.len() / self.block_size;
+                dma_buffer.buffer.as_slice_mut(0, chunk.len())?.copy_from_slice(chunk);
+                self.transfer(&io_queue.queue, dma_buffer, true, lba, block_count)?;
+                lba += block_count;
+            }
+            Ok(lba - offset_in_blocks)
+        })?;
+        if self.flush_after_write {
+            self.flush(&io_queue.queue)?;
+        }
+        Ok(written)
+    }
+
+    /// Transfers `block_count` blocks starting at `lba_start` between the namespace and the given DMA buffer
+    /// through the given I/O `queue`, reading from the namespace unless `write` is true.
+    fn transfer(&self, queue: &QueuePair, dma_buffer: &DmaBuffer, write: bool, lba_start: usize, block_count: usize) -> Result<(), &'static str> {
+        let (prp1, prp2) = dma_buffer.prp_entries(block_count * self.block_size);
+        let command = SubmissionEntry {
+            opcode: (if write { IoCommand::Write } else { IoCommand::Read }) as u8,
+            namespace_id: self.namespace_id,
+            prp1,
+            prp2,
+            cdw10: lba_start as u32,
+            cdw11: (lba_start >> 32) as u32,
+            // the number of blocks is stored minus one
+            cdw12: (block_count - 1) as u32,
+            .. SubmissionEntry::default()
+        };
+        queue.execute(command).map(|_| ())
+    }
+
+    /// Issues the flush command, which ensures that all written data has actually been stored in non-volatile media.
+    fn flush(&self, queue: &QueuePair) -> Result<(), &'static str> {
+        let command = SubmissionEntry {
+            opcode: IoCommand::Flush as u8,
+            namespace_id: self.namespace_id,
+            .. SubmissionEntry::default()
+        };
+        queue.execute(command).map(|_| ())
+    }
+
+    /// Checks that a transfer of the given `length_in_bytes` starting at `offset_in_blocks` fits within this namespace.
+    fn check_bounds(&self, length_in_bytes: usize, offset_in_blocks: usize) -> Result<(), &'static str> {
+        if length_in_bytes % self.block_size != 0 {
+            return Err("The buffer length must be a multiple of the NVMe namespace's block size.");
+        }
+        if offset_in_blocks + (length_in_bytes / self.block_size) > self.size_in_blocks {
+            return Err("offset_in_sectors was out of bounds");
+        }
+        Ok(())
+    }
+}
+
+impl StorageDevice for NvmeNamespace {
+    fn read_sectors(&mut self, buffer: &mut [u8], offset_in_sectors: usize) -> Result<usize, &'static str> {
+        self.read_blocks(buffer, offset_in_sectors)
+    }
+
+    fn write_sectors(&mut self, buffer: &[u8], offset_in_sectors: usize) -> Result<usize, &'static str> {
+        self.write_blocks(buffer, offset_in_sectors)
+    }
+
+    fn size_in_sectors(&self) -> usize {
+        self.size_in_blocks
+    }
+
+    fn sector_size_in_bytes(&self) -> usize {
+        self.block_size
+    }
+}
+
+/// A namespace as a `StorageDevice`, which is locked for the whole of each transfer.
+/// See [`NvmeController::namespaces()`](struct.NvmeController.html#method.namespaces) for transfers that don't lock it.
+pub type NvmeNamespaceRef = Arc<Mutex<NvmeNamespace>>;
+
+
+/// The location of a PCI device's MSI-X table, which holds the interrupt message of each of the device's vectors.
+#[derive(Debug)]
+struct MsixTable {
+    /// The index of the BAR that the table is located in.
+    bar_index: usize,
+    /// The offset of the table from the start of that BAR.
+    offset: usize,
+    /// The number of entries in the table.
+    size: usize,
+}
+
+impl MsixTable {
+    /// Returns the location of the given PCI device's MSI-X table, or `None` if the device doesn't support MSI-X.
+    fn find(pci_device: &PciDevice) -> Result<Option<MsixTable>, &'static str> {
+        let cap_addr = match pci_device.find_pci_capability(MSIX_CAPABILITY) {
+            Some(addr) => addr,
+            None => return Ok(None),
+        };
+        let control = pci_device.pci_read_16(cap_addr + MSIX_MESSAGE_CONTROL_OFFSET);
+        let table_offset = pci_device.pci_read_32(cap_addr + MSIX_TABLE_OFFSET_OFFSET);
+        let bar_index = (table_offset & MSIX_TABLE_BIR_MASK) as usize;
+        if bar_index >= pci_device.bars.len() {
+            return Err("NVMe controller's MSI-X table is in an invalid BAR");
+        }
+        Ok(Some(MsixTable {
+            bar_index,
+            offset: (table_offset & !MSIX_TABLE_BIR_MASK) as usize,
+            size: (control & MSIX_TABLE_SIZE_MASK) as usize + 1,
+        }))
+    }
+
+    /// Registers an interrupt for each of the `queue_count` I/O queues and routes that queue's MSI-X vector
+    /// to the core that the queue belongs to, i.e., the core at the same index in `cores`.
+    /// Vector `0` is reserved for the admin queue, so I/O queue `n` uses vector `n`.
+    ///
+    /// Returns the MSI-X vector of each I/O queue, along with the interrupt number registered for it.
+    /// If this fails partway through, the interrupts that were already registered are released.
+    fn route_vectors(&self,
+        pci_device: &PciDevice,
+        registers: &MutexIrqSafe<Registers>,
+        cores: &[u8],
+        queue_count: usize
+    ) -> Result<Vec<(u16, u8)>, &'static str> {
+        if queue_count + 1 > self.size {
+            return Err("MSI-X table doesn't have a vector for each queue");
+        }
+        // The table is usually located in BAR0 alongside the controller's registers, which are already mapped.
+        let mut locked_registers = registers.lock();
+        let mut separate_mapping;
+        let (table_pages, table_offset) = if self.bar_index == 0 {
+            (&mut locked_registers.mapped_pages, self.offset)
+        } else {
+            let table_base = PhysicalAddress::new(bar_address(pci_device, self.bar_index)?.value() + self.offset)?;
+            separate_mapping = map_device_memory(table_base, self.size * core::mem::size_of::<MsixVectorEntry>())?;
+            (&mut separate_mapping, table_base.value() % PAGE_SIZE)
+        };
+
+        let mut routes = Vec::with_capacity(queue_count);
+        for index in 0 .. queue_count {
+            let core = cores.get(index).cloned().unwrap_or_else(apic::get_my_apic_id);
+            let entry_offset = table_offset + (index + 1) * core::mem::size_of::<MsixVectorEntry>();
+            let result = interrupts::register_msi_interrupt(nvme_handler).and_then(|interrupt_num| {
+                routes.push(((index + 1) as u16, interrupt_num));
+                let entry = table_pages.as_type_mut::<MsixVectorEntry>(entry_offset)?;
+                entry.message_address_low.write(MSIX_ADDRESS_BASE | ((core as u32) << MSIX_ADDRESS_DEST_SHIFT));
+                entry.message_address_high.write(0);
+                entry.message_data.write(interrupt_num as u32);
+                // unmask the vector
+                entry.vector_control.write(0);
+                Ok(())
+            });
+            // MSI-X isn't enabled yet, so the vectors that were already routed are never raised.
+            if let Err(e) = result {
+                release_interrupts(routes.iter().map(|&(_, interrupt_num)| interrupt_num));
+                return Err(e);
+            }
+        }
+        Ok(routes)
+    }
+}
+
+
+/// Routes the MSI-X vectors of the given number of I/O queues to their cores, as described in `MsixTable::route_vectors()`,
+/// and enables MSI-X for the controller.
+///
+/// Returns the MSI-X vector of each I/O queue, or `None` for every queue if they must be polled instead.
+fn setup_interrupts(
+    pci_device: &PciDevice,
+    msix: Option<&MsixTable>,
+    registers: &MutexIrqSafe<Registers>,
+    cores: &[u8],
+    queue_count: usize,
+) -> Vec<Option<u16>> {
+    if apic::INTERRUPT_CHIP.load(Ordering::Acquire) == InterruptChip::PIC {
+        warn!("NvmeController::new(): MSI-X requires an APIC, falling back to polling");
+        return vec![None; queue_count];
+    }
+    let msix = match msix {
+        Some(msix) => msix,
+        None => {
+            warn!("NvmeController::new(): controller doesn't support MSI-X, falling back to polling");
+            return vec![None; queue_count];
+        }
+    };
+    let routes = match msix.route_vectors(pci_device, registers, cores, queue_count) {
+        Ok(routes) => routes,
+        Err(e) => {
+            warn!("NvmeController::new(): couldn't set up MSI-X interrupts, falling back to polling: {}", e);
+            return vec![None; queue_count];
+        }
+    };
+    match pci_device.pci_enable_msix() {
+        Ok(()) => {
+            pci_device.pci_set_interrupt_disable_bit();
+            routes.iter().map(|&(vector, _)| Some(vector)).collect()
+        }
+        Err(e) => {
+            release_interrupts(routes.iter().map(|&(_, interrupt_num)| interrupt_num));
+            warn!("NvmeController::new(): couldn't enable MSI-X interrupts, falling back to polling: {}", e);
+            vec![None; queue_count]
+        }
+    }
+}
+
+/// Deregisters the given interrupts, which were registered for the I/O queues' MSI-X vectors but won't be used.
+fn release_interrupts<I: Iterator<Item = u8>>(interrupt_nums: I) {
+    for interrupt_num in interrupt_nums {
+        if let Err(e) = interrupts::deregister_interrupt(interrupt_num, nvme_handler) {
+            error!("NvmeController: couldn't deregister interrupt {}: {}", interrupt_num, e);
+        }
+    }
+}
+
+/// Waits until the controller's ready status matches the given `ready` value.
+fn wait_for_ready(registers: &MutexIrqSafe<Registers>, ready: bool, timeout_ms: u32) -> Result<(), &'static str> {
+    poll(timeout_ms, || {
+        let status = registers.lock().controller().map(|regs| regs.csts.read()).ok()?;
+        if status & CSTS_FATAL != 0 {
+            Some(Err("NVMe controller reported a fatal error"))
+        } else if (status & CSTS_READY != 0) == ready {
+            Some(Ok(()))
+        } else {
+            None
+        }
+    }).unwrap_or(Err("timed out waiting for the NVMe controller to become (un)ready"))
+}
+
+/// Issues the identify command, which places the data structure selected by `cns` into the page at `buffer_phys_addr`.
+fn identify(admin_queue: &QueuePair, cns: IdentifyCns, namespace_id: u32, buffer_phys_addr: PhysicalAddress) -> Result<(), &'static str> {
+    admin_queue.execute(SubmissionEntry {
+        opcode: AdminCommand::Identify as u8,
+        namespace_id,
+        prp1: buffer_phys_addr.value() as u64,
+        cdw10: cns as u32,
+        .. SubmissionEntry::default()
+    }).map(|_| ())
+}
+
+/// Asks the controller for the given number of I/O queue pairs, and returns the number that it allocated.
+fn set_queue_count(admin_queue: &QueuePair, queue_count: usize) -> Result<usize, &'static str> {
+    // both counts are stored minus one
+    let requested = (queue_count - 1) as u32;
+    let completion = admin_queue.execute(SubmissionEntry {
+        opcode: AdminCommand::SetFeatures as u8,
+        cdw10: FEATURE_NUMBER_OF_QUEUES,
+        cdw11: (requested << 16) | requested,
+        .. SubmissionEntry::default()
+    })?;
+    let submission_queues = (completion.result & 0xFFFF) as usize + 1;
+    let completion_queues = (completion.result >> 16) as usize + 1;
+    Ok(core::cmp::min(queue_count, core::cmp::min(submission_queues, completion_queues)))
+}
+
+/// Tells the controller about the given I/O queue pair, creating its completion queue before its submission queue.
+fn create_io_queue_pair(admin_queue: &QueuePair, queue: &QueuePair) -> Result<(), &'static str> {
+    // The queue size is stored minus one. Both queues are physically contiguous (bit 0 of cdw11).
+    let cdw10 = ((queue.depth() as u32 - 1) << 16) | queue.id() as u32;
+    let interrupts = match queue.interrupt_vector() {
+        Some(vector) => ((vector as u32) << 16) | (1 << 1),
+        None => 0,
+    };
+    admin_queue.execute(SubmissionEntry {
+        opcode: AdminCommand::CreateIoCompletionQueue as u8,
+        prp1: queue.cq_phys_addr().value() as u64,
+        cdw10,
+        cdw11: interrupts | 1,
+        .. SubmissionEntry::default()
+    })?;
+    admin_queue.execute(SubmissionEntry {
+        opcode: AdminCommand::CreateIoSubmissionQueue as u8,
+        prp1: queue.sq_phys_addr().value() as u64,
+        cdw10,
+        // the completion queue that this submission queue's commands complete to
+        cdw11: ((queue.id() as u32) << 16) | 1,
+        .. SubmissionEntry::default()
+    })?;
+    Ok(())
+}
+
+/// Reads the little-endian `u32` at the given `offset` in `data`.
+fn read_u32(data: &[u8], offset: usize) -> u32 {
+    let mut bytes = [0u8; 4];
+    bytes.copy_from_slice(&data[offset .. offset + 4]);
+    u32::from_le_bytes(bytes)
+}
+
+/// Reads the little-endian `u64` at the given `offset` in `data`.
+fn read_u64(data: &[u8], offset: usize) -> u64 {
+    let mut bytes = [0u8; 8];
+    bytes.copy_from_slice(&data[offset .. offset + 8]);
+    u64::from_le_bytes(bytes)
+}
+
+/// Converts the given space-padded ASCII field of an identify data structure into a `String`.
+fn ascii_field(bytes: &[u8]) -> String {
+    String::from(core::str::from_utf8(bytes).unwrap_or("").trim())
+}
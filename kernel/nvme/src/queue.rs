@@ -0,0 +1,245 @@
+//! A pair of submission and completion queues, through which commands are given to an NVMe controller.
+//!
+//! Each queue pair may have several commands in flight at once, e.g., from tasks using different namespaces.
+//! A task that waits for its command to complete may thus find the completions of other tasks' commands,
+//! which it sets aside for those tasks to pick up.
+
+use core::fmt;
+use alloc::{
+    sync::Arc,
+    vec::Vec,
+};
+use irq_safety::MutexIrqSafe;
+use kernel_config::memory::PAGE_SIZE;
+use memory::{MappedPages, PhysicalAddress, create_contiguous_mapping};
+use wait_queue::WaitQueue;
+use driver_utils::{DEVICE_MAPPING_FLAGS, wait_or_poll};
+use regs::{Registers, SubmissionEntry, CompletionEntry};
+use super::COMMAND_TIMEOUT;
+
+
+/// The mutable state of a queue pair.
+struct QueueState {
+    /// The memory that holds the submission queue.
+    submission_queue: MappedPages,
+    /// The memory that holds the completion queue.
+    completion_queue: MappedPages,
+    /// The index of the submission queue entry that the next command is placed in.
+    sq_tail: u16,
+    /// The index of the oldest submission queue entry that the controller hasn't yet consumed.
+    sq_head: u16,
+    /// The index of the next completion queue entry that the controller will post.
+    cq_head: u16,
+    /// The phase tag that the controller sets in newly-posted completion queue entries.
+    phase: bool,
+    /// The identifier given to the next command.
+    next_command_id: u16,
+    /// Completions that have been taken from the completion queue,
+    /// but not yet by the tasks that issued their commands.
+    completed: Vec<CompletionEntry>,
+}
+
+/// A submission queue and the completion queue that its commands complete to.
+pub struct QueuePair {
+    /// The identifier of both queues, which is `0` for the admin queues.
+    id: u16,
+    /// The number of entries in each queue.
+    depth: u16,
+    /// The physical address of the submission queue.
+    sq_phys_addr: PhysicalAddress,
+    /// The physical address of the completion queue.
+    cq_phys_addr: PhysicalAddress,
+    /// The controller's registers, through which the queues' doorbells are rung.
+    registers: Arc<MutexIrqSafe<Registers>>,
+    state: MutexIrqSafe<QueueState>,
+    /// The tasks waiting for their commands to complete,
+    /// which are woken up by the interrupt handler of the completion queue.
+    pub(crate) waiters: WaitQueue,
+    /// The MSI-X vector that the completion queue raises, if its interrupts are used.
+    interrupt_vector: Option<u16>,
+}
+
+impl fmt::Debug for QueuePair {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("QueuePair")
+            .field("id", &self.id)
+            .field("depth", &self.depth)
+            .field("sq_phys_addr", &self.sq_phys_addr)
+            .field("cq_phys_addr", &self.cq_phys_addr)
+            .field("interrupt_vector", &self.interrupt_vector)
+            .finish()
+    }
+}
+
+impl QueuePair {
+    /// Allocates a new pair of queues with the given `id` that each have `depth` entries.
+    ///
+    /// The queues are not yet known to the controller, which must be told about them using their physical addresses.
+    /// If an `interrupt_vector` is given, tasks wait for the completion queue's interrupts rather than polling it.
+    pub fn new(
+        id: u16,
+        depth: u16,
+        registers: Arc<MutexIrqSafe<Registers>>,
+        interrupt_vector: Option<u16>,
+    ) -> Result<QueuePair, &'static str> {
+        let sq_size = depth as usize * core::mem::size_of::<SubmissionEntry>();
+        let cq_size = depth as usize * core::mem::size_of::<CompletionEntry>();
+        if sq_size > PAGE_SIZE || cq_size > PAGE_SIZE {
+            return Err("NVMe queues must fit within one page");
+        }
+        let (mut submission_queue, sq_phys_addr) = create_contiguous_mapping(PAGE_SIZE, DEVICE_MAPPING_FLAGS)?;
+        let (mut completion_queue, cq_phys_addr) = create_contiguous_mapping(PAGE_SIZE, DEVICE_MAPPING_FLAGS)?;
+        for byte in submission_queue.as_slice_mut::<u8>(0, PAGE_SIZE)?.iter_mut() {
+            *byte = 0;
+        }
+        // The controller sets the phase tag of the entries it posts during its first pass through the queue,
+        // so the queue must start out with all phase tags cleared.
+        for byte in completion_queue.as_slice_mut::<u8>(0, PAGE_SIZE)?.iter_mut() {
+            *byte = 0;
+        }
+
+        Ok(QueuePair {
+            id,
+            depth,
+            sq_phys_addr,
+            cq_phys_addr,
+            registers,
+            state: MutexIrqSafe::new(QueueState {
+                submission_queue,
+                completion_queue,
+                sq_tail: 0,
+                sq_head: 0,
+                cq_head: 0,
+                phase: true,
+                next_command_id: 0,
+                completed: Vec::new(),
+            }),
+            waiters: WaitQueue::new(),
+            interrupt_vector,
+        })
+    }
+
+    /// Returns the identifier of this queue pair.
+    pub fn id(&self) -> u16 {
+        self.id
+    }
+
+    /// Returns the number of entries in each of this pair's queues.
+    pub fn depth(&self) -> u16 {
+        self.depth
+    }
+
+    /// Returns the physical address of the submission queue.
+    pub fn sq_phys_addr(&self) -> PhysicalAddress {
+        self.sq_phys_addr
+    }
+
+    /// Returns the physical address of the completion queue.
+    pub fn cq_phys_addr(&self) -> PhysicalAddress {
+        self.cq_phys_addr
+    }
+
+    /// Returns the MSI-X vector that the completion queue raises, if its interrupts are used.
+    pub fn interrupt_vector(&self) -> Option<u16> {
+        self.interrupt_vector
+    }
+
+    /// Submits the given `command` and waits for it to complete.
+    ///
+    /// The command's identifier is chosen by this function.
+    /// Returns the command's completion entry, or an error if the command failed or timed out.
+    pub fn execute(&self, command: SubmissionEntry) -> Result<CompletionEntry, &'static str> {
+        // If the submission queue is full, wait until the controller has consumed some of its entries.
+        let command_id = self.wait_for(&|state| self.submit(state, command))??;
+        let completion = self.wait_for(&|state| {
+            let index = state.completed.iter().position(|c| c.command_id == command_id)?;
+            Some(state.completed.swap_remove(index))
+        })?;
+        if completion.is_success() {
+            Ok(completion)
+        } else {
+            error!("NVMe command {:?} on queue {} failed with status {:#X}", command, self.id, completion.status >> 1);
+            Err("NVMe command failed")
+        }
+    }
+
+    /// Places the given `command` into the submission queue and rings its doorbell.
+    ///
+    /// Returns the identifier given to the command, or `None` if the submission queue is full.
+    fn submit(&self, state: &mut QueueState, mut command: SubmissionEntry) -> Option<Result<u16, &'static str>> {
+        let next_tail = (state.sq_tail + 1) % self.depth;
+        if next_tail == state.sq_head {
+            return None;
+        }
+        let command_id = state.next_command_id;
+        state.next_command_id = state.next_command_id.wrapping_add(1);
+        // discard the leftover completion of an earlier command with the same identifier that timed out
+        state.completed.retain(|c| c.command_id != command_id);
+        command.command_id = command_id;
+
+        let offset = state.sq_tail as usize * core::mem::size_of::<SubmissionEntry>();
+        let result = state.submission_queue.as_type_mut::<SubmissionEntry>(offset).map(|entry| {
+            // SAFE: the entry is within the submission queue's mapped pages
+            unsafe { core::ptr::write_volatile(entry, command) };
+        });
+        if let Err(e) = result {
+            return Some(Err(e));
+        }
+        state.sq_tail = next_tail;
+        Some(self.registers.lock().write_doorbell(self.sq_doorbell_index(), next_tail).map(|_| command_id))
+    }
+
+    /// Moves all newly-posted entries from the completion queue into the list of completed commands,
+    /// and tells the controller that their space in the completion queue can be reused.
+    fn reap_completions(&self, state: &mut QueueState) -> Result<(), &'static str> {
+        let mut reaped = false;
+        loop {
+            let offset = state.cq_head as usize * core::mem::size_of::<CompletionEntry>();
+            let entry: CompletionEntry = {
+                let entry = state.completion_queue.as_type::<CompletionEntry>(offset)?;
+                // SAFE: the entry is within the completion queue's mapped pages
+                unsafe { core::ptr::read_volatile(entry) }
+            };
+            if entry.phase() != state.phase {
+                break;
+            }
+            state.sq_head = entry.sq_head;
+            state.completed.push(entry);
+            state.cq_head += 1;
+            if state.cq_head == self.depth {
+                state.cq_head = 0;
+                state.phase = !state.phase;
+            }
+            reaped = true;
+        }
+        if reaped {
+            self.registers.lock().write_doorbell(self.cq_doorbell_index(), state.cq_head)?;
+        }
+        Ok(())
+    }
+
+    /// Waits until the given `condition` returns `Some`, after taking new entries from the completion queue each time.
+    ///
+    /// This blocks on the completion queue's interrupts if they are used, and otherwise polls the completion queue.
+    fn wait_for<R>(&self, condition: &dyn Fn(&mut QueueState) -> Option<R>) -> Result<R, &'static str> {
+        let check = || {
+            let mut state = self.state.lock();
+            if let Err(e) = self.reap_completions(&mut state) {
+                error!("NVMe queue {}: couldn't reap completions: {}", self.id, e);
+            }
+            condition(&mut state)
+        };
+        let waiters = if self.interrupt_vector.is_some() { Some(&self.waiters) } else { None };
+        wait_or_poll(waiters, COMMAND_TIMEOUT, &check).ok_or("timed out waiting for an NVMe command to complete")
+    }
+
+    /// The index of the submission queue's tail doorbell among all of the controller's doorbells.
+    fn sq_doorbell_index(&self) -> usize {
+        2 * self.id as usize
+    }
+
+    /// The index of the completion queue's head doorbell among all of the controller's doorbells.
+    fn cq_doorbell_index(&self) -> usize {
+        2 * self.id as usize + 1
+    }
+}
@@ -0,0 +1,181 @@
+//! The memory-mapped registers of an NVMe controller,
+//! and the in-memory structures through which commands are given to it.
+//!
+//! See the NVM Express specification (revision 1.3) for more details.
+
+use core::fmt;
+use volatile::{Volatile, ReadOnly};
+use memory::MappedPages;
+
+
+/// CAP: the bit mask of the maximum number of entries in a queue, minus one
+pub const CAP_MQES_MASK:            u64 = 0xFFFF;
+/// CAP: the bit offset of the worst-case time to wait for the controller to become ready, in 500 ms units
+pub const CAP_TO_SHIFT:             u64 = 24;
+pub const CAP_TO_MASK:              u64 = 0xFF;
+/// CAP: the bit offset of the doorbell stride, which is given as a power of two of 4 bytes
+pub const CAP_DSTRD_SHIFT:          u64 = 32;
+pub const CAP_DSTRD_MASK:           u64 = 0xF;
+/// CAP: the controller supports the NVM command set
+pub const CAP_CSS_NVM:              u64 = 1 << 37;
+/// CAP: the bit offset of the minimum memory page size, which is given as a power of two of 4 KiB
+pub const CAP_MPSMIN_SHIFT:         u64 = 48;
+pub const CAP_MPSMIN_MASK:          u64 = 0xF;
+
+/// CC: enable the controller
+pub const CC_ENABLE:                u32 = 1 << 0;
+/// CC: the size of a submission queue entry, which is given as a power of two (64 bytes)
+pub const CC_IOSQES:                u32 = 6 << 16;
+/// CC: the size of a completion queue entry, which is given as a power of two (16 bytes)
+pub const CC_IOCQES:                u32 = 4 << 20;
+
+/// CSTS: the controller is ready to process commands
+pub const CSTS_READY:               u32 = 1 << 0;
+/// CSTS: the controller has encountered a fatal error
+pub const CSTS_FATAL:               u32 = 1 << 1;
+
+/// The offset of the first doorbell register from the start of the controller's registers.
+pub const DOORBELLS_OFFSET: usize = 0x1000;
+
+
+/// The memory-mapped registers of a controller, including the doorbell registers of its queues.
+pub struct Registers {
+    pub mapped_pages: MappedPages,
+    /// The distance between two adjacent doorbell registers, in bytes.
+    pub doorbell_stride: usize,
+}
+
+impl Registers {
+    pub fn controller(&self) -> Result<&ControllerRegisters, &'static str> {
+        self.mapped_pages.as_type(0)
+    }
+
+    pub fn controller_mut(&mut self) -> Result<&mut ControllerRegisters, &'static str> {
+        self.mapped_pages.as_type_mut(0)
+    }
+
+    /// Writes the given `value` to the doorbell register at the given `index`,
+    /// which is `2 * queue id` for a submission queue's tail and `2 * queue id + 1` for a completion queue's head.
+    pub fn write_doorbell(&mut self, index: usize, value: u16) -> Result<(), &'static str> {
+        let offset = DOORBELLS_OFFSET + index * self.doorbell_stride;
+        self.mapped_pages.as_type_mut::<Volatile<u32>>(offset)?.write(value as u32);
+        Ok(())
+    }
+}
+
+impl fmt::Debug for Registers {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Registers {{ doorbell_stride: {} }}", self.doorbell_stride)
+    }
+}
+
+/// The registers of an NVMe controller, which are located at the physical address given by its BAR0.
+/// They are followed by the doorbell registers of each queue, which are spaced apart by the doorbell stride.
+#[repr(C)]
+pub struct ControllerRegisters {
+    /// Controller capabilities
+    pub cap:                        ReadOnly<u64>,          // 0x00
+    /// Version
+    pub vs:                         ReadOnly<u32>,          // 0x08
+    /// Interrupt mask set, which isn't used with MSI-X
+    pub intms:                      Volatile<u32>,          // 0x0C
+    /// Interrupt mask clear, which isn't used with MSI-X
+    pub intmc:                      Volatile<u32>,          // 0x10
+    /// Controller configuration
+    pub cc:                         Volatile<u32>,          // 0x14
+    _reserved0:                     u32,                    // 0x18
+    /// Controller status
+    pub csts:                       ReadOnly<u32>,          // 0x1C
+    /// NVM subsystem reset
+    pub nssr:                       Volatile<u32>,          // 0x20
+    /// Admin queue attributes, i.e., the size of the admin queues
+    pub aqa:                        Volatile<u32>,          // 0x24
+    /// Admin submission queue base address, which must be page-aligned
+    pub asq:                        Volatile<u64>,          // 0x28
+    /// Admin completion queue base address, which must be page-aligned
+    pub acq:                        Volatile<u64>,          // 0x30
+}
+
+
+/// An entry in a submission queue, i.e., a command given to the controller.
+#[derive(Copy, Clone, Debug, Default)]
+#[repr(C)]
+pub struct SubmissionEntry {
+    pub opcode:                     u8,
+    /// Bits `[0:2)` select a fused operation, and bits `[6:8)` select how data is described (PRPs or SGLs).
+    pub flags:                      u8,
+    /// The identifier of this command, which is included in its completion entry.
+    pub command_id:                 u16,
+    pub namespace_id:               u32,
+    pub cdw2:                       u32,
+    pub cdw3:                       u32,
+    pub metadata_pointer:           u64,
+    /// The first physical region page (PRP) entry, i.e., the physical address of the command's data.
+    pub prp1:                       u64,
+    /// The second PRP entry, which is either the address of the second page of data
+    /// or the address of a list of PRP entries for the remaining pages.
+    pub prp2:                       u64,
+    pub cdw10:                      u32,
+    pub cdw11:                      u32,
+    pub cdw12:                      u32,
+    pub cdw13:                      u32,
+    pub cdw14:                      u32,
+    pub cdw15:                      u32,
+}
+
+/// An entry in a completion queue, which the controller posts once a command has completed.
+#[derive(Copy, Clone, Debug, Default)]
+#[repr(C)]
+pub struct CompletionEntry {
+    /// The command-specific result.
+    pub result:                     u32,
+    _reserved:                      u32,
+    /// The current head of the submission queue that the command came from,
+    /// which tells the host which submission queue entries can be reused.
+    pub sq_head:                    u16,
+    pub sq_id:                      u16,
+    /// The identifier of the command that completed.
+    pub command_id:                 u16,
+    /// Bit 0 is the phase tag, and bits `[1:16)` are the status of the command.
+    pub status:                     u16,
+}
+
+impl CompletionEntry {
+    /// Returns the phase tag of this entry, which the controller inverts each time it wraps around the completion queue.
+    pub fn phase(&self) -> bool {
+        self.status & 0x1 != 0
+    }
+
+    /// Returns `true` if the command completed successfully.
+    pub fn is_success(&self) -> bool {
+        self.status >> 1 == 0
+    }
+}
+
+
+/// The message control register of the MSI-X capability, relative to the capability's start.
+pub const MSIX_MESSAGE_CONTROL_OFFSET: u16 = 2;
+/// The table offset register of the MSI-X capability, relative to the capability's start.
+pub const MSIX_TABLE_OFFSET_OFFSET: u16 = 4;
+/// Message control: the bit mask of the number of entries in the MSI-X table, minus one
+pub const MSIX_TABLE_SIZE_MASK: u16 = 0x7FF;
+/// Table offset: the bit mask of the index of the BAR that the MSI-X table is located in
+pub const MSIX_TABLE_BIR_MASK: u32 = 0x7;
+
+/// An entry in a PCI device's MSI-X table, which describes the interrupt message for one vector.
+#[repr(C)]
+pub struct MsixVectorEntry {
+    /// The lower 32 bits of the address that the message is written to, which selects the destination core
+    pub message_address_low:        Volatile<u32>,
+    /// The upper 32 bits of the address that the message is written to
+    pub message_address_high:       Volatile<u32>,
+    /// The message data, which holds the interrupt number
+    pub message_data:               Volatile<u32>,
+    /// Bit 0 masks this vector
+    pub vector_control:             Volatile<u32>,
+}
+
+/// The base of the address that MSI-X messages are written to, see Intel SDM, vol 3, 10.11.
+pub const MSIX_ADDRESS_BASE: u32 = 0xFEE0_0000;
+/// The bit offset of the destination core's APIC id within an MSI-X message address
+pub const MSIX_ADDRESS_DEST_SHIFT: u32 = 12;
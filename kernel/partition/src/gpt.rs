@@ -0,0 +1,170 @@
+//! Parsing of the GPT (GUID Partition Table) format.
+//!
+//! A GPT-partitioned device has a protective MBR in its first sector, followed by the GPT header in its second sector.
+//! The header points to an array of partition entries, and both are protected by CRC32 checksums.
+//! A backup copy of the header is stored in the last sector of the device, which is used if the primary header is corrupt.
+
+use alloc::{
+    string::String,
+    vec::Vec,
+};
+use storage_device::StorageDevice;
+use super::{Guid, PartitionEntry, PartitionKind, read_sector, read_u32, read_u64};
+
+
+/// The signature at the start of a GPT header.
+const GPT_SIGNATURE: &[u8] = b"EFI PART";
+/// The minimum size of a GPT header, which is the size of all of its defined fields.
+const MIN_HEADER_SIZE: usize = 92;
+/// The offset of the header's own checksum, which is zeroed while the checksum is calculated.
+const HEADER_CRC_OFFSET: usize = 16;
+/// The minimum size of a partition entry.
+const MIN_ENTRY_SIZE: usize = 128;
+/// The maximum number of partition entries that are read, which is far more than any real GPT has.
+const MAX_ENTRY_COUNT: usize = 1024;
+/// The offset and maximum length (in UTF-16 code units) of the name within a partition entry.
+const ENTRY_NAME_OFFSET: usize = 56;
+const ENTRY_NAME_LENGTH: usize = 36;
+
+
+/// The fields of a GPT header that are needed to find the partition entries.
+#[derive(Debug)]
+struct GptHeader {
+    first_usable_lba: usize,
+    last_usable_lba: usize,
+    entries_lba: usize,
+    entry_count: usize,
+    entry_size: usize,
+    entries_crc: u32,
+}
+
+impl GptHeader {
+    /// Parses the given sector as a GPT header, returning `None` if it isn't a valid one.
+    fn parse(sector: &[u8]) -> Option<GptHeader> {
+        if sector.len() < MIN_HEADER_SIZE || &sector[0 .. 8] != GPT_SIGNATURE {
+            return None;
+        }
+        let header_size = read_u32(sector, 12) as usize;
+        if header_size < MIN_HEADER_SIZE || header_size > sector.len() {
+            return None;
+        }
+        let mut header = sector[.. header_size].to_vec();
+        header[HEADER_CRC_OFFSET .. HEADER_CRC_OFFSET + 4].copy_from_slice(&[0; 4]);
+        if crc32(&header) != read_u32(sector, HEADER_CRC_OFFSET) {
+            return None;
+        }
+        let entry_size = read_u32(sector, 84) as usize;
+        if entry_size < MIN_ENTRY_SIZE || entry_size % 8 != 0 {
+            return None;
+        }
+        Some(GptHeader {
+            first_usable_lba: read_u64(sector, 40) as usize,
+            last_usable_lba: read_u64(sector, 48) as usize,
+            entries_lba: read_u64(sector, 72) as usize,
+            entry_count: read_u32(sector, 80) as usize,
+            entry_size,
+            entries_crc: read_u32(sector, 88),
+        })
+    }
+
+    /// Reads this header's array of partition entries from the given storage `device`,
+    /// returning `None` if its checksum doesn't match.
+    fn read_entry_array(&self, device: &mut (dyn StorageDevice + Send)) -> Result<Option<Vec<u8>>, &'static str> {
+        if self.entry_count > MAX_ENTRY_COUNT {
+            return Err("GPT has too many partition entries");
+        }
+        let sector_size = device.sector_size_in_bytes();
+        let length = self.entry_count * self.entry_size;
+        let sector_count = (length + sector_size - 1) / sector_size;
+        if self.entries_lba + sector_count > device.size_in_sectors() {
+            return Ok(None);
+        }
+        let mut entries = vec![0u8; sector_count * sector_size];
+        device.read_sectors(&mut entries, self.entries_lba)?;
+        entries.truncate(length);
+        if crc32(&entries) != self.entries_crc {
+            return Ok(None);
+        }
+        Ok(Some(entries))
+    }
+}
+
+
+/// Reads the GPT of the given storage `device` and returns the partitions in it.
+///
+/// The primary GPT is used unless it is corrupt, in which case the backup GPT is used.
+pub(crate) fn read_entries(device: &mut (dyn StorageDevice + Send)) -> Result<Vec<PartitionEntry>, &'static str> {
+    let last_lba = device.size_in_sectors().checked_sub(1).ok_or("storage device is empty")?;
+    let mut found = None;
+    for &header_lba in [1, last_lba].iter() {
+        let header = match GptHeader::parse(&read_sector(device, header_lba)?) {
+            Some(header) => header,
+            None => continue,
+        };
+        if let Some(entries) = header.read_entry_array(device)? {
+            found = Some((header, entries));
+            break;
+        }
+    }
+    let (header, entries) = match found {
+        Some(found) => found,
+        None => return Err("storage device has a protective MBR, but neither its primary nor its backup GPT is valid"),
+    };
+
+    let mut partitions = Vec::new();
+    for (index, entry) in entries.chunks(header.entry_size).enumerate() {
+        let type_guid = parse_guid(&entry[0 .. 16]);
+        if type_guid.is_zero() {
+            continue;
+        }
+        let first_lba = read_u64(entry, 32) as usize;
+        // the last LBA is inclusive
+        let last_lba = read_u64(entry, 40) as usize;
+        if first_lba > last_lba || first_lba < header.first_usable_lba || last_lba > header.last_usable_lba {
+            warn!("partition: ignoring GPT partition {} with invalid bounds {}..={}", index + 1, first_lba, last_lba);
+            continue;
+        }
+        partitions.push(PartitionEntry {
+            number: index + 1,
+            first_sector: first_lba,
+            sector_count: last_lba - first_lba + 1,
+            kind: PartitionKind::Gpt {
+                type_guid,
+                unique_guid: parse_guid(&entry[16 .. 32]),
+                attributes: read_u64(entry, 48),
+                name: parse_name(&entry[ENTRY_NAME_OFFSET .. ENTRY_NAME_OFFSET + 2 * ENTRY_NAME_LENGTH]),
+            },
+        });
+    }
+    Ok(partitions)
+}
+
+fn parse_guid(bytes: &[u8]) -> Guid {
+    let mut guid = [0u8; 16];
+    guid.copy_from_slice(bytes);
+    Guid(guid)
+}
+
+/// Parses the given null-padded UTF-16LE partition name.
+fn parse_name(bytes: &[u8]) -> String {
+    let units = bytes.chunks(2)
+        .map(|unit| u16::from(unit[0]) | (u16::from(unit[1]) << 8))
+        .take_while(|&unit| unit != 0);
+    core::char::decode_utf16(units)
+        .map(|c| c.unwrap_or(core::char::REPLACEMENT_CHARACTER))
+        .collect()
+}
+
+/// Calculates the CRC32 checksum (as used by GPT, zlib, etc.) of the given `data`.
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= u32::from(byte);
+        for _ in 0 .. 8 {
+            // the polynomial is applied only if the lowest bit is set
+            let mask = 0u32.wrapping_sub(crc & 1);
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+        }
+    }
+    !crc
+}
@@ -0,0 +1,238 @@
+//! Support for partitioned storage devices.
+//!
+//! The partition table of a storage device is read using [`read_partitions()`](fn.read_partitions.html),
+//! which understands both the legacy MBR (Master Boot Record) format, including logical partitions within an extended partition,
+//! and the GPT (GUID Partition Table) format.
+//!
+//! Each partition is represented by a [`Partition`](struct.Partition.html), which itself implements `StorageDevice`
+//! by translating sector offsets into offsets on the underlying device.
+//! Thus, a filesystem can be mounted on a single partition just like it can on a whole storage device.
+
+#![no_std]
+
+#[macro_use] extern crate alloc;
+#[macro_use] extern crate log;
+extern crate spin;
+extern crate storage_device;
+
+mod mbr;
+mod gpt;
+
+use core::fmt;
+use alloc::{
+    string::String,
+    sync::Arc,
+    vec::Vec,
+};
+use spin::Mutex;
+use storage_device::{StorageDevice, StorageDeviceRef, BlockBounds};
+
+
+/// The kind of partition table that a storage device has.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PartitionTableType {
+    Mbr,
+    Gpt,
+}
+
+/// A globally-unique identifier (GUID), as used by GPT partition tables.
+#[derive(Copy, Clone, Default, PartialEq, Eq)]
+pub struct Guid(pub [u8; 16]);
+
+impl Guid {
+    /// Returns `true` if this GUID is all zeros, which marks an unused GPT partition entry.
+    pub fn is_zero(&self) -> bool {
+        self.0.iter().all(|&b| b == 0)
+    }
+}
+
+impl fmt::Display for Guid {
+    /// Formats this GUID in its canonical form, e.g., `C12A7328-F81F-11D2-BA4B-00A0C93EC93B`.
+    /// The first three fields are stored in little-endian byte order, and the last two in big-endian byte order.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let b = &self.0;
+        write!(f, "{:02X}{:02X}{:02X}{:02X}-{:02X}{:02X}-{:02X}{:02X}-{:02X}{:02X}-{:02X}{:02X}{:02X}{:02X}{:02X}{:02X}",
+            b[3], b[2], b[1], b[0], b[5], b[4], b[7], b[6], b[8], b[9], b[10], b[11], b[12], b[13], b[14], b[15]
+        )
+    }
+}
+
+impl fmt::Debug for Guid {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self)
+    }
+}
+
+/// Details about a partition that are specific to the kind of partition table it was found in.
+#[derive(Clone, Debug)]
+pub enum PartitionKind {
+    /// A primary or logical partition from an MBR partition table.
+    Mbr {
+        /// The system id, which roughly describes what the partition contains, e.g., `0x0C` for FAT32.
+        system_id: u8,
+        /// Whether the partition is marked as active (bootable).
+        bootable: bool,
+    },
+    /// A partition from a GPT partition table.
+    Gpt {
+        /// The GUID that describes what the partition contains.
+        type_guid: Guid,
+        /// The GUID that uniquely identifies this partition.
+        unique_guid: Guid,
+        /// The attribute flags of the partition.
+        attributes: u64,
+        /// The human-readable name of the partition, which may be empty.
+        name: String,
+    },
+}
+
+
+/// One partition of a storage device, which is itself a `StorageDevice`
+/// that covers a contiguous range of the underlying device's sectors.
+pub struct Partition {
+    /// The storage device that this partition is a part of.
+    device: StorageDeviceRef,
+    /// The number of this partition, starting at `1`.
+    /// Primary MBR partitions are numbered `1` to `4` by their slot in the MBR, and logical partitions from `5` onwards.
+    /// GPT partitions are numbered by their entry in the GPT.
+    number: usize,
+    /// The first sector of this partition on the underlying device.
+    first_sector: usize,
+    /// The number of sectors in this partition.
+    sector_count: usize,
+    /// The sector size of the underlying device.
+    sector_size: usize,
+    kind: PartitionKind,
+}
+
+impl fmt::Debug for Partition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Partition")
+            .field("number", &self.number)
+            .field("first_sector", &self.first_sector)
+            .field("sector_count", &self.sector_count)
+            .field("sector_size", &self.sector_size)
+            .field("kind", &self.kind)
+            .finish()
+    }
+}
+
+impl Partition {
+    /// Returns the storage device that this partition is a part of.
+    pub fn device(&self) -> &StorageDeviceRef {
+        &self.device
+    }
+
+    /// Returns the number of this partition, starting at `1`.
+    pub fn number(&self) -> usize {
+        self.number
+    }
+
+    /// Returns the first sector of this partition on the underlying device.
+    pub fn first_sector(&self) -> usize {
+        self.first_sector
+    }
+
+    /// Returns the details of this partition that are specific to the kind of partition table it was found in.
+    pub fn kind(&self) -> &PartitionKind {
+        &self.kind
+    }
+
+    /// Checks that a transfer of `length` bytes starting at `offset_in_sectors` lies entirely within this partition.
+    fn check_bounds(&self, length: usize, offset_in_sectors: usize) -> Result<BlockBounds, &'static str> {
+        if length % self.sector_size != 0 {
+            return Err("The buffer length must be a multiple of the partition's sector size.");
+        }
+        let bounds = self.block_bounds(offset_in_sectors * self.sector_size, length)?;
+        // `block_bounds()` truncates transfers that extend past the end of the partition.
+        if bounds.block_count() != length / self.sector_size {
+            return Err("transfer extends past the end of the partition");
+        }
+        Ok(bounds)
+    }
+}
+
+impl StorageDevice for Partition {
+    fn read_sectors(&mut self, buffer: &mut [u8], offset_in_sectors: usize) -> Result<usize, &'static str> {
+        let bounds = self.check_bounds(buffer.len(), offset_in_sectors)?;
+        self.device.lock().read_sectors(buffer, self.first_sector + bounds.range.start)
+    }
+
+    fn write_sectors(&mut self, buffer: &[u8], offset_in_sectors: usize) -> Result<usize, &'static str> {
+        let bounds = self.check_bounds(buffer.len(), offset_in_sectors)?;
+        self.device.lock().write_sectors(buffer, self.first_sector + bounds.range.start)
+    }
+
+    fn sector_size_in_bytes(&self) -> usize {
+        self.sector_size
+    }
+
+    fn size_in_sectors(&self) -> usize {
+        self.sector_count
+    }
+
+    fn underlying_device(&self) -> Option<(StorageDeviceRef, usize)> {
+        Some((Arc::clone(&self.device), self.first_sector))
+    }
+}
+
+pub type PartitionRef = Arc<Mutex<Partition>>;
+
+
+/// Reads the partition table of the given storage `device` and returns its type and all of the partitions in it,
+/// in order of their partition number.
+///
+/// Returns `Ok(None)` if the device doesn't have a partition table,
+/// e.g., if a filesystem occupies the whole device.
+pub fn read_partitions(device: &StorageDeviceRef) -> Result<Option<(PartitionTableType, Vec<Partition>)>, &'static str> {
+    let (table_type, entries) = {
+        let mut locked_device = device.lock();
+        let mbr_sector = read_sector(&mut *locked_device, 0)?;
+        match mbr::MasterBootRecord::parse(&mbr_sector, locked_device.size_in_sectors()) {
+            Some(mbr) if mbr.is_protective() => (PartitionTableType::Gpt, gpt::read_entries(&mut *locked_device)?),
+            Some(mbr) => (PartitionTableType::Mbr, mbr.read_entries(&mut *locked_device)?),
+            None => return Ok(None),
+        }
+    };
+    let sector_size = device.lock().sector_size_in_bytes();
+    let partitions = entries.into_iter().map(|entry| Partition {
+        device: Arc::clone(device),
+        number: entry.number,
+        first_sector: entry.first_sector,
+        sector_count: entry.sector_count,
+        sector_size,
+        kind: entry.kind,
+    }).collect();
+    Ok(Some((table_type, partitions)))
+}
+
+
+/// A partition as described by a partition table, before it is bound to its storage device.
+struct PartitionEntry {
+    number: usize,
+    first_sector: usize,
+    sector_count: usize,
+    kind: PartitionKind,
+}
+
+/// Reads the sector at the given `lba` from the given storage `device`.
+fn read_sector(device: &mut (dyn StorageDevice + Send), lba: usize) -> Result<Vec<u8>, &'static str> {
+    let mut sector = vec![0u8; device.sector_size_in_bytes()];
+    device.read_sectors(&mut sector, lba)?;
+    Ok(sector)
+}
+
+/// Reads the little-endian `u16` at the given `offset` in `data`.
+fn read_u16(data: &[u8], offset: usize) -> u16 {
+    u16::from(data[offset]) | (u16::from(data[offset + 1]) << 8)
+}
+
+/// Reads the little-endian `u32` at the given `offset` in `data`.
+fn read_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from(read_u16(data, offset)) | (u32::from(read_u16(data, offset + 2)) << 16)
+}
+
+/// Reads the little-endian `u64` at the given `offset` in `data`.
+fn read_u64(data: &[u8], offset: usize) -> u64 {
+    u64::from(read_u32(data, offset)) | (u64::from(read_u32(data, offset + 4)) << 32)
+}
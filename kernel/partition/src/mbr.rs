@@ -0,0 +1,182 @@
+//! Parsing of the legacy MBR (Master Boot Record) partition table,
+//! which holds up to four primary partitions in the first sector of a storage device.
+//!
+//! One of the primary partitions may be an extended partition, which holds a chain of
+//! Extended Boot Records (EBRs) that each describe one logical partition.
+
+use alloc::vec::Vec;
+use storage_device::StorageDevice;
+use super::{PartitionEntry, PartitionKind, read_sector, read_u32};
+
+
+/// The offset of the first partition entry within an MBR or EBR.
+const PARTITION_ENTRIES_OFFSET: usize = 446;
+/// The size of one partition entry.
+const PARTITION_ENTRY_SIZE: usize = 16;
+/// The number of partition entries in an MBR.
+const PARTITION_ENTRY_COUNT: usize = 4;
+/// The offset of the boot signature, which is the final two bytes of an MBR or EBR.
+const BOOT_SIGNATURE_OFFSET: usize = 510;
+const BOOT_SIGNATURE: [u8; 2] = [0x55, 0xAA];
+
+/// The status byte of a partition entry that is marked as active (bootable).
+const STATUS_ACTIVE: u8 = 0x80;
+/// The system id of the protective partition that covers the entire disk of a GPT-partitioned device.
+const SYSTEM_ID_GPT_PROTECTIVE: u8 = 0xEE;
+/// The system ids of extended partitions.
+const SYSTEM_IDS_EXTENDED: [u8; 3] = [0x05, 0x0F, 0x85];
+
+/// The number of the first logical partition, since numbers `1` to `4` are reserved for the primary partitions.
+const FIRST_LOGICAL_PARTITION_NUMBER: usize = 5;
+/// The maximum number of EBRs that are followed, in case a malformed chain of EBRs loops back on itself.
+const MAX_LOGICAL_PARTITIONS: usize = 128;
+
+
+/// One of the partition entries in an MBR or EBR.
+#[derive(Copy, Clone, Debug)]
+struct MbrEntry {
+    status: u8,
+    system_id: u8,
+    /// The first sector of the partition, relative to a starting point that depends on where the entry is.
+    relative_first_sector: usize,
+    sector_count: usize,
+}
+
+impl MbrEntry {
+    fn parse(sector: &[u8], index: usize) -> MbrEntry {
+        let entry = &sector[PARTITION_ENTRIES_OFFSET + index * PARTITION_ENTRY_SIZE ..];
+        MbrEntry {
+            status: entry[0],
+            system_id: entry[4],
+            relative_first_sector: read_u32(entry, 8) as usize,
+            sector_count: read_u32(entry, 12) as usize,
+        }
+    }
+
+    fn is_used(&self) -> bool {
+        self.system_id != 0 && self.sector_count != 0
+    }
+
+    fn is_extended(&self) -> bool {
+        SYSTEM_IDS_EXTENDED.contains(&self.system_id)
+    }
+
+    fn kind(&self) -> PartitionKind {
+        PartitionKind::Mbr {
+            system_id: self.system_id,
+            bootable: self.status == STATUS_ACTIVE,
+        }
+    }
+}
+
+/// Returns `true` if the given sector ends with the boot signature that MBRs and EBRs must have.
+fn has_boot_signature(sector: &[u8]) -> bool {
+    sector.len() >= BOOT_SIGNATURE_OFFSET + 2 && sector[BOOT_SIGNATURE_OFFSET .. BOOT_SIGNATURE_OFFSET + 2] == BOOT_SIGNATURE
+}
+
+
+/// The primary partition entries of an MBR.
+pub(crate) struct MasterBootRecord {
+    entries: [MbrEntry; PARTITION_ENTRY_COUNT],
+}
+
+impl MasterBootRecord {
+    /// Parses the given first sector of a storage device with `device_size_in_sectors` sectors as an MBR.
+    ///
+    /// Returns `None` if the sector doesn't hold a valid MBR.
+    /// Since a FAT boot sector also ends with the boot signature, the partition entries are checked
+    /// to be plausible, i.e., to have a valid status byte and lie within the device.
+    pub(crate) fn parse(sector: &[u8], device_size_in_sectors: usize) -> Option<MasterBootRecord> {
+        if !has_boot_signature(sector) {
+            return None;
+        }
+        let entries = [
+            MbrEntry::parse(sector, 0),
+            MbrEntry::parse(sector, 1),
+            MbrEntry::parse(sector, 2),
+            MbrEntry::parse(sector, 3),
+        ];
+        for entry in entries.iter() {
+            if entry.status != 0 && entry.status != STATUS_ACTIVE {
+                return None;
+            }
+            // A protective partition may claim to be larger than the device if the device is larger than 2 TiB.
+            if entry.is_used() && entry.system_id != SYSTEM_ID_GPT_PROTECTIVE
+                && entry.relative_first_sector + entry.sector_count > device_size_in_sectors
+            {
+                return None;
+            }
+        }
+        if !entries.iter().any(MbrEntry::is_used) {
+            return None;
+        }
+        Some(MasterBootRecord { entries })
+    }
+
+    /// Returns `true` if this is a protective MBR, which means that the device actually uses a GPT.
+    pub(crate) fn is_protective(&self) -> bool {
+        self.entries.iter().any(|e| e.system_id == SYSTEM_ID_GPT_PROTECTIVE)
+    }
+
+    /// Returns the primary partitions of this MBR,
+    /// followed by the logical partitions within its extended partition, if any.
+    pub(crate) fn read_entries(&self, device: &mut (dyn StorageDevice + Send)) -> Result<Vec<PartitionEntry>, &'static str> {
+        let mut partitions = Vec::new();
+        for (index, entry) in self.entries.iter().enumerate() {
+            if !entry.is_used() {
+                continue;
+            }
+            partitions.push(PartitionEntry {
+                number: index + 1,
+                first_sector: entry.relative_first_sector,
+                sector_count: entry.sector_count,
+                kind: entry.kind(),
+            });
+        }
+        if let Some(extended) = self.entries.iter().find(|e| e.is_used() && e.is_extended()) {
+            read_logical_partitions(device, extended.relative_first_sector, &mut partitions)?;
+        }
+        Ok(partitions)
+    }
+}
+
+/// Follows the chain of EBRs in the extended partition that starts at `extended_start`,
+/// and appends the logical partition that each one describes to `partitions`.
+///
+/// The first entry of each EBR describes a logical partition relative to that EBR,
+/// while its second entry points to the next EBR relative to the start of the extended partition.
+fn read_logical_partitions(
+    device: &mut (dyn StorageDevice + Send),
+    extended_start: usize,
+    partitions: &mut Vec<PartitionEntry>,
+) -> Result<(), &'static str> {
+    let device_size = device.size_in_sectors();
+    let mut ebr_sector = extended_start;
+    for number in FIRST_LOGICAL_PARTITION_NUMBER .. FIRST_LOGICAL_PARTITION_NUMBER + MAX_LOGICAL_PARTITIONS {
+        let sector = read_sector(device, ebr_sector)?;
+        if !has_boot_signature(&sector) {
+            warn!("partition: EBR at sector {} is missing its boot signature", ebr_sector);
+            return Ok(());
+        }
+        let logical = MbrEntry::parse(&sector, 0);
+        if logical.is_used() {
+            let first_sector = ebr_sector + logical.relative_first_sector;
+            if first_sector + logical.sector_count > device_size {
+                return Err("logical partition extends past the end of the storage device");
+            }
+            partitions.push(PartitionEntry {
+                number,
+                first_sector,
+                sector_count: logical.sector_count,
+                kind: logical.kind(),
+            });
+        }
+        let next = MbrEntry::parse(&sector, 1);
+        if !next.is_used() || !next.is_extended() {
+            return Ok(());
+        }
+        ebr_sector = extended_start + next.relative_first_sector;
+    }
+    warn!("partition: stopped following the EBR chain after {} logical partitions", MAX_LOGICAL_PARTITIONS);
+    Ok(())
+}
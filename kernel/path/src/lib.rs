@@ -1,4 +1,7 @@
 #![no_std]
+#[cfg(test)]
+#[macro_use] extern crate std;
+
 /// This crate contains all the necessary functions for navigating the virtual filesystem / obtaining specific
 /// directories via the Path struct 
 // #[macro_use] extern crate log;
@@ -6,10 +9,13 @@
 extern crate spin;
 extern crate fs_node;
 extern crate root;
+extern crate mount_table;
 
 use core::fmt;
-use core::ops::{Deref, DerefMut};
+use core::ops::Deref;
+use core::borrow::Borrow;
 use alloc::{
+    borrow::ToOwned,
     string::{String, ToString},
     vec::Vec,
     sync::Arc,
@@ -19,52 +25,208 @@ use fs_node::{FileOrDir, DirRef};
 pub const PATH_DELIMITER: &str = "/";
 pub const EXTENSION_DELIMITER: &str = ".";
 
+/// The maximum number of symbolic links that are followed while resolving a single path,
+/// beyond which the symbolic links are assumed to form a loop.
+pub const MAX_SYMLINK_FOLLOWS: usize = 40;
+
 
-/// A structure that represents a file  
-#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+/// A structure that represents the path to a file or directory.
+///
+/// Like the `Path` in the Rust standard library, this is a borrowed, unsized wrapper around a `str`,
+/// so it is always used behind a reference, e.g., `&Path`.
+/// Create one with [`Path::new()`](#method.new), or borrow one from an owned [`PathBuf`](struct.PathBuf.html).
+#[derive(Debug, Eq, PartialEq, Hash, PartialOrd, Ord)]
+#[repr(transparent)]
 pub struct Path {
+    path: str
+}
+
+/// An owned, mutable path, which is to [`Path`](struct.Path.html) as `String` is to `str`.
+#[derive(Debug, Clone, Default, Eq, PartialEq, Hash, PartialOrd, Ord)]
+pub struct PathBuf {
     path: String
 }
 
 impl Deref for Path {
-    type Target = String;
+    type Target = str;
 
-    fn deref(&self) -> &String {
+    fn deref(&self) -> &str {
         &self.path
     }
 }
-impl DerefMut for Path {
-    fn deref_mut(&mut self) -> &mut String {
-        &mut self.path
+
+impl Deref for PathBuf {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        Path::new(&self.path)
     }
 }
 
 impl fmt::Display for Path {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        write!(f, "{}", &self.path)
+    }
+}
+
+impl fmt::Display for PathBuf {
     fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
         write!(f, "{}", self.path)
     }
 }
 
-impl From<String> for Path {
+impl AsRef<Path> for Path {
+    fn as_ref(&self) -> &Path {
+        self
+    }
+}
+
+impl AsRef<Path> for PathBuf {
+    fn as_ref(&self) -> &Path {
+        self
+    }
+}
+
+impl AsRef<Path> for str {
+    fn as_ref(&self) -> &Path {
+        Path::new(self)
+    }
+}
+
+impl AsRef<Path> for String {
+    fn as_ref(&self) -> &Path {
+        Path::new(self)
+    }
+}
+
+impl AsRef<str> for Path {
+    fn as_ref(&self) -> &str {
+        &self.path
+    }
+}
+
+impl Borrow<Path> for PathBuf {
+    fn borrow(&self) -> &Path {
+        self
+    }
+}
+
+impl ToOwned for Path {
+    type Owned = PathBuf;
+
+    fn to_owned(&self) -> PathBuf {
+        self.to_path_buf()
+    }
+}
+
+impl From<String> for PathBuf {
     #[inline]
     fn from(path: String) -> Self {
-        Path {path: path}
+        PathBuf { path }
     }
 }
 
-impl From<Path> for String {
+impl<'a> From<&'a str> for PathBuf {
     #[inline]
-    fn from(path: Path) -> String {
+    fn from(path: &'a str) -> Self {
+        PathBuf { path: path.to_string() }
+    }
+}
+
+impl<'a> From<&'a Path> for PathBuf {
+    #[inline]
+    fn from(path: &'a Path) -> Self {
+        path.to_path_buf()
+    }
+}
+
+impl From<PathBuf> for String {
+    #[inline]
+    fn from(path: PathBuf) -> String {
         path.path
     }
 }
 
+impl PathBuf {
+    /// Creates a new, empty `PathBuf`.
+    pub fn new() -> Self {
+        PathBuf { path: String::new() }
+    }
+
+    /// Returns a borrowed `Path` of this `PathBuf`.
+    pub fn as_path(&self) -> &Path {
+        self
+    }
+
+    /// Consumes this `PathBuf` and returns its underlying `String`.
+    pub fn into_string(self) -> String {
+        self.path
+    }
+
+    /// Extends this path with the given `path`, separating them with the path delimiter `"/"`.
+    ///
+    /// If the given `path` is absolute, it replaces this path entirely.
+    pub fn push<P: AsRef<Path>>(&mut self, path: P) {
+        let path = path.as_ref();
+        if path.is_absolute() {
+            self.path.clear();
+        } else if !self.path.is_empty() && !self.path.ends_with(PATH_DELIMITER) {
+            self.path.push_str(PATH_DELIMITER);
+        }
+        self.path.push_str(path);
+    }
+
+    /// Truncates this path to its [`parent()`](struct.Path.html#method.parent).
+    /// Returns `false` and does nothing if there is no parent, e.g., for the root directory.
+    pub fn pop(&mut self) -> bool {
+        let parent_len = match self.parent() {
+            Some(parent) => parent.len(),
+            None => return false,
+        };
+        self.path.truncate(parent_len);
+        true
+    }
+
+    /// Replaces the extension of the file name at the end of this path with the given `extension`,
+    /// or adds it if there was none. If the given `extension` is empty, the existing extension is removed.
+    ///
+    /// Returns `false` and does nothing if this path doesn't end with a file name, e.g., `"/"` or `"foo/.."`.
+    pub fn set_extension(&mut self, extension: &str) -> bool {
+        let (name_start, name_end) = match self.file_name_range() {
+            Some(range) => range,
+            None => return false,
+        };
+        // a leading delimiter, as in ".bashrc", doesn't start an extension
+        let stem_end = match self.path[name_start .. name_end].rfind(EXTENSION_DELIMITER) {
+            Some(index) if index > 0 => name_start + index,
+            _ => name_end,
+        };
+        self.path.truncate(stem_end);
+        if !extension.is_empty() {
+            self.path.push_str(EXTENSION_DELIMITER);
+            self.path.push_str(extension);
+        }
+        true
+    }
+}
+
 impl Path {
-    /// Creates a new `Path` from the given String.
-    pub fn new(path: String) -> Self {
-        Path { path }
+    /// Wraps the given string slice as a `Path`, without copying it.
+    pub fn new<S: AsRef<str> + ?Sized>(path: &S) -> &Path {
+        // SAFETY: `Path` is just a wrapper around a `str`, so they have the same layout.
+        unsafe { &*(path.as_ref() as *const str as *const Path) }
+    }
+
+    /// Returns this path as a string slice.
+    pub fn as_str(&self) -> &str {
+        &self.path
+    }
+
+    /// Copies this path into a new owned `PathBuf`.
+    pub fn to_path_buf(&self) -> PathBuf {
+        PathBuf { path: self.path.to_string() }
     }
-    
+
     /// Returns an iterator over the components of this `Path`,
     /// split by the path delimiter `"/"`.
     pub fn components<'a>(&'a self) -> impl Iterator<Item = &'a str> {
@@ -101,7 +263,7 @@ impl Path {
 
     /// Returns the file extension, if present. 
     /// If there are multiple extensions as defined by the extension delimiter, `'.'`,
-    /// then the last one will be treated as the extension. 
+    /// then the last one will be treated as the extension.
     pub fn extension<'a>(&'a self) -> Option<&'a str> {
         self.basename()
             .rsplit(EXTENSION_DELIMITER)
@@ -109,41 +271,87 @@ impl Path {
             .next()
     }
 
-    /// Returns a canonical and absolute form of the current path (i.e. the path of the working directory)
-    /// TODO: FIXME:  this doesn't work if the `current_path` is absolute.
-    #[allow(dead_code)]
-    fn canonicalize(&self, current_path: &Path) -> Path {
-        let mut new_components = Vec::new();
-        // Push the components of the working directory to the components of the new path
-        new_components.extend(current_path.components());
-        // Push components of the path to the components of the new path
-        for component in self.components() {
-            if component == String::from(".") {
-                continue;
-            } else if component == String::from("..") {
-                new_components.pop();
-            } else {
-                new_components.push(component);
-            }
+    /// Returns this path without its final component, or `None` if it has no final component,
+    /// i.e., if it is the root directory or empty.
+    /// # Examples
+    /// `"/path/to/file.a"` -> `Some("/path/to")`
+    /// `"/file.a"` -> `Some("/")`
+    /// `"file.a"` -> `Some("")`
+    /// `"/"` -> `None`
+    pub fn parent(&self) -> Option<&Path> {
+        let trimmed = self.path.trim_end_matches(PATH_DELIMITER);
+        if trimmed.is_empty() {
+            return None;
         }
-        // Create the new path from its components 
-        let mut new_path = String::new();
-        let mut first_cmpnt = true; 
-        for component in new_components {
-            if first_cmpnt {
-                new_path.push_str(&format!("{}",  component));
-                first_cmpnt = false;
-            } 
-            else {
-                new_path.push_str(&format!("/{}",  component));
+        let parent = match trimmed.rfind(PATH_DELIMITER) {
+            Some(index) => trimmed[.. index].trim_end_matches(PATH_DELIMITER),
+            None => return Some(Path::new("")),
+        };
+        if parent.is_empty() {
+            // the only remaining component was the root directory
+            Some(Path::new(&self.path[.. 1]))
+        } else {
+            Some(Path::new(parent))
+        }
+    }
+
+    /// Creates a new `PathBuf` with the given `path` appended to this path.
+    /// If the given `path` is absolute, it replaces this path entirely.
+    /// See [`PathBuf::push()`](struct.PathBuf.html#method.push).
+    pub fn join<P: AsRef<Path>>(&self, path: P) -> PathBuf {
+        let mut joined = self.to_path_buf();
+        joined.push(path);
+        joined
+    }
+
+    /// Creates a new `PathBuf` like this path, but with the given file `extension`.
+    /// See [`PathBuf::set_extension()`](struct.PathBuf.html#method.set_extension).
+    pub fn with_extension(&self, extension: &str) -> PathBuf {
+        let mut path = self.to_path_buf();
+        path.set_extension(extension);
+        path
+    }
+
+    /// Returns a normalized form of this path, in which duplicate delimiters and trailing delimiters are removed,
+    /// `"."` components are removed, and each `".."` component is removed along with the component before it.
+    ///
+    /// A `".."` component at the beginning of a relative path is kept, whereas one at the root directory is removed.
+    /// An empty relative path is normalized to `"."`.
+    ///
+    /// Normalization is purely lexical, i.e., it doesn't access the filesystem.
+    /// Thus, if the component before a `".."` is a symbolic link, the normalized path may refer to a different
+    /// file than this path does, because [`get()`](#method.get) would resolve the `".."` relative to the target of that link.
+    /// # Examples
+    /// `"/a//b/./c/../d/"` -> `"/a/b/d"`
+    /// `"../a/../../b"` -> `"../../b"`
+    /// `"/../a"` -> `"/a"`
+    pub fn normalize(&self) -> PathBuf {
+        let mut new_components: Vec<&str> = Vec::new();
+        for component in self.components() {
+            match component {
+                "." => continue,
+                ".." => match new_components.last() {
+                    Some(&last) if last != ".." => { new_components.pop(); }
+                    _ if self.is_absolute() => { }
+                    _ => new_components.push(component),
+                },
+                _ => new_components.push(component),
             }
         }
-        Path::new(new_path)
+        let joined = new_components.join(PATH_DELIMITER);
+        let path = if self.is_absolute() {
+            format!("{}{}", PATH_DELIMITER, joined)
+        } else if joined.is_empty() {
+            String::from(".")
+        } else {
+            joined
+        };
+        PathBuf::from(path)
     }
-    
+
     /// Expresses the current Path, self, relative to another Path, other
     /// https://docs.rs/pathdiff/0.1.0/src/pathdiff/lib.rs.html#32-74
-    pub fn relative(&self, other: &Path) -> Option<Path> {
+    pub fn relative(&self, other: &Path) -> Option<PathBuf> {
         let mut ita_iter = self.components();
         let mut itb_iter = other.components();
         let mut comps: Vec<String> = Vec::new();
@@ -181,19 +389,101 @@ impl Path {
         }
         // Remove the trailing slash after the final path component
         new_path.pop();
-        Some(Path::new(new_path))
+        Some(PathBuf::from(new_path))
     }
-    
+
     /// Returns a boolean indicating whether this Path is absolute,
     /// i.e., whether it starts with the root directory.
     pub fn is_absolute(&self) -> bool {
         self.path.starts_with(PATH_DELIMITER)
     }
 
-    /// Returns the file or directory specified by the given path, 
-    /// which can either be absolute, or relative from the given the current working directory 
+    /// Returns `true` if any component of this path contains a wildcard character, `'*'` or `'?'`,
+    /// in which case it should be expanded with [`glob()`](#method.glob).
+    pub fn has_wildcards(&self) -> bool {
+        self.path.contains(|c| c == '*' || c == '?')
+    }
+
+    /// Expands this path as a glob pattern into the paths of all existing files and directories that it matches,
+    /// in which `'*'` matches any sequence of characters and `'?'` matches any single character within a path component.
+    ///
+    /// The pattern can either be absolute, or relative to the given `starting_dir`,
+    /// and the returned paths are absolute or relative accordingly.
+    /// As in most shells, a wildcard doesn't match a leading `'.'` in a file name, so hidden files are only matched
+    /// by a pattern component that itself starts with a `'.'`.
+    /// The paths that match each wildcard component are sorted by name.
+    ///
+    /// If this path doesn't contain any wildcards, the returned list contains only this path, if it exists.
+    pub fn glob(&self, starting_dir: &DirRef) -> Vec<PathBuf> {
+        let (start_path, start_dir) = if self.is_absolute() {
+            (PathBuf::from(PATH_DELIMITER), Arc::clone(root::get_root()))
+        } else {
+            (PathBuf::new(), Arc::clone(starting_dir))
+        };
+        let mut candidates = vec![(start_path, start_dir)];
+        let mut matches = Vec::new();
+
+        let mut components = self.components().peekable();
+        while let Some(pattern) = components.next() {
+            let is_last = components.peek().is_none();
+            let mut next_candidates = Vec::new();
+            for (path, dir) in candidates {
+                let names = if Path::new(pattern).has_wildcards() {
+                    let mut names: Vec<String> = dir.lock().list().into_iter()
+                        .filter(|name| wildcard_match(pattern, name))
+                        .collect();
+                    names.sort();
+                    names
+                } else {
+                    vec![pattern.to_string()]
+                };
+
+                for name in names {
+                    let child_path = path.join(&name);
+                    if is_last {
+                        if Path::new(&name).get_no_follow(&dir).is_some() {
+                            matches.push(child_path);
+                        }
+                    } else if let Some(FileOrDir::Dir(child_dir)) = Path::new(&name).get(&dir) {
+                        next_candidates.push((child_path, child_dir));
+                    }
+                }
+            }
+            candidates = next_candidates;
+        }
+        // a pattern without any components refers to the starting directory itself, e.g., "/"
+        if self.components().next().is_none() {
+            matches.extend(candidates.into_iter().map(|(path, _dir)| path));
+        }
+        matches
+    }
+
+    /// Returns the file or directory specified by the given path,
+    /// which can either be absolute, or relative from the given the current working directory
+    ///
+    /// If a filesystem is mounted on a directory along the path,
+    /// the lookup continues within the root directory of that mounted filesystem.
+    ///
+    /// Symbolic links along the path are followed, including one at the end of the path,
+    /// so the returned node is never a symbolic link.
+    /// Returns `None` if a symbolic link is dangling, or if more than [`MAX_SYMLINK_FOLLOWS`] links
+    /// are followed, which means that the links most likely form a loop.
+    ///
+    /// [`MAX_SYMLINK_FOLLOWS`]: constant.MAX_SYMLINK_FOLLOWS.html
     pub fn get(&self, starting_dir: &DirRef) -> Option<FileOrDir> {
-        // let current_path = { Path::new(starting_dir.lock().get_absolute_path()) };
+        self.resolve(starting_dir, true, &mut 0)
+    }
+
+    /// Like [`get()`](#method.get), but if the path ends in a symbolic link, that link itself is returned
+    /// rather than the node it points to. Symbolic links earlier in the path are still followed.
+    pub fn get_no_follow(&self, starting_dir: &DirRef) -> Option<FileOrDir> {
+        self.resolve(starting_dir, false, &mut 0)
+    }
+
+    /// The implementation of [`get()`](#method.get) and [`get_no_follow()`](#method.get_no_follow),
+    /// in which `follow_last` determines whether a symbolic link at the end of the path is followed,
+    /// and `follows` counts the symbolic links followed so far.
+    fn resolve(&self, starting_dir: &DirRef, follow_last: bool, follows: &mut usize) -> Option<FileOrDir> {
         let mut curr_dir = {
             if self.is_absolute() {
                 Arc::clone(root::get_root())
@@ -203,9 +493,10 @@ impl Path {
             }
         };
 
-        for component in self.components() {
+        let mut components = self.components().peekable();
+        while let Some(component) = components.next() {
             match component {
-                "." => { 
+                "." => {
                     // stay in the current directory, do nothing. 
                 }
                 ".." => {
@@ -214,13 +505,30 @@ impl Path {
                     curr_dir = parent_dir;
                 }
                 cmpnt => {
-                    // navigate to child directory, or return the child file
-                    let child_dir = match curr_dir.lock().get(cmpnt) {
-                        Some(FileOrDir::File(f)) => return Some(FileOrDir::File(f)),
-                        Some(FileOrDir::Dir(d)) => d,
-                        None => return None,
+                    // The lock on the current directory must be released before following a symbolic link,
+                    // since a relative link target is resolved starting from that directory.
+                    let child = curr_dir.lock().get(cmpnt)?;
+                    let child = match child {
+                        FileOrDir::Symlink(symlink) if follow_last || components.peek().is_some() => {
+                            *follows += 1;
+                            if *follows > MAX_SYMLINK_FOLLOWS {
+                                return None;
+                            }
+                            let target = symlink.lock().target();
+                            if target.is_empty() {
+                                return None;
+                            }
+                            Path::new(&target).resolve(&curr_dir, true, follows)?
+                        }
+                        other => other,
                     };
-                    curr_dir = child_dir;
+                    // navigate to child directory, or return the child file (or unfollowed symbolic link)
+                    let child_dir = match child {
+                        FileOrDir::Dir(d) => d,
+                        other => return Some(other),
+                    };
+                    // cross into the filesystem mounted on the child directory, if there is one
+                    curr_dir = mount_table::resolve(&child_dir);
                 }
             }
         }
@@ -236,8 +544,52 @@ impl Path {
             None
         }
     }
+
+    /// Returns the byte range of the file name at the end of this path, excluding trailing delimiters,
+    /// or `None` if this path doesn't end with a file name.
+    fn file_name_range(&self) -> Option<(usize, usize)> {
+        let end = self.path.trim_end_matches(PATH_DELIMITER).len();
+        let start = self.path[.. end].rfind(PATH_DELIMITER).map(|index| index + 1).unwrap_or(0);
+        match &self.path[start .. end] {
+            "" | "." | ".." => None,
+            _ => Some((start, end)),
+        }
+    }
 }
 
+
+/// Returns `true` if the given `name` matches the given `pattern`,
+/// in which `'*'` matches any sequence of characters and `'?'` matches any single character.
+/// A leading `'.'` in the `name` must be matched literally.
+fn wildcard_match(pattern: &str, name: &str) -> bool {
+    if name.starts_with('.') && !pattern.starts_with('.') {
+        return false;
+    }
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    // The position in the pattern just after the most recent '*', and the position in the name that it was matched up to.
+    let mut backtrack: Option<(usize, usize)> = None;
+    let (mut p, mut n) = (0, 0);
+    while n < name.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == name[n]) {
+            p += 1;
+            n += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            backtrack = Some((p + 1, n));
+            p += 1;
+        } else if let Some((star_p, star_n)) = backtrack {
+            // let the most recent '*' match one more character
+            backtrack = Some((star_p, star_n + 1));
+            p = star_p;
+            n = star_n + 1;
+        } else {
+            return false;
+        }
+    }
+    pattern[p ..].iter().all(|&c| c == '*')
+}
+
+
 pub enum PathComponent {
     RootDir,
     ParentDir,
@@ -252,4 +604,70 @@ impl PathComponent {
             PathComponent::ParentDir => String::from(".."),
         }
     }
-}
\ No newline at end of file
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn normalize() {
+        let normalized = |path: &str| Path::new(path).normalize().into_string();
+        assert_eq!(normalized("/a//b/./c/../d/"), "/a/b/d");
+        assert_eq!(normalized("../a/../../b"), "../../b");
+        assert_eq!(normalized("/../a"), "/a");
+        assert_eq!(normalized("/.."), "/");
+        assert_eq!(normalized("a/.."), ".");
+        assert_eq!(normalized(""), ".");
+        assert_eq!(normalized(".bashrc"), ".bashrc");
+    }
+
+    #[test]
+    fn parent() {
+        fn parent(path: &str) -> Option<&str> {
+            Path::new(path).parent().map(Path::as_str)
+        }
+        assert_eq!(parent("/path/to/file.a"), Some("/path/to"));
+        assert_eq!(parent("/path/to//dir/"), Some("/path/to"));
+        assert_eq!(parent("/file.a"), Some("/"));
+        assert_eq!(parent("//file.a"), Some("/"));
+        assert_eq!(parent("file.a"), Some(""));
+        assert_eq!(parent("/"), None);
+        assert_eq!(parent(""), None);
+    }
+
+    #[test]
+    fn set_extension() {
+        let with_extension = |path: &str, extension: &str| {
+            let mut path = PathBuf::from(path);
+            if path.set_extension(extension) { Some(path.into_string()) } else { None }
+        };
+        assert_eq!(with_extension("a/file.txt", "rs"), Some("a/file.rs".to_string()));
+        assert_eq!(with_extension("a.tar.gz", ""), Some("a.tar".to_string()));
+        assert_eq!(with_extension("dir.d/file", "o"), Some("dir.d/file.o".to_string()));
+        assert_eq!(with_extension("dir/file/", "o"), Some("dir/file.o".to_string()));
+        assert_eq!(with_extension(".bashrc", "old"), Some(".bashrc.old".to_string()));
+        assert_eq!(with_extension(".bashrc", ""), Some(".bashrc".to_string()));
+        assert_eq!(with_extension("/", "o"), None);
+        assert_eq!(with_extension("foo/..", "o"), None);
+    }
+
+    #[test]
+    fn wildcard_match() {
+        assert!(super::wildcard_match("*", "file.a"));
+        assert!(super::wildcard_match("*.a", "file.a"));
+        assert!(!super::wildcard_match("*.a", "file.b"));
+        assert!(super::wildcard_match("a**b", "ab"));
+        assert!(super::wildcard_match("a**b", "axyb"));
+        assert!(!super::wildcard_match("a**b", "axy"));
+        assert!(super::wildcard_match("a*b*c", "abcbc"));
+        assert!(super::wildcard_match("f?le", "file"));
+        assert!(!super::wildcard_match("f?le", "fle"));
+        assert!(!super::wildcard_match("*", ".bashrc"));
+        assert!(!super::wildcard_match("?bashrc", ".bashrc"));
+        assert!(super::wildcard_match(".*", ".bashrc"));
+        assert!(super::wildcard_match("", ""));
+        assert!(!super::wildcard_match("", "a"));
+    }
+}
@@ -0,0 +1,190 @@
+//! A RAM disk, which is a storage device whose sectors are held in memory.
+//!
+//! A [`RamDisk`](struct.RamDisk.html) is backed by a region of `MappedPages` and implements the `StorageDevice` trait,
+//! so it can be used anywhere a real disk can, e.g., beneath a `BlockIo` or a filesystem.
+//! Because it doesn't depend on any hardware, it offers the block layer and filesystems a deterministic device for testing.
+//!
+//! A RAM disk can either start out empty (all zeros) or be seeded with the contents of a file,
+//! such as a `MemFile` or a disk image that was loaded as a bootloader module
+//! (which `mod_mgmt` places as a file into a namespace directory).
+//!
+//! Once [`register()`](fn.register.html)ed, a RAM disk appears in `storage_manager`'s list of storage devices
+//! just like any other disk, via the single [`RamDiskController`](struct.RamDiskController.html).
+//!
+//! # Example
+//! ```rust
+//! let image = /* a `FileRef` holding a disk image */;
+//! let ramdisk = RamDisk::from_file(&image, 512, None)?;
+//! let device = ramdisk::register(ramdisk);
+//! if fat32::is_fat32(&device) { ... }
+//! ```
+
+#![no_std]
+
+extern crate alloc;
+#[macro_use] extern crate log;
+extern crate spin;
+extern crate memory;
+extern crate fs_node;
+extern crate storage_device;
+extern crate storage_manager;
+
+use alloc::{
+    boxed::Box,
+    sync::Arc,
+    vec::Vec,
+};
+use spin::{Mutex, Once};
+use memory::{MappedPages, EntryFlags};
+use fs_node::FileRef;
+use storage_device::{StorageController, StorageControllerRef, StorageDevice, StorageDeviceRef};
+
+
+/// The sector size of most disks, which is a good default for a RAM disk.
+pub const DEFAULT_SECTOR_SIZE: usize = 512;
+/// The smallest supported sector size.
+const MIN_SECTOR_SIZE: usize = 512;
+
+
+/// A storage device whose contents are held in memory, and are lost when it is dropped.
+pub struct RamDisk {
+    /// The memory that holds the contents of all sectors.
+    pages: MappedPages,
+    sector_size: usize,
+    sector_count: usize,
+}
+
+impl RamDisk {
+    /// Creates a new RAM disk with `sector_count` sectors of `sector_size` bytes each,
+    /// whose contents are all zeros.
+    ///
+    /// The `sector_size` must be a power of two that is at least 512 bytes.
+    pub fn new(sector_size: usize, sector_count: usize) -> Result<RamDisk, &'static str> {
+        check_sector_size(sector_size)?;
+        if sector_count == 0 {
+            return Err("RamDisk: sector count must not be zero");
+        }
+        let size_in_bytes = sector_size.checked_mul(sector_count).ok_or("RamDisk: capacity is too large")?;
+        let mut pages = memory::create_mapping(size_in_bytes, EntryFlags::WRITABLE)?;
+        // The newly-allocated frames may contain old data.
+        for byte in pages.as_slice_mut::<u8>(0, size_in_bytes)?.iter_mut() {
+            *byte = 0;
+        }
+        Ok(RamDisk { pages, sector_size, sector_count })
+    }
+
+    /// Creates a new RAM disk with sectors of `sector_size` bytes each,
+    /// and copies the entire contents of the given `file` to the start of it.
+    ///
+    /// If `sector_count` is `None`, the RAM disk is just large enough to hold the file's contents,
+    /// with the remainder of its final sector being zeros.
+    /// Otherwise, the RAM disk has `sector_count` sectors, which must be enough to hold the file's contents.
+    pub fn from_file(file: &FileRef, sector_size: usize, sector_count: Option<usize>) -> Result<RamDisk, &'static str> {
+        check_sector_size(sector_size)?;
+        let file = file.lock();
+        let size = file.size();
+        let required_sectors = (size + sector_size - 1) / sector_size;
+        let sector_count = match sector_count {
+            Some(count) if count < required_sectors => return Err("RamDisk: file is larger than the requested capacity"),
+            Some(count) => count,
+            // an empty file still results in a RAM disk with one sector
+            None => core::cmp::max(required_sectors, 1),
+        };
+        let mut ramdisk = RamDisk::new(sector_size, sector_count)?;
+        let bytes_read = file.read(ramdisk.pages.as_slice_mut(0, size)?, 0)?;
+        if bytes_read != size {
+            return Err("RamDisk: couldn't read the entire file");
+        }
+        debug!("Created RamDisk with {} sectors of {} bytes from a file of {} bytes", sector_count, sector_size, size);
+        Ok(ramdisk)
+    }
+
+    /// Returns the entire contents of this RAM disk.
+    pub fn as_slice(&self) -> Result<&[u8], &'static str> {
+        self.pages.as_slice(0, self.size_in_bytes())
+    }
+
+    /// Returns the byte offset of a transfer of `length` bytes starting at sector `offset_in_sectors`,
+    /// after checking that it is sector-aligned and lies within this RAM disk.
+    fn check_bounds(&self, length: usize, offset_in_sectors: usize) -> Result<usize, &'static str> {
+        if length % self.sector_size != 0 {
+            return Err("The buffer length must be a multiple of the RAM disk's sector size.");
+        }
+        if offset_in_sectors + (length / self.sector_size) > self.sector_count {
+            return Err("offset_in_sectors was out of bounds");
+        }
+        Ok(offset_in_sectors * self.sector_size)
+    }
+}
+
+/// Checks that the given `sector_size` is a power of two that is at least 512 bytes.
+fn check_sector_size(sector_size: usize) -> Result<(), &'static str> {
+    if sector_size < MIN_SECTOR_SIZE || !sector_size.is_power_of_two() {
+        return Err("RamDisk: sector size must be a power of two that is at least 512 bytes");
+    }
+    Ok(())
+}
+
+impl StorageDevice for RamDisk {
+    fn read_sectors(&mut self, buffer: &mut [u8], offset_in_sectors: usize) -> Result<usize, &'static str> {
+        let offset = self.check_bounds(buffer.len(), offset_in_sectors)?;
+        buffer.copy_from_slice(self.pages.as_slice(offset, buffer.len())?);
+        Ok(buffer.len() / self.sector_size)
+    }
+
+    fn write_sectors(&mut self, buffer: &[u8], offset_in_sectors: usize) -> Result<usize, &'static str> {
+        let offset = self.check_bounds(buffer.len(), offset_in_sectors)?;
+        self.pages.as_slice_mut(offset, buffer.len())?.copy_from_slice(buffer);
+        Ok(buffer.len() / self.sector_size)
+    }
+
+    fn sector_size_in_bytes(&self) -> usize {
+        self.sector_size
+    }
+
+    fn size_in_sectors(&self) -> usize {
+        self.sector_count
+    }
+}
+
+
+/// The controller that owns all registered RAM disks.
+///
+/// There is only one instance of this controller, which is added to `storage_manager`'s list of storage controllers
+/// when the first RAM disk is registered.
+pub struct RamDiskController {
+    ramdisks: Vec<Arc<Mutex<RamDisk>>>,
+}
+
+impl RamDiskController {
+    /// Returns an iterator over all RAM disks owned by this controller.
+    pub fn iter(&self) -> impl Iterator<Item = &Arc<Mutex<RamDisk>>> {
+        self.ramdisks.iter()
+    }
+}
+
+impl StorageController for RamDiskController {
+    fn devices<'c>(&'c self) -> Box<(dyn Iterator<Item = StorageDeviceRef> + 'c)> {
+        Box::new(
+            self.iter().map(|ramdisk_ref| Arc::clone(ramdisk_ref) as StorageDeviceRef)
+        )
+    }
+}
+
+/// The single `RamDiskController`, which is created when the first RAM disk is registered.
+static RAMDISK_CONTROLLER: Once<Arc<Mutex<RamDiskController>>> = Once::new();
+
+/// Registers the given `ramdisk` as a storage device with the `storage_manager`,
+/// such that it can be found and mounted by name just like any other disk.
+///
+/// Returns a reference to the registered RAM disk.
+pub fn register(ramdisk: RamDisk) -> StorageDeviceRef {
+    let controller = RAMDISK_CONTROLLER.call_once(|| {
+        let controller = Arc::new(Mutex::new(RamDiskController { ramdisks: Vec::new() }));
+        storage_manager::STORAGE_CONTROLLERS.lock().push(controller.clone() as StorageControllerRef);
+        controller
+    });
+    let ramdisk_ref = Arc::new(Mutex::new(ramdisk));
+    controller.lock().ramdisks.push(ramdisk_ref.clone());
+    ramdisk_ref
+}
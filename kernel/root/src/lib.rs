@@ -61,6 +61,21 @@ impl Directory for RootDirectory {
         self.children.keys().cloned().collect()
     }
 
+    fn rename(&mut self, old_name: &str, new_name: &str) -> Result<Option<FileOrDir>, &'static str> {
+        let mut node = self.children.remove(old_name).ok_or("no such file or directory")?;
+        if let Err(e) = node.set_name(String::from(new_name)) {
+            self.children.insert(String::from(old_name), node);
+            return Err(e);
+        }
+        self.insert(node)
+    }
+
+    fn detach(&mut self, name: &str) -> Result<FileOrDir, &'static str> {
+        let mut node = self.children.remove(name).ok_or("no such file or directory")?;
+        node.set_parent_dir(Weak::<Mutex<RootDirectory>>::new());
+        Ok(node)
+    }
+
     fn remove(&mut self, node: &FileOrDir) -> Option<FileOrDir> {
         // Prevents removal of root
         match node {
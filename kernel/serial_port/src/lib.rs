@@ -10,6 +10,7 @@ use irq_safety::MutexIrqSafe;
 const SERIAL_PORT_COM1: u16 = 0x3F8;
 const SERIAL_PORT_COM1_READY: u16 = SERIAL_PORT_COM1 + 5;
 const SERIAL_PORT_READY_MASK: u8 = 0x20;
+const SERIAL_PORT_DATA_READY_MASK: u8 = 0x01;
 
 static COM1: Port<u8> = Port::new(SERIAL_PORT_COM1);
 static COM1_READY: Port<u8> = Port::new(SERIAL_PORT_COM1_READY);
@@ -76,3 +77,25 @@ pub fn write_str(s: &str) -> fmt::Result {
 	let mut serial = SERIAL_PORT.lock();
 	serial.write_str(s)
 }
+
+/// Write the given bytes to the COM1 serial port, 
+/// which, unlike `write_str()`, need not be valid UTF-8.
+pub fn write_bytes(bytes: &[u8]) {
+	let mut serial = SERIAL_PORT.lock();
+	for &b in bytes {
+		serial.out_byte(b);
+	}
+}
+
+/// Reads the bytes that the COM1 serial port has already received into the given `buffer`, without blocking.
+/// 
+/// Returns the number of bytes read, which is `0` if no bytes have been received. 
+pub fn read_bytes(buffer: &mut [u8]) -> usize {
+	let _serial = SERIAL_PORT.lock();
+	let mut count = 0;
+	while count < buffer.len() && COM1_READY.read() & SERIAL_PORT_DATA_READY_MASK != 0 {
+		buffer[count] = COM1.read();
+		count += 1;
+	}
+	count
+}
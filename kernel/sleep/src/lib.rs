@@ -0,0 +1,269 @@
+//! Allows a `Task` to sleep for a given duration or until a given point in time.
+//!
+//! Time is measured in ticks of the local APIC timer,
+//! each of which lasts for one timeslice period (see `CONFIG_TIMESLICE_PERIOD_MICROSECONDS`).
+//! Thus, the granularity of a sleep is one timeslice, and a sleep is always rounded up
+//! to the next tick, such that a task never wakes up before its deadline.
+//!
+//! Each core has its own queue of sleeping tasks, ordered by deadline (a min-heap).
+//! A sleeping task is placed on the queue of the core it was running on when it went to sleep.
+//! Upon every timer tick, the interrupt handler on each core invokes [`timer_tick()`](fn.timer_tick.html),
+//! which unblocks all tasks on that core's queue whose deadline has passed.
+//!
+//! The global tick count is only advanced by the bootstrap processor (BSP),
+//! such that an [`Instant`](struct.Instant.html) means the same thing on every core.
+
+#![no_std]
+
+extern crate alloc;
+#[macro_use] extern crate log;
+#[macro_use] extern crate lazy_static;
+extern crate irq_safety;
+extern crate atomic_linked_list;
+extern crate kernel_config;
+extern crate apic;
+extern crate task;
+extern crate scheduler;
+
+use core::cmp::Ordering as CmpOrdering;
+use core::ops::{Add, Sub};
+use core::sync::atomic::{AtomicUsize, Ordering};
+use core::time::Duration;
+use alloc::collections::BinaryHeap;
+use irq_safety::MutexIrqSafe;
+use atomic_linked_list::atomic_map::AtomicMap;
+use kernel_config::time::CONFIG_TIMESLICE_PERIOD_MICROSECONDS;
+use task::{TaskRef, RunState};
+
+
+/// The number of timer ticks that have elapsed since the APIC timer was enabled on the BSP.
+static TICK_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+lazy_static! {
+    /// There is one queue of sleeping tasks per core, keyed by apic id.
+    /// Each core only wakes up the tasks on its own queue.
+    static ref SLEEP_QUEUES: AtomicMap<u8, MutexIrqSafe<BinaryHeap<SleepingTask>>> = AtomicMap::new();
+}
+
+
+/// A point in time, measured in timer ticks since the system started.
+///
+/// This is similar to `std::time::Instant`, but its granularity is only one timeslice period.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Instant {
+    ticks: usize,
+}
+
+impl Instant {
+    /// Returns the `Instant` corresponding to the current timer tick.
+    pub fn now() -> Instant {
+        Instant { ticks: TICK_COUNT.load(Ordering::SeqCst) }
+    }
+
+    /// Creates an `Instant` from the given absolute number of timer ticks.
+    pub fn from_ticks(ticks: usize) -> Instant {
+        Instant { ticks }
+    }
+
+    /// Returns the absolute number of timer ticks that this `Instant` represents.
+    pub fn ticks(&self) -> usize {
+        self.ticks
+    }
+
+    /// Returns the amount of time elapsed from the given `earlier` instant to this one,
+    /// or a zero duration if `earlier` is actually later than this one.
+    pub fn duration_since(&self, earlier: Instant) -> Duration {
+        ticks_to_duration(self.ticks.saturating_sub(earlier.ticks))
+    }
+
+    /// Returns the amount of time elapsed since this `Instant` was created.
+    pub fn elapsed(&self) -> Duration {
+        Instant::now().duration_since(*self)
+    }
+}
+
+impl Add<Duration> for Instant {
+    type Output = Instant;
+    fn add(self, duration: Duration) -> Instant {
+        Instant { ticks: self.ticks.saturating_add(duration_to_ticks(duration)) }
+    }
+}
+
+impl Sub<Instant> for Instant {
+    type Output = Duration;
+    fn sub(self, earlier: Instant) -> Duration {
+        self.duration_since(earlier)
+    }
+}
+
+
+/// Converts the given `Duration` into a number of timer ticks, rounding up to the next tick.
+pub fn duration_to_ticks(duration: Duration) -> usize {
+    let period = CONFIG_TIMESLICE_PERIOD_MICROSECONDS as u128;
+    ((duration.as_micros() + period - 1) / period) as usize
+}
+
+/// Converts the given number of timer ticks into a `Duration`.
+pub fn ticks_to_duration(ticks: usize) -> Duration {
+    Duration::from_micros(ticks as u64 * CONFIG_TIMESLICE_PERIOD_MICROSECONDS as u64)
+}
+
+
+/// A task that is waiting on a sleep queue until its `deadline`.
+///
+/// The ordering is reversed such that the `BinaryHeap` (a max-heap)
+/// returns the task with the earliest deadline first.
+struct SleepingTask {
+    deadline: Instant,
+    task: TaskRef,
+}
+impl PartialEq for SleepingTask {
+    fn eq(&self, other: &SleepingTask) -> bool {
+        self.deadline == other.deadline
+    }
+}
+impl Eq for SleepingTask { }
+impl PartialOrd for SleepingTask {
+    fn partial_cmp(&self, other: &SleepingTask) -> Option<CmpOrdering> {
+        Some(self.cmp(other))
+    }
+}
+impl Ord for SleepingTask {
+    fn cmp(&self, other: &SleepingTask) -> CmpOrdering {
+        other.deadline.cmp(&self.deadline)
+    }
+}
+
+
+/// Puts the current `Task` to sleep for (at least) the given `duration`.
+///
+/// A zero `duration` simply yields the CPU to another task.
+pub fn sleep(duration: Duration) -> Result<(), &'static str> {
+    sleep_until(Instant::now() + duration)
+}
+
+/// Puts the current `Task` to sleep until (at least) the given `deadline`.
+///
+/// If the `deadline` has already passed, this simply yields the CPU to another task.
+pub fn sleep_until(deadline: Instant) -> Result<(), &'static str> {
+    let curr_task = task::get_my_current_task().ok_or("sleep_until(): couldn't get current task")?;
+    if deadline <= Instant::now() {
+        scheduler::schedule();
+        return Ok(());
+    }
+
+    loop {
+        {
+            // The sleep queue lock disables interrupts, so the timer interrupt cannot unblock
+            // this task on this core between adding it to the queue and blocking it.
+            let mut queue = my_sleep_queue().lock();
+            if Instant::now() >= deadline {
+                remove_task(&mut queue, curr_task);
+                return Ok(());
+            }
+            add_to_queue(&mut queue, curr_task, deadline);
+            curr_task.block();
+        }
+        scheduler::schedule();
+
+        // Here, we have been woken up, but it may have been by someone other than the timer,
+        // so we must check the deadline again.
+        if Instant::now() >= deadline {
+            remove_from_sleep_queue(curr_task);
+            return Ok(());
+        }
+    }
+}
+
+
+/// Adds the given `task` to the current core's sleep queue,
+/// such that it will be unblocked once the given `deadline` has passed.
+/// If the task was already on that sleep queue, its previous deadline is replaced.
+///
+/// This does **not** block the task; the caller is responsible for doing that,
+/// which is useful for implementing timeouts on other waiting primitives, e.g., wait queues.
+/// To avoid a lost wakeup, the caller should block the task with interrupts disabled
+/// before the timer on this core has a chance to fire.
+pub fn add_to_sleep_queue(task: &TaskRef, deadline: Instant) {
+    let mut queue = my_sleep_queue().lock();
+    add_to_queue(&mut queue, task, deadline);
+}
+
+/// Removes the given `task` from every sleep queue, e.g., if it was woken up early by another event.
+///
+/// Returns `true` if the task was found on a sleep queue.
+pub fn remove_from_sleep_queue(task: &TaskRef) -> bool {
+    let mut found = false;
+    for (_core, queue) in SLEEP_QUEUES.iter() {
+        found |= remove_task(&mut queue.lock(), task);
+    }
+    found
+}
+
+/// Advances the system tick count (on the BSP only)
+/// and unblocks all tasks on this core's sleep queue whose deadline has passed.
+///
+/// This must be invoked upon every local APIC timer interrupt, on every core,
+/// before the scheduler is invoked.
+pub fn timer_tick() {
+    if apic::is_bsp() {
+        TICK_COUNT.fetch_add(1, Ordering::SeqCst);
+    }
+    let now = Instant::now();
+
+    let queue = match SLEEP_QUEUES.get(&apic::get_my_apic_id()) {
+        Some(q) => q,
+        _ => return, // no task has ever slept on this core
+    };
+    let mut locked_queue = queue.lock();
+    while locked_queue.peek().map(|st| st.deadline <= now).unwrap_or(false) {
+        if let Some(st) = locked_queue.pop() {
+            // only wake up tasks that are still blocked, e.g., not tasks that have since exited
+            let is_blocked = match st.task.lock().runstate {
+                RunState::Blocked => true,
+                _ => false,
+            };
+            if is_blocked {
+                st.task.unblock();
+            }
+        }
+    }
+}
+
+/// Returns the number of timer ticks that have elapsed since the system started.
+pub fn get_current_time_in_ticks() -> usize {
+    TICK_COUNT.load(Ordering::SeqCst)
+}
+
+
+/// Returns the sleep queue for the current core, creating it if it doesn't yet exist.
+fn my_sleep_queue() -> &'static MutexIrqSafe<BinaryHeap<SleepingTask>> {
+    let apic_id = apic::get_my_apic_id();
+    if let Some(queue) = SLEEP_QUEUES.get(&apic_id) {
+        return queue;
+    }
+    // Only the current core ever creates its own sleep queue, so there is no race here.
+    trace!("Created sleep queue for core {}", apic_id);
+    SLEEP_QUEUES.insert(apic_id, MutexIrqSafe::new(BinaryHeap::new()));
+    SLEEP_QUEUES.get(&apic_id).expect("BUG: sleep queue was not inserted")
+}
+
+/// Adds the `task` to the given locked sleep `queue`, replacing any previous entry for that task.
+fn add_to_queue(queue: &mut BinaryHeap<SleepingTask>, task: &TaskRef, deadline: Instant) {
+    remove_task(queue, task);
+    queue.push(SleepingTask {
+        deadline,
+        task: task.clone(),
+    });
+}
+
+/// Removes all entries for the `task` from the given locked sleep `queue`.
+/// Returns `true` if any entry was removed.
+fn remove_task(queue: &mut BinaryHeap<SleepingTask>, task: &TaskRef) -> bool {
+    if !queue.iter().any(|st| &st.task == task) {
+        return false;
+    }
+    let remaining: BinaryHeap<SleepingTask> = queue.drain().filter(|st| &st.task != task).collect();
+    *queue = remaining;
+    true
+}
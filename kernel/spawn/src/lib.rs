@@ -45,7 +45,7 @@ use irq_safety::{MutexIrqSafe, hold_interrupts, enable_interrupts};
 use memory::{get_kernel_mmi_ref, MemoryManagementInfo, VirtualAddress};
 use task::{Task, TaskRef, get_my_current_task, RunState, RestartInfo, TASKLIST};
 use mod_mgmt::{CrateNamespace, SectionType, SECTION_HASH_DELIMITER};
-use path::Path;
+use path::{Path, PathBuf};
 use apic::get_my_apic_id;
 use fs_node::FileOrDir;
 
@@ -131,7 +131,7 @@ pub fn new_task_builder<F, A, R>(
 ///    If not provided, the new Task will be spawned within the same namespace as the current task.
 /// 
 pub fn new_application_task_builder(
-    crate_object_file: Path, // TODO FIXME: use `mod_mgmt::IntoCrateObjectFile`,
+    crate_object_file: PathBuf, // TODO FIXME: use `mod_mgmt::IntoCrateObjectFile`,
     new_namespace: Option<Arc<CrateNamespace>>,
 ) -> Result<TaskBuilder<MainFunc, MainFuncArg, MainFuncRet>, &'static str> {
     
@@ -140,7 +140,7 @@ pub fn new_application_task_builder(
         .ok_or("spawn::new_application_task_builder(): couldn't get current task to use its CrateNamespace")?;
     
     let crate_object_file = match crate_object_file.get(namespace.dir())
-        .or_else(|| Path::new(format!("{}.o", &crate_object_file)).get(namespace.dir())) // retry with ".o" extension
+        .or_else(|| Path::new(&format!("{}.o", &crate_object_file)).get(namespace.dir())) // retry with ".o" extension
     {
         Some(FileOrDir::File(f)) => f,
         _ => return Err("Couldn't find specified file path for new application crate"),
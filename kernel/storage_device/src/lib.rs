@@ -114,6 +114,14 @@ pub trait StorageDevice: Downcast {
 
 	/// Returns the number of sectors in this drive.
     fn size_in_sectors(&self) -> usize;
+
+    /// If this storage device is only a region of another storage device, e.g., a partition,
+    /// returns that underlying storage device and the sector on it at which this device starts.
+    /// 
+    /// Returns `None` by default, for storage devices that are backed by actual hardware.
+    fn underlying_device(&self) -> Option<(StorageDeviceRef, usize)> {
+        None
+    }
     
     /// Returns the size of this drive in bytes, rounded up to the nearest sector size.
     /// This is nothing more than [`sector_size_in_bytes()`](#tymethod.sector_size_in_bytes)` * `[`size_in_sectors()`](#tymethod.size_in_sectors).
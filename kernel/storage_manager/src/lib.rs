@@ -3,22 +3,28 @@
 
 #![no_std]
 
-extern crate alloc;
+#[macro_use] extern crate alloc;
 #[macro_use] extern crate log;
 #[macro_use] extern crate lazy_static;
 extern crate spin;
 extern crate owning_ref;
 extern crate pci;
 extern crate ata;
+extern crate ahci;
+extern crate nvme;
+extern crate virtio;
+extern crate virtio_blk;
+extern crate partition;
 extern crate storage_device;
 
 use alloc::{
+    string::String,
     vec::Vec,
     sync::Arc,
 };
 use spin::Mutex;
 use pci::PciDevice;
-use storage_device::StorageControllerRef;
+use storage_device::{StorageControllerRef, StorageDeviceRef};
 
 pub use storage_device::*;
 
@@ -26,8 +32,113 @@ pub use storage_device::*;
 lazy_static! {
     /// A list of all of the available and initialized storage controllers that exist on this system.
     pub static ref STORAGE_CONTROLLERS: Mutex<Vec<StorageControllerRef>> = Mutex::new(Vec::new());
+
+    /// The partitions of each storage device whose partition table has already been scanned,
+    /// such that each partition is only ever represented by a single `StorageDeviceRef`.
+    /// 
+    /// Each storage device is paired with its partitions and their numbers.
+    static ref PARTITIONS: Mutex<Vec<(StorageDeviceRef, Vec<(usize, StorageDeviceRef)>)>> = Mutex::new(Vec::new());
+}
+
+/// The prefix of the name of each storage device, which is followed by the device's index,
+/// e.g., `"disk0"`, `"disk1"`, etc.
+pub const STORAGE_DEVICE_NAME_PREFIX: &str = "disk";
+
+/// The character that separates a storage device's name from the number of one of its partitions,
+/// e.g., `"disk0p1"` is the first partition of `"disk0"`.
+pub const PARTITION_NAME_SEPARATOR: char = 'p';
+
+
+/// Returns a list of all storage devices attached to all storage controllers,
+/// in the order that the controllers were initialized.
+/// 
+/// A storage device's index in this list is used in its name, see [`storage_device_name()`](fn.storage_device_name.html).
+pub fn storage_devices() -> Vec<StorageDeviceRef> {
+    let mut devices = Vec::new();
+    for controller in STORAGE_CONTROLLERS.lock().iter() {
+        devices.extend(controller.lock().devices());
+    }
+    devices
+}
+
+/// Returns the name of the storage device at the given `index` in the list returned by
+/// [`storage_devices()`](fn.storage_devices.html), e.g., `"disk0"`.
+pub fn storage_device_name(index: usize) -> String {
+    format!("{}{}", STORAGE_DEVICE_NAME_PREFIX, index)
 }
 
+/// Returns the name of the partition with the given `number` on the storage device at the given `index`,
+/// e.g., `"disk0p1"`.
+pub fn partition_name(index: usize, number: usize) -> String {
+    format!("{}{}{}", storage_device_name(index), PARTITION_NAME_SEPARATOR, number)
+}
+
+/// Returns the storage device with the given `name` if it exists.
+/// 
+/// The `name` can refer to either a whole storage device, e.g., `"disk0"`,
+/// or to a single partition on a storage device, e.g., `"disk0p1"`.
+pub fn find_storage_device(name: &str) -> Option<StorageDeviceRef> {
+    if !name.starts_with(STORAGE_DEVICE_NAME_PREFIX) {
+        return None;
+    }
+    let mut parts = name[STORAGE_DEVICE_NAME_PREFIX.len() ..].splitn(2, PARTITION_NAME_SEPARATOR);
+    let index = parts.next()?.parse::<usize>().ok()?;
+    let device = storage_devices().into_iter().nth(index)?;
+    match parts.next() {
+        Some(number) => {
+            let number = number.parse::<usize>().ok()?;
+            partitions(index, &device).into_iter()
+                .find(|(n, _)| *n == number)
+                .map(|(_, partition)| partition)
+        }
+        None => Some(device),
+    }
+}
+
+/// Returns a list of all storage devices and their names,
+/// in which each whole storage device, e.g., `"disk0"`, is followed by each of its partitions, e.g., `"disk0p1"`.
+/// 
+/// A storage device whose partition table can't be read is still included, but without any partitions.
+pub fn named_storage_devices() -> Vec<(String, StorageDeviceRef)> {
+    let mut named_devices = Vec::new();
+    for (index, device) in storage_devices().into_iter().enumerate() {
+        let partitions = partitions(index, &device);
+        named_devices.push((storage_device_name(index), device));
+        for (number, partition) in partitions {
+            named_devices.push((partition_name(index, number), partition));
+        }
+    }
+    named_devices
+}
+
+/// Returns the partitions of the given storage `device` at the given `index`, along with their numbers.
+/// 
+/// The partition table of each storage device is only scanned the first time this is called for it;
+/// afterwards, the same `StorageDeviceRef`s are returned for its partitions.
+/// A storage device whose partition table can't be read is treated as having no partitions.
+fn partitions(index: usize, device: &StorageDeviceRef) -> Vec<(usize, StorageDeviceRef)> {
+    let mut scanned = PARTITIONS.lock();
+    if let Some((_, partitions)) = scanned.iter().find(|(d, _)| same_device(d, device)) {
+        return partitions.clone();
+    }
+    let partitions = match partition::read_partitions(device) {
+        Ok(Some((_table_type, partitions))) => partitions.into_iter()
+            .map(|p| (p.number(), Arc::new(Mutex::new(p)) as StorageDeviceRef))
+            .collect(),
+        Ok(None) => Vec::new(),
+        Err(e) => {
+            warn!("failed to read the partition table of {}: {}", storage_device_name(index), e);
+            Vec::new()
+        }
+    };
+    scanned.push((Arc::clone(device), partitions.clone()));
+    partitions
+}
+
+/// Returns `true` if the two given references point to the same storage device.
+fn same_device(a: &StorageDeviceRef, b: &StorageDeviceRef) -> bool {
+    &**a as *const _ as *const u8 == &**b as *const _ as *const u8
+}
 
 /// Attempts to handle the initialization of the given `PciDevice`,
 /// if it is a recognized storage device.
@@ -36,7 +147,7 @@ lazy_static! {
 /// `Ok(false)` if the given `PciDevice` isn't a supported storage device,
 /// and an error if it fails to initialize a supported storage device.
 pub fn init_device(pci_device: &PciDevice) -> Result<bool, &'static str> {
-    // We currently only support IDE controllers for ATA drives (aka PATA).
+    // IDE controllers for ATA drives (aka PATA).
     if pci_device.class == 0x01 && pci_device.subclass == 0x01 {
         info!("IDE controller PCI device found at: {:?}", pci_device.location);
         let ide_controller = ata::IdeController::new(pci_device)?;
@@ -44,6 +155,30 @@ pub fn init_device(pci_device: &PciDevice) -> Result<bool, &'static str> {
         return Ok(true);
     }
 
+    // AHCI controllers for SATA drives, which have the SATA subclass and the AHCI programming interface.
+    if pci_device.class == 0x01 && pci_device.subclass == 0x06 && pci_device.prog_if == 0x01 {
+        info!("AHCI controller PCI device found at: {:?}", pci_device.location);
+        let ahci_controller = ahci::AhciController::new(pci_device)?;
+        STORAGE_CONTROLLERS.lock().push(Arc::new(Mutex::new(ahci_controller)));
+        return Ok(true);
+    }
+
+    // NVMe controllers, which have the non-volatile memory subclass and the NVMe programming interface.
+    if pci_device.class == 0x01 && pci_device.subclass == 0x08 && pci_device.prog_if == 0x02 {
+        info!("NVMe controller PCI device found at: {:?}", pci_device.location);
+        let nvme_controller = nvme::NvmeController::new(pci_device)?;
+        STORAGE_CONTROLLERS.lock().push(Arc::new(Mutex::new(nvme_controller)));
+        return Ok(true);
+    }
+
+    // Virtio block devices, which are identified by their vendor and device IDs rather than their class.
+    if virtio::device_type(pci_device) == Some(virtio::DEVICE_TYPE_BLOCK) {
+        info!("virtio block PCI device found at: {:?}", pci_device.location);
+        let virtio_blk_controller = virtio_blk::VirtioBlkController::new(pci_device)?;
+        STORAGE_CONTROLLERS.lock().push(Arc::new(Mutex::new(virtio_blk_controller)));
+        return Ok(true);
+    }
+
     // Here: in the future, handle other supported storage devices
 
     Ok(false)
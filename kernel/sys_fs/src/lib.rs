@@ -0,0 +1,443 @@
+//! A virtual filesystem that exposes the state of the kernel as files in the `/sys` directory,
+//! similar to the one in Linux, such that scripts and the shell can inspect the system
+//! with generic file tools rather than a custom application for each part of it.
+//!
+//! The `/sys` directory contains:
+//! * `pci`: one line for each PCI device, with its location, vendor and device IDs, class codes and interrupt line,
+//! * `memory`: the usage of physical memory by the frame allocator,
+//! * `interrupts`: the interrupt numbers that currently have a handler registered,
+//! * `runqueues`: the tasks in each core's runqueue,
+//! * `log_level`: the maximum level of log messages that are logged,
+//!   which can be changed by writing a level name to it, e.g., `info` or `trace`,
+//! * `namespaces`: a directory with one file for each `CrateNamespace` used by a task,
+//!   which lists the names of the crates loaded into that namespace, one per line.
+//!
+//! The contents of every file are generated each time it is read, so they are always up to date.
+//! Files are also created each time they are looked up, whereas the `/sys` and `namespaces`
+//! directories themselves exist for as long as the filesystem is mounted.
+
+#![no_std]
+
+#[macro_use] extern crate alloc;
+extern crate log;
+extern crate spin;
+extern crate fs_node;
+extern crate memory;
+extern crate kernel_config;
+extern crate root;
+extern crate vfs_node;
+extern crate mount_table;
+extern crate pci;
+extern crate apic;
+extern crate interrupts;
+extern crate task;
+extern crate runqueue;
+extern crate mod_mgmt;
+extern crate logger;
+
+
+use core::fmt::Write;
+use alloc::string::String;
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+use spin::Mutex;
+use log::Level;
+use fs_node::{DirRef, WeakDirRef, Directory, FileOrDir, File, FileRef, FsNode, Metadata, NodeKind, Permissions, Timestamp};
+use memory::MappedPages;
+use kernel_config::memory::PAGE_SIZE;
+use vfs_node::VFSDirectory;
+use mount_table::{FileSystem, MountOptions};
+use mod_mgmt::CrateNamespace;
+use task::TASKLIST;
+
+
+/// The name of the VFS directory that exposes kernel state in the root.
+pub const SYS_DIRECTORY_NAME: &str = "sys";
+/// The absolute path of the sys directory, which is currently below the root.
+pub const SYS_DIRECTORY_PATH: &str = "/sys";
+/// The name of the directory within the sys directory that holds a file for each `CrateNamespace`.
+pub const NAMESPACES_DIRECTORY_NAME: &str = "namespaces";
+
+const PCI_FILE_NAME: &str = "pci";
+const MEMORY_FILE_NAME: &str = "memory";
+const INTERRUPTS_FILE_NAME: &str = "interrupts";
+const RUNQUEUES_FILE_NAME: &str = "runqueues";
+const LOG_LEVEL_FILE_NAME: &str = "log_level";
+
+/// The names of all the files directly within the sys directory.
+const SYS_FILE_NAMES: [&str; 5] = [PCI_FILE_NAME, MEMORY_FILE_NAME, INTERRUPTS_FILE_NAME, RUNQUEUES_FILE_NAME, LOG_LEVEL_FILE_NAME];
+
+/// The first interrupt number after the CPU exceptions.
+const FIRST_IRQ: u8 = 32;
+
+
+/// Initializes the sys virtual filesystem and mounts it on a new directory within the root directory.
+pub fn init() -> Result<(), &'static str> {
+    SysFs::new()?;
+    Ok(())
+}
+
+
+/// The sysfs as a whole, which is what gets recorded in the mount table.
+struct SysFileSystem {
+    root: DirRef,
+}
+
+impl FileSystem for SysFileSystem {
+    fn root(&self) -> DirRef {
+        self.root.clone()
+    }
+}
+
+
+/// The top level directory that contains all generated files about kernel state.
+/// This directory exists in the root directory.
+pub struct SysFs {
+    /// A weak reference to this directory itself, which is the parent of all of its files.
+    self_ref: Weak<Mutex<SysFs>>,
+    namespaces: Option<DirRef>,
+}
+
+impl SysFs {
+    fn new() -> Result<DirRef, &'static str> {
+        let mountpoint = VFSDirectory::new(String::from(SYS_DIRECTORY_NAME), root::get_root())?;
+        let sysfs = Arc::new(Mutex::new(SysFs {
+            self_ref: Weak::new(),
+            namespaces: None,
+        }));
+        {
+            let mut locked_sysfs = sysfs.lock();
+            locked_sysfs.self_ref = Arc::downgrade(&sysfs);
+            locked_sysfs.namespaces = Some(NamespacesDir::new(Arc::downgrade(&sysfs) as WeakDirRef));
+        }
+
+        let dir_ref = sysfs as DirRef;
+        let filesystem = Arc::new(SysFileSystem { root: dir_ref.clone() });
+        mount_table::mount_filesystem(String::from("sysfs"), String::from("sysfs"), filesystem, &mountpoint, MountOptions::default())
+    }
+}
+
+impl FsNode for SysFs {
+    fn get_absolute_path(&self) -> String {
+        String::from(SYS_DIRECTORY_PATH)
+    }
+
+    fn get_name(&self) -> String {
+        String::from(SYS_DIRECTORY_NAME)
+    }
+
+    fn get_parent_dir(&self) -> Option<DirRef> {
+        Some(root::get_root().clone())
+    }
+
+    fn set_parent_dir(&mut self, _new_parent: WeakDirRef) {
+        // do nothing
+    }
+}
+
+impl Directory for SysFs {
+    fn insert(&mut self, _node: FileOrDir) -> Result<Option<FileOrDir>, &'static str> {
+        Err("cannot insert node into SysFs")
+    }
+
+    fn get(&self, name: &str) -> Option<FileOrDir> {
+        if name == NAMESPACES_DIRECTORY_NAME {
+            return self.namespaces.clone().map(FileOrDir::Dir);
+        }
+        let kind = match name {
+            PCI_FILE_NAME        => SysFileKind::Pci,
+            MEMORY_FILE_NAME     => SysFileKind::Memory,
+            INTERRUPTS_FILE_NAME => SysFileKind::Interrupts,
+            RUNQUEUES_FILE_NAME  => SysFileKind::Runqueues,
+            LOG_LEVEL_FILE_NAME  => SysFileKind::LogLevel,
+            _ => return None,
+        };
+        let file = SysFile::new(String::from(name), self.self_ref.clone() as WeakDirRef, kind);
+        Some(FileOrDir::File(Arc::new(Mutex::new(file)) as FileRef))
+    }
+
+    fn list(&self) -> Vec<String> {
+        let mut children: Vec<String> = SYS_FILE_NAMES.iter().map(|name| String::from(*name)).collect();
+        children.push(String::from(NAMESPACES_DIRECTORY_NAME));
+        children
+    }
+
+    fn remove(&mut self, _node: &FileOrDir) -> Option<FileOrDir> {
+        None
+    }
+
+    fn metadata(&self) -> Metadata {
+        generated_metadata(NodeKind::Directory, 0)
+    }
+}
+
+
+/// The directory that holds a file for each `CrateNamespace` that is currently used by any task,
+/// including the namespaces that those are recursively built atop.
+///
+/// A file is named after its namespace. If multiple namespaces share a name,
+/// e.g., the application namespaces of different tasks, all but the first are suffixed with `.1`, `.2`, etc.
+pub struct NamespacesDir {
+    self_ref: Weak<Mutex<NamespacesDir>>,
+    parent: WeakDirRef,
+}
+
+impl NamespacesDir {
+    fn new(parent: WeakDirRef) -> DirRef {
+        let dir = Arc::new(Mutex::new(NamespacesDir {
+            self_ref: Weak::new(),
+            parent,
+        }));
+        dir.lock().self_ref = Arc::downgrade(&dir);
+        dir
+    }
+}
+
+impl FsNode for NamespacesDir {
+    fn get_absolute_path(&self) -> String {
+        format!("{}/{}", SYS_DIRECTORY_PATH, NAMESPACES_DIRECTORY_NAME)
+    }
+
+    fn get_name(&self) -> String {
+        String::from(NAMESPACES_DIRECTORY_NAME)
+    }
+
+    fn get_parent_dir(&self) -> Option<DirRef> {
+        self.parent.upgrade()
+    }
+
+    fn set_parent_dir(&mut self, _new_parent: WeakDirRef) {
+        // do nothing
+    }
+}
+
+impl Directory for NamespacesDir {
+    fn insert(&mut self, _node: FileOrDir) -> Result<Option<FileOrDir>, &'static str> {
+        Err("cannot insert node into SysFs")
+    }
+
+    fn get(&self, name: &str) -> Option<FileOrDir> {
+        let (name, namespace) = all_namespaces().into_iter().find(|(ns_name, _ns)| ns_name == name)?;
+        let file = SysFile::new(name, self.self_ref.clone() as WeakDirRef, SysFileKind::Namespace(namespace));
+        Some(FileOrDir::File(Arc::new(Mutex::new(file)) as FileRef))
+    }
+
+    fn list(&self) -> Vec<String> {
+        all_namespaces().into_iter().map(|(name, _ns)| name).collect()
+    }
+
+    fn remove(&mut self, _node: &FileOrDir) -> Option<FileOrDir> {
+        None
+    }
+
+    fn metadata(&self) -> Metadata {
+        generated_metadata(NodeKind::Directory, 0)
+    }
+}
+
+/// Returns every `CrateNamespace` used by a task, starting with the initial kernel namespace,
+/// along with the unique name of its file.
+fn all_namespaces() -> Vec<(String, Arc<CrateNamespace>)> {
+    let mut namespaces: Vec<Arc<CrateNamespace>> = Vec::new();
+    {
+        let mut add = |namespace: Arc<CrateNamespace>| {
+            let mut next = Some(namespace);
+            while let Some(ns) = next {
+                if namespaces.iter().any(|existing| Arc::ptr_eq(existing, &ns)) {
+                    break;
+                }
+                next = ns.recursive_namespace().cloned();
+                namespaces.push(ns);
+            }
+        };
+        if let Some(kernel_namespace) = mod_mgmt::get_initial_kernel_namespace() {
+            add(kernel_namespace.clone());
+        }
+        for (_id, taskref) in TASKLIST.lock().iter() {
+            add(taskref.get_namespace());
+        }
+    }
+
+    let mut named: Vec<(String, Arc<CrateNamespace>)> = Vec::with_capacity(namespaces.len());
+    for namespace in namespaces {
+        let same_name_count = named.iter().filter(|(_name, ns)| ns.name() == namespace.name()).count();
+        let name = if same_name_count == 0 {
+            String::from(namespace.name())
+        } else {
+            format!("{}.{}", namespace.name(), same_name_count)
+        };
+        named.push((name, namespace));
+    }
+    named
+}
+
+
+/// The different kinds of files in the sysfs, which determine how a file's contents are generated.
+enum SysFileKind {
+    Pci,
+    Memory,
+    Interrupts,
+    Runqueues,
+    LogLevel,
+    Namespace(Arc<CrateNamespace>),
+}
+
+/// A lazily generated file that holds information about one part of the kernel's state.
+pub struct SysFile {
+    name: String,
+    parent: WeakDirRef,
+    kind: SysFileKind,
+}
+
+impl SysFile {
+    fn new(name: String, parent: WeakDirRef, kind: SysFileKind) -> SysFile {
+        SysFile { name, parent, kind }
+    }
+
+    /// Generates the contents of this file.
+    fn generate(&self) -> String {
+        let mut output = String::new();
+        let result = match self.kind {
+            SysFileKind::Pci => generate_pci(&mut output),
+            SysFileKind::Memory => generate_memory(&mut output),
+            SysFileKind::Interrupts => generate_interrupts(&mut output),
+            SysFileKind::Runqueues => generate_runqueues(&mut output),
+            SysFileKind::LogLevel => writeln!(output, "{}", log::max_level()),
+            SysFileKind::Namespace(ref namespace) => generate_namespace(&mut output, namespace),
+        };
+        if result.is_err() {
+            output.push_str("\n(error generating the remaining contents)\n");
+        }
+        output
+    }
+}
+
+impl FsNode for SysFile {
+    fn get_name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn get_parent_dir(&self) -> Option<DirRef> {
+        self.parent.upgrade()
+    }
+
+    fn set_parent_dir(&mut self, _new_parent: WeakDirRef) {
+        // do nothing
+    }
+}
+
+impl File for SysFile {
+    fn read(&self, buf: &mut [u8], offset: usize) -> Result<usize, &'static str> {
+        let output = self.generate();
+        if offset > output.len() {
+            return Err("read offset exceeds file size");
+        }
+        let count = core::cmp::min(buf.len(), output.len() - offset);
+        buf[..count].copy_from_slice(&output.as_bytes()[offset .. offset + count]);
+        Ok(count)
+    }
+
+    /// Only the `log_level` file can be written to, which sets the log level to the written level name.
+    /// The whole level name must be written at once; the offset is ignored.
+    fn write(&mut self, buf: &[u8], _offset: usize) -> Result<usize, &'static str> {
+        match self.kind {
+            SysFileKind::LogLevel => {
+                let level_name = core::str::from_utf8(buf).map_err(|_e| "log level name was not valid UTF-8")?;
+                let level = level_name.trim().parse::<Level>().map_err(|_e| "unknown log level name")?;
+                logger::set_log_level(level);
+                Ok(buf.len())
+            }
+            _ => Err("not permitted to write the contents of a sysfs file"),
+        }
+    }
+
+    fn size(&self) -> usize {
+        self.generate().len()
+    }
+
+    fn as_mapping(&self) -> Result<&MappedPages, &'static str> {
+        Err("sysfs files are autogenerated, cannot be memory mapped")
+    }
+
+    fn metadata(&self) -> Metadata {
+        let mut metadata = generated_metadata(NodeKind::File, self.size());
+        if let SysFileKind::LogLevel = self.kind {
+            metadata.permissions = Permissions::read_write();
+        }
+        metadata
+    }
+}
+
+
+fn generate_pci(output: &mut String) -> core::fmt::Result {
+    writeln!(output, "{:<12} {:<9} {:<8} {}", "LOCATION", "ID", "CLASS", "IRQ")?;
+    for device in pci::pci_device_iter() {
+        writeln!(output, "{:<12} {:04x}:{:04x} {:02x}.{:02x}.{:02x} {}",
+            format!("{}", device.location),
+            device.vendor_id, device.device_id,
+            device.class, device.subclass, device.prog_if,
+            device.int_line,
+        )?;
+    }
+    Ok(())
+}
+
+fn generate_memory(output: &mut String) -> core::fmt::Result {
+    let (available, occupied, allocated_frames) = match memory::get_frame_allocator_ref() {
+        Some(allocator) => {
+            let locked_allocator = allocator.lock();
+            (locked_allocator.available_bytes(), locked_allocator.occupied_bytes(), locked_allocator.allocated_frames())
+        }
+        None => return writeln!(output, "the frame allocator has not been initialized"),
+    };
+    writeln!(output, "{:<10} {} bytes", "available", available)?;
+    writeln!(output, "{:<10} {} bytes", "occupied", occupied)?;
+    writeln!(output, "{:<10} {} frames ({} bytes)", "allocated", allocated_frames, allocated_frames * PAGE_SIZE)
+}
+
+fn generate_interrupts(output: &mut String) -> core::fmt::Result {
+    for interrupt_num in FIRST_IRQ..=core::u8::MAX {
+        if interrupts::is_interrupt_registered(interrupt_num) {
+            writeln!(output, "{:#04x}", interrupt_num)?;
+        }
+    }
+    Ok(())
+}
+
+fn generate_runqueues(output: &mut String) -> core::fmt::Result {
+    let mut cores: Vec<u8> = apic::get_lapics().iter().map(|(&apic_id, _)| apic_id).collect();
+    cores.sort();
+    for core in cores {
+        writeln!(output, "core {}:", core)?;
+        if let Some(rq) = runqueue::get_runqueue(core) {
+            for taskref in rq.read().iter() {
+                let task = taskref.lock();
+                writeln!(output, "    {:<5} {}", task.id, task.name)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn generate_namespace(output: &mut String, namespace: &CrateNamespace) -> core::fmt::Result {
+    let mut result = Ok(());
+    namespace.for_each_crate(false, |crate_name, _crate_ref| {
+        result = writeln!(output, "{}", crate_name);
+        result.is_ok()
+    });
+    result
+}
+
+
+/// Returns the metadata of a sysfs node, whose contents are generated on demand and thus are always new.
+fn generated_metadata(kind: NodeKind, size: usize) -> Metadata {
+    let now = Timestamp::now();
+    Metadata {
+        kind,
+        size,
+        created: Some(now),
+        modified: Some(now),
+        accessed: Some(now),
+        permissions: Permissions::read_only(),
+    }
+}
@@ -37,6 +37,7 @@ extern crate tss;
 extern crate mod_mgmt;
 extern crate context_switch;
 extern crate environment;
+extern crate file_handle;
 extern crate root;
 extern crate x86_64;
 extern crate spin;
@@ -63,6 +64,7 @@ use mod_mgmt::{
     AppCrateRef,
 };
 use environment::Environment;
+use file_handle::FileDescriptorTable;
 use spin::Mutex;
 use x86_64::registers::msr::{rdmsr, wrmsr, IA32_FS_BASE};
 
@@ -254,6 +256,9 @@ pub struct Task {
     pub kill_handler: Option<KillHandler>,
     /// The environment of the task, Wrapped in an Arc & Mutex because it is shared among child and parent tasks
     pub env: Arc<Mutex<Environment>>,
+    /// The files that this Task has opened, keyed by file descriptor.
+    /// A new Task starts with a copy of the table of the Task that created it, so the open files themselves are shared.
+    pub file_descriptors: Arc<Mutex<FileDescriptorTable>>,
     /// The function that should be run as a last-ditch attempt to recover from this task's failure,
     /// e.g., this can be called when unwinding itself fails. 
     /// Typically, it will point to this Task's specific instance of `spawn::task_cleanup_failure()`,
@@ -278,7 +283,8 @@ impl fmt::Debug for Task {
 impl Task {
     /// Creates a new Task structure and initializes it to be non-Runnable.
     /// By default, the new `Task` will inherit some of the same states from the currently-running `Task`:
-    /// its `Environment`, `MemoryManagementInfo`, `CrateNamespace`, and `app_crate` reference.
+    /// its `Environment`, `MemoryManagementInfo`, `CrateNamespace`, and `app_crate` reference,
+    /// as well as a copy of its table of open files.
     /// If needed, those states can be changed by setting them for the returned `Task`.
     /// 
     /// # Arguments
@@ -294,16 +300,16 @@ impl Task {
         failure_cleanup_function: FailureCleanupFunction
     ) -> Result<Task, &'static str> {
         let curr_task = get_my_current_task().ok_or("Task::new(): couldn't get current task (not yet initialized)")?;
-        let (mmi, namespace, env, app_crate) = {
+        let (mmi, namespace, env, app_crate, file_descriptors) = {
             let t = curr_task.lock();
-            (Arc::clone(&t.mmi), Arc::clone(&t.namespace), Arc::clone(&t.env), t.app_crate.clone())
+            (Arc::clone(&t.mmi), Arc::clone(&t.namespace), Arc::clone(&t.env), t.app_crate.clone(), t.file_descriptors.lock().clone())
         };
 
         let kstack = kstack
             .or_else(|| mmi.lock().alloc_stack(KERNEL_STACK_SIZE_IN_PAGES))
             .ok_or("couldn't allocate kernel stack!")?;
 
-        Ok(Task::new_internal(kstack, mmi, namespace, env, app_crate, failure_cleanup_function))
+        Ok(Task::new_internal(kstack, mmi, namespace, env, app_crate, file_descriptors, failure_cleanup_function))
     }
     
     /// The internal routine for creating a `Task`, which does not make assumptions 
@@ -314,6 +320,7 @@ impl Task {
         mmi: MmiRef, namespace: Arc<CrateNamespace>,
         env: Arc<Mutex<Environment>>,
         app_crate: Option<Arc<AppCrateRef>>,
+        file_descriptors: FileDescriptorTable,
         failure_cleanup_function: FailureCleanupFunction,
     ) -> Self {
          /// The counter of task IDs
@@ -343,6 +350,7 @@ impl Task {
             namespace,
             kill_handler: None,
             env,
+            file_descriptors: Arc::new(Mutex::new(file_descriptors)),
             failure_cleanup_function,
             restart_info: None,
             
@@ -889,6 +897,11 @@ impl TaskRef {
         Arc::clone(&self.0.deref().0.lock().env)
     }
 
+    /// Gets a reference to this task's table of open files.
+    pub fn get_file_descriptors(&self) -> Arc<Mutex<FileDescriptorTable>> {
+        Arc::clone(&self.0.deref().0.lock().file_descriptors)
+    }
+
     /// Gets a reference to this task's `CrateNamespace`.
     pub fn get_namespace(&self) -> Arc<CrateNamespace> {
         Arc::clone(&self.0.deref().0.lock().namespace)
@@ -940,7 +953,7 @@ pub fn bootstrap_task(
         .ok_or("The initial kernel CrateNamespace must be initialized before the tasking subsystem.")?
         .clone();
     let default_env = Arc::new(Mutex::new(Environment::default()));
-    let mut bootstrap_task = Task::new_internal(kstack, kernel_mmi_ref, default_namespace, default_env, None, bootstrap_task_cleanup_failure);
+    let mut bootstrap_task = Task::new_internal(kstack, kernel_mmi_ref, default_namespace, default_env, None, FileDescriptorTable::new(), bootstrap_task_cleanup_failure);
     bootstrap_task.name = format!("bootstrap_task_core_{}", apic_id);
     bootstrap_task.runstate = RunState::Runnable;
     bootstrap_task.running_on_cpu = Some(apic_id); 
@@ -33,16 +33,20 @@ extern crate memory;
 extern crate task;
 extern crate path;
 extern crate root;
+extern crate vfs_node;
+extern crate mount_table;
 
 
 use alloc::string::{String, ToString};
 use alloc::vec::Vec;
 use spin::Mutex;
 use alloc::sync::Arc;
-use fs_node::{DirRef, WeakDirRef, Directory, FileOrDir, File, FileRef, FsNode};
+use fs_node::{DirRef, WeakDirRef, Directory, FileOrDir, File, FileRef, FsNode, Metadata, NodeKind, Permissions, Timestamp};
 use memory::MappedPages;
 use task::{TaskRef, TASKLIST, RunState};
-use path::Path;
+use path::{Path, PathBuf};
+use vfs_node::VFSDirectory;
+use mount_table::{FileSystem, MountOptions};
 
 
 /// The name of the VFS directory that exposes task info in the root. 
@@ -51,13 +55,25 @@ pub const TASKS_DIRECTORY_NAME: &str = "tasks";
 pub const TASKS_DIRECTORY_PATH: &str = "/tasks"; 
 
 
-/// Initializes the tasks virtual filesystem directory within the root directory.
+/// Initializes the tasks virtual filesystem and mounts it on a new directory within the root directory.
 pub fn init() -> Result<(), &'static str> {
     TaskFs::new()?;
     Ok(())
 }
 
 
+/// The taskfs as a whole, which is what gets recorded in the mount table.
+struct TaskFileSystem {
+    root: DirRef,
+}
+
+impl FileSystem for TaskFileSystem {
+    fn root(&self) -> DirRef {
+        self.root.clone()
+    }
+}
+
+
 /// The top level directory that includes a dynamically-generated list of all `Task`s,
 /// each comprising a `TaskDir`.
 /// This directory exists in the root directory.
@@ -65,20 +81,22 @@ pub struct TaskFs { }
 
 impl TaskFs {
     fn new() -> Result<DirRef, &'static str> {
-        let root = root::get_root();
+        let mountpoint = VFSDirectory::new(String::from(TASKS_DIRECTORY_NAME), root::get_root())?;
         let dir_ref = Arc::new(Mutex::new(TaskFs { })) as DirRef;
-        root.lock().insert(FileOrDir::Dir(dir_ref.clone()))?;
-        Ok(dir_ref)
+        let filesystem = Arc::new(TaskFileSystem { root: dir_ref.clone() });
+        let options = MountOptions { read_only: true, ..Default::default() };
+        mount_table::mount_filesystem(String::from("taskfs"), String::from("taskfs"), filesystem, &mountpoint, options)
     }
 
     fn get_self_pointer(&self) -> Option<DirRef> {
-        root::get_root().lock().get_dir(&self.get_name())
+        let mountpoint = root::get_root().lock().get_dir(&self.get_name())?;
+        Some(mount_table::resolve(&mountpoint))
     }
 
     fn get_internal(&self, node: &str) -> Result<FileOrDir, &'static str> {
         let id = node.parse::<usize>().map_err(|_e| "could not parse Task id as usize")?;
         let task_ref = task::get_task(id).ok_or("could not get taskref from TASKLIST")?;
-        let parent_dir = self.get_self_pointer().ok_or("BUG: tasks directory wasn't in root")?;
+        let parent_dir = self.get_self_pointer().ok_or("BUG: tasks directory wasn't mounted in root")?;
         let dir_name = task_ref.lock().id.to_string(); 
         // lazily compute a new TaskDir everytime the caller wants to get a TaskDir
         let task_dir = TaskDir::new(dir_name, &parent_dir, task_ref.clone())?;        
@@ -134,6 +152,10 @@ impl Directory for TaskFs {
         None
     }
 
+    fn metadata(&self) -> Metadata {
+        generated_metadata(NodeKind::Directory, 0)
+    }
+
 }
 
 
@@ -145,7 +167,7 @@ pub struct TaskDir {
     /// The name of the directory
     pub name: String,
     /// The absolute path of the TaskDir
-    path: Path,
+    path: PathBuf,
     taskref: TaskRef,
     /// We can store the parent (TaskFs) because it is a persistent directory
     parent: DirRef,
@@ -157,7 +179,7 @@ impl TaskDir {
         let task_id = taskref.lock().id.clone();
         let directory = TaskDir {
             name: name,
-            path: Path::new(format!("{}/{}", TASKS_DIRECTORY_PATH, task_id)),
+            path: PathBuf::from(format!("{}/{}", TASKS_DIRECTORY_PATH, task_id)),
             taskref: taskref,
             parent: Arc::clone(parent),
         };
@@ -195,6 +217,10 @@ impl Directory for TaskDir {
     fn remove(&mut self, _: &FileOrDir) -> Option<FileOrDir> { 
         None
     }
+
+    fn metadata(&self) -> Metadata {
+        generated_metadata(NodeKind::Directory, 0)
+    }
 }
 
 impl FsNode for TaskDir {
@@ -222,7 +248,7 @@ impl FsNode for TaskDir {
 pub struct TaskFile {
     taskref: TaskRef,
     task_id: usize,
-    path: Path, 
+    path: PathBuf, 
 }
 
 impl TaskFile {
@@ -231,7 +257,7 @@ impl TaskFile {
         TaskFile {
             taskref,
             task_id,
-            path: Path::new(format!("{}/{}/task_info", TASKS_DIRECTORY_PATH, task_id)), 
+            path: PathBuf::from(format!("{}/{}/task_info", TASKS_DIRECTORY_PATH, task_id)), 
         }
     }
 
@@ -277,7 +303,7 @@ impl FsNode for TaskFile {
     }
 
     fn get_parent_dir(&self) -> Option<DirRef> {
-        let path = Path::new(format!("{}/{}", TASKS_DIRECTORY_PATH, self.task_id));
+        let path = PathBuf::from(format!("{}/{}", TASKS_DIRECTORY_PATH, self.task_id));
         match Path::get_absolute(&path) {
             Some(FileOrDir::Dir(d)) => Some(d),
             _ => None,
@@ -296,7 +322,7 @@ impl File for TaskFile {
             return Err("read offset exceeds file size");
         }
         let count = core::cmp::min(buf.len(), output.len() - offset);
-        buf[..count].copy_from_slice(&output.as_bytes()[offset..count]);
+        buf[..count].copy_from_slice(&output.as_bytes()[offset .. offset + count]);
         Ok(count)
     }
 
@@ -311,6 +337,10 @@ impl File for TaskFile {
     fn as_mapping(&self) -> Result<&MappedPages, &'static str> {
         Err("task files are autogenerated, cannot be memory mapped")
     }
+
+    fn metadata(&self) -> Metadata {
+        generated_metadata(NodeKind::File, self.size())
+    }
 }
 
 
@@ -323,7 +353,7 @@ impl File for TaskFile {
 pub struct MmiDir {
     taskref: TaskRef,
     task_id: usize,
-    path: Path, 
+    path: PathBuf, 
 }
 
 impl MmiDir {
@@ -333,7 +363,7 @@ impl MmiDir {
         MmiDir {
             taskref,
             task_id,
-            path: Path::new(format!("{}/{}/mmi", TASKS_DIRECTORY_PATH, task_id)),
+            path: PathBuf::from(format!("{}/{}/mmi", TASKS_DIRECTORY_PATH, task_id)),
         }
     }
 }
@@ -363,6 +393,10 @@ impl Directory for MmiDir {
     fn remove(&mut self, _: &FileOrDir) -> Option<FileOrDir> {
         None
     }
+
+    fn metadata(&self) -> Metadata {
+        generated_metadata(NodeKind::Directory, 0)
+    }
 }
 
 impl FsNode for MmiDir {
@@ -375,7 +409,7 @@ impl FsNode for MmiDir {
     }
 
     fn get_parent_dir(&self) -> Option<DirRef> {
-        let path = Path::new(format!("{}/{}", TASKS_DIRECTORY_PATH, self.task_id));
+        let path = PathBuf::from(format!("{}/{}", TASKS_DIRECTORY_PATH, self.task_id));
         match Path::get_absolute(&path) {
             Some(FileOrDir::Dir(d)) => Some(d),
             _ => None,
@@ -394,7 +428,7 @@ impl FsNode for MmiDir {
 pub struct MmiFile {
     taskref: TaskRef,
     task_id: usize,
-    path: Path, 
+    path: PathBuf, 
 }
 
 impl MmiFile {
@@ -403,7 +437,7 @@ impl MmiFile {
         MmiFile {
             taskref,
             task_id,
-            path: Path::new(format!("{}/{}/mmi/MmiInfo", TASKS_DIRECTORY_PATH, task_id)), 
+            path: PathBuf::from(format!("{}/{}/mmi/MmiInfo", TASKS_DIRECTORY_PATH, task_id)), 
         }
     }
 
@@ -425,7 +459,7 @@ impl FsNode for MmiFile {
     }
 
     fn get_parent_dir(&self) -> Option<DirRef> {
-        let path = Path::new(format!("{}/{}/mmi", TASKS_DIRECTORY_PATH, self.task_id));
+        let path = PathBuf::from(format!("{}/{}/mmi", TASKS_DIRECTORY_PATH, self.task_id));
         match Path::get_absolute(&path) {
             Some(FileOrDir::Dir(d)) => Some(d),
             _ => None,
@@ -444,7 +478,7 @@ impl File for MmiFile {
             return Err("read offset exceeds file size");
         }
         let count = core::cmp::min(buf.len(), output.len() - offset);
-        buf[..count].copy_from_slice(&output.as_bytes()[offset..count]);
+        buf[..count].copy_from_slice(&output.as_bytes()[offset .. offset + count]);
         Ok(count)
     }
 
@@ -459,5 +493,23 @@ impl File for MmiFile {
     fn as_mapping(&self) -> Result<&MappedPages, &'static str> {
         Err("task files are autogenerated, cannot be memory mapped")
     }
+
+    fn metadata(&self) -> Metadata {
+        generated_metadata(NodeKind::File, self.size())
+    }
 }
 
+
+/// Returns the metadata of a task filesystem node, whose contents are generated on demand
+/// and thus are always new and cannot be written to.
+fn generated_metadata(kind: NodeKind, size: usize) -> Metadata {
+    let now = Timestamp::now();
+    Metadata {
+        kind,
+        size,
+        created: Some(now),
+        modified: Some(now),
+        accessed: Some(now),
+        permissions: Permissions::read_only(),
+    }
+}
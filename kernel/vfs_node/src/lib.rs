@@ -1,7 +1,7 @@
 #![no_std]
 
-//! This crate contains a very basic, generic concrete implementation of the Directory
-//! and File traits. 
+//! This crate contains a very basic, generic concrete implementation of the Directory,
+//! File and Symlink traits. 
 //! The VFSDirectory and VFSFile are intended to be used as regular nodes within the filesystem
 //! that require no special functionality as well as for inspiration for creating other concrete implementations
 //!s of the Directory and File traits. 
@@ -12,12 +12,13 @@ extern crate spin;
 extern crate fs_node;
 extern crate memory;
 
+use alloc::boxed::Box;
 use alloc::string::String;
 use alloc::vec::Vec;
 use spin::Mutex;
 use alloc::sync::{Arc, Weak};
 use alloc::collections::BTreeMap;
-use fs_node::{DirRef, FileRef, WeakDirRef, Directory, FileOrDir, File, FsNode};
+use fs_node::{DirRef, FileRef, SymlinkRef, WeakDirRef, Directory, FileOrDir, File, Symlink, FsNode, Metadata, Permissions, Timestamps, FsEvent, FsEventSink, Watchers};
 use memory::MappedPages;
 
 
@@ -29,6 +30,8 @@ pub struct VFSDirectory {
     pub children: BTreeMap<String, FileOrDir>,
     /// A weak reference to the parent directory
     pub parent: WeakDirRef,
+    /// The sinks that are notified when a child is created, removed or renamed
+    pub watchers: Watchers,
 }
 
 impl VFSDirectory {
@@ -39,22 +42,28 @@ impl VFSDirectory {
             name: name,
             children: BTreeMap::new(),
             parent: Arc::downgrade(parent),
+            watchers: Watchers::new(),
         };
         let dir_ref = Arc::new(Mutex::new(directory)) as DirRef;
         parent.lock().insert(FileOrDir::Dir(dir_ref.clone()))?;
         Ok(dir_ref)
     }
+
+    /// Adds the given `node` as a child called `name`, replacing and returning any existing child with that name,
+    /// without notifying the watchers of this directory.
+    fn insert_child(&mut self, name: String, node: FileOrDir) -> Option<FileOrDir> {
+        let mut old_node = self.children.insert(name, node)?;
+        old_node.set_parent_dir(Weak::<Mutex<VFSDirectory>>::new());
+        Some(old_node)
+    }
 }
 
 impl Directory for VFSDirectory {
     fn insert(&mut self, node: FileOrDir) -> Result<Option<FileOrDir>, &'static str> {
         let name = node.get_name();
-        if let Some(mut old_node) = self.children.insert(name, node) {
-            old_node.set_parent_dir(Weak::<Mutex<VFSDirectory>>::new());
-            Ok(Some(old_node))
-        } else {
-            Ok(None)
-        }
+        let old_node = self.insert_child(name.clone(), node);
+        self.watchers.notify(FsEvent::Created(name));
+        Ok(old_node)
     }
 
     fn get(&self, name: &str) -> Option<FileOrDir> {
@@ -66,14 +75,39 @@ impl Directory for VFSDirectory {
         self.children.keys().cloned().collect()
     }
 
+    fn rename(&mut self, old_name: &str, new_name: &str) -> Result<Option<FileOrDir>, &'static str> {
+        let mut node = self.children.remove(old_name).ok_or("no such file or directory")?;
+        if let Err(e) = node.set_name(String::from(new_name)) {
+            self.children.insert(String::from(old_name), node);
+            return Err(e);
+        }
+        let old_node = self.insert_child(String::from(new_name), node);
+        self.watchers.notify(FsEvent::Renamed { from: String::from(old_name), to: String::from(new_name) });
+        Ok(old_node)
+    }
+
+    fn detach(&mut self, name: &str) -> Result<FileOrDir, &'static str> {
+        let mut node = self.children.remove(name).ok_or("no such file or directory")?;
+        node.set_parent_dir(Weak::<Mutex<VFSDirectory>>::new());
+        self.watchers.notify(FsEvent::Removed(String::from(name)));
+        Ok(node)
+    }
+
     fn remove(&mut self, node: &FileOrDir) -> Option<FileOrDir> {
-        if let Some(mut old_node) = self.children.remove(&node.get_name()) {
+        let name = node.get_name();
+        if let Some(mut old_node) = self.children.remove(&name) {
             old_node.set_parent_dir(Weak::<Mutex<VFSDirectory>>::new());
+            self.watchers.notify(FsEvent::Removed(name));
             Some(old_node)
         } else {
             None
         }
     }
+
+    fn watch(&mut self, sink: Box<dyn FsEventSink>) -> Result<(), &'static str> {
+        self.watchers.add(sink);
+        Ok(())
+    }
 }
 
 impl FsNode for VFSDirectory {
@@ -89,6 +123,11 @@ impl FsNode for VFSDirectory {
     fn set_parent_dir(&mut self, new_parent: WeakDirRef) {
         self.parent = new_parent;
     }
+
+    fn set_name(&mut self, new_name: String) -> Result<(), &'static str> {
+        self.name = new_name;
+        Ok(())
+    }
 }
 
 pub struct VFSFile {
@@ -100,6 +139,8 @@ pub struct VFSFile {
     _contents: String,
     /// A weak reference to the parent directory
     parent: WeakDirRef,
+    /// When this file was created, last modified and last accessed
+    timestamps: Timestamps,
 }
 
 impl VFSFile {
@@ -109,6 +150,7 @@ impl VFSFile {
             size: size, 
             _contents: contents,
             parent: Arc::downgrade(parent),
+            timestamps: Timestamps::new(),
         };
         let file_ref = Arc::new(Mutex::new(file)) as FileRef;
         parent.lock().insert(FileOrDir::File(file_ref.clone()))?;
@@ -132,6 +174,10 @@ impl File for VFSFile {
     fn as_mapping(&self) -> Result<&MappedPages, &'static str> {
         Err("cannot treat a VFSFile as a memory mapped region")
     }
+
+    fn metadata(&self) -> Metadata {
+        Metadata::file(self.size, &self.timestamps, Permissions::read_write())
+    }
 }
 
 impl FsNode for VFSFile {
@@ -146,4 +192,59 @@ impl FsNode for VFSFile {
     fn set_parent_dir(&mut self, new_parent: WeakDirRef) {
         self.parent = new_parent;
     }
-}
\ No newline at end of file
+
+    fn set_name(&mut self, new_name: String) -> Result<(), &'static str> {
+        self.name = new_name;
+        Ok(())
+    }
+}
+
+/// A symbolic link that holds the path of its target in memory.
+pub struct VFSSymlink {
+    /// The name of the symbolic link
+    name: String,
+    /// The path that this link points to
+    target: String,
+    /// A weak reference to the parent directory
+    parent: WeakDirRef,
+}
+
+impl VFSSymlink {
+    /// Creates a new symbolic link called `name` in the given `parent` directory that points to the given `target` path,
+    /// which is either absolute or relative to the `parent` directory.
+    pub fn new(name: String, target: String, parent: &DirRef) -> Result<SymlinkRef, &'static str> {
+        let symlink = VFSSymlink {
+            name: name,
+            target: target,
+            parent: Arc::downgrade(parent),
+        };
+        let symlink_ref = Arc::new(Mutex::new(symlink)) as SymlinkRef;
+        parent.lock().insert(FileOrDir::Symlink(symlink_ref.clone()))?;
+        Ok(symlink_ref)
+    }
+}
+
+impl Symlink for VFSSymlink {
+    fn target(&self) -> String {
+        self.target.clone()
+    }
+}
+
+impl FsNode for VFSSymlink {
+    fn get_name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn get_parent_dir(&self) -> Option<DirRef> {
+        self.parent.upgrade()
+    }
+
+    fn set_parent_dir(&mut self, new_parent: WeakDirRef) {
+        self.parent = new_parent;
+    }
+
+    fn set_name(&mut self, new_name: String) -> Result<(), &'static str> {
+        self.name = new_name;
+        Ok(())
+    }
+}
@@ -0,0 +1,99 @@
+//! The legacy virtio PCI transport, whose registers are I/O ports in BAR0.
+//!
+//! See section 4.1.4.8 ("Legacy Interfaces: A Note on PCI Device Layout") of the virtio 1.0 specification.
+
+use port_io::Port;
+use pci::PciDevice;
+use kernel_config::memory::PAGE_SIZE;
+use queue::Virtqueue;
+
+
+/// The offsets of the legacy registers from the start of the I/O BAR.
+const DEVICE_FEATURES:  u16 = 0x00;
+const DRIVER_FEATURES:  u16 = 0x04;
+/// The page frame number of the selected virtqueue.
+const QUEUE_ADDRESS:    u16 = 0x08;
+const QUEUE_SIZE:       u16 = 0x0C;
+const QUEUE_SELECT:     u16 = 0x0E;
+const QUEUE_NOTIFY:     u16 = 0x10;
+const DEVICE_STATUS:    u16 = 0x12;
+const ISR_STATUS:       u16 = 0x13;
+/// The offset of the device-specific configuration, which would be moved back if MSI-X were enabled.
+const DEVICE_CONFIG:    u16 = 0x14;
+
+
+/// The registers of a virtio device that uses the legacy transport.
+pub struct LegacyTransport {
+    /// The first I/O port of the device's registers.
+    io_base: u16,
+}
+
+impl LegacyTransport {
+    pub fn new(pci_device: &PciDevice) -> Result<LegacyTransport, &'static str> {
+        let bar0 = pci_device.bars[0];
+        if bar0 & 0x1 == 0 {
+            return Err("virtio: legacy device's BAR0 is not an I/O BAR");
+        }
+        Ok(LegacyTransport {
+            io_base: (bar0 & !0x3) as u16,
+        })
+    }
+
+    pub fn status(&self) -> u8 {
+        Port::<u8>::new(self.io_base + DEVICE_STATUS).read()
+    }
+
+    pub fn set_status(&mut self, status: u8) {
+        unsafe { Port::<u8>::new(self.io_base + DEVICE_STATUS).write(status) }
+    }
+
+    pub fn device_features(&self) -> u32 {
+        Port::<u32>::new(self.io_base + DEVICE_FEATURES).read()
+    }
+
+    pub fn set_driver_features(&mut self, features: u32) {
+        unsafe { Port::<u32>::new(self.io_base + DRIVER_FEATURES).write(features) }
+    }
+
+    /// Returns the size of the virtqueue with the given `index`, which is `0` if that queue doesn't exist.
+    pub fn queue_size(&mut self, index: u16) -> u16 {
+        unsafe { Port::<u16>::new(self.io_base + QUEUE_SELECT).write(index) };
+        Port::<u16>::new(self.io_base + QUEUE_SIZE).read()
+    }
+
+    /// Tells the device where the given `queue` is, which must have the size that the device dictates.
+    ///
+    /// The legacy transport only takes the page frame number of the queue's descriptor table,
+    /// and expects the rest of the queue to follow it with a fixed layout.
+    pub fn setup_queue(&mut self, queue: &Virtqueue) -> Result<(), &'static str> {
+        if self.queue_size(queue.index()) != queue.size() {
+            return Err("virtio: legacy virtqueue must have the size that the device dictates");
+        }
+        let frame_number = queue.desc_phys_addr().value() / PAGE_SIZE;
+        if frame_number > core::u32::MAX as usize {
+            return Err("virtio: legacy virtqueue must be located below 16 TiB");
+        }
+        unsafe { Port::<u32>::new(self.io_base + QUEUE_ADDRESS).write(frame_number as u32) };
+        Ok(())
+    }
+
+    pub fn notify(&mut self, index: u16) {
+        unsafe { Port::<u16>::new(self.io_base + QUEUE_NOTIFY).write(index) }
+    }
+
+    pub fn read_isr(&self) -> u8 {
+        Port::<u8>::new(self.io_base + ISR_STATUS).read()
+    }
+
+    pub fn read_config_u8(&self, offset: usize) -> u8 {
+        Port::<u8>::new(self.io_base + DEVICE_CONFIG + offset as u16).read()
+    }
+
+    pub fn read_config_u16(&self, offset: usize) -> u16 {
+        Port::<u16>::new(self.io_base + DEVICE_CONFIG + offset as u16).read()
+    }
+
+    pub fn read_config_u32(&self, offset: usize) -> u32 {
+        Port::<u32>::new(self.io_base + DEVICE_CONFIG + offset as u16).read()
+    }
+}
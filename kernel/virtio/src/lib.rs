@@ -0,0 +1,349 @@
+//! Support for virtio devices attached via PCI, such as the block and network devices emulated by QEMU.
+//!
+//! A virtio device is driven through a *transport*, which gives access to the device's status, feature bits,
+//! device-specific configuration, and virtqueues. There are two PCI transports:
+//! * the legacy transport of virtio 0.9.5, whose registers are I/O ports in BAR0, and
+//! * the modern transport of virtio 1.0, whose register regions are located through vendor-specific PCI capabilities.
+//!
+//! A [`Transport`](enum.Transport.html) uses the modern transport if the device offers it (as both modern and
+//! transitional devices do), and otherwise falls back to the legacy transport.
+//!
+//! Requests are passed to a device through one or more [`Virtqueue`](struct.Virtqueue.html)s,
+//! each of which holds chains of buffers in memory that the device reads from or writes to via DMA.
+//!
+//! A device driver initializes its device in the following order, as required by the virtio specification:
+//! 1. [`Transport::new()`](enum.Transport.html#method.new) resets the device and acknowledges it,
+//! 2. [`negotiate_features()`](enum.Transport.html#method.negotiate_features) agrees on the set of optional features,
+//! 3. [`create_queue()`](enum.Transport.html#method.create_queue) sets up each of the device's virtqueues,
+//! 4. [`driver_ok()`](enum.Transport.html#method.driver_ok) tells the device that it can now be used.
+
+#![no_std]
+#![feature(abi_x86_interrupt)]
+
+extern crate alloc;
+#[macro_use] extern crate log;
+#[macro_use] extern crate lazy_static;
+extern crate spin;
+extern crate irq_safety;
+extern crate volatile;
+extern crate x86_64;
+extern crate port_io;
+extern crate kernel_config;
+extern crate memory;
+extern crate pci;
+extern crate pic;
+extern crate interrupts;
+extern crate wait_queue;
+extern crate driver_utils;
+
+mod legacy;
+mod modern;
+mod queue;
+
+use alloc::{
+    boxed::Box,
+    sync::Arc,
+    vec::Vec,
+};
+use irq_safety::MutexIrqSafe;
+use x86_64::structures::idt::ExceptionStackFrame;
+use pci::{PciDevice, PCI_INTERRUPT_LINE, PCI_SUBSYSTEM_ID};
+use pic::PIC_MASTER_OFFSET;
+use wait_queue::WaitQueue;
+use legacy::LegacyTransport;
+use modern::ModernTransport;
+
+pub use queue::{Buffer, Virtqueue};
+
+
+/// The PCI vendor id of all virtio devices.
+pub const VIRTIO_VENDOR_ID: u16 = 0x1AF4;
+
+/// The virtio device type of network cards.
+pub const DEVICE_TYPE_NETWORK: u16 = 1;
+/// The virtio device type of block devices, i.e., disks.
+pub const DEVICE_TYPE_BLOCK: u16 = 2;
+
+/// The feature bit that indicates compliance with virtio 1.0, which must be accepted when using the modern transport.
+pub const VIRTIO_F_VERSION_1: u64 = 1 << 32;
+
+/// Device status: the driver has noticed the device.
+const STATUS_ACKNOWLEDGE: u8 = 1;
+/// Device status: the driver knows how to drive the device.
+const STATUS_DRIVER: u8 = 2;
+/// Device status: the driver is set up and ready to drive the device.
+const STATUS_DRIVER_OK: u8 = 4;
+/// Device status: the driver has acknowledged all the features it understands (modern transport only).
+const STATUS_FEATURES_OK: u8 = 8;
+/// Device status: the driver has given up on the device.
+const STATUS_FAILED: u8 = 128;
+
+/// The bit of the ISR status that indicates that the device has used buffers in one of its virtqueues.
+const ISR_QUEUE_INTERRUPT: u8 = 1;
+
+/// How long to wait for the device to finish resetting, in milliseconds.
+const RESET_TIMEOUT_MS: u32 = 1000;
+
+
+/// Returns the virtio device type of the given PCI device, e.g., [`DEVICE_TYPE_BLOCK`](constant.DEVICE_TYPE_BLOCK.html),
+/// or `None` if it isn't a virtio device.
+pub fn device_type(pci_device: &PciDevice) -> Option<u16> {
+    if pci_device.vendor_id != VIRTIO_VENDOR_ID {
+        return None;
+    }
+    match pci_device.device_id {
+        // Transitional devices, which support the legacy transport, give their device type as their PCI subsystem id.
+        0x1000 ..= 0x103F => Some(pci_device.pci_read_16(PCI_SUBSYSTEM_ID)),
+        // Modern-only devices give their device type as an offset from the base device id.
+        0x1040 ..= 0x107F => Some(pci_device.device_id - 0x1040),
+        _ => None,
+    }
+}
+
+
+/// The means of accessing a virtio device's registers, which is either the legacy or the modern PCI transport.
+pub enum Transport {
+    Legacy(LegacyTransport),
+    Modern(Box<ModernTransport>),
+}
+
+impl Transport {
+    /// Finds the registers of the given virtio PCI device, resets the device,
+    /// and tells it that a driver has been found for it.
+    pub fn new(pci_device: &PciDevice) -> Result<Transport, &'static str> {
+        // set the bus mastering bit for this PciDevice, which allows it to use DMA
+        pci_device.pci_set_command_bus_master_bit();
+
+        let mut transport = match ModernTransport::new(pci_device)? {
+            Some(modern) => Transport::Modern(Box::new(modern)),
+            None => Transport::Legacy(LegacyTransport::new(pci_device)?),
+        };
+
+        // Writing zero to the status resets the device, which has finished resetting once the status reads as zero.
+        transport.set_status(0);
+        driver_utils::poll(RESET_TIMEOUT_MS, || if transport.status() == 0 { Some(()) } else { None })
+            .ok_or("virtio: timed out waiting for the device to reset")?;
+        transport.set_status(STATUS_ACKNOWLEDGE);
+        transport.set_status(STATUS_ACKNOWLEDGE | STATUS_DRIVER);
+        Ok(transport)
+    }
+
+    /// Returns `true` if this is the modern (virtio 1.0) transport.
+    pub fn is_modern(&self) -> bool {
+        match self {
+            Transport::Legacy(_) => false,
+            Transport::Modern(_) => true,
+        }
+    }
+
+    /// Accepts the features in `supported_features` that the device also offers, and returns the accepted features.
+    ///
+    /// With the modern transport, `VIRTIO_F_VERSION_1` is always accepted,
+    /// and the device may still refuse the accepted features, in which case an error is returned.
+    /// With the legacy transport, only the lower 32 feature bits exist.
+    pub fn negotiate_features(&mut self, supported_features: u64) -> Result<u64, &'static str> {
+        let device_features = self.device_features();
+        let mut features = device_features & supported_features;
+        if self.is_modern() {
+            if device_features & VIRTIO_F_VERSION_1 == 0 {
+                self.fail();
+                return Err("virtio: modern device doesn't offer VIRTIO_F_VERSION_1");
+            }
+            features |= VIRTIO_F_VERSION_1;
+        } else {
+            features &= 0xFFFF_FFFF;
+        }
+        self.set_driver_features(features);
+
+        if self.is_modern() {
+            let status = self.status();
+            self.set_status(status | STATUS_FEATURES_OK);
+            if self.status() & STATUS_FEATURES_OK == 0 {
+                self.fail();
+                return Err("virtio: device didn't accept the negotiated features");
+            }
+        }
+        debug!("virtio: device features {:#X}, accepted features {:#X}", device_features, features);
+        Ok(features)
+    }
+
+    /// Creates the virtqueue with the given `index` and tells the device where it is.
+    ///
+    /// The queue has at most `max_size` entries, but the legacy transport always uses the size that the device dictates.
+    pub fn create_queue(&mut self, index: u16, max_size: u16) -> Result<Virtqueue, &'static str> {
+        let device_size = match self {
+            Transport::Legacy(legacy) => legacy.queue_size(index),
+            Transport::Modern(modern) => modern.queue_size(index)?,
+        };
+        if device_size == 0 {
+            return Err("virtio: device doesn't have a virtqueue with that index");
+        }
+        let size = match self {
+            Transport::Legacy(_) => device_size,
+            // queue sizes must be powers of two
+            Transport::Modern(_) => core::cmp::min(device_size, max_size.next_power_of_two()),
+        };
+        let queue = Virtqueue::new(index, size)?;
+        match self {
+            Transport::Legacy(legacy) => legacy.setup_queue(&queue)?,
+            Transport::Modern(modern) => modern.setup_queue(&queue)?,
+        }
+        Ok(queue)
+    }
+
+    /// Tells the device that the driver is ready to drive it, which must be done after all virtqueues have been created.
+    pub fn driver_ok(&mut self) {
+        let status = self.status();
+        self.set_status(status | STATUS_DRIVER_OK);
+    }
+
+    /// Tells the device that the driver has given up on it.
+    pub fn fail(&mut self) {
+        let status = self.status();
+        self.set_status(status | STATUS_FAILED);
+    }
+
+    /// Tells the device that new buffers are available in the virtqueue with the given `index`.
+    pub fn notify(&mut self, index: u16) -> Result<(), &'static str> {
+        match self {
+            Transport::Legacy(legacy) => {
+                legacy.notify(index);
+                Ok(())
+            }
+            Transport::Modern(modern) => modern.notify(index),
+        }
+    }
+
+    /// Reads and thereby clears the device's ISR status, which also acknowledges its interrupt.
+    pub fn read_isr(&self) -> u8 {
+        match self {
+            Transport::Legacy(legacy) => legacy.read_isr(),
+            Transport::Modern(modern) => modern.read_isr(),
+        }
+    }
+
+    /// Reads the byte at the given `offset` of the device-specific configuration.
+    pub fn read_config_u8(&self, offset: usize) -> Result<u8, &'static str> {
+        match self {
+            Transport::Legacy(legacy) => Ok(legacy.read_config_u8(offset)),
+            Transport::Modern(modern) => modern.read_config(offset),
+        }
+    }
+
+    /// Reads the `u16` at the given `offset` of the device-specific configuration.
+    pub fn read_config_u16(&self, offset: usize) -> Result<u16, &'static str> {
+        match self {
+            Transport::Legacy(legacy) => Ok(legacy.read_config_u16(offset)),
+            Transport::Modern(modern) => modern.read_config(offset),
+        }
+    }
+
+    /// Reads the `u32` at the given `offset` of the device-specific configuration.
+    pub fn read_config_u32(&self, offset: usize) -> Result<u32, &'static str> {
+        match self {
+            Transport::Legacy(legacy) => Ok(legacy.read_config_u32(offset)),
+            Transport::Modern(modern) => modern.read_config(offset),
+        }
+    }
+
+    /// Reads the `u64` at the given `offset` of the device-specific configuration.
+    ///
+    /// The two halves are read separately, so this retries until the device didn't change its configuration in between.
+    pub fn read_config_u64(&self, offset: usize) -> Result<u64, &'static str> {
+        loop {
+            let generation = self.config_generation();
+            let low = self.read_config_u32(offset)? as u64;
+            let high = self.read_config_u32(offset + 4)? as u64;
+            if self.config_generation() == generation {
+                return Ok((high << 32) | low);
+            }
+        }
+    }
+
+    fn config_generation(&self) -> u8 {
+        match self {
+            // The legacy transport has no way to detect configuration changes.
+            Transport::Legacy(_) => 0,
+            Transport::Modern(modern) => modern.config_generation(),
+        }
+    }
+
+    fn status(&self) -> u8 {
+        match self {
+            Transport::Legacy(legacy) => legacy.status(),
+            Transport::Modern(modern) => modern.status(),
+        }
+    }
+
+    fn set_status(&mut self, status: u8) {
+        match self {
+            Transport::Legacy(legacy) => legacy.set_status(status),
+            Transport::Modern(modern) => modern.set_status(status),
+        }
+    }
+
+    fn device_features(&mut self) -> u64 {
+        match self {
+            Transport::Legacy(legacy) => legacy.device_features() as u64,
+            Transport::Modern(modern) => modern.device_features(),
+        }
+    }
+
+    fn set_driver_features(&mut self, features: u64) {
+        match self {
+            Transport::Legacy(legacy) => legacy.set_driver_features(features as u32),
+            Transport::Modern(modern) => modern.set_driver_features(features),
+        }
+    }
+}
+
+
+/// A virtio device whose interrupts are handled by `virtio_handler()`.
+struct InterruptSource {
+    transport: Arc<MutexIrqSafe<Transport>>,
+    /// The interrupt number that the device raises.
+    interrupt_num: u8,
+    /// The tasks waiting for the device to use buffers in its virtqueues.
+    waiters: Arc<WaitQueue>,
+}
+
+lazy_static! {
+    /// The virtio devices whose interrupts are handled by `virtio_handler()`.
+    static ref INTERRUPT_SOURCES: MutexIrqSafe<Vec<InterruptSource>> = MutexIrqSafe::new(Vec::new());
+}
+
+/// The interrupt handler shared by all virtio devices.
+///
+/// Reading a device's ISR status acknowledges its interrupt,
+/// after which the tasks waiting on that device are woken up to take the used buffers from its virtqueues.
+extern "x86-interrupt" fn virtio_handler(_stack_frame: &mut ExceptionStackFrame) {
+    let sources = INTERRUPT_SOURCES.lock();
+    let mut irq = sources.first().map(|source| source.interrupt_num);
+    for source in sources.iter() {
+        let isr = source.transport.lock().read_isr();
+        if isr & ISR_QUEUE_INTERRUPT != 0 {
+            irq = Some(source.interrupt_num);
+            while source.waiters.notify_one() { }
+        }
+    }
+    interrupts::eoi(irq);
+}
+
+/// Registers the interrupt handler for the given virtio PCI device, whose registers are accessed through `transport`.
+///
+/// The device raises its legacy PCI interrupt, which may be shared with other virtio devices.
+/// Returns the wait queue whose tasks are woken up whenever the device has used buffers in any of its virtqueues.
+pub fn register_interrupt_handler(pci_device: &PciDevice, transport: &Arc<MutexIrqSafe<Transport>>) -> Result<Arc<WaitQueue>, &'static str> {
+    let interrupt_num = pci_device.pci_read_8(PCI_INTERRUPT_LINE) + PIC_MASTER_OFFSET;
+    let mut sources = INTERRUPT_SOURCES.lock();
+    if !sources.iter().any(|source| source.interrupt_num == interrupt_num) {
+        interrupts::register_interrupt(interrupt_num, virtio_handler)?;
+    }
+    let waiters = Arc::new(WaitQueue::new());
+    sources.push(InterruptSource {
+        transport: Arc::clone(transport),
+        interrupt_num,
+        waiters: Arc::clone(&waiters),
+    });
+    Ok(waiters)
+}
@@ -0,0 +1,265 @@
+//! The modern virtio PCI transport of virtio 1.0, whose registers are memory-mapped.
+//!
+//! The device's registers are split into several regions, each of which can be located anywhere within the device's BARs.
+//! Each region is described by a vendor-specific PCI capability.
+//! See section 4.1.4 ("Virtio Structure PCI Capabilities") of the virtio 1.0 specification.
+
+use alloc::vec::Vec;
+use volatile::{Volatile, ReadOnly};
+use kernel_config::memory::PAGE_SIZE;
+use memory::{MappedPages, PhysicalAddress};
+use pci::{PciDevice, PCI_CAPABILITIES, PCI_STATUS};
+use queue::Virtqueue;
+use driver_utils::{bar_address, map_device_memory};
+
+
+/// The id of vendor-specific PCI capabilities, which virtio uses to describe its register regions.
+const PCI_CAPABILITY_VENDOR_SPECIFIC: u8 = 0x09;
+/// The bit of the PCI status register that indicates that the device has a list of capabilities.
+const PCI_STATUS_CAPABILITIES_LIST: u16 = 1 << 4;
+/// The maximum number of capabilities that are visited, in case a malformed list of capabilities loops back on itself.
+const MAX_CAPABILITIES: usize = 48;
+
+/// The types of register regions, as given by the `cfg_type` of a virtio PCI capability.
+const CFG_TYPE_COMMON: u8 = 1;
+const CFG_TYPE_NOTIFY: u8 = 2;
+const CFG_TYPE_ISR:    u8 = 3;
+const CFG_TYPE_DEVICE: u8 = 4;
+
+
+/// The common configuration region, which is the same for all device types.
+#[repr(C)]
+struct CommonConfig {
+    device_feature_select:  Volatile<u32>,
+    device_feature:         ReadOnly<u32>,
+    driver_feature_select:  Volatile<u32>,
+    driver_feature:         Volatile<u32>,
+    msix_config:            Volatile<u16>,
+    num_queues:             ReadOnly<u16>,
+    device_status:          Volatile<u8>,
+    config_generation:      ReadOnly<u8>,
+    /// The following fields refer to the virtqueue selected by this field.
+    queue_select:           Volatile<u16>,
+    queue_size:             Volatile<u16>,
+    queue_msix_vector:      Volatile<u16>,
+    queue_enable:           Volatile<u16>,
+    queue_notify_off:       ReadOnly<u16>,
+    /// The 64-bit physical addresses of the queue's parts, which are accessed as two 32-bit halves.
+    queue_desc_low:         Volatile<u32>,
+    queue_desc_high:        Volatile<u32>,
+    queue_driver_low:       Volatile<u32>,
+    queue_driver_high:      Volatile<u32>,
+    queue_device_low:       Volatile<u32>,
+    queue_device_high:      Volatile<u32>,
+}
+
+
+/// A register region within one of the device's BARs, which has been mapped into memory.
+struct Region {
+    mapped_pages: MappedPages,
+    /// The offset of the region from the start of the `mapped_pages`, since the region need not be page-aligned.
+    offset: usize,
+    /// The size of the region in bytes.
+    length: usize,
+}
+
+impl Region {
+    /// Maps the region described by the virtio PCI capability at `cap_addr` in the configuration space of the given device.
+    fn map(pci_device: &PciDevice, cap_addr: u16) -> Result<Region, &'static str> {
+        let bar_index = pci_device.pci_read_8(cap_addr + 4) as usize;
+        let offset = pci_device.pci_read_32(cap_addr + 8) as usize;
+        let length = pci_device.pci_read_32(cap_addr + 12) as usize;
+        let phys_addr = PhysicalAddress::new(bar_address(pci_device, bar_index)?.value() + offset)?;
+        Ok(Region {
+            mapped_pages: map_device_memory(phys_addr, length)?,
+            offset: phys_addr.value() % PAGE_SIZE,
+            length,
+        })
+    }
+
+    /// Reads the value of type `T` at the given `offset` within this region.
+    fn read<T: Copy>(&self, offset: usize) -> Result<T, &'static str> {
+        if offset + core::mem::size_of::<T>() > self.length {
+            return Err("virtio: register offset is beyond the end of its region");
+        }
+        let value = self.mapped_pages.as_type::<T>(self.offset + offset)?;
+        // SAFE: the value is within the region's mapped pages
+        Ok(unsafe { core::ptr::read_volatile(value) })
+    }
+
+    /// Writes the given `value` of type `T` to the given `offset` within this region.
+    fn write<T: Copy>(&mut self, offset: usize, value: T) -> Result<(), &'static str> {
+        if offset + core::mem::size_of::<T>() > self.length {
+            return Err("virtio: register offset is beyond the end of its region");
+        }
+        let destination = self.mapped_pages.as_type_mut::<T>(self.offset + offset)?;
+        // SAFE: the destination is within the region's mapped pages
+        unsafe { core::ptr::write_volatile(destination, value) };
+        Ok(())
+    }
+}
+
+
+/// The registers of a virtio device that uses the modern transport.
+pub struct ModernTransport {
+    common: Region,
+    notify: Region,
+    /// The notification register of a queue is located at the queue's notify offset times this multiplier.
+    notify_offset_multiplier: usize,
+    isr: Region,
+    /// The device-specific configuration, which doesn't exist for some device types.
+    device: Option<Region>,
+    /// The offset within the `notify` region of the notification register of each queue that has been set up.
+    notify_offsets: Vec<(u16, usize)>,
+}
+
+impl ModernTransport {
+    /// Finds and maps the register regions of the given device.
+    ///
+    /// Returns `None` if the device doesn't offer the modern transport, i.e., if it lacks any of the required regions.
+    pub fn new(pci_device: &PciDevice) -> Result<Option<ModernTransport>, &'static str> {
+        let mut common = None;
+        let mut notify = None;
+        let mut isr = None;
+        let mut device = None;
+        for cap_addr in vendor_capabilities(pci_device) {
+            // If there are several capabilities of the same type, the first one is preferred.
+            match pci_device.pci_read_8(cap_addr + 3) {
+                CFG_TYPE_COMMON if common.is_none() => common = Some(cap_addr),
+                CFG_TYPE_NOTIFY if notify.is_none() => notify = Some(cap_addr),
+                CFG_TYPE_ISR    if isr.is_none()    => isr = Some(cap_addr),
+                CFG_TYPE_DEVICE if device.is_none() => device = Some(cap_addr),
+                _ => { }
+            }
+        }
+        let (common, notify, isr) = match (common, notify, isr) {
+            (Some(common), Some(notify), Some(isr)) => (common, notify, isr),
+            _ => return Ok(None),
+        };
+
+        let common_region = Region::map(pci_device, common)?;
+        if common_region.length < core::mem::size_of::<CommonConfig>() {
+            return Err("virtio: common configuration region is too small");
+        }
+        Ok(Some(ModernTransport {
+            common: common_region,
+            notify: Region::map(pci_device, notify)?,
+            // the multiplier follows the generic fields of the notification capability
+            notify_offset_multiplier: pci_device.pci_read_32(notify + 16) as usize,
+            isr: Region::map(pci_device, isr)?,
+            device: match device {
+                Some(cap_addr) => Some(Region::map(pci_device, cap_addr)?),
+                None => None,
+            },
+            notify_offsets: Vec::new(),
+        }))
+    }
+
+    fn common(&self) -> &CommonConfig {
+        // The size of the region was checked when it was mapped.
+        self.common.mapped_pages.as_type(self.common.offset).expect("BUG: virtio common configuration isn't mapped")
+    }
+
+    fn common_mut(&mut self) -> &mut CommonConfig {
+        self.common.mapped_pages.as_type_mut(self.common.offset).expect("BUG: virtio common configuration isn't mapped")
+    }
+
+    pub fn status(&self) -> u8 {
+        self.common().device_status.read()
+    }
+
+    pub fn set_status(&mut self, status: u8) {
+        self.common_mut().device_status.write(status);
+    }
+
+    pub fn config_generation(&self) -> u8 {
+        self.common().config_generation.read()
+    }
+
+    pub fn device_features(&mut self) -> u64 {
+        let common = self.common_mut();
+        common.device_feature_select.write(0);
+        let low = common.device_feature.read() as u64;
+        common.device_feature_select.write(1);
+        let high = common.device_feature.read() as u64;
+        (high << 32) | low
+    }
+
+    pub fn set_driver_features(&mut self, features: u64) {
+        let common = self.common_mut();
+        common.driver_feature_select.write(0);
+        common.driver_feature.write(features as u32);
+        common.driver_feature_select.write(1);
+        common.driver_feature.write((features >> 32) as u32);
+    }
+
+    /// Returns the maximum size of the virtqueue with the given `index`, which is `0` if that queue doesn't exist.
+    pub fn queue_size(&mut self, index: u16) -> Result<u16, &'static str> {
+        let common = self.common_mut();
+        if index >= common.num_queues.read() {
+            return Ok(0);
+        }
+        common.queue_select.write(index);
+        Ok(common.queue_size.read())
+    }
+
+    /// Tells the device where the parts of the given `queue` are, and enables it.
+    pub fn setup_queue(&mut self, queue: &Virtqueue) -> Result<(), &'static str> {
+        let notify_offset = {
+            let common = self.common_mut();
+            common.queue_select.write(queue.index());
+            common.queue_size.write(queue.size());
+            let desc = queue.desc_phys_addr().value();
+            let driver = queue.avail_phys_addr().value();
+            let device = queue.used_phys_addr().value();
+            common.queue_desc_low.write(desc as u32);
+            common.queue_desc_high.write((desc >> 32) as u32);
+            common.queue_driver_low.write(driver as u32);
+            common.queue_driver_high.write((driver >> 32) as u32);
+            common.queue_device_low.write(device as u32);
+            common.queue_device_high.write((device >> 32) as u32);
+            common.queue_enable.write(1);
+            common.queue_notify_off.read() as usize * self.notify_offset_multiplier
+        };
+        self.notify_offsets.retain(|&(index, _)| index != queue.index());
+        self.notify_offsets.push((queue.index(), notify_offset));
+        Ok(())
+    }
+
+    pub fn notify(&mut self, index: u16) -> Result<(), &'static str> {
+        let offset = self.notify_offsets.iter()
+            .find(|&&(i, _)| i == index)
+            .map(|&(_, offset)| offset)
+            .ok_or("virtio: tried to notify a virtqueue that hasn't been set up")?;
+        self.notify.write(offset, index)
+    }
+
+    pub fn read_isr(&self) -> u8 {
+        self.isr.read(0).unwrap_or(0)
+    }
+
+    pub fn read_config<T: Copy>(&self, offset: usize) -> Result<T, &'static str> {
+        self.device.as_ref().ok_or("virtio: device has no device-specific configuration")?.read(offset)
+    }
+}
+
+
+/// Returns the addresses of all vendor-specific capabilities in the configuration space of the given PCI device.
+fn vendor_capabilities(pci_device: &PciDevice) -> Vec<u16> {
+    let mut capabilities = Vec::new();
+    if pci_device.pci_read_16(PCI_STATUS) & PCI_STATUS_CAPABILITIES_LIST == 0 {
+        return capabilities;
+    }
+    // the bottom 2 bits of each capability pointer are reserved
+    let mut cap_addr = (pci_device.pci_read_8(PCI_CAPABILITIES) & 0xFC) as u16;
+    for _ in 0 .. MAX_CAPABILITIES {
+        if cap_addr == 0 {
+            break;
+        }
+        if pci_device.pci_read_8(cap_addr) == PCI_CAPABILITY_VENDOR_SPECIFIC {
+            capabilities.push(cap_addr);
+        }
+        cap_addr = (pci_device.pci_read_8(cap_addr + 1) & 0xFC) as u16;
+    }
+    capabilities
+}
@@ -0,0 +1,253 @@
+//! A split virtqueue, through which buffers are passed to and from a virtio device.
+//!
+//! A virtqueue consists of three parts in physically-contiguous memory:
+//! * the descriptor table, whose entries each describe one buffer and can be chained together,
+//! * the available ring, in which the driver places the first descriptor of each chain that it passes to the device,
+//! * the used ring, in which the device places the first descriptor of each chain that it has finished with.
+//!
+//! The parts are laid out as required by the legacy transport, with the used ring starting on a new page,
+//! which also satisfies the alignment requirements of the modern transport.
+//! See section 2.4 ("Virtqueues") of the virtio 1.0 specification.
+
+use core::sync::atomic::{fence, Ordering};
+use kernel_config::memory::PAGE_SIZE;
+use memory::{MappedPages, PhysicalAddress, create_contiguous_mapping};
+use driver_utils::DEVICE_MAPPING_FLAGS;
+
+
+/// The maximum number of entries in a virtqueue, as defined by the virtio specification.
+const MAX_QUEUE_SIZE: u16 = 32768;
+
+/// Descriptor flag: the chain continues with the descriptor in the `next` field.
+const DESC_F_NEXT:  u16 = 1;
+/// Descriptor flag: the buffer is written to by the device, rather than read from.
+const DESC_F_WRITE: u16 = 2;
+
+
+/// An entry in the descriptor table, which describes one buffer.
+#[derive(Copy, Clone, Debug, Default)]
+#[repr(C)]
+struct Descriptor {
+    phys_addr: u64,
+    length: u32,
+    flags: u16,
+    /// The index of the next descriptor in the chain, if the `DESC_F_NEXT` flag is set.
+    next: u16,
+}
+
+/// An entry in the used ring, which describes a chain of buffers that the device has finished with.
+#[derive(Copy, Clone, Debug, Default)]
+#[repr(C)]
+struct UsedElement {
+    /// The index of the first descriptor of the chain.
+    id: u32,
+    /// The number of bytes that the device wrote into the chain's device-writable buffers.
+    length: u32,
+}
+
+
+/// A buffer in physically-contiguous memory that is passed to a device as part of a chain of buffers.
+#[derive(Copy, Clone, Debug)]
+pub struct Buffer {
+    pub phys_addr: PhysicalAddress,
+    pub length: usize,
+    /// Whether the device writes to this buffer, rather than reading from it.
+    /// All device-writable buffers in a chain must follow all of its device-readable buffers.
+    pub device_writable: bool,
+}
+
+
+/// A split virtqueue.
+pub struct Virtqueue {
+    /// The index of this queue among the device's queues.
+    index: u16,
+    /// The number of descriptors in this queue, which is also the number of entries in each ring.
+    size: u16,
+    /// The memory that holds all three parts of this queue.
+    memory: MappedPages,
+    /// The physical address of `memory`, which is where the descriptor table starts.
+    phys_addr: PhysicalAddress,
+    /// The offset of the available ring from the start of `memory`.
+    avail_offset: usize,
+    /// The offset of the used ring from the start of `memory`.
+    used_offset: usize,
+    /// The first descriptor of the list of free descriptors, which are linked together by their `next` field.
+    free_head: u16,
+    /// The number of free descriptors.
+    free_count: u16,
+    /// The index of the next entry of the available ring that a chain will be placed in, which increases forever.
+    next_avail: u16,
+    /// The index of the next entry of the used ring that the device will place a chain in, which increases forever.
+    next_used: u16,
+}
+
+impl Virtqueue {
+    /// Allocates a new virtqueue with the given `index` and `size`, which must be a power of two.
+    ///
+    /// The queue is not yet known to the device, which must be told about it using its physical addresses.
+    pub fn new(index: u16, size: u16) -> Result<Virtqueue, &'static str> {
+        if size == 0 || size > MAX_QUEUE_SIZE || !size.is_power_of_two() {
+            return Err("virtio: virtqueue size must be a power of two no greater than 32768");
+        }
+        let queue_size = size as usize;
+        let avail_offset = queue_size * core::mem::size_of::<Descriptor>();
+        // the available ring has flags, an index, its entries, and the `used_event` field
+        let avail_size = 2 * (3 + queue_size);
+        let used_offset = align_up(avail_offset + avail_size, PAGE_SIZE);
+        // the used ring has flags, an index, its entries, and the `avail_event` field
+        let used_size = 4 + queue_size * core::mem::size_of::<UsedElement>() + 2;
+        let total_size = used_offset + align_up(used_size, PAGE_SIZE);
+
+        let (mut memory, phys_addr) = create_contiguous_mapping(total_size, DEVICE_MAPPING_FLAGS)?;
+        for byte in memory.as_slice_mut::<u8>(0, total_size)?.iter_mut() {
+            *byte = 0;
+        }
+        // Link all descriptors together into the list of free descriptors.
+        {
+            let descriptors = memory.as_slice_mut::<Descriptor>(0, queue_size)?;
+            for (i, descriptor) in descriptors.iter_mut().enumerate() {
+                descriptor.next = ((i + 1) % queue_size) as u16;
+            }
+        }
+
+        Ok(Virtqueue {
+            index,
+            size,
+            memory,
+            phys_addr,
+            avail_offset,
+            used_offset,
+            free_head: 0,
+            free_count: size,
+            next_avail: 0,
+            next_used: 0,
+        })
+    }
+
+    /// Returns the index of this queue among the device's queues.
+    pub fn index(&self) -> u16 {
+        self.index
+    }
+
+    /// Returns the number of descriptors in this queue.
+    pub fn size(&self) -> u16 {
+        self.size
+    }
+
+    /// Returns the number of descriptors that are not currently in use by the device.
+    pub fn free_count(&self) -> u16 {
+        self.free_count
+    }
+
+    /// Returns the physical address of the descriptor table.
+    pub fn desc_phys_addr(&self) -> PhysicalAddress {
+        self.phys_addr
+    }
+
+    /// Returns the physical address of the available ring, which the modern transport calls the "driver area".
+    pub fn avail_phys_addr(&self) -> PhysicalAddress {
+        self.phys_addr + self.avail_offset
+    }
+
+    /// Returns the physical address of the used ring, which the modern transport calls the "device area".
+    pub fn used_phys_addr(&self) -> PhysicalAddress {
+        self.phys_addr + self.used_offset
+    }
+
+    /// Places the given chain of `buffers` into this queue, making it available to the device.
+    /// The device must then be notified through its transport.
+    ///
+    /// Returns the id of the chain, which is returned by `pop_used()` once the device has finished with the chain.
+    /// Returns an error if there aren't enough free descriptors for the chain.
+    pub fn add(&mut self, buffers: &[Buffer]) -> Result<u16, &'static str> {
+        if buffers.is_empty() {
+            return Err("virtio: cannot add an empty chain of buffers to a virtqueue");
+        }
+        if buffers.len() > self.free_count as usize {
+            return Err("virtio: not enough free descriptors in the virtqueue");
+        }
+        let head = self.free_head;
+        {
+            let descriptors = self.memory.as_slice_mut::<Descriptor>(0, self.size as usize)?;
+            let mut index = head;
+            for (i, buffer) in buffers.iter().enumerate() {
+                let descriptor = &mut descriptors[index as usize];
+                let next = descriptor.next;
+                let mut flags = if buffer.device_writable { DESC_F_WRITE } else { 0 };
+                if i + 1 < buffers.len() {
+                    flags |= DESC_F_NEXT;
+                }
+                let new_descriptor = Descriptor {
+                    phys_addr: buffer.phys_addr.value() as u64,
+                    length: buffer.length as u32,
+                    flags,
+                    next,
+                };
+                // SAFE: the descriptor is within this queue's mapped pages
+                unsafe { core::ptr::write_volatile(descriptor, new_descriptor) };
+                // The last descriptor's `next` field still points to the rest of the free list.
+                self.free_head = next;
+                index = next;
+            }
+        }
+        self.free_count -= buffers.len() as u16;
+
+        let slot = (self.next_avail % self.size) as usize;
+        self.next_avail = self.next_avail.wrapping_add(1);
+        let next_avail = self.next_avail;
+        let ring = self.memory.as_slice_mut::<u16>(self.avail_offset, 2 + self.size as usize)?;
+        // SAFE: the ring entries are within this queue's mapped pages
+        unsafe { core::ptr::write_volatile(&mut ring[2 + slot], head) };
+        // The device must see the chain before it sees the new index of the available ring.
+        fence(Ordering::SeqCst);
+        unsafe { core::ptr::write_volatile(&mut ring[1], next_avail) };
+        fence(Ordering::SeqCst);
+        Ok(head)
+    }
+
+    /// Takes the next chain of buffers that the device has finished with from the used ring, and frees its descriptors.
+    ///
+    /// Returns the id of the chain and the number of bytes that the device wrote into it,
+    /// or `None` if the device hasn't finished with any more chains.
+    pub fn pop_used(&mut self) -> Option<(u16, usize)> {
+        let used_index = {
+            let index = self.memory.as_type::<u16>(self.used_offset + 2).ok()?;
+            // SAFE: the index is within this queue's mapped pages
+            unsafe { core::ptr::read_volatile(index) }
+        };
+        if used_index == self.next_used {
+            return None;
+        }
+        // The used element must not be read before the index that says it is valid.
+        fence(Ordering::SeqCst);
+        let slot = (self.next_used % self.size) as usize;
+        let element = {
+            let offset = self.used_offset + 4 + slot * core::mem::size_of::<UsedElement>();
+            let element = self.memory.as_type::<UsedElement>(offset).ok()?;
+            // SAFE: the element is within this queue's mapped pages
+            unsafe { core::ptr::read_volatile(element) }
+        };
+        self.next_used = self.next_used.wrapping_add(1);
+
+        // Return the chain's descriptors to the front of the free list.
+        let head = element.id as u16;
+        let descriptors = self.memory.as_slice_mut::<Descriptor>(0, self.size as usize).ok()?;
+        let mut tail = head;
+        let mut count = 1;
+        while descriptors[tail as usize].flags & DESC_F_NEXT != 0 && count < self.size {
+            tail = descriptors[tail as usize].next;
+            count += 1;
+        }
+        descriptors[tail as usize].next = self.free_head;
+        self.free_head = head;
+        self.free_count += count;
+        Some((head, element.length as usize))
+    }
+
+}
+
+
+/// Rounds the given `value` up to the next multiple of `align`, which must be a power of two.
+fn align_up(value: usize, align: usize) -> usize {
+    (value + align - 1) & !(align - 1)
+}
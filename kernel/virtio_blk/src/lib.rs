@@ -0,0 +1,324 @@
+//! Support for virtio block devices, such as the `virtio-blk` disks emulated by QEMU.
+//!
+//! The primary structs of interest are [`VirtioBlkController`](struct.VirtioBlkController.html),
+//! which implements `StorageController`, and [`VirtioBlkDrive`](struct.VirtioBlkDrive.html), which implements `StorageDevice`.
+//! Each virtio block device is a separate PCI device, so each controller has exactly one drive.
+//!
+//! Requests are passed to the device through its single virtqueue, using either the legacy or modern virtio PCI transport.
+//! Each request is a chain of three buffers: a header that the device reads, which gives the request type and sector,
+//! the data buffer, and a status byte that the device writes once the request has completed.
+
+#![no_std]
+
+extern crate alloc;
+#[macro_use] extern crate log;
+extern crate spin;
+extern crate irq_safety;
+extern crate kernel_config;
+extern crate memory;
+extern crate pci;
+extern crate wait_queue;
+extern crate storage_device;
+extern crate virtio;
+extern crate driver_utils;
+
+use core::{fmt, time::Duration};
+use alloc::{
+    boxed::Box,
+    sync::Arc,
+};
+use spin::Mutex;
+use irq_safety::MutexIrqSafe;
+use kernel_config::memory::PAGE_SIZE;
+use memory::{MappedPages, PhysicalAddress, create_contiguous_mapping};
+use pci::PciDevice;
+use wait_queue::WaitQueue;
+use storage_device::{StorageDevice, StorageDeviceRef, StorageController};
+use virtio::{Buffer, Transport, Virtqueue};
+use driver_utils::{DEVICE_MAPPING_FLAGS, wait_or_poll};
+
+
+/// Virtio block devices always address their contents in units of 512-byte sectors.
+const SECTOR_SIZE_IN_BYTES: usize = 512;
+/// The size of the buffer that each drive uses for DMA transfers.
+const BUFFER_SIZE_IN_BYTES: usize = 128 * 1024;
+/// The maximum number of entries in the request queue.
+/// Only one request is in flight at a time, which needs three descriptors.
+const MAX_QUEUE_SIZE: u16 = 16;
+/// The index of the request queue, which is the only queue of a block device.
+const REQUEST_QUEUE_INDEX: u16 = 0;
+
+/// How long to wait for a request to complete before giving up.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Feature bit: the device is read-only.
+const VIRTIO_BLK_F_RO: u64 = 1 << 5;
+/// Feature bit: the device supports the flush request, i.e., it may have a volatile write cache.
+const VIRTIO_BLK_F_FLUSH: u64 = 1 << 9;
+
+/// The offset of the capacity (in 512-byte sectors) within the device-specific configuration.
+const CONFIG_CAPACITY_OFFSET: usize = 0;
+
+/// The offset of the status byte in the request memory, which follows the request header.
+const STATUS_OFFSET: usize = core::mem::size_of::<RequestHeader>();
+
+/// The status that the device writes once a request has completed successfully.
+const STATUS_OK: u8 = 0;
+/// The status that the device writes if a request was not supported.
+const STATUS_UNSUPPORTED: u8 = 2;
+/// A status value that the device never writes, which is placed in the status byte before each request.
+const STATUS_PENDING: u8 = 0xFF;
+
+
+/// The types of requests that are given to a block device.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[repr(u32)]
+enum RequestType {
+    Read  = 0,
+    Write = 1,
+    /// Commit all written data in the device's volatile write cache to non-volatile storage.
+    Flush = 4,
+}
+
+/// The header at the start of each request, which the device reads.
+#[derive(Copy, Clone, Debug)]
+#[repr(C)]
+struct RequestHeader {
+    request_type: u32,
+    reserved: u32,
+    /// The first sector of the transfer, in 512-byte units.
+    sector: u64,
+}
+
+
+/// A virtio PCI device that acts as a block storage controller with a single drive.
+#[derive(Debug)]
+pub struct VirtioBlkController {
+    drive: VirtioBlkDriveRef,
+}
+
+impl VirtioBlkController {
+    /// Initializes the virtio block device described by the given PCI device.
+    pub fn new(pci_device: &PciDevice) -> Result<VirtioBlkController, &'static str> {
+        let drive = VirtioBlkDrive::new(pci_device)?;
+        info!("virtio block device at {:?}: {} sectors, {} transport{}{}",
+            pci_device.location,
+            drive.size_in_sectors,
+            if drive.transport.lock().is_modern() { "modern" } else { "legacy" },
+            if drive.read_only { ", read-only" } else { "" },
+            if drive.flush_after_write { ", write cache" } else { "" },
+        );
+        Ok(VirtioBlkController {
+            drive: Arc::new(Mutex::new(drive)),
+        })
+    }
+
+    /// Returns this controller's drive.
+    pub fn drive(&self) -> &VirtioBlkDriveRef {
+        &self.drive
+    }
+}
+
+impl StorageController for VirtioBlkController {
+    fn devices<'c>(&'c self) -> Box<(dyn Iterator<Item = StorageDeviceRef> + 'c)> {
+        Box::new(
+            core::iter::once(Arc::clone(&self.drive) as StorageDeviceRef)
+        )
+    }
+}
+
+
+/// The drive of a virtio block device.
+pub struct VirtioBlkDrive {
+    /// The device's registers, which are shared with the interrupt handler.
+    transport: Arc<MutexIrqSafe<Transport>>,
+    /// The queue through which requests are given to the device.
+    /// It is only locked briefly to add or take requests, so that it can be checked while waiting for a request.
+    queue: Mutex<Virtqueue>,
+    /// The tasks waiting for a request to complete, which are woken up by the device's interrupt handler.
+    /// If the interrupt handler couldn't be registered, the request queue is polled instead.
+    waiters: Option<Arc<WaitQueue>>,
+    size_in_sectors: usize,
+    read_only: bool,
+    /// Whether written data must be flushed from the device's volatile write cache.
+    flush_after_write: bool,
+    /// The physically-contiguous buffer that all data transfers of this drive go through.
+    buffer: MappedPages,
+    buffer_phys_addr: PhysicalAddress,
+    /// The memory that holds the header and status byte of the current request.
+    request: MappedPages,
+    request_phys_addr: PhysicalAddress,
+}
+
+impl fmt::Debug for VirtioBlkDrive {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("VirtioBlkDrive")
+            .field("size_in_sectors", &self.size_in_sectors)
+            .field("read_only", &self.read_only)
+            .field("flush_after_write", &self.flush_after_write)
+            .field("uses_interrupts", &self.waiters.is_some())
+            .field("buffer_phys_addr", &self.buffer_phys_addr)
+            .finish()
+    }
+}
+
+pub type VirtioBlkDriveRef = Arc<Mutex<VirtioBlkDrive>>;
+
+impl VirtioBlkDrive {
+    fn new(pci_device: &PciDevice) -> Result<VirtioBlkDrive, &'static str> {
+        let mut transport = Transport::new(pci_device)?;
+        let features = transport.negotiate_features(VIRTIO_BLK_F_RO | VIRTIO_BLK_F_FLUSH)?;
+        let size_in_sectors = transport.read_config_u64(CONFIG_CAPACITY_OFFSET)? as usize;
+        let queue = transport.create_queue(REQUEST_QUEUE_INDEX, MAX_QUEUE_SIZE)?;
+        if queue.size() < 3 {
+            transport.fail();
+            return Err("virtio block device's request queue is too small");
+        }
+        let (buffer, buffer_phys_addr) = create_contiguous_mapping(BUFFER_SIZE_IN_BYTES, DEVICE_MAPPING_FLAGS)?;
+        let (request, request_phys_addr) = create_contiguous_mapping(PAGE_SIZE, DEVICE_MAPPING_FLAGS)?;
+        transport.driver_ok();
+
+        let transport = Arc::new(MutexIrqSafe::new(transport));
+        let waiters = match virtio::register_interrupt_handler(pci_device, &transport) {
+            Ok(waiters) => Some(waiters),
+            Err(e) => {
+                warn!("VirtioBlkDrive::new(): couldn't register interrupt handler, falling back to polling: {}", e);
+                None
+            }
+        };
+
+        Ok(VirtioBlkDrive {
+            transport,
+            queue: Mutex::new(queue),
+            waiters,
+            size_in_sectors,
+            read_only: features & VIRTIO_BLK_F_RO != 0,
+            flush_after_write: features & VIRTIO_BLK_F_FLUSH != 0,
+            buffer,
+            buffer_phys_addr,
+            request,
+            request_phys_addr,
+        })
+    }
+
+    /// Returns `true` if this drive can only be read from.
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    /// Issues a request of the given type for `sector_count` sectors starting at `sector`, and waits for it to complete.
+    /// The data of reads and writes is transferred to or from the beginning of this drive's DMA buffer.
+    fn execute(&mut self, request_type: RequestType, sector: usize, sector_count: usize) -> Result<(), &'static str> {
+        let header = RequestHeader {
+            request_type: request_type as u32,
+            reserved: 0,
+            sector: sector as u64,
+        };
+        *self.request.as_type_mut::<RequestHeader>(0)? = header;
+        *self.request.as_type_mut::<u8>(STATUS_OFFSET)? = STATUS_PENDING;
+
+        let header_buffer = Buffer {
+            phys_addr: self.request_phys_addr,
+            length: core::mem::size_of::<RequestHeader>(),
+            device_writable: false,
+        };
+        let status_buffer = Buffer {
+            phys_addr: self.request_phys_addr + STATUS_OFFSET,
+            length: 1,
+            device_writable: true,
+        };
+        let id = if request_type == RequestType::Flush {
+            self.queue.lock().add(&[header_buffer, status_buffer])?
+        } else {
+            let data_buffer = Buffer {
+                phys_addr: self.buffer_phys_addr,
+                length: sector_count * SECTOR_SIZE_IN_BYTES,
+                device_writable: request_type == RequestType::Read,
+            };
+            self.queue.lock().add(&[header_buffer, data_buffer, status_buffer])?
+        };
+        self.transport.lock().notify(REQUEST_QUEUE_INDEX)?;
+
+        self.wait_for_completion(id)?;
+        let status = *self.request.as_type::<u8>(STATUS_OFFSET)?;
+        match status {
+            STATUS_OK => Ok(()),
+            STATUS_UNSUPPORTED => Err("virtio block device doesn't support the request"),
+            _ => {
+                error!("VirtioBlkDrive: {:?} request for {} sectors at sector {} failed with status {}",
+                    request_type, sector_count, sector, status
+                );
+                Err("virtio block request failed")
+            }
+        }
+    }
+
+    /// Waits until the device has finished with the request whose chain of buffers has the given `id`.
+    ///
+    /// This blocks on the device's interrupts if they are handled, and otherwise polls the request queue.
+    fn wait_for_completion(&self, id: u16) -> Result<(), &'static str> {
+        let check = || {
+            let mut queue = self.queue.lock();
+            // Chains of earlier requests that timed out may be used before this one.
+            while let Some((used_id, _length)) = queue.pop_used() {
+                if used_id == id {
+                    return Some(());
+                }
+            }
+            None
+        };
+        wait_or_poll(self.waiters.as_ref().map(|w| &**w), REQUEST_TIMEOUT, &check)
+            .ok_or("timed out waiting for a virtio block request to complete")
+    }
+
+    /// Checks that a transfer of the given `length_in_bytes` starting at `offset_in_sectors` fits within this drive.
+    fn check_bounds(&self, length_in_bytes: usize, offset_in_sectors: usize) -> Result<(), &'static str> {
+        if length_in_bytes % SECTOR_SIZE_IN_BYTES != 0 {
+            return Err("The buffer length must be a multiple of sector size (512) bytes.");
+        }
+        if offset_in_sectors + (length_in_bytes / SECTOR_SIZE_IN_BYTES) > self.size_in_sectors {
+            return Err("offset_in_sectors was out of bounds");
+        }
+        Ok(())
+    }
+}
+
+impl StorageDevice for VirtioBlkDrive {
+    fn read_sectors(&mut self, buffer: &mut [u8], offset_in_sectors: usize) -> Result<usize, &'static str> {
+        self.check_bounds(buffer.len(), offset_in_sectors)?;
+        let mut sector = offset_in_sectors;
+        for chunk in buffer.chunks_mut(BUFFER_SIZE_IN_BYTES) {
+            let sector_count = chunk.len() / SECTOR_SIZE_IN_BYTES;
+            self.execute(RequestType::Read, sector, sector_count)?;
+            chunk.copy_from_slice(self.buffer.as_slice(0, chunk.len())?);
+            sector += sector_count;
+        }
+        Ok(sector - offset_in_sectors)
+    }
+
+    fn write_sectors(&mut self, buffer: &[u8], offset_in_sectors: usize) -> Result<usize, &'static str> {
+        if self.read_only {
+            return Err("virtio block device is read-only");
+        }
+        self.check_bounds(buffer.len(), offset_in_sectors)?;
+        let mut sector = offset_in_sectors;
+        for chunk in buffer.chunks(BUFFER_SIZE_IN_BYTES) {
+            let sector_count = chunk.len() / SECTOR_SIZE_IN_BYTES;
+            self.buffer.as_slice_mut(0, chunk.len())?.copy_from_slice(chunk);
+            self.execute(RequestType::Write, sector, sector_count)?;
+            sector += sector_count;
+        }
+        if self.flush_after_write {
+            self.execute(RequestType::Flush, 0, 0)?;
+        }
+        Ok(sector - offset_in_sectors)
+    }
+
+    fn sector_size_in_bytes(&self) -> usize {
+        SECTOR_SIZE_IN_BYTES
+    }
+
+    fn size_in_sectors(&self) -> usize {
+        self.size_in_sectors
+    }
+}
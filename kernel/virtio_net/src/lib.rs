@@ -0,0 +1,313 @@
+//! Support for virtio network devices, such as the `virtio-net` NICs emulated by QEMU.
+//!
+//! The primary struct of interest is [`VirtioNetNic`](struct.VirtioNetNic.html), which implements `NetworkInterfaceCard`,
+//! so it can be used with `ethernet_smoltcp_device::EthernetNetworkInterface` just like the e1000 NIC.
+//!
+//! A network device has a receive queue and a transmit queue, which are accessed through either the legacy or modern virtio PCI transport.
+//! Each frame is passed in a chain of two buffers: a virtio-net header, followed by the frame itself.
+//! We don't negotiate any checksum or segmentation offloads, so the header of each transmitted frame is all zeros
+//! and the header of each received frame can be ignored.
+//!
+//! Receive chains are kept available to the device at all times, each of which holds a `ReceiveBuffer` from this driver's pool.
+//! Unlike the e1000 driver, sending a frame doesn't wait for the device to transmit it;
+//! its `TransmitBuffer` is kept alive until the device has finished with it.
+
+#![no_std]
+
+#[macro_use] extern crate alloc;
+#[macro_use] extern crate log;
+#[macro_use] extern crate lazy_static;
+extern crate spin;
+extern crate irq_safety;
+extern crate kernel_config;
+extern crate memory;
+extern crate pci;
+extern crate mpmc;
+extern crate network_interface_card;
+extern crate nic_buffers;
+extern crate nic_initialization;
+extern crate virtio;
+extern crate driver_utils;
+
+use alloc::{
+    collections::VecDeque,
+    sync::Arc,
+    vec::Vec,
+};
+use spin::Once;
+use irq_safety::MutexIrqSafe;
+use kernel_config::memory::PAGE_SIZE;
+use memory::{MappedPages, PhysicalAddress, create_contiguous_mapping};
+use pci::PciDevice;
+use network_interface_card::NetworkInterfaceCard;
+use nic_buffers::{TransmitBuffer, ReceiveBuffer, ReceivedFrame};
+use nic_initialization::{NIC_MAPPING_FLAGS, init_rx_buf_pool};
+use virtio::{Buffer, Transport, Virtqueue, VIRTIO_F_VERSION_1};
+use driver_utils::DEVICE_MAPPING_FLAGS;
+
+
+/// The index of the queue through which the device gives us received frames.
+const RX_QUEUE_INDEX: u16 = 0;
+/// The index of the queue through which we give the device frames to transmit.
+const TX_QUEUE_INDEX: u16 = 1;
+/// The maximum number of entries in each queue.
+/// Each frame needs two descriptors, so half as many frames can be in each queue at once.
+const MAX_QUEUE_SIZE: u16 = 256;
+
+/// Each receive buffer is a single page, which is large enough for a full Ethernet frame
+/// because we don't negotiate any segmentation offloads.
+const RX_BUFFER_SIZE_IN_BYTES: u16 = PAGE_SIZE as u16;
+
+/// How long to wait for the device to finish with a transmitted frame when the transmit queue is full.
+const TX_TIMEOUT_MS: u32 = 1000;
+
+/// Feature bit: the device's configuration holds its MAC address.
+const VIRTIO_NET_F_MAC: u64 = 1 << 5;
+
+/// The offset of the MAC address within the device-specific configuration.
+const CONFIG_MAC_OFFSET: usize = 0;
+
+/// The size of the virtio-net header with the legacy transport, since we don't negotiate `VIRTIO_NET_F_MRG_RXBUF`.
+const LEGACY_HEADER_SIZE: usize = 10;
+/// The size of the virtio-net header with the modern transport, which always includes the `num_buffers` field.
+const MODERN_HEADER_SIZE: usize = 12;
+
+
+/// The single instance of the virtio network device.
+static VIRTIO_NET_NIC: Once<MutexIrqSafe<VirtioNetNic>> = Once::new();
+
+/// Returns a reference to the VirtioNetNic wrapped in a MutexIrqSafe,
+/// if it exists and has been initialized.
+pub fn get_virtio_net_nic() -> Option<&'static MutexIrqSafe<VirtioNetNic>> {
+    VIRTIO_NET_NIC.try()
+}
+
+/// How many ReceiveBuffers are preallocated for this driver to use.
+const RX_BUFFER_POOL_SIZE: usize = 256;
+lazy_static! {
+    /// The pool of pre-allocated receive buffers that are used by the virtio network device
+    /// and temporarily given to higher layers in the networking stack.
+    static ref RX_BUFFER_POOL: mpmc::Queue<ReceiveBuffer> = mpmc::Queue::with_capacity(RX_BUFFER_POOL_SIZE);
+}
+
+
+/// A chain of buffers in the receive queue that the device hasn't yet written a frame into.
+struct ReceiveChain {
+    /// The buffer that the frame will be written into.
+    buffer: ReceiveBuffer,
+    /// The index of the header slot that the virtio-net header will be written into.
+    header_slot: usize,
+}
+
+
+/// A virtio PCI device that acts as an Ethernet network interface card.
+pub struct VirtioNetNic {
+    /// The device's registers, which are shared with the interrupt handler.
+    transport: Arc<MutexIrqSafe<Transport>>,
+    rx_queue: Virtqueue,
+    tx_queue: Virtqueue,
+    /// The chains in the receive queue, indexed by their chain id.
+    rx_chains: Vec<Option<ReceiveChain>>,
+    /// The buffers of frames in the transmit queue that the device may still be reading, indexed by their chain id.
+    tx_buffers: Vec<Option<TransmitBuffer>>,
+    /// Frames that have been taken from the receive queue but not yet by higher layers.
+    received_frames: VecDeque<ReceivedFrame>,
+    /// The size of the virtio-net header that precedes each frame, which depends on the transport.
+    header_size: usize,
+    /// The memory that holds all virtio-net headers: the first header is used by all transmitted frames,
+    /// and is followed by one header slot for each chain in the receive queue.
+    /// It is only accessed by the device after initialization.
+    _headers: MappedPages,
+    headers_phys_addr: PhysicalAddress,
+    /// The indices of the header slots that aren't used by any chain in the receive queue.
+    free_header_slots: Vec<usize>,
+    mac_address: [u8; 6],
+}
+
+impl NetworkInterfaceCard for VirtioNetNic {
+
+    fn send_packet(&mut self, transmit_buffer: TransmitBuffer) -> Result<(), &'static str> {
+        self.reclaim_transmit_buffers();
+        if self.tx_queue.free_count() < 2 {
+            self.wait_for_transmit_queue()?;
+        }
+        let header_buffer = Buffer {
+            phys_addr: self.headers_phys_addr,
+            length: self.header_size,
+            device_writable: false,
+        };
+        let frame_buffer = Buffer {
+            phys_addr: transmit_buffer.phys_addr,
+            length: transmit_buffer.length as usize,
+            device_writable: false,
+        };
+        let id = self.tx_queue.add(&[header_buffer, frame_buffer])?;
+        self.tx_buffers[id as usize] = Some(transmit_buffer);
+        self.transport.lock().notify(TX_QUEUE_INDEX)
+    }
+
+    fn get_received_frame(&mut self) -> Option<ReceivedFrame> {
+        self.received_frames.pop_front()
+    }
+
+    fn poll_receive(&mut self) -> Result<(), &'static str> {
+        let mut replenished = false;
+        while let Some((id, length)) = self.rx_queue.pop_used() {
+            let chain = self.rx_chains.get_mut(id as usize)
+                .and_then(|chain| chain.take())
+                .ok_or("virtio_net: device used an unknown receive chain")?;
+            self.free_header_slots.push(chain.header_slot);
+
+            // Give the device a new receive buffer in place of the one it just filled.
+            self.add_receive_chain()?;
+            replenished = true;
+
+            let mut buffer = chain.buffer;
+            if length <= self.header_size {
+                warn!("VirtioNetNic::poll_receive(): device used a receive chain without a frame, length {}", length);
+                continue;
+            }
+            buffer.length = (length - self.header_size) as u16;
+            self.received_frames.push_back(ReceivedFrame(vec![buffer]));
+        }
+
+        if replenished {
+            self.transport.lock().notify(RX_QUEUE_INDEX)?;
+        }
+        Ok(())
+    }
+
+    fn mac_address(&self) -> [u8; 6] {
+        self.mac_address
+    }
+}
+
+
+impl VirtioNetNic {
+    /// Initializes the virtio network device described by the given PCI device.
+    ///
+    /// Only one virtio network device is currently supported.
+    pub fn init(pci_device: &PciDevice) -> Result<&'static MutexIrqSafe<VirtioNetNic>, &'static str> {
+        if VIRTIO_NET_NIC.try().is_some() {
+            return Err("virtio_net: only one virtio network device is supported");
+        }
+
+        let mut transport = Transport::new(pci_device)?;
+        let features = transport.negotiate_features(VIRTIO_NET_F_MAC)?;
+        if features & VIRTIO_NET_F_MAC == 0 {
+            transport.fail();
+            return Err("virtio_net: device doesn't provide a MAC address");
+        }
+        let mut mac_address = [0u8; 6];
+        for (i, byte) in mac_address.iter_mut().enumerate() {
+            *byte = transport.read_config_u8(CONFIG_MAC_OFFSET + i)?;
+        }
+        let header_size = if features & VIRTIO_F_VERSION_1 != 0 { MODERN_HEADER_SIZE } else { LEGACY_HEADER_SIZE };
+
+        let rx_queue = transport.create_queue(RX_QUEUE_INDEX, MAX_QUEUE_SIZE)?;
+        let tx_queue = transport.create_queue(TX_QUEUE_INDEX, MAX_QUEUE_SIZE)?;
+        if rx_queue.size() < 2 || tx_queue.size() < 2 {
+            transport.fail();
+            return Err("virtio_net: device's queues are too small");
+        }
+        let num_rx_chains = (rx_queue.size() / 2) as usize;
+
+        let headers_size = (1 + num_rx_chains) * header_size;
+        let (mut headers, headers_phys_addr) = create_contiguous_mapping(headers_size, DEVICE_MAPPING_FLAGS)?;
+        for byte in headers.as_slice_mut::<u8>(0, headers_size)?.iter_mut() {
+            *byte = 0;
+        }
+
+        init_rx_buf_pool(RX_BUFFER_POOL_SIZE, RX_BUFFER_SIZE_IN_BYTES, &RX_BUFFER_POOL)?;
+        transport.driver_ok();
+
+        let transport = Arc::new(MutexIrqSafe::new(transport));
+        // Received frames are taken by polling, but the device's interrupts must still be acknowledged,
+        // especially since they may be shared with other virtio devices.
+        if let Err(e) = virtio::register_interrupt_handler(pci_device, &transport) {
+            warn!("VirtioNetNic::init(): couldn't register interrupt handler: {}", e);
+        }
+
+        let rx_chains = (0 .. rx_queue.size()).map(|_| None).collect();
+        let tx_buffers = (0 .. tx_queue.size()).map(|_| None).collect();
+        let mut nic = VirtioNetNic {
+            transport,
+            rx_queue,
+            tx_queue,
+            rx_chains,
+            tx_buffers,
+            received_frames: VecDeque::new(),
+            header_size,
+            _headers: headers,
+            headers_phys_addr,
+            free_header_slots: (1 ..= num_rx_chains).collect(),
+            mac_address,
+        };
+        for _ in 0 .. num_rx_chains {
+            nic.add_receive_chain()?;
+        }
+        nic.transport.lock().notify(RX_QUEUE_INDEX)?;
+
+        info!("virtio network device at {:?}: MAC {:02X?}, {} transport, {} receive buffers",
+            pci_device.location,
+            nic.mac_address,
+            if nic.transport.lock().is_modern() { "modern" } else { "legacy" },
+            num_rx_chains,
+        );
+        let nic_ref = VIRTIO_NET_NIC.call_once(|| MutexIrqSafe::new(nic));
+        Ok(nic_ref)
+    }
+
+    /// Places a new chain into the receive queue, which consists of a header slot and a receive buffer from the pool.
+    /// The device must then be notified through its transport.
+    fn add_receive_chain(&mut self) -> Result<(), &'static str> {
+        let buffer = match RX_BUFFER_POOL.pop() {
+            Some(rx_buf) => rx_buf,
+            None => {
+                warn!("virtio_net: RX BUF POOL WAS EMPTY.... reallocating! This means that no task is consuming the accumulated received ethernet frames.");
+                let (mp, phys_addr) = create_contiguous_mapping(RX_BUFFER_SIZE_IN_BYTES as usize, NIC_MAPPING_FLAGS)?;
+                ReceiveBuffer::new(mp, phys_addr, RX_BUFFER_SIZE_IN_BYTES, &RX_BUFFER_POOL)
+            }
+        };
+        let header_slot = self.free_header_slots.pop().ok_or("virtio_net: no free header slot for a receive chain")?;
+        let header_buffer = Buffer {
+            phys_addr: self.headers_phys_addr + header_slot * self.header_size,
+            length: self.header_size,
+            device_writable: true,
+        };
+        let frame_buffer = Buffer {
+            phys_addr: buffer.phys_addr,
+            length: RX_BUFFER_SIZE_IN_BYTES as usize,
+            device_writable: true,
+        };
+        match self.rx_queue.add(&[header_buffer, frame_buffer]) {
+            Ok(id) => {
+                self.rx_chains[id as usize] = Some(ReceiveChain { buffer, header_slot });
+                Ok(())
+            }
+            Err(e) => {
+                // the receive buffer is returned to the pool when dropped
+                self.free_header_slots.push(header_slot);
+                Err(e)
+            }
+        }
+    }
+
+    /// Drops the buffers of all transmitted frames that the device has finished with.
+    fn reclaim_transmit_buffers(&mut self) {
+        while let Some((id, _length)) = self.tx_queue.pop_used() {
+            if let Some(tx_buffer) = self.tx_buffers.get_mut(id as usize) {
+                tx_buffer.take();
+            }
+        }
+    }
+
+    /// Busy-waits until the device has finished with at least one transmitted frame,
+    /// freeing enough descriptors in the transmit queue for another frame.
+    fn wait_for_transmit_queue(&mut self) -> Result<(), &'static str> {
+        driver_utils::poll(TX_TIMEOUT_MS, || {
+            self.reclaim_transmit_buffers();
+            if self.tx_queue.free_count() >= 2 { Some(()) } else { None }
+        }).ok_or("virtio_net: timed out waiting for the device to transmit queued frames")
+    }
+}
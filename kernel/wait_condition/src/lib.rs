@@ -7,6 +7,7 @@
 extern crate task;
 extern crate wait_queue;
 
+use core::time::Duration;
 use task::TaskRef;
 use wait_queue::{WaitQueue, WaitError};
 
@@ -65,6 +66,21 @@ impl<F: Fn() -> bool> WaitCondition<F> {
         })
     }
 
+    /// Similar to [`wait`](#method.wait), but this function gives up waiting
+    /// once the given `timeout` has elapsed, returning `Err(WaitError::Timeout)`.
+    pub fn wait_timeout(&self, timeout: Duration) -> Result<(), WaitError> {
+        if (self.condition_fn)() {
+            return Ok(());
+        }
+        self.wait_queue.wait_until_timeout(&|| {
+            if (self.condition_fn)() {
+                Some(())
+            } else {
+                None
+            }
+        }, timeout)
+    }
+
     /// This function should be invoked after the wait condition has been met
     /// and you are ready to notify other waiting tasks.
     /// The condition function within this `WaitCondition` object will be run again to ensure it has been met. 
@@ -5,11 +5,14 @@ extern crate alloc;
 extern crate irq_safety;
 extern crate task;
 extern crate scheduler;
+extern crate sleep;
 
 
+use core::time::Duration;
 use alloc::collections::VecDeque;
 use irq_safety::MutexIrqSafe;
 use task::TaskRef;
+use sleep::Instant;
 
 
 /// An object that holds a blocked `Task` 
@@ -169,6 +172,81 @@ impl WaitQueue {
         }
     }
 
+    /// Similar to [`wait`](#method.wait), but this function gives up waiting
+    /// once the given `timeout` has elapsed, returning `Err(WaitError::Timeout)`.
+    /// 
+    /// Returns `Ok(())` if the `Task` was woken up through the notify mechanism before the `timeout`.
+    /// The granularity of the `timeout` is one timer tick; see the `sleep` crate.
+    pub fn wait_timeout(&self, timeout: Duration) -> Result<(), WaitError> {
+        let curr_task = task::get_my_current_task().ok_or(WaitError::NoCurrentTask)?;
+        let deadline = Instant::now() + timeout;
+
+        // Do the following atomically, in addition to the steps described in `wait_until()`:
+        // add the current task to this core's sleep queue, such that it will be woken up at the deadline.
+        {
+            let mut wq_locked = self.0.lock();
+            if !wq_locked.contains(curr_task) {
+                wq_locked.push_back(curr_task.clone());
+            }
+            sleep::add_to_sleep_queue(curr_task, deadline);
+            curr_task.block();
+        }
+
+        loop {
+            scheduler::schedule();
+
+            // Here, we have been woken up, either by a notify, by the timer, or spuriously.
+            let mut wq_locked = self.0.lock();
+            if !wq_locked.contains(curr_task) {
+                // A notify removed this task from the waitqueue, so we no longer need the timer.
+                sleep::remove_from_sleep_queue(curr_task);
+                return Ok(());
+            }
+            if Instant::now() >= deadline {
+                wq_locked.retain(|t| t != curr_task);
+                sleep::remove_from_sleep_queue(curr_task);
+                return Err(WaitError::Timeout);
+            }
+            // trace!("WaitQueue::wait_timeout():  spurious wakeup, blocking again: {:?}", curr_task);
+            sleep::add_to_sleep_queue(curr_task, deadline);
+            curr_task.block();
+        }
+    }
+
+    /// Similar to [`wait_until`](#method.wait_until), but this function gives up waiting
+    /// once the given `timeout` has elapsed, returning `Err(WaitError::Timeout)`.
+    /// 
+    /// The `condition` is checked once more after the `timeout` has elapsed, 
+    /// so a `condition` that was met at the last moment is not reported as a timeout. 
+    pub fn wait_until_timeout<R>(&self, condition: &dyn Fn(/* &VecDeque<TaskRef> */) -> Option<R>, timeout: Duration) -> Result<R, WaitError> {
+        let curr_task = task::get_my_current_task().ok_or(WaitError::NoCurrentTask)?;
+        let deadline = Instant::now() + timeout;
+
+        loop {
+            {
+                let mut wq_locked = self.0.lock();
+                if let Some(ret) = condition(/* &wq_locked */) {
+                    wq_locked.retain(|t| t != curr_task);
+                    sleep::remove_from_sleep_queue(curr_task);
+                    return Ok(ret);
+                }
+                if Instant::now() >= deadline {
+                    wq_locked.retain(|t| t != curr_task);
+                    sleep::remove_from_sleep_queue(curr_task);
+                    return Err(WaitError::Timeout);
+                }
+                if !wq_locked.contains(curr_task) {
+                    wq_locked.push_back(curr_task.clone());
+                }
+                sleep::add_to_sleep_queue(curr_task, deadline);
+                curr_task.block();
+            }
+            scheduler::schedule();
+
+            // Here, we have been woken up, so loop back around and check the condition and deadline again
+        }
+    }
+
     /// Wake up one random `Task` that is waiting on this queue.
     /// # Return
     /// * returns `Ok(true)` if a `Task` was successfully woken up,
@@ -42,7 +42,7 @@ use shapes::{Coord, Rectangle};
 use framebuffer_compositor::{FRAME_COMPOSITOR};
 use keycodes_ascii::{KeyAction, KeyEvent, Keycode};
 use mouse_data::MouseEvent;
-use path::Path;
+use path::PathBuf;
 use spin::{Mutex, Once};
 use window_inner::{WindowInner, WindowMovingStatus};
 
@@ -794,7 +794,7 @@ fn keyboard_handle_application(key_input: KeyEvent) -> Result<(), &'static str>
         let new_app_namespace = mod_mgmt::create_application_namespace(None)?;
         let shell_objfile = new_app_namespace.dir().get_file_starting_with("shell-")
             .ok_or("Couldn't find shell application file to run upon Ctrl+Alt+T")?;
-        let path = Path::new(shell_objfile.lock().get_absolute_path());
+        let path = PathBuf::from(shell_objfile.lock().get_absolute_path());
         spawn::new_application_task_builder(path, Some(new_app_namespace))?
             .name(format!("shell"))
             .spawn()?;